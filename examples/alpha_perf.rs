@@ -39,6 +39,7 @@ impl Example for App {
             MixBlendMode::Normal,
             Vec::new(),
             GlyphRasterSpace::Screen,
+            None,
         );
 
         for _ in 0 .. self.rect_count {
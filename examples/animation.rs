@@ -64,6 +64,7 @@ impl Example for App {
             MixBlendMode::Normal,
             filters,
             GlyphRasterSpace::Screen,
+            None,
         );
 
         let complex_clip = ComplexClipRegion {
@@ -197,6 +197,7 @@ impl Example for App {
             MixBlendMode::Normal,
             Vec::new(),
             GlyphRasterSpace::Screen,
+            None,
         );
 
         let image_mask_key = api.generate_image_key();
@@ -215,6 +215,7 @@ impl Example for App {
             api::MixBlendMode::Normal,
             Vec::new(),
             api::GlyphRasterSpace::Screen,
+            None,
         );
 
         let info = api::LayoutPrimitiveInfo::new((30, 30).by(500, 500));
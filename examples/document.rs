@@ -118,6 +118,7 @@ impl Example for App {
                 MixBlendMode::Normal,
                 Vec::new(),
                 GlyphRasterSpace::Screen,
+                None,
             );
             builder.push_rect(
                 &LayoutPrimitiveInfo::new(local_rect),
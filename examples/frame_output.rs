@@ -106,6 +106,7 @@ impl App {
             MixBlendMode::Normal,
             Vec::new(),
             GlyphRasterSpace::Screen,
+            None,
         );
 
         builder.push_rect(&info, ColorF::new(1.0, 1.0, 0.0, 1.0));
@@ -150,6 +151,7 @@ impl Example for App {
             MixBlendMode::Normal,
             Vec::new(),
             GlyphRasterSpace::Screen,
+            None,
         );
 
         builder.push_image(
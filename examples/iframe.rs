@@ -44,6 +44,7 @@ impl Example for App {
             MixBlendMode::Normal,
             Vec::new(),
             GlyphRasterSpace::Screen,
+            None,
         );
 
         // green rect visible == success
@@ -77,6 +78,7 @@ impl Example for App {
             MixBlendMode::Normal,
             Vec::new(),
             GlyphRasterSpace::Screen,
+            None,
         );
         // red rect under the iframe: if this is visible, things have gone wrong
         builder.push_rect(&info, ColorF::new(1.0, 0.0, 0.0, 1.0));
@@ -46,6 +46,7 @@ impl Example for App {
             MixBlendMode::Normal,
             Vec::new(),
             GlyphRasterSpace::Screen,
+            None,
         );
 
         let image_size = LayoutSize::new(100.0, 100.0);
@@ -189,6 +189,7 @@ impl Window {
             MixBlendMode::Normal,
             Vec::new(),
             GlyphRasterSpace::Screen,
+            None,
         );
 
         let info = LayoutPrimitiveInfo::new(LayoutRect::new(
@@ -40,6 +40,7 @@ impl Example for App {
             MixBlendMode::Normal,
             Vec::new(),
             GlyphRasterSpace::Screen,
+            None,
         );
 
         if true {
@@ -53,6 +54,7 @@ impl Example for App {
                 MixBlendMode::Normal,
                 Vec::new(),
                 GlyphRasterSpace::Screen,
+                None,
             );
             // set the scrolling clip
             let clip_id = builder.define_scroll_frame(
@@ -93,6 +93,7 @@ impl Example for App {
             MixBlendMode::Normal,
             Vec::new(),
             GlyphRasterSpace::Screen,
+            None,
         );
 
         let yuv_chanel1 = api.generate_image_key();
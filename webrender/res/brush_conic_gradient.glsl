@@ -0,0 +1,79 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#define VECS_PER_SPECIFIC_BRUSH 2
+
+#include shared,prim_shared,brush,gradient_shared
+
+#define TWO_PI 6.283185307179586
+
+flat varying float vAngle;
+flat varying float vStartOffset;
+flat varying float vEndOffset;
+
+#ifdef WR_VERTEX_SHADER
+
+struct ConicGradient {
+    vec4 center_start_end_offset;
+    float angle;
+    int extend_mode;
+    vec2 stretch_size;
+};
+
+ConicGradient fetch_conic_gradient(int address) {
+    vec4 data[2] = fetch_from_resource_cache_2(address);
+    return ConicGradient(
+        data[0],
+        data[1].x,
+        int(data[1].y),
+        data[1].zw
+    );
+}
+
+void brush_vs(
+    VertexInfo vi,
+    int prim_address,
+    RectWithSize local_rect,
+    RectWithSize segment_rect,
+    ivec3 user_data,
+    mat4 transform,
+    PictureTask pic_task,
+    int brush_flags,
+    vec4 texel_rect
+) {
+    ConicGradient gradient = fetch_conic_gradient(prim_address);
+
+    write_gradient_vertex(
+        vi,
+        local_rect,
+        segment_rect,
+        user_data,
+        brush_flags,
+        texel_rect,
+        gradient.center_start_end_offset.xy,
+        gradient.extend_mode,
+        gradient.stretch_size
+    );
+
+    vStartOffset = gradient.center_start_end_offset.z;
+    vEndOffset = gradient.center_start_end_offset.w;
+    vAngle = gradient.angle;
+}
+#endif
+
+#ifdef WR_FRAGMENT_SHADER
+Fragment brush_fs() {
+    vec2 pd = compute_gradient_pos();
+
+    // The angle is measured clockwise from the upwards direction,
+    // and mapped to the [0, 1) range of a full turn.
+    float turn = atan(pd.x, -pd.y) - vAngle;
+    turn = fract(turn / TWO_PI);
+
+    // Remap the full turn to the normalized stop range.
+    float offset = (turn - vStartOffset) / (vEndOffset - vStartOffset);
+
+    return gradient_fragment(offset);
+}
+#endif
@@ -4,23 +4,11 @@
 
 #define VECS_PER_SPECIFIC_BRUSH 2
 
-#include shared,prim_shared,brush
+#include shared,prim_shared,brush,gradient_shared
 
-flat varying int vGradientAddress;
-flat varying float vGradientRepeat;
-
-flat varying vec2 vCenter;
 flat varying float vStartRadius;
 flat varying float vEndRadius;
 
-varying vec2 vPos;
-flat varying vec2 vRepeatedSize;
-
-#ifdef WR_FEATURE_ALPHA_PASS
-varying vec2 vLocalPos;
-flat varying vec2 vTileRepeat;
-#endif
-
 #ifdef WR_VERTEX_SHADER
 
 struct RadialGradient {
@@ -53,61 +41,32 @@ void brush_vs(
 ) {
     RadialGradient gradient = fetch_radial_gradient(prim_address);
 
-    if ((brush_flags & BRUSH_FLAG_SEGMENT_RELATIVE) != 0) {
-        vPos = (vi.local_pos - segment_rect.p0) / segment_rect.size;
-        vPos = vPos * (texel_rect.zw - texel_rect.xy) + texel_rect.xy;
-    } else {
-        vPos = vi.local_pos - local_rect.p0;
-    }
+    write_gradient_vertex(
+        vi,
+        local_rect,
+        segment_rect,
+        user_data,
+        brush_flags,
+        texel_rect,
+        gradient.center_start_end_radius.xy,
+        gradient.extend_mode,
+        gradient.stretch_size
+    );
 
-    vCenter = gradient.center_start_end_radius.xy;
     vStartRadius = gradient.center_start_end_radius.z;
     vEndRadius = gradient.center_start_end_radius.w;
 
     // Transform all coordinates by the y scale so the
     // fragment shader can work with circles
-    vec2 tile_repeat = local_rect.size / gradient.stretch_size;
     vPos.y *= gradient.ratio_xy;
     vCenter.y *= gradient.ratio_xy;
-    vRepeatedSize = gradient.stretch_size;
-    vRepeatedSize.y *=  gradient.ratio_xy;
-
-    vGradientAddress = user_data.x;
-
-    // Whether to repeat the gradient instead of clamping.
-    vGradientRepeat = float(gradient.extend_mode != EXTEND_MODE_CLAMP);
-
-#ifdef WR_FEATURE_ALPHA_PASS
-    vTileRepeat = tile_repeat.xy;
-    vLocalPos = vi.local_pos;
-#endif
+    vRepeatedSize.y *= gradient.ratio_xy;
 }
 #endif
 
 #ifdef WR_FRAGMENT_SHADER
 Fragment brush_fs() {
-
-#ifdef WR_FEATURE_ALPHA_PASS
-    // Handle top and left inflated edges (see brush_image).
-    vec2 local_pos = max(vPos, vec2(0.0));
-
-    // Apply potential horizontal and vertical repetitions.
-    vec2 pos = mod(local_pos, vRepeatedSize);
-
-    vec2 prim_size = vRepeatedSize * vTileRepeat;
-    // Handle bottom and right inflated edges (see brush_image).
-    if (local_pos.x >= prim_size.x) {
-        pos.x = vRepeatedSize.x;
-    }
-    if (local_pos.y >= prim_size.y) {
-        pos.y = vRepeatedSize.y;
-    }
-#else
-    // Apply potential horizontal and vertical repetitions.
-    vec2 pos = mod(vPos, vRepeatedSize);
-#endif
-
-    vec2 pd = pos - vCenter;
+    vec2 pd = compute_gradient_pos();
     float rd = vEndRadius - vStartRadius;
 
     // Solve for t in length(t - pd) = vStartRadius + t * rd
@@ -145,14 +104,6 @@ Fragment brush_fs() {
         }
     }
 
-    vec4 color = sample_gradient(vGradientAddress,
-                                 offset,
-                                 vGradientRepeat);
-
-#ifdef WR_FEATURE_ALPHA_PASS
-    color *= init_transform_fs(vLocalPos);
-#endif
-
-    return Fragment(color);
+    return gradient_fragment(offset);
 }
 #endif
@@ -24,7 +24,8 @@ flat varying ivec4 vConfig;
 flat varying vec4 vClipCenter_Sign;
 
 // An outer and inner elliptical radii for border
-// corner clipping.
+// corner clipping. The inner radii aren't clamped, so
+// that a square inner corner is positioned correctly.
 flat varying vec4 vClipRadii;
 
 // Reference point for determine edge clip lines.
@@ -190,7 +191,7 @@ void main(void) {
     vColor10 = color1[0];
     vColor11 = color1[1];
     vClipCenter_Sign = vec4(outer + clip_sign * aRadii, clip_sign);
-    vClipRadii = vec4(aRadii, max(aRadii - aWidths, 0.0));
+    vClipRadii = vec4(aRadii, aRadii - aWidths);
     vColorLine = vec4(outer, aWidths.y * -clip_sign.y, aWidths.x * clip_sign.x);
     vEdgeReference = vec4(edge_reference, edge_reference + aWidths);
     vClipParams1 = aClipParams1;
@@ -213,28 +214,69 @@ void main(void) {
 #endif
 
 #ifdef WR_FRAGMENT_SHADER
+// Get the signed distance to the corner of a rounded rect inset from
+// the outer border edge, whose radii are the outer radii minus the
+// inset. As in CSS, the inset corner becomes square once either of its
+// radii reaches zero, in which case its edges lie outside of the corner
+// ellipse.
+float distance_to_inset_corner(
+    vec2 clip_relative_pos,
+    vec2 inset_radii,
+    float aa_range
+) {
+    if (all(greaterThan(inset_radii, vec2(0.0)))) {
+        return distance_to_ellipse(clip_relative_pos, inset_radii, aa_range);
+    }
+
+    vec2 d = abs(clip_relative_pos) - inset_radii;
+    return clamp(max(d.x, d.y), -aa_range, aa_range);
+}
+
+// In evaluate_color_for_style_in_edge, the second color of a side is
+// used for the half of the border next to the edge reference. That's
+// the outer edge for the top and left sides, and the inner edge for the
+// bottom and right sides.
+bool is_top_or_left_side(int segment, int side) {
+    switch (segment) {
+        case SEGMENT_TOP_LEFT:
+            return true;
+        case SEGMENT_TOP_RIGHT:
+            return side == 0;
+        case SEGMENT_BOTTOM_LEFT:
+            return side == 1;
+        case SEGMENT_BOTTOM_RIGHT:
+        default:
+            return false;
+    }
+}
+
 vec4 evaluate_color_for_style_in_corner(
     vec2 clip_relative_pos,
     int style,
     vec4 color0,
     vec4 color1,
     vec4 clip_radii,
-    float mix_factor,
-    int segment,
+    bool top_or_left,
+    int edge_axis,
     float aa_range
 ) {
     switch (style) {
         case BORDER_STYLE_DOUBLE: {
-            // Get the distances from 0.33 of the radii, and
-            // also 0.67 of the radii. Use these to form a
-            // SDF subtraction which will clip out the inside
-            // third of the rounded edge.
-            float d_radii_a = distance_to_ellipse(
+            // Thin double borders are drawn solid, as in the edges.
+            if (vPartialWidths[edge_axis] <= 1.0) {
+                break;
+            }
+
+            // Get the distances to the curves inset by a third and
+            // two thirds of the widths. Use these to form a SDF
+            // subtraction which will clip out the inside third of
+            // the rounded edge.
+            float d_radii_a = distance_to_inset_corner(
                 clip_relative_pos,
                 clip_radii.xy - vPartialWidths.xy,
                 aa_range
             );
-            float d_radii_b = distance_to_ellipse(
+            float d_radii_b = distance_to_inset_corner(
                 clip_relative_pos,
                 clip_radii.xy - 2.0 * vPartialWidths.xy,
                 aa_range
@@ -245,23 +287,16 @@ vec4 evaluate_color_for_style_in_corner(
         }
         case BORDER_STYLE_GROOVE:
         case BORDER_STYLE_RIDGE: {
-            float d = distance_to_ellipse(
+            float d = distance_to_inset_corner(
                 clip_relative_pos,
                 clip_radii.xy - vPartialWidths.zw,
                 aa_range
             );
+            // The alpha is one for the inner half of the border.
             float alpha = distance_aa(aa_range, d);
-            float swizzled_factor;
-            switch (segment) {
-                case SEGMENT_TOP_LEFT: swizzled_factor = 0.0; break;
-                case SEGMENT_TOP_RIGHT: swizzled_factor = mix_factor; break;
-                case SEGMENT_BOTTOM_RIGHT: swizzled_factor = 1.0; break;
-                case SEGMENT_BOTTOM_LEFT: swizzled_factor = 1.0 - mix_factor; break;
-                default: swizzled_factor = 0.0; break;
-            };
-            vec4 c0 = mix(color1, color0, swizzled_factor);
-            vec4 c1 = mix(color0, color1, swizzled_factor);
-            return mix(c0, c1, alpha);
+            vec4 outer_color = top_or_left ? color1 : color0;
+            vec4 inner_color = top_or_left ? color0 : color1;
+            return mix(outer_color, inner_color, alpha);
         }
         default:
             break;
@@ -353,7 +388,7 @@ void main(void) {
 
     if (in_clip_region) {
         float d_radii_a = distance_to_ellipse(clip_relative_pos, vClipRadii.xy, aa_range);
-        float d_radii_b = distance_to_ellipse(clip_relative_pos, vClipRadii.zw, aa_range);
+        float d_radii_b = distance_to_inset_corner(clip_relative_pos, vClipRadii.zw, aa_range);
         float d_radii = max(d_radii_a, -d_radii_b);
         d = max(d, d_radii);
 
@@ -363,8 +398,8 @@ void main(void) {
             vColor00,
             vColor01,
             vClipRadii,
-            mix_factor,
-            segment,
+            is_top_or_left_side(segment, 0),
+            edge_axis.x,
             aa_range
         );
         color1 = evaluate_color_for_style_in_corner(
@@ -373,8 +408,8 @@ void main(void) {
             vColor10,
             vColor11,
             vClipRadii,
-            mix_factor,
-            segment,
+            is_top_or_left_side(segment, 1),
+            edge_axis.y,
             aa_range
         );
     } else {
@@ -10,16 +10,23 @@ varying vec3 vClipMaskImageUv;
 flat varying vec4 vClipMaskUvRect;
 flat varying vec4 vClipMaskUvInnerRect;
 flat varying float vLayer;
+flat varying int vChannel;
+
+// Must match the ImageMaskChannel enum in clip.rs
+#define MASK_CHANNEL_RED        0
+#define MASK_CHANNEL_ALPHA      1
+#define MASK_CHANNEL_LUMINANCE  2
 
 #ifdef WR_VERTEX_SHADER
 struct ImageMaskData {
     RectWithSize local_rect;
+    int channel;
 };
 
 ImageMaskData fetch_mask_data(ivec2 address) {
-    vec4 data = fetch_from_resource_cache_1_direct(address);
-    RectWithSize local_rect = RectWithSize(data.xy, data.zw);
-    ImageMaskData mask_data = ImageMaskData(local_rect);
+    vec4 data[2] = fetch_from_resource_cache_2_direct(address);
+    RectWithSize local_rect = RectWithSize(data[0].xy, data[0].zw);
+    ImageMaskData mask_data = ImageMaskData(local_rect, int(data[1].x));
     return mask_data;
 }
 
@@ -37,6 +44,7 @@ void main(void) {
 
     vPos = vi.local_pos;
     vLayer = res.layer;
+    vChannel = mask.channel;
 
     vClipMaskImageUv = vec3((vPos.xy / vPos.z - local_rect.p0) / local_rect.size, 0.0);
     vec2 texture_size = vec2(textureSize(sColor0, 0));
@@ -56,7 +64,23 @@ void main(void) {
         clamp(vClipMaskImageUv.xy, vec2(0.0, 0.0), vec2(1.0, 1.0));
     vec2 source_uv = clamp(clamped_mask_uv * vClipMaskUvRect.zw + vClipMaskUvRect.xy,
         vClipMaskUvInnerRect.xy, vClipMaskUvInnerRect.zw);
-    float clip_alpha = texture(sColor0, vec3(source_uv, vLayer)).r; //careful: texture has type A8
+    vec4 texel = TEX_SAMPLE(sColor0, vec3(source_uv, vLayer));
+
+    float clip_alpha;
+    switch (vChannel) {
+        case MASK_CHANNEL_ALPHA:
+            clip_alpha = texel.a;
+            break;
+        case MASK_CHANNEL_LUMINANCE:
+            // The texel is premultiplied, so this is the luminance
+            // scaled by alpha, as CSS masking requires.
+            clip_alpha = dot(texel.rgb, vec3(0.2125, 0.7154, 0.0721));
+            break;
+        default:
+            // Clip node masks are single channel (R8) images.
+            clip_alpha = texel.r;
+            break;
+    }
 
     oFragColor = vec4(alpha * clip_alpha, 1.0, 1.0, 1.0);
 }
@@ -0,0 +1,85 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#include shared,clip_shared
+
+// Draws the coverage of a path clip, which has been flattened into
+// line edges on the CPU. The edges follow the header in the GPU cache.
+
+#define FILL_RULE_NONZERO   0
+#define FILL_RULE_EVENODD   1
+
+varying vec3 vLocalPos;
+
+flat varying int vEdgeCount;
+flat varying int vFillRule;
+flat varying ivec2 vEdgesAddress;
+
+#ifdef WR_VERTEX_SHADER
+
+struct PathClipData {
+    RectWithSize bounds;
+    int edge_count;
+    int fill_rule;
+};
+
+PathClipData fetch_path_clip(ivec2 address) {
+    vec4 data[2] = fetch_from_resource_cache_2_direct(address);
+    return PathClipData(
+        RectWithSize(data[0].xy, data[0].zw),
+        int(data[1].x),
+        int(data[1].y)
+    );
+}
+
+void main(void) {
+    ClipMaskInstance cmi = fetch_clip_item();
+    ClipArea area = fetch_clip_area(cmi.render_task_address);
+    Transform transform = fetch_transform(cmi.transform_id);
+    PathClipData path = fetch_path_clip(cmi.clip_data_address);
+
+    ClipVertexInfo vi = write_clip_tile_vertex(path.bounds, transform, area);
+    vLocalPos = vi.local_pos;
+
+    vEdgeCount = path.edge_count;
+    vFillRule = path.fill_rule;
+    vEdgesAddress = cmi.clip_data_address + ivec2(2, 0);
+}
+#endif
+
+#ifdef WR_FRAGMENT_SHADER
+void main(void) {
+    vec2 local_pos = vLocalPos.xy / vLocalPos.z;
+    float aa_range = compute_aa_range(local_pos);
+
+    int winding = 0;
+    float min_distance = 1.0e10;
+
+    for (int i = 0; i < vEdgeCount; i++) {
+        vec4 edge = texelFetch(sResourceCache, vEdgesAddress + ivec2(i, 0), 0);
+        vec2 p0 = edge.xy;
+        vec2 p1 = edge.zw;
+
+        // Count the edges crossing a ray going right from this point,
+        // with their direction.
+        if ((p0.y <= local_pos.y) != (p1.y <= local_pos.y)) {
+            float t = (local_pos.y - p0.y) / (p1.y - p0.y);
+            if (mix(p0.x, p1.x, t) > local_pos.x) {
+                winding += p1.y > p0.y ? 1 : -1;
+            }
+        }
+
+        // Keep the distance to the nearest edge for anti-aliasing.
+        vec2 d = p1 - p0;
+        float len2 = dot(d, d);
+        float t = len2 > 0.0 ? clamp(dot(local_pos - p0, d) / len2, 0.0, 1.0) : 0.0;
+        min_distance = min(min_distance, distance(local_pos, p0 + t * d));
+    }
+
+    bool inside = vFillRule == FILL_RULE_EVENODD ? (winding & 1) != 0 : winding != 0;
+    float signed_distance = inside ? -min_distance : min_distance;
+
+    oFragColor = vec4(distance_aa(aa_range, signed_distance));
+}
+#endif
@@ -0,0 +1,211 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#include shared,prim_shared
+
+// Draws a single SVG filter primitive into its render task. The inputs
+// are other render tasks of the same size, bound to sColor0 and sColor1.
+
+#define SVG_FILTER_IDENTITY             0
+#define SVG_FILTER_FLOOD                1
+#define SVG_FILTER_OFFSET               2
+#define SVG_FILTER_COMPONENT_TRANSFER   3
+#define SVG_FILTER_COMPOSITE            4
+
+#define COMPOSITE_OVER          0
+#define COMPOSITE_IN            1
+#define COMPOSITE_OUT           2
+#define COMPOSITE_ATOP          3
+#define COMPOSITE_XOR           4
+#define COMPOSITE_ARITHMETIC    5
+
+#define TRANSFER_IDENTITY   0
+#define TRANSFER_TABLE      1
+#define TRANSFER_DISCRETE   2
+#define TRANSFER_LINEAR     3
+#define TRANSFER_GAMMA      4
+
+varying vec3 vInput1Uv;
+varying vec3 vInput2Uv;
+flat varying vec4 vInput1UvBounds;
+flat varying vec4 vInput2UvBounds;
+flat varying vec4 vInput1UvRect;
+flat varying vec4 vInput2UvRect;
+flat varying int vFilterKind;
+flat varying ivec2 vInputAlphaOnly;
+flat varying int vCompositeOperator;
+flat varying vec4 vFilterData;
+flat varying ivec2 vExtraDataAddress;
+
+#ifdef WR_VERTEX_SHADER
+
+in int aFilterRenderTaskAddress;
+in int aFilterInput1TaskAddress;
+in int aFilterInput2TaskAddress;
+in int aFilterKind;
+in int aFilterInputFlags;
+in int aFilterGenericInt;
+in ivec2 aFilterExtraDataAddress;
+
+void main(void) {
+    RenderTaskCommonData task = fetch_render_task_common_data(aFilterRenderTaskAddress);
+    RectWithSize target_rect = task.task_rect;
+    vec2 pos = target_rect.p0 + target_rect.size * aPosition.xy;
+
+    vFilterKind = aFilterKind;
+    vCompositeOperator = aFilterGenericInt;
+    vInputAlphaOnly = ivec2(aFilterInputFlags & 1, (aFilterInputFlags >> 1) & 1);
+    vExtraDataAddress = aFilterExtraDataAddress;
+    vFilterData = fetch_from_resource_cache_1_direct(aFilterExtraDataAddress);
+
+    // An offset shifts the input, so that each pixel reads the
+    // input at its own position minus the offset.
+    vec2 offset = vec2(0.0);
+    if (aFilterKind == SVG_FILTER_OFFSET) {
+        offset = vFilterData.xy;
+    }
+    vec2 task_pos = pos - target_rect.p0 - offset;
+
+    RenderTaskCommonData input_1 = fetch_render_task_common_data(aFilterInput1TaskAddress);
+    vec2 texture_size_1 = vec2(textureSize(sColor0, 0).xy);
+    vInput1Uv = vec3(
+        (input_1.task_rect.p0 + task_pos) / texture_size_1,
+        input_1.texture_layer_index
+    );
+    vInput1UvBounds = vec4(input_1.task_rect.p0,
+                           input_1.task_rect.p0 + input_1.task_rect.size) / texture_size_1.xyxy;
+    vInput1UvRect = vec4(input_1.task_rect.p0 + vec2(0.5),
+                         input_1.task_rect.p0 + input_1.task_rect.size - vec2(0.5)) / texture_size_1.xyxy;
+
+    RenderTaskCommonData input_2 = fetch_render_task_common_data(aFilterInput2TaskAddress);
+    vec2 texture_size_2 = vec2(textureSize(sColor1, 0).xy);
+    vInput2Uv = vec3(
+        (input_2.task_rect.p0 + task_pos) / texture_size_2,
+        input_2.texture_layer_index
+    );
+    vInput2UvBounds = vec4(input_2.task_rect.p0,
+                           input_2.task_rect.p0 + input_2.task_rect.size) / texture_size_2.xyxy;
+    vInput2UvRect = vec4(input_2.task_rect.p0 + vec2(0.5),
+                         input_2.task_rect.p0 + input_2.task_rect.size - vec2(0.5)) / texture_size_2.xyxy;
+
+    gl_Position = uTransform * vec4(pos, 0.0, 1.0);
+}
+#endif
+
+#ifdef WR_FRAGMENT_SHADER
+
+bool is_outside(vec2 uv, vec4 bounds) {
+    return any(lessThan(uv, bounds.xy)) || any(greaterThanEqual(uv, bounds.zw));
+}
+
+// Samples outside of an input are transparent black.
+vec4 sample_input_1() {
+    if (is_outside(vInput1Uv.xy, vInput1UvBounds)) {
+        return vec4(0.0);
+    }
+    vec2 uv = clamp(vInput1Uv.xy, vInput1UvRect.xy, vInput1UvRect.zw);
+    vec4 color = texture(sColor0, vec3(uv, vInput1Uv.z));
+    return vInputAlphaOnly.x != 0 ? vec4(0.0, 0.0, 0.0, color.a) : color;
+}
+
+vec4 sample_input_2() {
+    if (is_outside(vInput2Uv.xy, vInput2UvBounds)) {
+        return vec4(0.0);
+    }
+    vec2 uv = clamp(vInput2Uv.xy, vInput2UvRect.xy, vInput2UvRect.zw);
+    vec4 color = texture(sColor1, vec3(uv, vInput2Uv.z));
+    return vInputAlphaOnly.y != 0 ? vec4(0.0, 0.0, 0.0, color.a) : color;
+}
+
+// Reads the value of a table transfer function, which are packed
+// four per block after the channel headers.
+float fetch_table_value(int first_block, int index) {
+    ivec2 address = vExtraDataAddress + ivec2(first_block + index / 4, 0);
+    vec4 block = texelFetch(sResourceCache, address, 0);
+    return block[index % 4];
+}
+
+float apply_transfer(int channel, float c) {
+    vec4 header = texelFetch(sResourceCache, vExtraDataAddress + ivec2(channel, 0), 0);
+    int kind = int(header.x);
+    int count = int(header.y);
+    int first_block = int(header.z);
+
+    if (kind == TRANSFER_TABLE && count > 0) {
+        if (count == 1) {
+            return fetch_table_value(first_block, 0);
+        }
+        float x = c * float(count - 1);
+        int k = clamp(int(floor(x)), 0, count - 2);
+        float v0 = fetch_table_value(first_block, k);
+        float v1 = fetch_table_value(first_block, k + 1);
+        return mix(v0, v1, x - float(k));
+    } else if (kind == TRANSFER_DISCRETE && count > 0) {
+        int k = clamp(int(floor(c * float(count))), 0, count - 1);
+        return fetch_table_value(first_block, k);
+    } else if (kind == TRANSFER_LINEAR) {
+        return header.y * c + header.z;
+    } else if (kind == TRANSFER_GAMMA) {
+        return header.y * pow(c, header.z) + header.w;
+    }
+
+    return c;
+}
+
+vec4 component_transfer(vec4 color) {
+    // The transfer functions operate on unpremultiplied colors.
+    vec4 c = vec4(color.a > 0.0 ? color.rgb / color.a : vec3(0.0), color.a);
+    for (int i = 0; i < 4; i++) {
+        c[i] = clamp(apply_transfer(i, c[i]), 0.0, 1.0);
+    }
+    return vec4(c.rgb * c.a, c.a);
+}
+
+vec4 composite(vec4 src, vec4 dest) {
+    switch (vCompositeOperator) {
+        case COMPOSITE_OVER:
+            return src + dest * (1.0 - src.a);
+        case COMPOSITE_IN:
+            return src * dest.a;
+        case COMPOSITE_OUT:
+            return src * (1.0 - dest.a);
+        case COMPOSITE_ATOP:
+            return src * dest.a + dest * (1.0 - src.a);
+        case COMPOSITE_XOR:
+            return src * (1.0 - dest.a) + dest * (1.0 - src.a);
+        case COMPOSITE_ARITHMETIC: {
+            vec4 k = vFilterData;
+            vec4 result = k.x * src * dest + k.y * src + k.z * dest + vec4(k.w);
+            result = clamp(result, 0.0, 1.0);
+            // Keep the result a valid premultiplied color.
+            return vec4(min(result.rgb, vec3(result.a)), result.a);
+        }
+        default:
+            return src;
+    }
+}
+
+void main(void) {
+    vec4 color;
+
+    switch (vFilterKind) {
+        case SVG_FILTER_FLOOD:
+            color = vFilterData;
+            break;
+        case SVG_FILTER_COMPONENT_TRANSFER:
+            color = component_transfer(sample_input_1());
+            break;
+        case SVG_FILTER_COMPOSITE:
+            color = composite(sample_input_1(), sample_input_2());
+            break;
+        case SVG_FILTER_IDENTITY:
+        case SVG_FILTER_OFFSET:
+        default:
+            color = sample_input_1();
+            break;
+    }
+
+    oFragColor = color;
+}
+#endif
@@ -0,0 +1,93 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+// Shared by the gradient brushes that compute the gradient offset from the
+// position of the fragment relative to a center point.
+
+flat varying int vGradientAddress;
+flat varying float vGradientRepeat;
+
+flat varying vec2 vCenter;
+
+varying vec2 vPos;
+flat varying vec2 vRepeatedSize;
+
+#ifdef WR_FEATURE_ALPHA_PASS
+varying vec2 vLocalPos;
+flat varying vec2 vTileRepeat;
+#endif
+
+#ifdef WR_VERTEX_SHADER
+void write_gradient_vertex(
+    VertexInfo vi,
+    RectWithSize local_rect,
+    RectWithSize segment_rect,
+    ivec3 user_data,
+    int brush_flags,
+    vec4 texel_rect,
+    vec2 center,
+    int extend_mode,
+    vec2 stretch_size
+) {
+    if ((brush_flags & BRUSH_FLAG_SEGMENT_RELATIVE) != 0) {
+        vPos = (vi.local_pos - segment_rect.p0) / segment_rect.size;
+        vPos = vPos * (texel_rect.zw - texel_rect.xy) + texel_rect.xy;
+    } else {
+        vPos = vi.local_pos - local_rect.p0;
+    }
+
+    vCenter = center;
+    vRepeatedSize = stretch_size;
+
+    vGradientAddress = user_data.x;
+
+    // Whether to repeat the gradient instead of clamping.
+    vGradientRepeat = float(extend_mode != EXTEND_MODE_CLAMP);
+
+#ifdef WR_FEATURE_ALPHA_PASS
+    vTileRepeat = local_rect.size / stretch_size;
+    vLocalPos = vi.local_pos;
+#endif
+}
+#endif
+
+#ifdef WR_FRAGMENT_SHADER
+// Returns the position of the fragment relative to the center of the
+// gradient, after applying the repetitions of the tile.
+vec2 compute_gradient_pos() {
+#ifdef WR_FEATURE_ALPHA_PASS
+    // Handle top and left inflated edges (see brush_image).
+    vec2 local_pos = max(vPos, vec2(0.0));
+
+    // Apply potential horizontal and vertical repetitions.
+    vec2 pos = mod(local_pos, vRepeatedSize);
+
+    vec2 prim_size = vRepeatedSize * vTileRepeat;
+    // Handle bottom and right inflated edges (see brush_image).
+    if (local_pos.x >= prim_size.x) {
+        pos.x = vRepeatedSize.x;
+    }
+    if (local_pos.y >= prim_size.y) {
+        pos.y = vRepeatedSize.y;
+    }
+#else
+    // Apply potential horizontal and vertical repetitions.
+    vec2 pos = mod(vPos, vRepeatedSize);
+#endif
+
+    return pos - vCenter;
+}
+
+Fragment gradient_fragment(float offset) {
+    vec4 color = sample_gradient(vGradientAddress,
+                                 offset,
+                                 vGradientRepeat);
+
+#ifdef WR_FEATURE_ALPHA_PASS
+    color *= init_transform_fs(vLocalPos);
+#endif
+
+    return Fragment(color);
+}
+#endif
@@ -49,6 +49,7 @@ pub enum BrushBatchKind {
     },
     YuvImage(ImageBufferKind, YuvFormat, YuvColorSpace),
     RadialGradient,
+    ConicGradient,
     LinearGradient,
 }
 
@@ -619,6 +620,7 @@ impl AlphaBatchBuilder {
                     BrushKind::Image { ref visible_tiles, .. } => !visible_tiles.is_empty(),
                     BrushKind::LinearGradient { ref visible_tiles, .. } => !visible_tiles.is_empty(),
                     BrushKind::RadialGradient { ref visible_tiles, .. } => !visible_tiles.is_empty(),
+                    BrushKind::ConicGradient { ref visible_tiles, .. } => !visible_tiles.is_empty(),
                     _ => false,
                 }
             }
@@ -932,7 +934,9 @@ impl AlphaBatchBuilder {
                                 batch.push(PrimitiveInstance::from(instance));
                                 false
                             }
-                            Some(PictureCompositeMode::Blit) => {
+                            Some(PictureCompositeMode::Blit) |
+                            Some(PictureCompositeMode::Mask) |
+                            Some(PictureCompositeMode::SvgFilter(..)) => {
                                 let cache_task_id = picture
                                     .surface
                                     .as_ref()
@@ -1047,6 +1051,20 @@ impl AlphaBatchBuilder {
                             prim_headers,
                         );
                     }
+                    BrushKind::ConicGradient { ref stops_handle, ref visible_tiles, .. } if !visible_tiles.is_empty() => {
+                        add_gradient_tiles(
+                            visible_tiles,
+                            stops_handle,
+                            BrushBatchKind::ConicGradient,
+                            specified_blend_mode,
+                            &task_relative_bounding_rect,
+                            clip_task_address,
+                            gpu_cache,
+                            &mut self.batch_list,
+                            &prim_header,
+                            prim_headers,
+                        );
+                    }
                     _ => {
                         if let Some((batch_kind, textures, user_data)) = brush.get_batch_params(
                                 ctx.resource_cache,
@@ -1486,6 +1504,17 @@ impl BrushPrimitive {
                     ],
                 ))
             }
+            BrushKind::ConicGradient { ref stops_handle, .. } => {
+                Some((
+                    BrushBatchKind::ConicGradient,
+                    BatchTextures::no_texture(),
+                    [
+                        stops_handle.as_int(gpu_cache),
+                        0,
+                        0,
+                    ],
+                ))
+            }
             BrushKind::LinearGradient { ref stops_handle, .. } => {
                 Some((
                     BrushBatchKind::LinearGradient,
@@ -1585,6 +1614,7 @@ impl AlphaBatchHelpers for PrimitiveStore {
                     BrushKind::Solid { .. } |
                     BrushKind::YuvImage { .. } |
                     BrushKind::RadialGradient { .. } |
+                    BrushKind::ConicGradient { .. } |
                     BrushKind::LinearGradient { .. } |
                     BrushKind::Border { .. } |
                     BrushKind::Picture { .. } => {
@@ -1728,6 +1758,8 @@ pub struct ClipBatcher {
     pub images: FastHashMap<SourceTexture, Vec<ClipMaskInstance>>,
     pub box_shadows: FastHashMap<SourceTexture, Vec<ClipMaskInstance>>,
     pub line_decorations: Vec<ClipMaskInstance>,
+    /// Path draws fill the flattened edges of a clip path.
+    pub paths: Vec<ClipMaskInstance>,
 }
 
 impl ClipBatcher {
@@ -1737,6 +1769,7 @@ impl ClipBatcher {
             images: FastHashMap::default(),
             box_shadows: FastHashMap::default(),
             line_decorations: Vec::new(),
+            paths: Vec::new(),
         }
     }
 
@@ -1783,7 +1816,7 @@ impl ClipBatcher {
                 let gpu_address = gpu_cache.get_address(handle);
 
                 match *source {
-                    ClipSource::Image(ref mask) => {
+                    ClipSource::Image(ref mask, _) => {
                         if let Ok(cache_item) = resource_cache.get_cached_image(
                             ImageRequest {
                                 key: mask.image,
@@ -1811,6 +1844,12 @@ impl ClipBatcher {
                             ..instance
                         });
                     }
+                    ClipSource::Path(..) => {
+                        self.paths.push(ClipMaskInstance {
+                            clip_data_address: gpu_address,
+                            ..instance
+                        });
+                    }
                     ClipSource::BoxShadow(ref info) => {
                         let rt_handle = info
                             .cache_handle
@@ -1867,6 +1906,7 @@ fn get_buffer_kind(texture: SourceTexture) -> ImageBufferKind {
                 }
             }
         }
+        SourceTexture::CompressedTextureCache(_) => ImageBufferKind::Texture2D,
         _ => ImageBufferKind::Texture2DArray,
     }
 }
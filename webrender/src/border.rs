@@ -4,6 +4,7 @@
 
 use api::{BorderRadius, BorderSide, BorderStyle, BorderWidths, ColorF};
 use api::{ColorU, DeviceRect, DeviceSize, LayoutSizeAu, LayoutPrimitiveInfo, LayoutToDeviceScale};
+use api::PremultipliedColorF;
 use api::{DevicePixel, DeviceVector2D, DevicePoint, DeviceIntSize, LayoutRect, LayoutSize, NormalBorder};
 use app_units::Au;
 use ellipse::Ellipse;
@@ -254,28 +255,35 @@ impl BorderCornerClipSource {
         // Work out a dash length (and therefore dash count)
         // based on the width of the border edges. The "correct"
         // dash length is not mentioned in the CSS borders
-        // spec. The calculation below is similar, but not exactly
-        // the same as what Gecko uses.
-        // TODO(gw): Iterate on this to get it closer to what Gecko
-        //           uses for dash length.
+        // spec. The calculation below matches the one used for
+        // the edges in get_edge_info, so that the pattern flows
+        // around the corner without a visible change in period.
 
         let (ellipse, max_clip_count) = match kind {
             BorderCornerClipKind::Dash => {
-                let ellipse = Ellipse::new(corner_radius);
+                // When the inner edge of the corner is square, there
+                // is no curve to distribute dashes along, so the
+                // corner is drawn solid, joining the half dashes at
+                // the ends of the adjacent edges.
+                if corner_radius.width <= widths.width || corner_radius.height <= widths.height {
+                    (Ellipse::new(DeviceSize::zero()), 0)
+                } else {
+                    let ellipse = Ellipse::new(corner_radius);
 
-                // The desired dash length is ~3x the border width.
-                let average_border_width = 0.5 * (widths.width + widths.height);
-                let desired_dash_arc_length = average_border_width * 3.0;
+                    // The desired dash length is ~3x the border width.
+                    let average_border_width = 0.5 * (widths.width + widths.height);
+                    let desired_dash_arc_length = average_border_width * 3.0;
 
-                // Get the ideal number of dashes for that arc length.
-                // This is scaled by 0.5 since there is an on/off length
-                // for each dash.
-                let desired_count = 0.5 * ellipse.total_arc_length / desired_dash_arc_length;
+                    // Get the number of dash periods (dash plus gap) that
+                    // best fit the arc. The arc starts and ends with a half
+                    // dash, which join up with the half dashes at the ends of
+                    // the edges, so there is one more dash than periods.
+                    let period_count = (0.5 * ellipse.total_arc_length / desired_dash_arc_length)
+                        .round()
+                        .max(1.0);
 
-                // Round that up to the nearest integer, so that the dash length
-                // doesn't exceed the ratio above. Add one extra dash to cover
-                // the last half-dash of the arc.
-                (ellipse, desired_count.ceil() as usize)
+                    (ellipse, period_count as usize + 1)
+                }
             }
             BorderCornerClipKind::Dot => {
                 let mut corner_radius = corner_radius;
@@ -294,18 +302,18 @@ impl BorderCornerClipSource {
                     let inner_radius = (corner_radius - widths * 0.5).abs();
                     let ellipse = Ellipse::new(inner_radius);
 
-                    // Allocate a "worst case" number of dot clips. This can be
-                    // calculated by taking the minimum edge radius, since that
-                    // will result in the maximum number of dots along the path.
-                    let min_diameter = widths.width.min(widths.height);
-
-                    // Get the number of circles (assuming spacing of one diameter
-                    // between dots).
-                    let max_dot_count = 0.5 * ellipse.total_arc_length / min_diameter;
-
-                    // Add space for one extra dot since they are centered at the
-                    // start of the arc.
-                    (ellipse, max_dot_count.ceil() as usize)
+                    // Space the dots by twice their average diameter, as
+                    // the edges do, with half a space at each end of the
+                    // arc. Don't let the dots overlap when the widths of
+                    // the sides differ.
+                    let average_diameter = 0.5 * (widths.width + widths.height);
+                    let max_diameter = widths.width.max(widths.height);
+                    let dot_count = (0.5 * ellipse.total_arc_length / average_diameter)
+                        .round()
+                        .min((ellipse.total_arc_length / max_diameter).floor())
+                        .max(1.0);
+
+                    (ellipse, dot_count as usize)
                 }
             }
         };
@@ -319,13 +327,43 @@ impl BorderCornerClipSource {
         }
     }
 
+    // Get a point on the corner ellipse and the tangent at that point,
+    // in the local space of the given segment. Arc lengths outside of the
+    // ellipse continue along the tangent at its nearest end, which lets
+    // the dashes at the ends of the arc extend past the segment.
+    fn get_point_and_tangent(
+        &self,
+        arc_length: f32,
+        outer: DevicePoint,
+        clip_sign: DeviceVector2D,
+    ) -> (DevicePoint, DeviceVector2D) {
+        let clamped_arc_length = arc_length.max(0.0).min(self.ellipse.total_arc_length);
+        let theta = self.ellipse.find_angle_for_arc_length(clamped_arc_length);
+        let (point, tangent) = self.ellipse.get_point_and_tangent(theta);
+
+        let tangent = DeviceVector2D::new(tangent.x, tangent.y);
+        let extension = arc_length - clamped_arc_length;
+        let point = DevicePoint::new(point.x, point.y) + tangent.normalize() * extension;
+
+        let point = DevicePoint::new(
+            outer.x + clip_sign.x * (self.radius.width - point.x),
+            outer.y + clip_sign.y * (self.radius.height - point.y),
+        );
+
+        let tangent = DeviceVector2D::new(
+            -tangent.x * clip_sign.x,
+            -tangent.y * clip_sign.y,
+        );
+
+        (point, tangent)
+    }
+
     // TODO(gw): The naming and structure of BorderCornerClipSource
     //           don't really make sense. I've left it this way
     //           for now in order to reduce the size of the
-    //           patch a bit. In the future, when we spent some
-    //           time working on dot/dash placement, we should
-    //           restructure this code to be more consistent
-    //           with how border rendering works now.
+    //           patch a bit. In the future, we should restructure
+    //           this code to be more consistent with how border
+    //           rendering works now.
     pub fn write(self, segment: BorderSegment) -> Vec<[f32; 8]> {
         let mut dot_dash_data = Vec::new();
 
@@ -347,47 +385,30 @@ impl BorderCornerClipSource {
 
         match self.kind {
             BorderCornerClipKind::Dash => {
-                // Get the correct dash arc length.
+                // The dashes are centered on whole periods along the arc,
+                // so that the first and last are half dashes which meet
+                // the half dashes at the ends of the edges.
+                let period_count = self.max_clip_count.saturating_sub(1);
                 let dash_arc_length =
-                    0.5 * self.ellipse.total_arc_length / self.max_clip_count as f32;
-                // Start the first dash at one quarter the length of a single dash
-                // along the arc line. This is arbitrary but looks reasonable in
-                // most cases. We need to spend some time working on a more
-                // sophisticated dash placement algorithm that takes into account
-                // the offset of the dashes along edge segments.
-                let mut current_arc_length = 0.25 * dash_arc_length;
-                for _ in 0 .. self.max_clip_count {
-                    let arc_length0 = current_arc_length;
-                    current_arc_length += dash_arc_length;
-
-                    let arc_length1 = current_arc_length;
-                    current_arc_length += dash_arc_length;
-
-                    let alpha = self.ellipse.find_angle_for_arc_length(arc_length0);
-                    let beta =  self.ellipse.find_angle_for_arc_length(arc_length1);
-
-                    let (point0, tangent0) =  self.ellipse.get_point_and_tangent(alpha);
-                    let (point1, tangent1) =  self.ellipse.get_point_and_tangent(beta);
-
-                    let point0 = DevicePoint::new(
-                        outer.x + clip_sign.x * (self.radius.width - point0.x),
-                        outer.y + clip_sign.y * (self.radius.height - point0.y),
-                    );
-
-                    let tangent0 = DeviceVector2D::new(
-                        -tangent0.x * clip_sign.x,
-                        -tangent0.y * clip_sign.y,
-                    );
-
-                    let point1 = DevicePoint::new(
-                        outer.x + clip_sign.x * (self.radius.width - point1.x),
-                        outer.y + clip_sign.y * (self.radius.height - point1.y),
-                    );
-
-                    let tangent1 = DeviceVector2D::new(
-                        -tangent1.x * clip_sign.x,
-                        -tangent1.y * clip_sign.y,
-                    );
+                    0.5 * self.ellipse.total_arc_length / period_count.max(1) as f32;
+
+                for dash_index in 0 .. self.max_clip_count {
+                    let center = 2.0 * dash_arc_length * dash_index as f32;
+
+                    // The end dashes are clipped by the segment bounds, so
+                    // extend them past the ends of the arc rather than
+                    // anti-aliasing their edges against the adjacent edges.
+                    let mut arc_length0 = center - 0.5 * dash_arc_length;
+                    let mut arc_length1 = center + 0.5 * dash_arc_length;
+                    if dash_index == 0 {
+                        arc_length0 -= dash_arc_length;
+                    }
+                    if dash_index == period_count {
+                        arc_length1 += dash_arc_length;
+                    }
+
+                    let (point0, tangent0) = self.get_point_and_tangent(arc_length0, outer, clip_sign);
+                    let (point1, tangent1) = self.get_point_and_tangent(arc_length1, outer, clip_sign);
 
                     dot_dash_data.push([
                         point0.x,
@@ -401,7 +422,7 @@ impl BorderCornerClipSource {
                     ]);
                 }
             }
-            BorderCornerClipKind::Dot if self.max_clip_count == 1 => {
+            BorderCornerClipKind::Dot if self.ellipse.total_arc_length == 0.0 => {
                 let dot_diameter = lerp(self.widths.width, self.widths.height, 0.5);
                 dot_dash_data.push([
                     self.widths.width / 2.0, self.widths.height / 2.0, 0.5 * dot_diameter, 0.,
@@ -409,92 +430,25 @@ impl BorderCornerClipSource {
                 ]);
             }
             BorderCornerClipKind::Dot => {
-                let mut forward_dots = Vec::new();
-                let mut back_dots = Vec::new();
-                let mut leftover_arc_length = 0.0;
-
-                // Alternate between adding dots at the start and end of the
-                // ellipse arc. This ensures that we always end up with an exact
-                // half dot at each end of the arc, to match up with the edges.
-                forward_dots.push(DotInfo::new(self.widths.width, self.widths.width));
-                back_dots.push(DotInfo::new(
-                    self.ellipse.total_arc_length - self.widths.height,
-                    self.widths.height,
-                ));
+                // Distribute the dots evenly along the arc, with half a
+                // space at each end to match up with the edges. The dot
+                // diameter is a lerp between each edge's dot diameter,
+                // based on the linear distance along the arc.
+                let dot_spacing = self.ellipse.total_arc_length / self.max_clip_count as f32;
 
                 for dot_index in 0 .. self.max_clip_count {
-                    let prev_forward_pos = *forward_dots.last().unwrap();
-                    let prev_back_pos = *back_dots.last().unwrap();
-
-                    // Select which end of the arc to place a dot from.
-                    // This just alternates between the start and end of
-                    // the arc, which ensures that there is always an
-                    // exact half-dot at each end of the ellipse.
-                    let going_forward = dot_index & 1 == 0;
-
-                    let (next_dot_pos, leftover) = if going_forward {
-                        let next_dot_pos =
-                            prev_forward_pos.arc_pos + 2.0 * prev_forward_pos.diameter;
-                        (next_dot_pos, prev_back_pos.arc_pos - next_dot_pos)
-                    } else {
-                        let next_dot_pos = prev_back_pos.arc_pos - 2.0 * prev_back_pos.diameter;
-                        (next_dot_pos, next_dot_pos - prev_forward_pos.arc_pos)
-                    };
-
-                    // Use a lerp between each edge's dot
-                    // diameter, based on the linear distance
-                    // along the arc to get the diameter of the
-                    // dot at this arc position.
-                    let t = next_dot_pos / self.ellipse.total_arc_length;
+                    let arc_length = (dot_index as f32 + 0.5) * dot_spacing;
+                    let t = arc_length / self.ellipse.total_arc_length;
                     let dot_diameter = lerp(self.widths.width, self.widths.height, t);
 
-                    // If we can't fit a dot, bail out.
-                    if leftover < dot_diameter {
-                        leftover_arc_length = leftover;
-                        break;
-                    }
-
-                    // We can place a dot!
-                    let dot = DotInfo::new(next_dot_pos, dot_diameter);
-                    if going_forward {
-                        forward_dots.push(dot);
-                    } else {
-                        back_dots.push(dot);
-                    }
-                }
-
-                // Now step through the dots, and distribute any extra
-                // leftover space on the arc between them evenly. Once
-                // the final arc position is determined, generate the correct
-                // arc positions and angles that get passed to the clip shader.
-                let number_of_dots = forward_dots.len() + back_dots.len();
-                let extra_space_per_dot = leftover_arc_length / (number_of_dots - 1) as f32;
-
-                let create_dot_data = |ellipse: &Ellipse<DevicePixel>, arc_length: f32, radius: f32| -> [f32; 8] {
                     // Represents the GPU data for drawing a single dot to a clip mask. The order
                     // these are specified must stay in sync with the way this data is read in the
                     // dot clip shader.
-                    let theta = ellipse.find_angle_for_arc_length(arc_length);
-                    let (center, _) = ellipse.get_point_and_tangent(theta);
-
-                    let center = DevicePoint::new(
-                        outer.x + clip_sign.x * (self.radius.width - center.x),
-                        outer.y + clip_sign.y * (self.radius.height - center.y),
-                    );
-
-                    [center.x, center.y, radius, 0.0, 0.0, 0.0, 0.0, 0.0]
-                };
-
-                for (i, dot) in forward_dots.iter().enumerate() {
-                    let extra_dist = i as f32 * extra_space_per_dot;
-                    let dot_data = create_dot_data(&self.ellipse, dot.arc_pos + extra_dist, 0.5 * dot.diameter);
-                    dot_dash_data.push(dot_data);
-                }
-
-                for (i, dot) in back_dots.iter().enumerate() {
-                    let extra_dist = i as f32 * extra_space_per_dot;
-                    let dot_data = create_dot_data(&self.ellipse, dot.arc_pos - extra_dist, 0.5 * dot.diameter);
-                    dot_dash_data.push(dot_data);
+                    let (center, _) = self.get_point_and_tangent(arc_length, outer, clip_sign);
+                    dot_dash_data.push([
+                        center.x, center.y, 0.5 * dot_diameter, 0.0,
+                        0.0, 0.0, 0.0, 0.0,
+                    ]);
                 }
             }
         }
@@ -503,18 +457,6 @@ impl BorderCornerClipSource {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-struct DotInfo {
-    arc_pos: f32,
-    diameter: f32,
-}
-
-impl DotInfo {
-    fn new(arc_pos: f32, diameter: f32) -> DotInfo {
-        DotInfo { arc_pos, diameter }
-    }
-}
-
 #[derive(Debug)]
 pub struct BorderSegmentInfo {
     task_rect: DeviceRect,
@@ -569,27 +511,29 @@ fn get_edge_info(
 
     match style {
         BorderStyle::Dashed => {
+            // The edge is filled with whole periods of a dash and a gap,
+            // each starting and ending with a half dash, so that the
+            // pattern continues into the corners without any leftover
+            // space at the ends.
+            if avail_size <= 0.0 {
+                return EdgeInfo::new(0.0, 0.0, 0.0);
+            }
             let dash_size = 3.0 * side_width;
-            let approx_dash_count = (avail_size - dash_size) / dash_size;
-            let dash_count = 1.0 + 2.0 * (approx_dash_count / 2.0).floor();
-            let used_size = dash_count * dash_size;
-            let extra_space = avail_size - used_size;
-            let device_size = 2.0 * dash_size * scale;
-            let offset = (extra_space * 0.5).round();
-            EdgeInfo::new(offset, used_size, device_size)
+            let period_count = (avail_size / (2.0 * dash_size)).round().max(1.0);
+            let period_size = avail_size / period_count;
+            EdgeInfo::new(0.0, avail_size, period_size * scale)
         }
         BorderStyle::Dotted => {
+            // Each period holds a dot centered between half a space at
+            // either end, so that the spacing around the corners matches
+            // the spacing along the edge.
             let dot_and_space_size = 2.0 * side_width;
             if avail_size < dot_and_space_size * 0.75 {
                 return EdgeInfo::new(0.0, 0.0, 0.0);
             }
-            let approx_dot_count = avail_size / dot_and_space_size;
-            let dot_count = approx_dot_count.floor().max(1.0);
-            let used_size = dot_count * dot_and_space_size;
-            let extra_space = avail_size - used_size;
-            let device_size = dot_and_space_size * scale;
-            let offset = (extra_space * 0.5).round();
-            EdgeInfo::new(offset, used_size, device_size)
+            let dot_count = (avail_size / dot_and_space_size).round().max(1.0);
+            let period_size = avail_size / dot_count;
+            EdgeInfo::new(0.0, avail_size, period_size * scale)
         }
         _ => {
             EdgeInfo::new(0.0, avail_size, 8.0)
@@ -983,44 +927,38 @@ fn add_segment(
         BorderSegment::TopRight |
         BorderSegment::BottomLeft |
         BorderSegment::BottomRight => {
-            // TODO(gw): Similarly to the old border code, we don't correctly handle a a corner
-            //           that is dashed on one edge, and dotted on another. We can handle this
-            //           in the future by submitting two instances, each one with one side
-            //           color set to have an alpha of 0.
-            if (style0 == BorderStyle::Dotted && style1 == BorderStyle::Dashed) ||
-               (style0 == BorderStyle::Dashed && style0 == BorderStyle::Dotted) {
-                warn!("TODO: Handle a corner with dotted / dashed transition.");
-            }
-
-            let clip_kind = match style0 {
-                BorderStyle::Dashed => Some(BorderCornerClipKind::Dash),
-                BorderStyle::Dotted => Some(BorderCornerClipKind::Dot),
-                _ => None,
-            };
-
-            match clip_kind {
-                Some(clip_kind) => {
-                    let clip_source = BorderCornerClipSource::new(
-                        radius,
-                        widths,
-                        clip_kind,
-                    );
-
-                    // TODO(gw): Restructure the BorderCornerClipSource code
-                    //           so that we don't allocate a Vec here.
-                    let clip_list = clip_source.write(segment);
-
-                    for params in clip_list {
-                        instances.push(BorderInstance {
-                            flags: base_flags | ((clip_kind as i32) << 24),
-                            clip_params: params,
-                            ..base_instance
-                        });
-                    }
-                }
-                None => {
-                    instances.push(base_instance);
-                }
+            // A corner between a dashed or dotted side and a side of another
+            // style is drawn twice, each time with the color of one of the
+            // sides made transparent, so that each half of the corner is
+            // clipped by the pattern of its own side.
+            let clip_kind0 = get_corner_clip_kind(style0);
+            let clip_kind1 = get_corner_clip_kind(style1);
+
+            if clip_kind0 == clip_kind1 {
+                add_corner_instances(base_instance, clip_kind0, segment, widths, radius, instances);
+            } else {
+                add_corner_instances(
+                    BorderInstance {
+                        color1: PremultipliedColorF::TRANSPARENT,
+                        ..base_instance
+                    },
+                    clip_kind0,
+                    segment,
+                    widths,
+                    radius,
+                    instances,
+                );
+                add_corner_instances(
+                    BorderInstance {
+                        color0: PremultipliedColorF::TRANSPARENT,
+                        ..base_instance
+                    },
+                    clip_kind1,
+                    segment,
+                    widths,
+                    radius,
+                    instances,
+                );
             }
         }
         BorderSegment::Top |
@@ -1032,42 +970,47 @@ fn add_segment(
 
             match style0 {
                 BorderStyle::Dashed => {
-                    let rect = if is_vertical {
-                        let half_dash_size = task_rect.size.height * 0.5;
-                        let y0 = task_rect.origin.y;
-                        let y1 = y0 + half_dash_size.round();
-
-                        DeviceRect::from_floats(
-                            task_rect.origin.x,
-                            y0,
-                            task_rect.origin.x + task_rect.size.width,
-                            y1,
-                        )
+                    // Draw a half dash at each end of the period, which
+                    // join up into whole dashes when the edge repeats.
+                    let (period_size, quarter_period_size) = if is_vertical {
+                        (task_rect.size.height, task_rect.size.height * 0.25)
                     } else {
-                        let half_dash_size = task_rect.size.width * 0.5;
-                        let x0 = task_rect.origin.x;
-                        let x1 = x0 + half_dash_size.round();
-
-                        DeviceRect::from_floats(
-                            x0,
-                            task_rect.origin.y,
-                            x1,
-                            task_rect.origin.y + task_rect.size.height,
-                        )
+                        (task_rect.size.width, task_rect.size.width * 0.25)
                     };
 
-                    instances.push(BorderInstance {
-                        local_rect: rect,
-                        ..base_instance
-                    });
+                    for &(p0, p1) in &[
+                        (0.0, quarter_period_size),
+                        (period_size - quarter_period_size, period_size),
+                    ] {
+                        let rect = if is_vertical {
+                            DeviceRect::from_floats(
+                                task_rect.origin.x,
+                                task_rect.origin.y + p0,
+                                task_rect.origin.x + task_rect.size.width,
+                                task_rect.origin.y + p1,
+                            )
+                        } else {
+                            DeviceRect::from_floats(
+                                task_rect.origin.x + p0,
+                                task_rect.origin.y,
+                                task_rect.origin.x + p1,
+                                task_rect.origin.y + task_rect.size.height,
+                            )
+                        };
+
+                        instances.push(BorderInstance {
+                            local_rect: rect,
+                            ..base_instance
+                        });
+                    }
                 }
                 BorderStyle::Dotted => {
                     let (x, y, r) = if is_vertical {
                         (widths.width * 0.5,
-                         widths.width,
+                         task_rect.size.height * 0.5,
                          widths.width * 0.5)
                     } else {
-                        (widths.height,
+                        (task_rect.size.width * 0.5,
                          widths.height * 0.5,
                          widths.height * 0.5)
                     };
@@ -1088,6 +1031,54 @@ fn add_segment(
     }
 }
 
+fn get_corner_clip_kind(style: BorderStyle) -> Option<BorderCornerClipKind> {
+    match style {
+        BorderStyle::Dashed => Some(BorderCornerClipKind::Dash),
+        BorderStyle::Dotted => Some(BorderCornerClipKind::Dot),
+        _ => None,
+    }
+}
+
+fn add_corner_instances(
+    base_instance: BorderInstance,
+    clip_kind: Option<BorderCornerClipKind>,
+    segment: BorderSegment,
+    widths: DeviceSize,
+    radius: DeviceSize,
+    instances: &mut Vec<BorderInstance>,
+) {
+    match clip_kind {
+        Some(clip_kind) => {
+            let clip_source = BorderCornerClipSource::new(
+                radius,
+                widths,
+                clip_kind,
+            );
+
+            // TODO(gw): Restructure the BorderCornerClipSource code
+            //           so that we don't allocate a Vec here.
+            let clip_list = clip_source.write(segment);
+
+            // A dashed corner with no curve is drawn solid.
+            if clip_list.is_empty() {
+                instances.push(base_instance);
+                return;
+            }
+
+            for params in clip_list {
+                instances.push(BorderInstance {
+                    flags: base_instance.flags | ((clip_kind as i32) << 24),
+                    clip_params: params,
+                    ..base_instance
+                });
+            }
+        }
+        None => {
+            instances.push(base_instance);
+        }
+    }
+}
+
 fn add_corner_segment(
     image_rect: LayoutRect,
     task_rect: DeviceRect,
@@ -4,7 +4,8 @@
 
 use api::{BorderRadius, ClipMode, ComplexClipRegion, DeviceIntRect, DevicePixelScale, ImageMask};
 use api::{ImageRendering, LayoutRect, LayoutSize, LayoutPoint, LayoutVector2D, LocalClip};
-use api::{BoxShadowClipMode, LayoutToWorldScale, LineOrientation, LineStyle};
+use api::{BoxShadowClipMode, FillRule, ImageFormat, LayoutToWorldScale, LineOrientation, LineStyle};
+use api::{MaskMode, PathOp};
 use border::{ensure_no_corner_overlap};
 use box_shadow::{BLUR_SAMPLE_SCALE, BoxShadowClipSource, BoxShadowCacheKey};
 use clip_scroll_tree::{ClipChainIndex, CoordinateSystemId, SpatialNodeIndex};
@@ -17,6 +18,8 @@ use render_task::to_cache_size;
 use resource_cache::{ImageRequest, ResourceCache};
 use util::{LayoutToWorldFastTransform, MaxRect, calculate_screen_bounding_rect};
 use util::{extract_inner_rect_safe, pack_as_float};
+use std::hash::{Hash, Hasher};
+use std::mem;
 use std::sync::Arc;
 
 #[derive(Debug)]
@@ -34,11 +37,185 @@ pub struct LineDecorationClipSource {
     wavy_line_thickness: f32,
 }
 
+/// The maximum number of edges a path clip is flattened into. Each edge
+/// takes a GPU cache block, and the blocks of a single clip source have
+/// to fit in one row of the GPU cache texture.
+const MAX_PATH_CLIP_EDGES: usize = 1000;
+
+/// The maximum number of line segments a single curve is flattened into.
+const MAX_CURVE_SEGMENTS: usize = 64;
+
+/// A clip path, flattened into its edges in the local space of the clip node.
+#[derive(Clone, Debug)]
+pub struct PathClipSource {
+    pub edges: Vec<(LayoutPoint, LayoutPoint)>,
+    pub bounds: LayoutRect,
+    pub fill_rule: FillRule,
+}
+
+impl PathClipSource {
+    /// Flattens a clip path, or returns None if it has more edges than
+    /// can be drawn even with every curve flattened into a single line.
+    pub fn new(path: &[PathOp], fill_rule: FillRule, offset: &LayoutVector2D) -> Option<Self> {
+        // Curves are flattened into segments of about this many local
+        // pixels. Very detailed paths get coarser segments to stay under
+        // the edge limit, for as long as there are curves to coarsen.
+        let mut tolerance = 2.0;
+        let (mut edges, mut has_split_curves) = flatten_path(path, offset, tolerance);
+        while edges.len() > MAX_PATH_CLIP_EDGES && has_split_curves {
+            tolerance *= 2.0;
+            let (coarser_edges, coarser_has_split_curves) = flatten_path(path, offset, tolerance);
+            edges = coarser_edges;
+            has_split_curves = coarser_has_split_curves;
+        }
+        if edges.len() > MAX_PATH_CLIP_EDGES {
+            warn!("Clip path has {} edges, more than {}, ignoring it", edges.len(), MAX_PATH_CLIP_EDGES);
+            return None;
+        }
+
+        let bounds = match edges.first() {
+            Some(&(first, _)) => {
+                let (mut min, mut max) = (first, first);
+                for &(p0, p1) in &edges {
+                    for p in &[p0, p1] {
+                        min = LayoutPoint::new(min.x.min(p.x), min.y.min(p.y));
+                        max = LayoutPoint::new(max.x.max(p.x), max.y.max(p.y));
+                    }
+                }
+                LayoutRect::new(min, (max - min).to_size())
+            }
+            None => LayoutRect::zero(),
+        };
+
+        Some(PathClipSource {
+            edges,
+            bounds,
+            fill_rule,
+        })
+    }
+
+    /// Returns true if the point is inside the filled path. This matches
+    /// what cs_clip_path.glsl computes, without the anti-aliasing.
+    pub fn contains(&self, point: &LayoutPoint) -> bool {
+        let mut winding = 0;
+        for &(p0, p1) in &self.edges {
+            if (p0.y <= point.y) != (p1.y <= point.y) {
+                let t = (point.y - p0.y) / (p1.y - p0.y);
+                if p0.x + t * (p1.x - p0.x) > point.x {
+                    winding += if p1.y > p0.y { 1 } else { -1 };
+                }
+            }
+        }
+
+        match self.fill_rule {
+            FillRule::NonZero => winding != 0,
+            FillRule::EvenOdd => winding % 2 != 0,
+        }
+    }
+}
+
+/// Flattens a path into the edges of its closed subpaths, splitting
+/// curves into segments of roughly `tolerance` local pixels. Also returns
+/// whether any curve was split into more than one segment, which a
+/// larger tolerance could reduce.
+fn flatten_path(
+    path: &[PathOp],
+    offset: &LayoutVector2D,
+    tolerance: f32,
+) -> (Vec<(LayoutPoint, LayoutPoint)>, bool) {
+    fn push_edge(edges: &mut Vec<(LayoutPoint, LayoutPoint)>, from: LayoutPoint, to: LayoutPoint) {
+        if from != to {
+            edges.push((from, to));
+        }
+    }
+
+    // The number of segments to split a curve into, based on the length
+    // of its control polygon, which is never shorter than the curve.
+    fn segment_count(points: &[LayoutPoint], tolerance: f32) -> usize {
+        let length: f32 = points.windows(2).map(|p| (p[1] - p[0]).length()).sum();
+        ((length / tolerance).ceil() as usize).max(1).min(MAX_CURVE_SEGMENTS)
+    }
+
+    let mut edges = Vec::new();
+    let mut has_split_curves = false;
+    let mut subpath_start = None;
+    let mut current = LayoutPoint::zero() + *offset;
+
+    for op in path {
+        match *op {
+            PathOp::MoveTo(to) => {
+                if let Some(start) = subpath_start {
+                    push_edge(&mut edges, current, start);
+                }
+                current = to + *offset;
+                subpath_start = Some(current);
+            }
+            PathOp::LineTo(to) => {
+                subpath_start.get_or_insert(current);
+                let to = to + *offset;
+                push_edge(&mut edges, current, to);
+                current = to;
+            }
+            PathOp::QuadraticTo(ctrl, to) => {
+                subpath_start.get_or_insert(current);
+                let (from, ctrl, to) = (current, ctrl + *offset, to + *offset);
+                let count = segment_count(&[from, ctrl, to], tolerance);
+                has_split_curves |= count > 1;
+                for i in 1 .. count + 1 {
+                    let t = i as f32 / count as f32;
+                    let mt = 1.0 - t;
+                    let (a, b, c) = (mt * mt, 2.0 * mt * t, t * t);
+                    let p = LayoutPoint::new(
+                        a * from.x + b * ctrl.x + c * to.x,
+                        a * from.y + b * ctrl.y + c * to.y,
+                    );
+                    push_edge(&mut edges, current, p);
+                    current = p;
+                }
+                current = to;
+            }
+            PathOp::CubicTo(ctrl1, ctrl2, to) => {
+                subpath_start.get_or_insert(current);
+                let (from, ctrl1, ctrl2, to) =
+                    (current, ctrl1 + *offset, ctrl2 + *offset, to + *offset);
+                let count = segment_count(&[from, ctrl1, ctrl2, to], tolerance);
+                has_split_curves |= count > 1;
+                for i in 1 .. count + 1 {
+                    let t = i as f32 / count as f32;
+                    let mt = 1.0 - t;
+                    let (a, b, c, d) = (mt * mt * mt, 3.0 * mt * mt * t, 3.0 * mt * t * t, t * t * t);
+                    let p = LayoutPoint::new(
+                        a * from.x + b * ctrl1.x + c * ctrl2.x + d * to.x,
+                        a * from.y + b * ctrl1.y + c * ctrl2.y + d * to.y,
+                    );
+                    push_edge(&mut edges, current, p);
+                    current = p;
+                }
+                current = to;
+            }
+            PathOp::Close => {
+                if let Some(start) = subpath_start {
+                    push_edge(&mut edges, current, start);
+                    current = start;
+                }
+            }
+        }
+    }
+
+    // Filled subpaths are implicitly closed.
+    if let Some(start) = subpath_start {
+        push_edge(&mut edges, current, start);
+    }
+
+    (edges, has_split_curves)
+}
+
 #[derive(Clone, Debug)]
 pub struct ClipRegion {
     pub main: LayoutRect,
     pub image_mask: Option<ImageMask>,
     pub complex_clips: Vec<ComplexClipRegion>,
+    pub path: Option<PathClipSource>,
 }
 
 impl ClipRegion {
@@ -62,6 +239,7 @@ impl ClipRegion {
             main: rect,
             image_mask,
             complex_clips,
+            path: None,
         }
     }
 
@@ -82,13 +260,33 @@ impl ClipRegion {
     }
 }
 
+/// Describes how the texels of an image clip are turned into coverage.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImageMaskKind {
+    /// An image mask defined on a clip node, sampled from its first channel.
+    Clip,
+    /// The mask of a stacking context, converted as per its mask mode.
+    StackingContext(MaskMode),
+}
+
+/// The channel of an image mask the clip shader reads, matching
+/// the constants in cs_clip_image.glsl.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u32)]
+pub enum ImageMaskChannel {
+    Red = 0,
+    Alpha = 1,
+    Luminance = 2,
+}
+
 #[derive(Debug)]
 pub enum ClipSource {
     Rectangle(LayoutRect, ClipMode),
     RoundedRectangle(LayoutRect, BorderRadius, ClipMode),
-    Image(ImageMask),
+    Image(ImageMask, ImageMaskKind),
     BoxShadow(BoxShadowClipSource),
     LineDecoration(LineDecorationClipSource),
+    Path(PathClipSource),
 }
 
 impl From<ClipRegion> for ClipSources {
@@ -96,7 +294,7 @@ impl From<ClipRegion> for ClipSources {
         let mut clips = Vec::new();
 
         if let Some(info) = region.image_mask {
-            clips.push(ClipSource::Image(info));
+            clips.push(ClipSource::Image(info, ImageMaskKind::Clip));
         }
 
         clips.push(ClipSource::Rectangle(region.main, ClipMode::Clip));
@@ -109,6 +307,10 @@ impl From<ClipRegion> for ClipSources {
             ));
         }
 
+        if let Some(path) = region.path {
+            clips.push(ClipSource::Path(path));
+        }
+
         ClipSources::new(clips)
     }
 }
@@ -265,9 +467,67 @@ impl ClipSource {
         }
     }
 
-    pub fn is_image_or_line_decoration_clip(&self) -> bool {
+    /// Hashes the geometry of a clip node in its local space. The clips are
+    /// part of the content of the cached pictures they clip, even when they
+    /// are defined outside of the stacking context of the picture.
+    pub fn hash_layout<H: Hasher>(&self, state: &mut H) {
+        fn hash_components<H: Hasher>(components: &[f32], state: &mut H) {
+            for component in components {
+                component.to_bits().hash(state);
+            }
+        }
+        fn hash_rect<H: Hasher>(rect: &LayoutRect, state: &mut H) {
+            hash_components(
+                &[rect.origin.x, rect.origin.y, rect.size.width, rect.size.height],
+                state,
+            );
+        }
+
+        mem::discriminant(self).hash(state);
+        match *self {
+            ClipSource::Rectangle(ref rect, mode) => {
+                hash_rect(rect, state);
+                (mode == ClipMode::Clip).hash(state);
+            }
+            ClipSource::RoundedRectangle(ref rect, ref radii, mode) => {
+                hash_rect(rect, state);
+                hash_components(
+                    &[
+                        radii.top_left.width,
+                        radii.top_left.height,
+                        radii.top_right.width,
+                        radii.top_right.height,
+                        radii.bottom_left.width,
+                        radii.bottom_left.height,
+                        radii.bottom_right.width,
+                        radii.bottom_right.height,
+                    ],
+                    state,
+                );
+                (mode == ClipMode::Clip).hash(state);
+            }
+            ClipSource::Image(ref mask, _) => {
+                mask.image.hash(state);
+                hash_rect(&mask.rect, state);
+                mask.repeat.hash(state);
+            }
+            ClipSource::Path(ref path) => {
+                for &(p0, p1) in &path.edges {
+                    hash_components(&[p0.x, p0.y, p1.x, p1.y], state);
+                }
+                path.fill_rule.hash(state);
+            }
+            // These are only ever clips of single primitives, which are
+            // covered by the hash of the items.
+            ClipSource::BoxShadow(..) | ClipSource::LineDecoration(..) => {}
+        }
+    }
+
+    /// Returns true if this clip can only be applied with a clip mask,
+    /// rather than by segmenting the primitive.
+    pub fn is_mask_only_clip(&self) -> bool {
         match *self {
-            ClipSource::Image(..) | ClipSource::LineDecoration(..) => true,
+            ClipSource::Image(..) | ClipSource::LineDecoration(..) | ClipSource::Path(..) => true,
             _ => false,
         }
     }
@@ -279,17 +539,16 @@ pub struct ClipSources {
     pub local_inner_rect: LayoutRect,
     pub local_outer_rect: Option<LayoutRect>,
     pub only_rectangular_clips: bool,
-    pub has_image_or_line_decoration_clip: bool,
+    pub has_mask_only_clip: bool,
 }
 
 impl ClipSources {
     pub fn new(clips: Vec<ClipSource>) -> Self {
         let (local_inner_rect, local_outer_rect) = Self::calculate_inner_and_outer_rects(&clips);
 
-        let has_image_or_line_decoration_clip =
-            clips.iter().any(|clip| clip.is_image_or_line_decoration_clip());
+        let has_mask_only_clip = clips.iter().any(|clip| clip.is_mask_only_clip());
         let only_rectangular_clips =
-            !has_image_or_line_decoration_clip && clips.iter().all(|clip| clip.is_rect());
+            !has_mask_only_clip && clips.iter().all(|clip| clip.is_rect());
         let clips = clips
             .into_iter()
             .map(|clip| (clip, GpuCacheHandle::new()))
@@ -300,7 +559,7 @@ impl ClipSources {
             local_inner_rect,
             local_outer_rect,
             only_rectangular_clips,
-            has_image_or_line_decoration_clip,
+            has_mask_only_clip,
         }
     }
 
@@ -323,13 +582,18 @@ impl ClipSources {
         let mut can_calculate_outer_rect = false;
         for source in clips {
             match *source {
-                ClipSource::Image(ref mask) => {
+                ClipSource::Image(ref mask, _) => {
                     if !mask.repeat {
                         can_calculate_outer_rect = true;
                         local_outer = local_outer.and_then(|r| r.intersection(&mask.rect));
                     }
                     local_inner = None;
                 }
+                ClipSource::Path(ref path) => {
+                    can_calculate_outer_rect = true;
+                    local_outer = local_outer.and_then(|r| r.intersection(&path.bounds));
+                    local_inner = None;
+                }
                 ClipSource::Rectangle(rect, mode) => {
                     // Once we encounter a clip-out, we just assume the worst
                     // case clip mask size, for now.
@@ -389,8 +653,17 @@ impl ClipSources {
         for &mut (ref mut source, ref mut handle) in &mut self.clips {
             if let Some(mut request) = gpu_cache.request(handle) {
                 match *source {
-                    ClipSource::Image(ref mask) => {
-                        let data = ImageMaskData { local_rect: mask.rect };
+                    ClipSource::Image(ref mask, kind) => {
+                        let format = resource_cache
+                            .get_image_properties(mask.image)
+                            .map(|properties| properties.descriptor.format);
+                        let channel = match (kind, format) {
+                            (ImageMaskKind::Clip, _) |
+                            (_, Some(ImageFormat::R8)) => ImageMaskChannel::Red,
+                            (ImageMaskKind::StackingContext(MaskMode::Alpha), _) => ImageMaskChannel::Alpha,
+                            (ImageMaskKind::StackingContext(MaskMode::Luminance), _) => ImageMaskChannel::Luminance,
+                        };
+                        let data = ImageMaskData { local_rect: mask.rect, channel };
                         data.write_gpu_blocks(request);
                     }
                     ClipSource::BoxShadow(ref info) => {
@@ -425,11 +698,24 @@ impl ClipSources {
                             0.0,
                         ]);
                     }
+                    ClipSource::Path(ref path) => {
+                        request.push(path.bounds);
+                        // Note: the fill rule has to match `FILL_RULE_*` in cs_clip_path.glsl.
+                        request.push([
+                            pack_as_float(path.edges.len() as u32),
+                            pack_as_float(path.fill_rule as u32),
+                            0.0,
+                            0.0,
+                        ]);
+                        for &(p0, p1) in &path.edges {
+                            request.push([p0.x, p0.y, p1.x, p1.y]);
+                        }
+                    }
                 }
             }
 
             match *source {
-                ClipSource::Image(ref mask) => {
+                ClipSource::Image(ref mask, _) => {
                     resource_cache.request_image(
                         ImageRequest {
                             key: mask.image,
@@ -652,3 +938,71 @@ pub struct ClipWorkItem {
     pub coordinate_system_id: CoordinateSystemId,
 }
 
+
+#[cfg(test)]
+mod test_clip {
+    use api::{FillRule, LayoutPoint, LayoutVector2D, PathOp};
+    use super::{MAX_PATH_CLIP_EDGES, PathClipSource};
+
+    #[test]
+    fn path_clip_curves_are_coarsened() {
+        // A closed path of long cubic curves, each of which would be split
+        // into the most segments at the finest tolerance.
+        let mut path = vec![PathOp::MoveTo(LayoutPoint::new(0.0, 0.0))];
+        for i in 0 .. 100 {
+            let x = (i + 1) as f32 * 10.0;
+            path.push(PathOp::CubicTo(
+                LayoutPoint::new(x - 5.0, 500.0),
+                LayoutPoint::new(x - 5.0, -500.0),
+                LayoutPoint::new(x, 0.0),
+            ));
+        }
+        path.push(PathOp::LineTo(LayoutPoint::new(1000.0, 100.0)));
+        path.push(PathOp::LineTo(LayoutPoint::new(0.0, 100.0)));
+
+        let clip = PathClipSource::new(&path, FillRule::NonZero, &LayoutVector2D::zero()).unwrap();
+        assert!(clip.edges.len() <= MAX_PATH_CLIP_EDGES);
+        assert!(clip.edges.len() > 300);
+        // Every subpath stays closed, which truncating the edges would break.
+        assert_eq!(clip.edges.first().unwrap().0, clip.edges.last().unwrap().1);
+        assert!(clip.contains(&LayoutPoint::new(500.0, 90.0)));
+        assert!(!clip.contains(&LayoutPoint::new(500.0, 110.0)));
+    }
+
+    #[test]
+    fn path_clip_with_too_many_lines_is_rejected() {
+        let points: Vec<LayoutPoint> = (0 .. MAX_PATH_CLIP_EDGES + 1)
+            .map(|i| {
+                let angle = i as f32 * 2.0 * ::std::f32::consts::PI / (MAX_PATH_CLIP_EDGES + 1) as f32;
+                LayoutPoint::new(100.0 * angle.cos(), 100.0 * angle.sin())
+            })
+            .collect();
+        let path = PathOp::polygon(&points);
+        let offset = LayoutVector2D::zero();
+        assert!(PathClipSource::new(&path, FillRule::NonZero, &offset).is_none());
+        // One line fewer fits.
+        let path = &path[.. MAX_PATH_CLIP_EDGES];
+        assert!(PathClipSource::new(path, FillRule::NonZero, &offset).is_some());
+    }
+
+    #[test]
+    fn path_clip_contains_curve() {
+        // A half disc bulging to the right of a vertical line, with the
+        // offset of its reference frame.
+        let path = [
+            PathOp::MoveTo(LayoutPoint::new(0.0, 0.0)),
+            PathOp::QuadraticTo(LayoutPoint::new(100.0, 50.0), LayoutPoint::new(0.0, 100.0)),
+            PathOp::Close,
+        ];
+        let offset = LayoutVector2D::new(10.0, 20.0);
+        let clip = PathClipSource::new(&path, FillRule::NonZero, &offset).unwrap();
+        assert!(clip.edges.len() > 2);
+        // The curve peaks halfway, at x = 60.
+        assert!(clip.contains(&LayoutPoint::new(55.0, 70.0)));
+        assert!(!clip.contains(&LayoutPoint::new(65.0, 70.0)));
+        // Off the peak, the curve is well inside its control polygon,
+        // which is at x = 40 here.
+        assert!(!clip.contains(&LayoutPoint::new(38.0, 35.0)));
+        assert!(clip.contains(&LayoutPoint::new(30.0, 35.0)));
+    }
+}
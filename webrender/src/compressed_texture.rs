@@ -0,0 +1,1158 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! CPU decoders for the block-compressed image formats. These are used
+//! when the device can't sample from a compressed format directly.
+
+use api::{COMPRESSED_BLOCK_DIMENSION, ImageData, ImageDescriptor, ImageFormat};
+use std::sync::Arc;
+
+const BLOCK_DIMENSION: usize = COMPRESSED_BLOCK_DIMENSION as usize;
+const BLOCK_PIXELS: usize = BLOCK_DIMENSION * BLOCK_DIMENSION;
+
+// The intensity modifiers of the ETC1 / ETC2 individual and differential modes.
+const ETC1_MODIFIERS: [[i32; 2]; 8] = [
+    [2, 8], [5, 17], [9, 29], [13, 42], [18, 60], [24, 80], [33, 106], [47, 183],
+];
+
+// The distances of the ETC2 T and H modes.
+const ETC2_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+// The number of trits, quints and bits of each value of the ASTC integer
+// sequence encoding, for the ranges from 2 to 256 values.
+const ASTC_RANGES: [(u32, u32, u32); 21] = [
+    (0, 0, 1), (1, 0, 0), (0, 0, 2), (0, 1, 0), (1, 0, 1), (0, 0, 3), (0, 1, 1),
+    (1, 0, 2), (0, 0, 4), (0, 1, 2), (1, 0, 3), (0, 0, 5), (0, 1, 3), (1, 0, 4),
+    (0, 0, 6), (0, 1, 4), (1, 0, 5), (0, 0, 7), (0, 1, 5), (1, 0, 6), (0, 0, 8),
+];
+
+// Colors are quantized to at least 6 values.
+const ASTC_MIN_COLOR_RANGE: usize = 4;
+
+// The color of the texels of ASTC blocks that can't be decoded, such as the
+// ones using reserved encodings or HDR endpoints, as BGRA8.
+const ASTC_ERROR_COLOR: [u8; 4] = [0xff, 0, 0xff, 0xff];
+
+// Pixels of a decoded block, in row-major order, as BGRA8.
+type BlockPixels = [[u8; 4]; BLOCK_PIXELS];
+
+/// Decodes compressed raw image data, returning the descriptor and the
+/// data of the equivalent BGRA8 image. External and blob image data
+/// can't be decoded.
+pub fn decompress_image(
+    descriptor: &ImageDescriptor,
+    data: Option<ImageData>,
+) -> (ImageDescriptor, Option<ImageData>) {
+    let data = data.and_then(|data| match data {
+        ImageData::Raw(bytes) => {
+            let pixels = decompress_to_bgra8(descriptor, &bytes);
+            Some(ImageData::Raw(Arc::new(pixels)))
+        }
+        ImageData::External(..) | ImageData::Blob(..) => {
+            error!("Unable to decode {:?} image data", descriptor.format);
+            None
+        }
+    });
+    let descriptor = ImageDescriptor {
+        format: ImageFormat::BGRA8,
+        stride: None,
+        offset: 0,
+        ..*descriptor
+    };
+    (descriptor, data)
+}
+
+/// Decodes the compressed image described by `descriptor` into
+/// a tightly packed BGRA8 buffer.
+fn decompress_to_bgra8(descriptor: &ImageDescriptor, data: &[u8]) -> Vec<u8> {
+    let format = descriptor.format;
+    let block_size = format
+        .compressed_block_size()
+        .expect("BUG: not a compressed image format") as usize;
+    let width = descriptor.size.width as usize;
+    let height = descriptor.size.height as usize;
+    let stride = descriptor.compute_stride() as usize;
+    let block_columns = (width + BLOCK_DIMENSION - 1) / BLOCK_DIMENSION;
+    let block_rows = format.row_count(descriptor.size.height) as usize;
+
+    let mut pixels = vec![0; width * height * 4];
+    let mut block_pixels = [[0; 4]; BLOCK_PIXELS];
+
+    for block_y in 0 .. block_rows {
+        for block_x in 0 .. block_columns {
+            let offset = descriptor.offset as usize + block_y * stride + block_x * block_size;
+            decode_block(format, &data[offset .. offset + block_size], &mut block_pixels);
+
+            // Blocks on the right and bottom edges may be partially outside of the image.
+            for y in 0 .. BLOCK_DIMENSION.min(height - block_y * BLOCK_DIMENSION) {
+                for x in 0 .. BLOCK_DIMENSION.min(width - block_x * BLOCK_DIMENSION) {
+                    let pixel_x = block_x * BLOCK_DIMENSION + x;
+                    let pixel_y = block_y * BLOCK_DIMENSION + y;
+                    let dst = (pixel_y * width + pixel_x) * 4;
+                    pixels[dst .. dst + 4].copy_from_slice(&block_pixels[y * BLOCK_DIMENSION + x]);
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+fn decode_block(format: ImageFormat, block: &[u8], pixels: &mut BlockPixels) {
+    match format {
+        ImageFormat::BC1 => {
+            decode_bc1_color(block, true, pixels);
+        }
+        ImageFormat::BC3 => {
+            decode_bc1_color(&block[8 ..], false, pixels);
+            decode_bc3_alpha(&block[.. 8], pixels);
+        }
+        ImageFormat::ETC2 => {
+            decode_etc2_color(block, pixels);
+        }
+        ImageFormat::ETC2A8 => {
+            decode_etc2_color(&block[8 ..], pixels);
+            decode_eac_alpha(&block[.. 8], pixels);
+        }
+        ImageFormat::ASTC4x4 => {
+            if decode_astc_block(block, pixels).is_none() {
+                for pixel in pixels.iter_mut() {
+                    *pixel = ASTC_ERROR_COLOR;
+                }
+            }
+        }
+        ImageFormat::R8 |
+        ImageFormat::BGRA8 |
+        ImageFormat::RGBAF32 |
+        ImageFormat::RG8 |
+        ImageFormat::RGBAI32 => unreachable!(),
+    }
+}
+
+fn rgb565_to_bgra8(color: u16) -> [u8; 4] {
+    let r = (color >> 11) & 0x1f;
+    let g = (color >> 5) & 0x3f;
+    let b = color & 0x1f;
+    [
+        ((b << 3) | (b >> 2)) as u8,
+        ((g << 2) | (g >> 4)) as u8,
+        ((r << 3) | (r >> 2)) as u8,
+        0xff,
+    ]
+}
+
+fn mix(a: [u8; 4], b: [u8; 4], weight_a: u32, weight_b: u32) -> [u8; 4] {
+    let mut result = [0; 4];
+    for i in 0 .. 4 {
+        result[i] = ((a[i] as u32 * weight_a + b[i] as u32 * weight_b) / (weight_a + weight_b)) as u8;
+    }
+    result
+}
+
+// Decodes a BC1 color block. The 1-bit alpha mode is only
+// available for BC1 images, BC3 always uses four colors.
+fn decode_bc1_color(block: &[u8], allow_alpha: bool, pixels: &mut BlockPixels) {
+    let c0 = block[0] as u16 | (block[1] as u16) << 8;
+    let c1 = block[2] as u16 | (block[3] as u16) << 8;
+    let color0 = rgb565_to_bgra8(c0);
+    let color1 = rgb565_to_bgra8(c1);
+
+    let palette = if c0 > c1 || !allow_alpha {
+        [color0, color1, mix(color0, color1, 2, 1), mix(color0, color1, 1, 2)]
+    } else {
+        [color0, color1, mix(color0, color1, 1, 1), [0, 0, 0, 0]]
+    };
+
+    let indices = block[4 .. 8]
+        .iter()
+        .rev()
+        .fold(0u32, |acc, &byte| acc << 8 | byte as u32);
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        *pixel = palette[(indices >> (2 * i) & 0x3) as usize];
+    }
+}
+
+fn decode_bc3_alpha(block: &[u8], pixels: &mut BlockPixels) {
+    let a0 = block[0] as u32;
+    let a1 = block[1] as u32;
+    let mut palette = [block[0], block[1], 0, 0, 0, 0, 0, 0xff];
+    if a0 > a1 {
+        for i in 1 .. 7 {
+            palette[i as usize + 1] = ((a0 * (7 - i) + a1 * i) / 7) as u8;
+        }
+    } else {
+        for i in 1 .. 5 {
+            palette[i as usize + 1] = ((a0 * (5 - i) + a1 * i) / 5) as u8;
+        }
+    }
+
+    let indices = block[2 .. 8]
+        .iter()
+        .rev()
+        .fold(0u64, |acc, &byte| acc << 8 | byte as u64);
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        pixel[3] = palette[(indices >> (3 * i) & 0x7) as usize];
+    }
+}
+
+fn extend_4(value: i32) -> i32 {
+    (value << 4) | value
+}
+
+fn extend_5(value: i32) -> i32 {
+    (value << 3) | (value >> 2)
+}
+
+fn extend_6(value: i32) -> i32 {
+    (value << 2) | (value >> 4)
+}
+
+fn extend_7(value: i32) -> i32 {
+    (value << 1) | (value >> 6)
+}
+
+fn offset_rgb(color: [i32; 3], offset: i32) -> [i32; 3] {
+    [color[0] + offset, color[1] + offset, color[2] + offset]
+}
+
+fn rgb_to_bgra8(color: [i32; 3]) -> [u8; 4] {
+    let clamp = |value: i32| value.max(0).min(0xff) as u8;
+    [clamp(color[2]), clamp(color[1]), clamp(color[0]), 0xff]
+}
+
+fn decode_etc2_color(block: &[u8], pixels: &mut BlockPixels) {
+    let b = |i: usize| block[i] as i32;
+
+    // The pixel indices are stored column by column, as two bit planes.
+    let msbs = b(4) << 8 | b(5);
+    let lsbs = b(6) << 8 | b(7);
+    let mut indices = [0; BLOCK_PIXELS];
+    for x in 0 .. BLOCK_DIMENSION {
+        for y in 0 .. BLOCK_DIMENSION {
+            let k = x * BLOCK_DIMENSION + y;
+            indices[y * BLOCK_DIMENSION + x] = ((msbs >> k & 1) << 1 | (lsbs >> k & 1)) as usize;
+        }
+    }
+
+    let tables = [(b(3) >> 5) & 0x7, (b(3) >> 2) & 0x7];
+    let flip = b(3) & 0x1 != 0;
+
+    if b(3) & 0x2 == 0 {
+        // Individual mode.
+        let base = [
+            [extend_4(b(0) >> 4), extend_4(b(1) >> 4), extend_4(b(2) >> 4)],
+            [extend_4(b(0) & 0xf), extend_4(b(1) & 0xf), extend_4(b(2) & 0xf)],
+        ];
+        decode_etc1_subblocks(base, tables, flip, &indices, pixels);
+        return;
+    }
+
+    // Differential mode, unless one of the channels overflows, which selects
+    // one of the ETC2 specific T, H and planar modes.
+    let sign_extend = |value: i32| (value << 29) >> 29;
+    let r = b(0) >> 3;
+    let g = b(1) >> 3;
+    let bl = b(2) >> 3;
+    let r2 = r + sign_extend(b(0) & 0x7);
+    let g2 = g + sign_extend(b(1) & 0x7);
+    let bl2 = bl + sign_extend(b(2) & 0x7);
+
+    if r2 < 0 || r2 > 31 {
+        // T mode.
+        let base1 = [
+            extend_4(((b(0) >> 1) & 0xc) | (b(0) & 0x3)),
+            extend_4(b(1) >> 4),
+            extend_4(b(1) & 0xf),
+        ];
+        let base2 = [
+            extend_4(b(2) >> 4),
+            extend_4(b(2) & 0xf),
+            extend_4(b(3) >> 4),
+        ];
+        let distance = ETC2_DISTANCES[(((b(3) >> 1) & 0x6) | (b(3) & 0x1)) as usize];
+        let palette = [
+            base1,
+            offset_rgb(base2, distance),
+            base2,
+            offset_rgb(base2, -distance),
+        ];
+        for (pixel, &index) in pixels.iter_mut().zip(indices.iter()) {
+            *pixel = rgb_to_bgra8(palette[index]);
+        }
+    } else if g2 < 0 || g2 > 31 {
+        // H mode.
+        let base1 = [
+            extend_4((b(0) >> 3) & 0xf),
+            extend_4(((b(0) & 0x7) << 1) | ((b(1) >> 4) & 0x1)),
+            extend_4((b(1) & 0x8) | ((b(1) & 0x3) << 1) | (b(2) >> 7)),
+        ];
+        let base2 = [
+            extend_4((b(2) >> 3) & 0xf),
+            extend_4(((b(2) & 0x7) << 1) | (b(3) >> 7)),
+            extend_4((b(3) >> 3) & 0xf),
+        ];
+        let value = |c: [i32; 3]| c[0] << 16 | c[1] << 8 | c[2];
+        let distance_index = (b(3) & 0x4) | ((b(3) & 0x1) << 1) |
+            (value(base1) >= value(base2)) as i32;
+        let distance = ETC2_DISTANCES[distance_index as usize];
+        let palette = [
+            offset_rgb(base1, distance),
+            offset_rgb(base1, -distance),
+            offset_rgb(base2, distance),
+            offset_rgb(base2, -distance),
+        ];
+        for (pixel, &index) in pixels.iter_mut().zip(indices.iter()) {
+            *pixel = rgb_to_bgra8(palette[index]);
+        }
+    } else if bl2 < 0 || bl2 > 31 {
+        // Planar mode, the colors are interpolated between
+        // the origin, horizontal and vertical colors.
+        let o = [
+            extend_6((b(0) >> 1) & 0x3f),
+            extend_7(((b(0) & 0x1) << 6) | ((b(1) >> 1) & 0x3f)),
+            extend_6(((b(1) & 0x1) << 5) | (b(2) & 0x18) | ((b(2) & 0x3) << 1) | (b(3) >> 7)),
+        ];
+        let h = [
+            extend_6(((b(3) & 0x7c) >> 1) | (b(3) & 0x1)),
+            extend_7((b(4) >> 1) & 0x7f),
+            extend_6(((b(4) & 0x1) << 5) | (b(5) >> 3)),
+        ];
+        let v = [
+            extend_6(((b(5) & 0x7) << 3) | (b(6) >> 5)),
+            extend_7(((b(6) & 0x1f) << 2) | (b(7) >> 6)),
+            extend_6(b(7) & 0x3f),
+        ];
+        for y in 0 .. BLOCK_DIMENSION as i32 {
+            for x in 0 .. BLOCK_DIMENSION as i32 {
+                let mut color = [0; 3];
+                for i in 0 .. 3 {
+                    color[i] = (x * (h[i] - o[i]) + y * (v[i] - o[i]) + 4 * o[i] + 2) >> 2;
+                }
+                pixels[(y * BLOCK_DIMENSION as i32 + x) as usize] = rgb_to_bgra8(color);
+            }
+        }
+    } else {
+        // Differential mode.
+        let base = [
+            [extend_5(r), extend_5(g), extend_5(bl)],
+            [extend_5(r2), extend_5(g2), extend_5(bl2)],
+        ];
+        decode_etc1_subblocks(base, tables, flip, &indices, pixels);
+    }
+}
+
+// Decodes the two sub-blocks of the individual and differential modes,
+// which are either side by side, or on top of each other when flipped.
+fn decode_etc1_subblocks(
+    base: [[i32; 3]; 2],
+    tables: [i32; 2],
+    flip: bool,
+    indices: &[usize; BLOCK_PIXELS],
+    pixels: &mut BlockPixels,
+) {
+    for y in 0 .. BLOCK_DIMENSION {
+        for x in 0 .. BLOCK_DIMENSION {
+            let i = y * BLOCK_DIMENSION + x;
+            let second_subblock = if flip { y >= 2 } else { x >= 2 };
+            let subblock = second_subblock as usize;
+            let modifiers = ETC1_MODIFIERS[tables[subblock] as usize];
+            let modifier = match indices[i] {
+                0 => modifiers[0],
+                1 => modifiers[1],
+                2 => -modifiers[0],
+                _ => -modifiers[1],
+            };
+            pixels[i] = rgb_to_bgra8(offset_rgb(base[subblock], modifier));
+        }
+    }
+}
+
+fn decode_eac_alpha(block: &[u8], pixels: &mut BlockPixels) {
+    let base = block[0] as i32;
+    let multiplier = (block[1] >> 4) as i32;
+    let modifiers = EAC_MODIFIERS[(block[1] & 0xf) as usize];
+
+    // The 3-bit indices are stored column by column, most significant first.
+    let indices = block[2 .. 8]
+        .iter()
+        .fold(0u64, |acc, &byte| acc << 8 | byte as u64);
+    for x in 0 .. BLOCK_DIMENSION {
+        for y in 0 .. BLOCK_DIMENSION {
+            let k = x * BLOCK_DIMENSION + y;
+            let index = (indices >> (45 - 3 * k) & 0x7) as usize;
+            let alpha = base + modifiers[index] * multiplier;
+            pixels[y * BLOCK_DIMENSION + x][3] = alpha.max(0).min(0xff) as u8;
+        }
+    }
+}
+
+// Reads the bits of an ASTC block, from the start of the block, or from its
+// end for the weights, which are stored in the reverse bit order. The bits
+// past `end` read as zeros.
+struct AstcBitReader<'a> {
+    block: &'a [u8],
+    position: u32,
+    end: u32,
+    reversed: bool,
+}
+
+impl<'a> AstcBitReader<'a> {
+    fn new(block: &'a [u8], position: u32, end: u32, reversed: bool) -> Self {
+        AstcBitReader {
+            block,
+            position,
+            end,
+            reversed,
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        let mut value = 0;
+        for i in 0 .. count {
+            let bit = self.position + i;
+            if bit < self.end {
+                let bit = if self.reversed { 127 - bit } else { bit };
+                value |= (self.block[(bit / 8) as usize] as u32 >> (bit % 8) & 1) << i;
+            }
+        }
+        self.position += count;
+        value
+    }
+}
+
+fn astc_bits(block: &[u8], start: u32, count: u32) -> u32 {
+    AstcBitReader::new(block, start, 128, false).read(count)
+}
+
+// The number of bits needed to encode `count` values of the given range.
+fn astc_encoded_bits(range: usize, count: u32) -> u32 {
+    let (trits, quints, bits) = ASTC_RANGES[range];
+    bits * count + trits * (8 * count + 4) / 5 + quints * (7 * count + 2) / 3
+}
+
+// Decodes values of the integer sequence encoding, as pairs of
+// their low bits and of their trit or quint, if any.
+fn decode_astc_integers(reader: &mut AstcBitReader, range: usize, values: &mut [(u32, u32)]) {
+    let (trits, quints, bits) = ASTC_RANGES[range];
+    if trits != 0 {
+        // The bits of the five trits packed together are
+        // interleaved with the low bits of the values.
+        for group in values.chunks_mut(5) {
+            let mut low = [0; 5];
+            let mut packed = 0;
+            let mut shift = 0;
+            for (i, &packed_bits) in [2, 2, 1, 2, 1].iter().enumerate() {
+                low[i] = reader.read(bits);
+                packed |= reader.read(packed_bits) << shift;
+                shift += packed_bits;
+            }
+            let high = decode_trits(packed);
+            for (i, value) in group.iter_mut().enumerate() {
+                *value = (low[i], high[i]);
+            }
+        }
+    } else if quints != 0 {
+        for group in values.chunks_mut(3) {
+            let mut low = [0; 3];
+            let mut packed = 0;
+            let mut shift = 0;
+            for (i, &packed_bits) in [3, 2, 2].iter().enumerate() {
+                low[i] = reader.read(bits);
+                packed |= reader.read(packed_bits) << shift;
+                shift += packed_bits;
+            }
+            let high = decode_quints(packed);
+            for (i, value) in group.iter_mut().enumerate() {
+                *value = (low[i], high[i]);
+            }
+        }
+    } else {
+        for value in values.iter_mut() {
+            *value = (reader.read(bits), 0);
+        }
+    }
+}
+
+fn decode_trits(packed: u32) -> [u32; 5] {
+    let bit = |value: u32, i: u32| value >> i & 1;
+    let (c, t3, t4);
+    if packed >> 2 & 0x7 == 0x7 {
+        c = (packed >> 5 & 0x7) << 2 | (packed & 0x3);
+        t3 = 2;
+        t4 = 2;
+    } else {
+        c = packed & 0x1f;
+        if packed >> 5 & 0x3 == 0x3 {
+            t3 = bit(packed, 7);
+            t4 = 2;
+        } else {
+            t3 = packed >> 5 & 0x3;
+            t4 = bit(packed, 7);
+        }
+    }
+    let (t0, t1, t2);
+    if c & 0x3 == 0x3 {
+        t0 = bit(c, 3) << 1 | (bit(c, 2) & !bit(c, 3));
+        t1 = bit(c, 4);
+        t2 = 2;
+    } else if c >> 2 & 0x3 == 0x3 {
+        t0 = c & 0x3;
+        t1 = 2;
+        t2 = 2;
+    } else {
+        t0 = bit(c, 1) << 1 | (bit(c, 0) & !bit(c, 1));
+        t1 = c >> 2 & 0x3;
+        t2 = bit(c, 4);
+    }
+    [t0, t1, t2, t3, t4]
+}
+
+fn decode_quints(packed: u32) -> [u32; 3] {
+    let bit = |value: u32, i: u32| value >> i & 1;
+    if packed >> 1 & 0x3 == 0x3 && packed >> 5 & 0x3 == 0 {
+        let q2 = bit(packed, 0) << 2 |
+            (bit(packed, 4) & !bit(packed, 0)) << 1 |
+            (bit(packed, 3) & !bit(packed, 0));
+        return [4, 4, q2];
+    }
+    let (c, q2);
+    if packed >> 1 & 0x3 == 0x3 {
+        c = (packed >> 3 & 0x3) << 3 | (!packed >> 5 & 0x3) << 1 | bit(packed, 0);
+        q2 = 4;
+    } else {
+        c = packed & 0x1f;
+        q2 = packed >> 5 & 0x3;
+    }
+    if c & 0x7 == 0x5 {
+        [c >> 3 & 0x3, 4, q2]
+    } else {
+        [c & 0x7, c >> 3 & 0x3, q2]
+    }
+}
+
+// Repeats the bits of a value until it has `target_bits` bits.
+fn replicate_bits(value: u32, bits: u32, target_bits: u32) -> u32 {
+    let mut result = 0;
+    let mut result_bits = 0;
+    while result_bits < target_bits {
+        result = result << bits | value;
+        result_bits += bits;
+    }
+    result >> (result_bits - target_bits)
+}
+
+// Unquantizes a color value to the 0 - 255 range.
+fn unquantize_astc_color(range: usize, (low, high): (u32, u32)) -> u32 {
+    let (trits, quints, bits) = ASTC_RANGES[range];
+    if trits == 0 && quints == 0 {
+        return replicate_bits(low, bits, 8);
+    }
+
+    // The low bits, except the lowest one, are spread across a 9 bit
+    // value, which is added to the trit or quint scaled to the range.
+    let a = if low & 1 != 0 { 0x1ff } else { 0 };
+    let l = low >> 1;
+    let (b, c) = match (trits != 0, bits) {
+        (true, 1) => (0, 204),
+        (false, 1) => (0, 113),
+        (true, 2) => (l << 8 | l << 4 | l << 2 | l << 1, 93),
+        (false, 2) => (l << 8 | l << 3 | l << 2, 54),
+        (true, 3) => (l << 7 | l << 2 | l, 44),
+        (false, 3) => (l << 7 | l << 1 | l >> 1, 26),
+        (true, 4) => (l << 6 | l, 22),
+        (false, 4) => (l << 6 | l >> 1, 13),
+        (true, 5) => (l << 5 | l >> 2, 11),
+        (false, 5) => (l << 5 | l >> 3, 6),
+        (true, 6) => (l << 4 | l >> 4, 5),
+        _ => unreachable!(),
+    };
+    let t = (high * c + b) ^ a;
+    (a & 0x80) | (t >> 2)
+}
+
+// Unquantizes a weight to the 0 - 64 range.
+fn unquantize_astc_weight(range: usize, (low, high): (u32, u32)) -> u32 {
+    let (trits, quints, bits) = ASTC_RANGES[range];
+    let weight = if trits == 0 && quints == 0 {
+        replicate_bits(low, bits, 6)
+    } else if bits == 0 {
+        if trits != 0 {
+            [0, 32, 63][high as usize]
+        } else {
+            [0, 16, 32, 47, 63][high as usize]
+        }
+    } else {
+        let a = if low & 1 != 0 { 0x7f } else { 0 };
+        let l = low >> 1;
+        let (b, c) = match (trits != 0, bits) {
+            (true, 1) => (0, 50),
+            (false, 1) => (0, 28),
+            (true, 2) => (l << 6 | l << 2 | l, 23),
+            (false, 2) => (l << 6 | l << 1, 13),
+            (true, 3) => (l << 5 | l, 11),
+            _ => unreachable!(),
+        };
+        let t = (high * c + b) ^ a;
+        (a & 0x20) | (t >> 2)
+    };
+    if weight > 32 { weight + 1 } else { weight }
+}
+
+struct AstcBlockMode {
+    grid_width: u32,
+    grid_height: u32,
+    weight_range: usize,
+    dual_plane: bool,
+}
+
+fn decode_astc_block_mode(mode: u32) -> Option<AstcBlockMode> {
+    let a = mode >> 5 & 0x3;
+    let mut dual_plane = mode >> 10 & 0x1 != 0;
+    let mut high_precision = mode >> 9 & 0x1 != 0;
+    let mut range = mode >> 4 & 0x1;
+    let (grid_width, grid_height);
+
+    if mode & 0x3 != 0 {
+        range |= (mode & 0x3) << 1;
+        let b = mode >> 7 & 0x3;
+        match mode >> 2 & 0x3 {
+            0 => {
+                grid_width = b + 4;
+                grid_height = a + 2;
+            }
+            1 => {
+                grid_width = b + 8;
+                grid_height = a + 2;
+            }
+            2 => {
+                grid_width = a + 2;
+                grid_height = b + 8;
+            }
+            _ if mode & 0x100 != 0 => {
+                grid_width = (b & 0x1) + 2;
+                grid_height = a + 2;
+            }
+            _ => {
+                grid_width = a + 2;
+                grid_height = (b & 0x1) + 6;
+            }
+        }
+    } else {
+        range |= (mode >> 2 & 0x3) << 1;
+        if mode >> 2 & 0x3 == 0 {
+            return None;
+        }
+        let b = mode >> 9 & 0x3;
+        match mode >> 7 & 0x3 {
+            0 => {
+                grid_width = 12;
+                grid_height = a + 2;
+            }
+            1 => {
+                grid_width = a + 2;
+                grid_height = 12;
+            }
+            2 => {
+                grid_width = a + 6;
+                grid_height = b + 6;
+                dual_plane = false;
+                high_precision = false;
+            }
+            _ => match a {
+                0 => {
+                    grid_width = 6;
+                    grid_height = 10;
+                }
+                1 => {
+                    grid_width = 10;
+                    grid_height = 6;
+                }
+                _ => return None,
+            },
+        }
+    }
+
+    Some(AstcBlockMode {
+        grid_width,
+        grid_height,
+        weight_range: (range - 2 + 6 * high_precision as u32) as usize,
+        dual_plane,
+    })
+}
+
+// The hash that distributes the texels of a block between its partitions.
+fn hash_astc_partition_seed(mut seed: u32) -> u32 {
+    seed ^= seed >> 15;
+    seed = seed.wrapping_sub(seed << 17);
+    seed = seed.wrapping_add(seed << 7);
+    seed = seed.wrapping_add(seed << 4);
+    seed ^= seed >> 5;
+    seed = seed.wrapping_add(seed << 16);
+    seed ^= seed >> 7;
+    seed ^= seed >> 3;
+    seed ^= seed << 6;
+    seed ^= seed >> 17;
+    seed
+}
+
+fn select_astc_partition(seed: u32, x: u32, y: u32, partition_count: u32) -> usize {
+    // Blocks of less than 31 texels use doubled coordinates.
+    let x = x << 1;
+    let y = y << 1;
+    let seed = seed + (partition_count - 1) * 1024;
+    let random = hash_astc_partition_seed(seed);
+
+    let mut seeds = [0; 8];
+    for (i, s) in seeds.iter_mut().enumerate() {
+        *s = random >> (4 * i) & 0xf;
+        *s *= *s;
+    }
+    let (shift1, shift2) = if seed & 1 != 0 {
+        (if seed & 2 != 0 { 4 } else { 5 }, if partition_count == 3 { 6 } else { 5 })
+    } else {
+        (if partition_count == 3 { 6 } else { 5 }, if seed & 2 != 0 { 4 } else { 5 })
+    };
+    for (i, s) in seeds.iter_mut().enumerate() {
+        *s >>= if i % 2 == 0 { shift1 } else { shift2 };
+    }
+
+    // The third coordinate is always zero for 2D blocks, so the
+    // seeds it is multiplied with don't matter.
+    let a = (seeds[0] * x + seeds[1] * y + (random >> 14)) & 0x3f;
+    let b = (seeds[2] * x + seeds[3] * y + (random >> 10)) & 0x3f;
+    let c = if partition_count < 3 { 0 } else { (seeds[4] * x + seeds[5] * y + (random >> 6)) & 0x3f };
+    let d = if partition_count < 4 { 0 } else { (seeds[6] * x + seeds[7] * y + (random >> 2)) & 0x3f };
+
+    if a >= b && a >= c && a >= d {
+        0
+    } else if b >= c && b >= d {
+        1
+    } else if c >= d {
+        2
+    } else {
+        3
+    }
+}
+
+// Splits a value and an offset that were stored with their most
+// significant bits swapped, returning the value and the signed offset.
+fn bit_transfer_signed(offset: i32, value: i32) -> (i32, i32) {
+    let value = (value >> 1) | (offset & 0x80);
+    let offset = (offset >> 1) & 0x3f;
+    (value, if offset & 0x20 != 0 { offset - 0x40 } else { offset })
+}
+
+fn blue_contract(color: [i32; 4]) -> [i32; 4] {
+    [(color[0] + color[2]) >> 1, (color[1] + color[2]) >> 1, color[2], color[3]]
+}
+
+// Decodes the two RGBA endpoints of a partition. HDR endpoint modes
+// aren't supported.
+fn decode_astc_endpoints(mode: u32, values: &[u32]) -> Option<[[u32; 4]; 2]> {
+    let v = |i: usize| values[i] as i32;
+    let endpoints = match mode {
+        0 => [[v(0), v(0), v(0), 0xff], [v(1), v(1), v(1), 0xff]],
+        1 => {
+            let l0 = (v(0) >> 2) | (v(1) & 0xc0);
+            let l1 = l0 + (v(1) & 0x3f);
+            [[l0, l0, l0, 0xff], [l1, l1, l1, 0xff]]
+        }
+        4 => [[v(0), v(0), v(0), v(2)], [v(1), v(1), v(1), v(3)]],
+        5 => {
+            let (l, dl) = bit_transfer_signed(v(1), v(0));
+            let (a, da) = bit_transfer_signed(v(3), v(2));
+            [[l, l, l, a], [l + dl, l + dl, l + dl, a + da]]
+        }
+        6 | 10 => {
+            let (a0, a1) = if mode == 10 { (v(4), v(5)) } else { (0xff, 0xff) };
+            let scale = |c: i32| (c * v(3)) >> 8;
+            [[scale(v(0)), scale(v(1)), scale(v(2)), a0], [v(0), v(1), v(2), a1]]
+        }
+        8 | 12 => {
+            let (a0, a1) = if mode == 12 { (v(6), v(7)) } else { (0xff, 0xff) };
+            let e0 = [v(0), v(2), v(4), a0];
+            let e1 = [v(1), v(3), v(5), a1];
+            if v(1) + v(3) + v(5) >= v(0) + v(2) + v(4) {
+                [e0, e1]
+            } else {
+                [blue_contract(e1), blue_contract(e0)]
+            }
+        }
+        9 | 13 => {
+            let (r, dr) = bit_transfer_signed(v(1), v(0));
+            let (g, dg) = bit_transfer_signed(v(3), v(2));
+            let (b, db) = bit_transfer_signed(v(5), v(4));
+            let (a, da) = if mode == 13 { bit_transfer_signed(v(7), v(6)) } else { (0xff, 0) };
+            let e0 = [r, g, b, a];
+            let e1 = [r + dr, g + dg, b + db, a + da];
+            if dr + dg + db >= 0 {
+                [e0, e1]
+            } else {
+                [blue_contract(e1), blue_contract(e0)]
+            }
+        }
+        _ => return None,
+    };
+
+    let clamp = |c: i32| c.max(0).min(0xff) as u32;
+    let mut result = [[0; 4]; 2];
+    for (endpoint, decoded) in result.iter_mut().zip(endpoints.iter()) {
+        for (channel, &value) in endpoint.iter_mut().zip(decoded.iter()) {
+            *channel = clamp(value);
+        }
+    }
+    Some(result)
+}
+
+// Bilinearly interpolates the weight of a texel from the weight grid.
+fn infill_astc_weight(
+    weights: &[u32],
+    block_mode: &AstcBlockMode,
+    plane: usize,
+    x: u32,
+    y: u32,
+) -> u32 {
+    let block_dimension = BLOCK_DIMENSION as u32;
+    let scale = (1024 + block_dimension / 2) / (block_dimension - 1);
+    let gs = (scale * x * (block_mode.grid_width - 1) + 32) >> 6;
+    let gt = (scale * y * (block_mode.grid_height - 1) + 32) >> 6;
+    let (js, fs) = (gs >> 4, gs & 0xf);
+    let (jt, ft) = (gt >> 4, gt & 0xf);
+
+    let plane_count = if block_mode.dual_plane { 2 } else { 1 };
+    let weight = |gx: u32, gy: u32| {
+        if gx < block_mode.grid_width && gy < block_mode.grid_height {
+            weights[(gy * block_mode.grid_width + gx) as usize * plane_count + plane]
+        } else {
+            0
+        }
+    };
+
+    let w11 = (fs * ft + 8) >> 4;
+    let w10 = ft - w11;
+    let w01 = fs - w11;
+    let w00 = 16 - fs - ft + w11;
+    (weight(js, jt) * w00 + weight(js + 1, jt) * w01 +
+        weight(js, jt + 1) * w10 + weight(js + 1, jt + 1) * w11 + 8) >> 4
+}
+
+// Decodes an ASTC block, returning `None` if it is reserved, uses HDR
+// colors, or doesn't fit in the block footprint.
+fn decode_astc_block(block: &[u8], pixels: &mut BlockPixels) -> Option<()> {
+    if astc_bits(block, 0, 9) == 0x1fc {
+        // A void-extent block has a single 16 bit RGBA color. Its
+        // extent is only an optimization hint.
+        if astc_bits(block, 9, 3) != 0x6 {
+            return None;
+        }
+        let channel = |i: u32| (astc_bits(block, 64 + 16 * i, 16) >> 8) as u8;
+        let color = [channel(2), channel(1), channel(0), channel(3)];
+        for pixel in pixels.iter_mut() {
+            *pixel = color;
+        }
+        return Some(());
+    }
+
+    let block_mode = decode_astc_block_mode(astc_bits(block, 0, 11))?;
+    let plane_count = if block_mode.dual_plane { 2 } else { 1 };
+    let weight_count = block_mode.grid_width * block_mode.grid_height * plane_count;
+    let weight_bits = astc_encoded_bits(block_mode.weight_range, weight_count);
+    if block_mode.grid_width > BLOCK_DIMENSION as u32 ||
+        block_mode.grid_height > BLOCK_DIMENSION as u32 ||
+        weight_bits < 24 || weight_bits > 96 {
+        return None;
+    }
+
+    let partition_count = astc_bits(block, 11, 2) + 1;
+    if block_mode.dual_plane && partition_count == 4 {
+        return None;
+    }
+
+    // The endpoint modes of the partitions. When these aren't all the same,
+    // they are in the same class of modes or in the next one, and the
+    // bits that don't fit in the header are stored below the weights.
+    let mut endpoint_modes = [0; 4];
+    let mut below_weights = 128 - weight_bits;
+    let color_start = if partition_count == 1 {
+        endpoint_modes[0] = astc_bits(block, 13, 4);
+        17
+    } else {
+        let class_selector = astc_bits(block, 23, 2);
+        if class_selector == 0 {
+            let mode = astc_bits(block, 25, 4);
+            for endpoint_mode in endpoint_modes.iter_mut() {
+                *endpoint_mode = mode;
+            }
+        } else {
+            let extra_bits = 3 * partition_count - 4;
+            below_weights -= extra_bits;
+            let modes = astc_bits(block, 25, 4) | astc_bits(block, below_weights, extra_bits) << 4;
+            for i in 0 .. partition_count {
+                let class = class_selector - 1 + (modes >> i & 0x1);
+                let mode = modes >> (partition_count + 2 * i) & 0x3;
+                endpoint_modes[i as usize] = class << 2 | mode;
+            }
+        }
+        29
+    };
+    let endpoint_modes = &endpoint_modes[.. partition_count as usize];
+
+    // The component that uses the weights of the second plane.
+    let dual_plane_component = if block_mode.dual_plane {
+        below_weights -= 2;
+        Some(astc_bits(block, below_weights, 2) as usize)
+    } else {
+        None
+    };
+
+    // The colors use the largest range that fits in the remaining bits.
+    let color_count: u32 = endpoint_modes.iter().map(|mode| ((mode >> 2) + 1) * 2).sum();
+    if color_count > 18 || below_weights < color_start {
+        return None;
+    }
+    let color_bits = below_weights - color_start;
+    let color_range = (ASTC_MIN_COLOR_RANGE .. ASTC_RANGES.len())
+        .rev()
+        .find(|&range| astc_encoded_bits(range, color_count) <= color_bits)?;
+
+    let mut encoded_colors = [(0, 0); 18];
+    let mut reader = AstcBitReader::new(
+        block,
+        color_start,
+        color_start + astc_encoded_bits(color_range, color_count),
+        false,
+    );
+    decode_astc_integers(&mut reader, color_range, &mut encoded_colors[.. color_count as usize]);
+    let mut colors = [0; 18];
+    for (color, &encoded) in colors.iter_mut().zip(encoded_colors.iter()) {
+        *color = unquantize_astc_color(color_range, encoded);
+    }
+
+    let mut endpoints = [[[0; 4]; 2]; 4];
+    let mut color_offset = 0;
+    for (endpoint, &mode) in endpoints.iter_mut().zip(endpoint_modes.iter()) {
+        let count = (((mode >> 2) + 1) * 2) as usize;
+        *endpoint = decode_astc_endpoints(mode, &colors[color_offset .. color_offset + count])?;
+        color_offset += count;
+    }
+
+    let mut encoded_weights = [(0, 0); 64];
+    let mut reader = AstcBitReader::new(block, 0, weight_bits, true);
+    decode_astc_integers(
+        &mut reader,
+        block_mode.weight_range,
+        &mut encoded_weights[.. weight_count as usize],
+    );
+    let mut weights = [0; 64];
+    for (weight, &encoded) in weights.iter_mut().zip(encoded_weights.iter()) {
+        *weight = unquantize_astc_weight(block_mode.weight_range, encoded);
+    }
+
+    let partition_seed = astc_bits(block, 13, 10);
+    for y in 0 .. BLOCK_DIMENSION as u32 {
+        for x in 0 .. BLOCK_DIMENSION as u32 {
+            let partition = if partition_count == 1 {
+                0
+            } else {
+                select_astc_partition(partition_seed, x, y, partition_count)
+            };
+            let (e0, e1) = (endpoints[partition][0], endpoints[partition][1]);
+            let plane_weights = [
+                infill_astc_weight(&weights, &block_mode, 0, x, y),
+                if block_mode.dual_plane { infill_astc_weight(&weights, &block_mode, 1, x, y) } else { 0 },
+            ];
+
+            // The endpoints are expanded to 16 bits before being interpolated.
+            let mut rgba = [0; 4];
+            for (i, channel) in rgba.iter_mut().enumerate() {
+                let weight = plane_weights[(dual_plane_component == Some(i)) as usize];
+                let c0 = e0[i] << 8 | e0[i];
+                let c1 = e1[i] << 8 | e1[i];
+                *channel = (((c0 * (64 - weight) + c1 * weight + 32) >> 6) >> 8) as u8;
+            }
+            pixels[(y * BLOCK_DIMENSION as u32 + x) as usize] = [rgba[2], rgba[1], rgba[0], rgba[3]];
+        }
+    }
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use super::*;
+
+    fn decode(format: ImageFormat, block: &[u8]) -> BlockPixels {
+        let mut pixels = [[0; 4]; BLOCK_PIXELS];
+        decode_block(format, block, &mut pixels);
+        pixels
+    }
+
+    #[test]
+    fn bc1_opaque() {
+        // Pure red and black, with every pixel using the second interpolated color.
+        let pixels = decode(ImageFormat::BC1, &[0x00, 0xf8, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff]);
+        for pixel in pixels.iter() {
+            assert_eq!(*pixel, [0, 0, 85, 0xff]);
+        }
+    }
+
+    #[test]
+    fn bc1_transparent() {
+        // The first color is smaller than the second one, so the
+        // fourth palette entry is transparent black.
+        let pixels = decode(ImageFormat::BC1, &[0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        for pixel in pixels.iter() {
+            assert_eq!(*pixel, [0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn bc3_alpha() {
+        // The first three pixels use the alpha indices 0, 1 and 2.
+        let pixels = decode(
+            ImageFormat::BC3,
+            &[0xff, 0x00, 0x88, 0x00, 0x00, 0x00, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0],
+        );
+        assert_eq!(pixels[0][3], 0xff);
+        assert_eq!(pixels[1][3], 0);
+        assert_eq!(pixels[2][3], 218);
+        assert_eq!(pixels[3][3], 0xff);
+    }
+
+    #[test]
+    fn etc2_individual() {
+        let pixels = decode(ImageFormat::ETC2, &[0x88, 0x44, 0x22, 0x00, 0, 0, 0, 0]);
+        for pixel in pixels.iter() {
+            assert_eq!(*pixel, [36, 70, 138, 0xff]);
+        }
+    }
+
+    #[test]
+    fn etc2_differential() {
+        // The red channel of the right sub-block is one step below the left one.
+        let pixels = decode(ImageFormat::ETC2, &[0x87, 0x80, 0x80, 0x02, 0, 0, 0, 0]);
+        assert_eq!(pixels[0], [134, 134, 134, 0xff]);
+        assert_eq!(pixels[3], [134, 134, 125, 0xff]);
+        assert_eq!(pixels[15], [134, 134, 125, 0xff]);
+    }
+
+    #[test]
+    fn eac_alpha() {
+        let mut block = [0xff; 16];
+        block[0] = 128;
+        block[1] = 0x1d;
+        let pixels = decode(ImageFormat::ETC2A8, &block);
+        for pixel in pixels.iter() {
+            assert_eq!(pixel[3], 137);
+        }
+    }
+
+    // Sets the bits of `value` in an ASTC block, from bit `start`.
+    fn set_astc_bits(block: &mut [u8; 16], start: u32, count: u32, value: u32) {
+        for i in 0 .. count {
+            let bit = start + i;
+            if value >> i & 1 != 0 {
+                block[(bit / 8) as usize] |= 1 << (bit % 8);
+            }
+        }
+    }
+
+    #[test]
+    fn astc_void_extent() {
+        let block = [
+            0xfc, 0xfd, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0x00, 0xff, 0x00, 0x80, 0x00, 0x00, 0xff, 0xff,
+        ];
+        let pixels = decode(ImageFormat::ASTC4x4, &block);
+        for pixel in pixels.iter() {
+            assert_eq!(*pixel, [0, 0x80, 0xff, 0xff]);
+        }
+    }
+
+    #[test]
+    fn astc_reserved_block_mode() {
+        let pixels = decode(ImageFormat::ASTC4x4, &[0; 16]);
+        for pixel in pixels.iter() {
+            assert_eq!(*pixel, ASTC_ERROR_COLOR);
+        }
+    }
+
+    #[test]
+    fn astc_weight_grid() {
+        // A single partition with black and white endpoints, and a
+        // 4x4 grid of 2 bit weights that increase from left to right.
+        let mut block = [0; 16];
+        set_astc_bits(&mut block, 0, 11, 0x042);
+        set_astc_bits(&mut block, 13, 4, 8);
+        for (i, &value) in [0, 0xff, 0, 0xff, 0, 0xff].iter().enumerate() {
+            set_astc_bits(&mut block, 17 + 8 * i as u32, 8, value);
+        }
+        for i in 0 .. 16 {
+            let weight = i % 4;
+            for bit in 0 .. 2 {
+                set_astc_bits(&mut block, 127 - (2 * i + bit), 1, weight >> bit & 1);
+            }
+        }
+
+        let pixels = decode(ImageFormat::ASTC4x4, &block);
+        for (i, pixel) in pixels.iter().enumerate() {
+            let gray = [0, 84, 171, 0xff][i % 4];
+            assert_eq!(*pixel, [gray, gray, gray, 0xff]);
+        }
+    }
+
+    #[test]
+    fn astc_trits_and_quints() {
+        // Every combination of digits has an encoding.
+        let mut trits = HashSet::new();
+        for packed in 0 .. 0x100 {
+            let digits = decode_trits(packed);
+            assert!(digits.iter().all(|&digit| digit < 3));
+            trits.insert(digits);
+        }
+        assert_eq!(trits.len(), 243);
+
+        let mut quints = HashSet::new();
+        for packed in 0 .. 0x80 {
+            let digits = decode_quints(packed);
+            assert!(digits.iter().all(|&digit| digit < 5));
+            quints.insert(digits);
+        }
+        assert_eq!(quints.len(), 125);
+    }
+
+    #[test]
+    fn partial_blocks() {
+        // A 6x2 image is made of two blocks, the second one only partially covered.
+        let descriptor = ImageDescriptor::new(6, 2, ImageFormat::BC1, true, false);
+        let data = [
+            0x00, 0xf8, 0x00, 0xf8, 0x00, 0x00, 0x00, 0x00,
+            0x1f, 0x00, 0x1f, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let pixels = decompress_to_bgra8(&descriptor, &data);
+        assert_eq!(pixels.len(), 6 * 2 * 4);
+        assert_eq!(&pixels[0 .. 4], &[0, 0, 0xff, 0xff]);
+        assert_eq!(&pixels[(6 + 5) * 4 ..], &[0xff, 0, 0, 0xff]);
+    }
+}
@@ -4,7 +4,7 @@
 
 use super::super::shader_source;
 use api::{ColorF, ImageFormat};
-use api::{DeviceIntPoint, DeviceIntRect, DeviceUintRect, DeviceUintSize};
+use api::{DeviceIntPoint, DeviceIntRect, DeviceIntSize, DeviceUintRect, DeviceUintSize};
 use api::TextureTarget;
 #[cfg(any(feature = "debug_renderer", feature="capture"))]
 use api::ImageDescriptor;
@@ -14,6 +14,7 @@ use internal_types::{FastHashMap, RenderTargetInfo};
 use log::Level;
 use smallvec::SmallVec;
 use std::cell::RefCell;
+use std::cmp;
 use std::fs::File;
 use std::io::Read;
 use std::marker::PhantomData;
@@ -51,6 +52,13 @@ const GL_FORMAT_BGRA_GL: gl::GLuint = gl::BGRA;
 
 const GL_FORMAT_BGRA_GLES: gl::GLuint = gl::BGRA_EXT;
 
+// Compressed formats, which are not all exposed by the GL bindings.
+const GL_COMPRESSED_RGBA_S3TC_DXT1: gl::GLuint = 0x83F1;
+const GL_COMPRESSED_RGBA_S3TC_DXT5: gl::GLuint = 0x83F3;
+const GL_COMPRESSED_RGB8_ETC2: gl::GLuint = 0x9274;
+const GL_COMPRESSED_RGBA8_ETC2_EAC: gl::GLuint = 0x9278;
+const GL_COMPRESSED_RGBA_ASTC_4X4: gl::GLuint = 0x93B0;
+
 const SHADER_VERSION_GL: &str = "#version 150\n";
 const SHADER_VERSION_GLES: &str = "#version 300 es\n";
 
@@ -1090,7 +1098,15 @@ impl Device {
         self.set_texture_parameters(texture.target, texture.filter);
         self.update_target_storage::<u8>(texture, &rt_info, true, None);
 
-        let rect = DeviceIntRect::new(DeviceIntPoint::zero(), old_size.to_i32());
+        // The texture may also be shrinking, in which case only the part
+        // that fits in the new size is preserved.
+        let rect = DeviceIntRect::new(
+            DeviceIntPoint::zero(),
+            DeviceIntSize::new(
+                cmp::min(old_size.width, new_size.width) as i32,
+                cmp::min(old_size.height, new_size.height) as i32,
+            ),
+        );
         for (read_fbo, &draw_fbo) in old_fbos.into_iter().zip(&texture.fbo_ids) {
             self.bind_read_target_impl(read_fbo);
             self.bind_draw_target_impl(draw_fbo);
@@ -1267,6 +1283,32 @@ impl Device {
 
     fn update_texture_storage<T: Texel>(&mut self, texture: &Texture, pixels: Option<&[T]>) {
         let desc = self.gl_describe_format(texture.format);
+
+        if texture.format.is_compressed() {
+            // The storage of compressed textures has to be allocated with compressed data.
+            let blank_data;
+            let data = match pixels {
+                Some(pixels) => texels_to_u8_slice(pixels),
+                None => {
+                    let size = texture.format.row_size(texture.width) *
+                        texture.format.row_count(texture.height);
+                    blank_data = vec![0; size as usize];
+                    &blank_data[..]
+                }
+            };
+            assert_eq!(texture.target, gl::TEXTURE_2D);
+            self.gl.compressed_tex_image_2d(
+                texture.target,
+                0,
+                desc.internal as _,
+                texture.width as _,
+                texture.height as _,
+                0,
+                data,
+            );
+            return;
+        }
+
         match texture.target {
             gl::TEXTURE_2D_ARRAY => {
                 self.gl.tex_image_3d(
@@ -1608,6 +1650,8 @@ impl Device {
         self.bind_texture(DEFAULT_TEXTURE, texture);
 
         let buffer = match self.upload_method {
+            // Compressed data is always uploaded immediately, see `update_compressed`.
+            _ if texture.format.is_compressed() => None,
             UploadMethod::Immediate => None,
             UploadMethod::PixelBuffer(hint) => {
                 let upload_size = upload_count * mem::size_of::<T>();
@@ -2186,6 +2230,28 @@ impl Device {
         supports_extension(&self.extensions, extension)
     }
 
+    /// Returns the compressed image formats that textures can be created with.
+    pub fn supported_compressed_formats(&self) -> Vec<ImageFormat> {
+        let mut formats = Vec::new();
+        if self.supports_extension("GL_EXT_texture_compression_s3tc") {
+            formats.push(ImageFormat::BC1);
+            formats.push(ImageFormat::BC3);
+        }
+        // ETC2 is a part of OpenGL ES 3.0, and of the ES3 compatibility of desktop GL.
+        let supports_etc2 = match self.gl.get_type() {
+            gl::GlType::Gl => self.supports_extension("GL_ARB_ES3_compatibility"),
+            gl::GlType::Gles => true,
+        };
+        if supports_etc2 {
+            formats.push(ImageFormat::ETC2);
+            formats.push(ImageFormat::ETC2A8);
+        }
+        if self.supports_extension("GL_KHR_texture_compression_astc_ldr") {
+            formats.push(ImageFormat::ASTC4x4);
+        }
+        formats
+    }
+
     pub fn echo_driver_messages(&self) {
         for msg in self.gl.get_debug_messages() {
             let level = match msg.severity {
@@ -2244,10 +2310,37 @@ impl Device {
                 external: gl::RG,
                 pixel_type: gl::UNSIGNED_BYTE,
             },
+            ImageFormat::BC1 |
+            ImageFormat::BC3 |
+            ImageFormat::ETC2 |
+            ImageFormat::ETC2A8 |
+            ImageFormat::ASTC4x4 => {
+                let internal = gl_compressed_format(format);
+                FormatDesc {
+                    internal: internal as _,
+                    external: internal,
+                    pixel_type: gl::UNSIGNED_BYTE,
+                }
+            }
         }
     }
 }
 
+fn gl_compressed_format(format: ImageFormat) -> gl::GLuint {
+    match format {
+        ImageFormat::BC1 => GL_COMPRESSED_RGBA_S3TC_DXT1,
+        ImageFormat::BC3 => GL_COMPRESSED_RGBA_S3TC_DXT5,
+        ImageFormat::ETC2 => GL_COMPRESSED_RGB8_ETC2,
+        ImageFormat::ETC2A8 => GL_COMPRESSED_RGBA8_ETC2_EAC,
+        ImageFormat::ASTC4x4 => GL_COMPRESSED_RGBA_ASTC_4X4,
+        ImageFormat::R8 |
+        ImageFormat::BGRA8 |
+        ImageFormat::RGBAF32 |
+        ImageFormat::RG8 |
+        ImageFormat::RGBAI32 => panic!("{:?} is not a compressed format", format),
+    }
+}
+
 struct FormatDesc {
     internal: gl::GLint,
     external: gl::GLuint,
@@ -2314,6 +2407,14 @@ impl<'a, T> TextureUploader<'a, T> {
         stride: Option<u32>,
         data: &[T],
     ) {
+        if self.target.texture.format.is_compressed() {
+            let bytes = unsafe {
+                slice::from_raw_parts(data.as_ptr() as *const u8, data.len() * mem::size_of::<T>())
+            };
+            self.target.update_compressed(rect, stride, bytes);
+            return;
+        }
+
         match self.buffer {
             Some(ref mut buffer) => {
                 let upload_size = mem::size_of::<T>() * data.len();
@@ -2366,6 +2467,11 @@ impl<'a> UploadTarget<'a> {
             ImageFormat::RG8 => (gl::RG, 2, gl::UNSIGNED_BYTE),
             ImageFormat::RGBAF32 => (gl::RGBA, 16, gl::FLOAT),
             ImageFormat::RGBAI32 => (gl::RGBA_INTEGER, 16, gl::INT),
+            ImageFormat::BC1 |
+            ImageFormat::BC3 |
+            ImageFormat::ETC2 |
+            ImageFormat::ETC2A8 |
+            ImageFormat::ASTC4x4 => unreachable!(),
         };
 
         let row_length = match chunk.stride {
@@ -2427,6 +2533,38 @@ impl<'a> UploadTarget<'a> {
     }
 }
 
+impl<'a> UploadTarget<'a> {
+    // The GL bindings can only upload compressed data from the client memory
+    // (and not from a PBO), with the rows of blocks tightly packed.
+    fn update_compressed(&mut self, rect: DeviceUintRect, stride: Option<u32>, data: &[u8]) {
+        let format = self.texture.format;
+        let row_size = format.row_size(rect.size.width) as usize;
+        let row_count = format.row_count(rect.size.height) as usize;
+        let stride = stride.map_or(row_size, |stride| stride as usize);
+
+        let packed_data;
+        let data = if stride == row_size {
+            &data[.. row_size * row_count]
+        } else {
+            packed_data = (0 .. row_count)
+                .flat_map(|row| data[row * stride .. row * stride + row_size].iter().cloned())
+                .collect::<Vec<u8>>();
+            &packed_data[..]
+        };
+
+        self.gl.compressed_tex_sub_image_2d(
+            self.texture.target,
+            0,
+            rect.origin.x as _,
+            rect.origin.y as _,
+            rect.size.width as _,
+            rect.size.height as _,
+            gl_compressed_format(format),
+            data,
+        );
+    }
+}
+
 fn texels_to_u8_slice<T: Texel>(texels: &[T]) -> &[u8] {
     unsafe {
         slice::from_raw_parts(texels.as_ptr() as *const u8, texels.len() * mem::size_of::<T>())
@@ -6,18 +6,20 @@
 use api::{AlphaType, BorderDetails, BorderDisplayItem, BuiltDisplayListIter, ClipAndScrollInfo};
 use api::{ClipId, ColorF, ComplexClipRegion, DeviceIntPoint, DeviceIntRect, DeviceIntSize};
 use api::{DevicePixelScale, DeviceUintRect, DisplayItemRef, ExtendMode, ExternalScrollId};
-use api::{FilterOp, FontInstanceKey, GlyphInstance, GlyphOptions, GlyphRasterSpace, GradientStop};
-use api::{IframeDisplayItem, ImageKey, ImageRendering, ItemRange, LayoutPoint};
+use api::{FilterOp, FontInstanceKey, FontRenderMode, GlyphInstance, GlyphOptions, GlyphRasterSpace};
+use api::GradientStop;
+use api::{IframeDisplayItem, ImageKey, ImageMask, ImageRendering, ItemRange, LayoutPoint};
 use api::{LayoutPrimitiveInfo, LayoutRect, LayoutSize, LayoutTransform, LayoutVector2D};
-use api::{LineOrientation, LineStyle, LocalClip, NinePatchBorderSource, PipelineId};
+use api::{LineOrientation, LineStyle, LocalClip, NinePatchBorderSource, PathOp, PipelineId};
 use api::{PropertyBinding, ReferenceFrame, RepeatMode, ScrollFrameDisplayItem, ScrollSensitivity};
 use api::{Shadow, SpecificDisplayItem, StackingContext, StickyFrameDisplayItem, TexelRect};
-use api::{TransformStyle, YuvColorSpace, YuvData};
-use clip::{ClipRegion, ClipSource, ClipSources, ClipStore};
+use api::{TextDecoration, TextEmphasis, TransformStyle, YuvColorSpace, YuvData};
+use bincode::serialize;
+use clip::{ClipRegion, ClipSource, ClipSources, ClipStore, ImageMaskKind, PathClipSource};
 use clip_scroll_tree::{ClipChainIndex, ClipNodeIndex, ClipScrollTree, SpatialNodeIndex};
 use euclid::vec2;
 use frame_builder::{ChasePrimitive, FrameBuilder, FrameBuilderConfig};
-use glyph_rasterizer::FontInstance;
+use glyph_rasterizer::{FontContexts, FontInstance};
 use gpu_cache::GpuCacheHandle;
 use gpu_types::BrushFlags;
 use hit_test::{HitTestingItem, HitTestingRun};
@@ -27,13 +29,16 @@ use picture::PictureCompositeMode;
 use prim_store::{BrushClipMaskKind, BrushKind, BrushPrimitive, BrushSegmentDescriptor};
 use prim_store::{EdgeAaSegmentMask, ImageSource};
 use prim_store::{BorderSource, BrushSegment, PictureIndex, PrimitiveContainer, PrimitiveIndex, PrimitiveStore};
-use prim_store::{OpacityBinding, ScrollNodeAndClipChain, TextRunPrimitiveCpu};
+use prim_store::{OpacityBinding, ScrollNodeAndClipChain, TextRunGlyphs, TextRunPrimitiveCpu};
 use render_backend::{DocumentView};
 use resource_cache::{FontInstanceMap, ImageRequest};
 use scene::{Scene, ScenePipeline, StackingContextHelpers};
 use scene_builder::{BuiltScene, SceneRequest};
 use spatial_node::{SpatialNodeType, StickyFrameInfo};
-use std::{f32, mem, usize};
+use std::{cmp, f32, mem, usize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 use tiling::{CompositeOps, ScrollbarPrimitive};
 use util::{MaxRect, RectHelpers, recycle_vec};
 
@@ -169,6 +174,9 @@ pub struct DisplayListFlattener<'a> {
     /// The map of all font instances.
     font_instances: FontInstanceMap,
 
+    /// The font contexts, to measure the glyphs that text decorations skip.
+    font_contexts: Arc<FontContexts>,
+
     /// A set of pipelines that the caller has requested be made available as
     /// output textures.
     output_pipelines: &'a FastHashSet<PipelineId>,
@@ -216,6 +224,7 @@ impl<'a> DisplayListFlattener<'a> {
         scene: &Scene,
         clip_scroll_tree: &mut ClipScrollTree,
         font_instances: FontInstanceMap,
+        font_contexts: Arc<FontContexts>,
         view: &DocumentView,
         output_pipelines: &FastHashSet<PipelineId>,
         frame_builder_config: &FrameBuilderConfig,
@@ -234,6 +243,7 @@ impl<'a> DisplayListFlattener<'a> {
             scene,
             clip_scroll_tree,
             font_instances,
+            font_contexts,
             config: *frame_builder_config,
             output_pipelines,
             id_to_index_mapper: ClipIdToIndexMapper::default(),
@@ -283,6 +293,17 @@ impl<'a> DisplayListFlattener<'a> {
         self.scene.get_display_list_for_pipeline(pipeline_id).get(complex_clips).collect()
     }
 
+    fn get_clip_path(
+        &self,
+        pipeline_id: PipelineId,
+        path: ItemRange<PathOp>,
+    ) -> Vec<PathOp> {
+        if path.is_empty() {
+            return vec![];
+        }
+        self.scene.get_display_list_for_pipeline(pipeline_id).get(path).collect()
+    }
+
     fn get_clip_chain_items(
         &self,
         pipeline_id: PipelineId,
@@ -345,7 +366,7 @@ impl<'a> DisplayListFlattener<'a> {
             );
         }
 
-        self.pop_stacking_context();
+        self.pop_stacking_context(None);
     }
 
     fn flatten_items(
@@ -419,6 +440,9 @@ impl<'a> DisplayListFlattener<'a> {
         clip_and_scroll_ids: &ClipAndScrollInfo,
         reference_frame_relative_offset: &LayoutVector2D,
     ) {
+        if let Some(ref image_mask) = info.image_mask {
+            self.add_image_dependency(image_mask.image);
+        }
         let complex_clips = self.get_complex_clips(pipeline_id, item.complex_clip().0);
         let clip_region = ClipRegion::create_for_clip_node(
             *item.clip_rect(),
@@ -487,12 +511,26 @@ impl<'a> DisplayListFlattener<'a> {
             return;
         }
 
+        // The items between here and the matching pop are the content of the
+        // pictures for this stacking context, and are hashed when it's popped.
+        let content_start = traversal.unread_data();
+        let content_offset = reference_frame_relative_offset + item.rect().origin.to_vector();
+
         let composition_operations = {
             // TODO(optimization?): self.traversal.display_list()
             let display_list = self.scene.get_display_list_for_pipeline(pipeline_id);
+            let mut mask = stacking_context.mask;
+            if let Some(ref mut mask) = mask {
+                mask.rect = mask.rect.translate(&content_offset);
+            }
             CompositeOps::new(
                 stacking_context.filter_ops_for_compositing(display_list, item.filters()),
                 stacking_context.mix_blend_mode_for_compositing(),
+                mask,
+                stacking_context.filter_primitives_for_compositing(
+                    display_list,
+                    item.filter_primitives(),
+                ),
             )
         };
 
@@ -510,10 +548,11 @@ impl<'a> DisplayListFlattener<'a> {
         self.flatten_items(
             traversal,
             pipeline_id,
-            reference_frame_relative_offset + item.rect().origin.to_vector(),
+            content_offset,
         );
 
-        self.pop_stacking_context();
+        let content_len = content_start.len() - traversal.unread_data().len();
+        self.pop_stacking_context(Some(&content_start[.. content_len]));
     }
 
     fn flatten_iframe(
@@ -534,6 +573,16 @@ impl<'a> DisplayListFlattener<'a> {
 
         self.id_to_index_mapper.initialize_for_pipeline(pipeline);
 
+        // The iframe content isn't part of the items of the enclosing
+        // stacking contexts, so add it to their content hashes separately.
+        let mut hasher = DefaultHasher::new();
+        iframe_pipeline_id.hash(&mut hasher);
+        pipeline.display_list.data().hash(&mut hasher);
+        let iframe_content_hash = hasher.finish();
+        for sc in &mut self.sc_stack {
+            sc.iframe_content_hashes.push(iframe_content_hash);
+        }
+
         //TODO: use or assert on `clip_and_scroll_ids.clip_node_id` ?
         let clip_chain_index = self.add_clip_node(
             info.clip_id,
@@ -606,15 +655,83 @@ impl<'a> DisplayListFlattener<'a> {
                 );
             }
             SpecificDisplayItem::Text(ref text_info) => {
-                self.add_text(
-                    clip_and_scroll,
-                    reference_frame_relative_offset,
-                    &prim_info,
-                    &text_info.font_key,
-                    &text_info.color,
-                    item.glyphs(),
-                    text_info.glyph_options,
-                );
+                let decorations = text_info.decorations;
+                if decorations.is_empty() {
+                    self.add_text(
+                        clip_and_scroll,
+                        reference_frame_relative_offset,
+                        &prim_info,
+                        &text_info.font_key,
+                        &text_info.color,
+                        TextRunGlyphs::DisplayList(item.glyphs()),
+                        text_info.glyph_options,
+                    );
+                } else {
+                    // The decorations span the glyphs of the whole run, including
+                    // the items that follow, which a long run is split into.
+                    let mut glyph_ranges = vec![item.glyphs()];
+                    let mut subtraversal = item.sub_iter();
+                    for _ in 0 .. text_info.continuation_count {
+                        match subtraversal.next() {
+                            Some(continuation) => glyph_ranges.push(continuation.glyphs()),
+                            None => break,
+                        }
+                    }
+                    let glyphs: Vec<GlyphInstance> = glyph_ranges
+                        .iter()
+                        .flat_map(|range| item.display_list().get(*range))
+                        .collect();
+
+                    // Underlines and overlines are drawn below the text, and
+                    // line-throughs above it.
+                    let below_text = decorations.underline
+                        .iter()
+                        .chain(decorations.overline.iter());
+                    for decoration in below_text {
+                        self.add_text_decoration(
+                            clip_and_scroll,
+                            reference_frame_relative_offset,
+                            &prim_info,
+                            &text_info.font_key,
+                            &glyphs,
+                            decoration,
+                            decoration.skip_ink,
+                        );
+                    }
+                    for glyph_range in glyph_ranges {
+                        self.add_text(
+                            clip_and_scroll,
+                            reference_frame_relative_offset,
+                            &prim_info,
+                            &text_info.font_key,
+                            &text_info.color,
+                            TextRunGlyphs::DisplayList(glyph_range),
+                            text_info.glyph_options,
+                        );
+                    }
+                    if let Some(ref decoration) = decorations.line_through {
+                        self.add_text_decoration(
+                            clip_and_scroll,
+                            reference_frame_relative_offset,
+                            &prim_info,
+                            &text_info.font_key,
+                            &glyphs,
+                            decoration,
+                            false,
+                        );
+                    }
+                    if let Some(ref emphasis) = decorations.emphasis {
+                        self.add_text_emphasis(
+                            clip_and_scroll,
+                            reference_frame_relative_offset,
+                            &prim_info,
+                            &text_info.font_key,
+                            &glyphs,
+                            emphasis,
+                        );
+                    }
+                    return Some(subtraversal);
+                }
             }
             SpecificDisplayItem::Rectangle(ref info) => {
                 self.add_solid_rectangle(
@@ -669,6 +786,21 @@ impl<'a> DisplayListFlattener<'a> {
                 let prim = PrimitiveContainer::Brush(BrushPrimitive::new(brush_kind, None));
                 self.add_primitive(clip_and_scroll, &prim_info, Vec::new(), prim);
             }
+            SpecificDisplayItem::ConicGradient(ref info) => {
+                let brush_kind = self.create_brush_kind_for_conic_gradient(
+                    &prim_info,
+                    info.gradient.center,
+                    info.gradient.angle,
+                    info.gradient.start_offset,
+                    info.gradient.end_offset,
+                    item.gradient_stops(),
+                    info.gradient.extend_mode,
+                    info.tile_size,
+                    info.tile_spacing,
+                );
+                let prim = PrimitiveContainer::Brush(BrushPrimitive::new(brush_kind, None));
+                self.add_primitive(clip_and_scroll, &prim_info, Vec::new(), prim);
+            }
             SpecificDisplayItem::BoxShadow(ref box_shadow_info) => {
                 let bounds = box_shadow_info
                     .box_bounds
@@ -729,13 +861,24 @@ impl<'a> DisplayListFlattener<'a> {
                 );
             }
             SpecificDisplayItem::Clip(ref info) => {
+                if let Some(ref image_mask) = info.image_mask {
+                    self.add_image_dependency(image_mask.image);
+                }
                 let complex_clips = self.get_complex_clips(pipeline_id, item.complex_clip().0);
-                let clip_region = ClipRegion::create_for_clip_node(
+                let mut clip_region = ClipRegion::create_for_clip_node(
                     *item.clip_rect(),
                     complex_clips,
                     info.image_mask,
                     &reference_frame_relative_offset,
                 );
+                let path = self.get_clip_path(pipeline_id, item.clip_path());
+                if !path.is_empty() {
+                    clip_region.path = PathClipSource::new(
+                        &path,
+                        info.fill_rule,
+                        &reference_frame_relative_offset,
+                    );
+                }
                 self.add_clip_node(info.id, clip_and_scroll_ids.scroll_node_id, clip_region);
             }
             SpecificDisplayItem::ClipChain(ref info) => {
@@ -773,7 +916,8 @@ impl<'a> DisplayListFlattener<'a> {
             }
 
             // Do nothing; these are dummy items for the display list parser
-            SpecificDisplayItem::SetGradientStops => {}
+            SpecificDisplayItem::SetGradientStops |
+            SpecificDisplayItem::SetFilterPrimitives => {}
 
             SpecificDisplayItem::PopStackingContext | SpecificDisplayItem::PopReferenceFrame => {
                 unreachable!("Should have returned in parent method.")
@@ -828,17 +972,33 @@ impl<'a> DisplayListFlattener<'a> {
             None => return,
         };
 
+        let context_3d = self.current_rendering_context_3d();
         let new_item = HitTestingItem::new(tag, info);
         match self.hit_testing_runs.last_mut() {
-            Some(&mut HitTestingRun(ref mut items, prev_clip_and_scroll))
-                if prev_clip_and_scroll == clip_and_scroll => {
+            Some(&mut HitTestingRun(ref mut items, prev_clip_and_scroll, prev_context_3d))
+                if prev_clip_and_scroll == clip_and_scroll && prev_context_3d == context_3d => {
                 items.push(new_item);
                 return;
             }
             _ => {}
         }
 
-        self.hit_testing_runs.push(HitTestingRun(vec![new_item], clip_and_scroll));
+        self.hit_testing_runs.push(HitTestingRun(vec![new_item], clip_and_scroll, context_3d));
+    }
+
+    /// Returns the container picture of the 3d rendering context that primitives
+    /// added to the current stacking context participate in, if any.
+    fn current_rendering_context_3d(&self) -> Option<PictureIndex> {
+        match self.sc_stack.last() {
+            Some(sc) if sc.participating_in_3d_context => {
+                self.sc_stack
+                    .iter()
+                    .rev()
+                    .filter_map(|sc| sc.rendering_context_3d_pic_index)
+                    .next()
+            }
+            _ => None,
+        }
     }
 
     /// Add an already created primitive to the draw lists.
@@ -1047,6 +1207,75 @@ impl<'a> DisplayListFlattener<'a> {
             *self.picture_stack.last().unwrap()
         };
 
+        // The mask is applied after any filters, so its picture is the
+        // outermost one. The mask image is drawn by the clip mask of
+        // the picture primitive, in the same way as other clips.
+        if let Some(mask) = composite_ops.mask {
+            let mask_pic_index = self.prim_store.add_image_picture(
+                Some(PictureCompositeMode::Mask),
+                false,
+                pipeline_id,
+                current_reference_frame_index,
+                None,
+                true,
+            );
+
+            let image_mask = ImageMask {
+                image: mask.image,
+                rect: mask.rect,
+                repeat: false,
+            };
+            let clip_sources = self.clip_store.insert(ClipSources::new(vec![
+                ClipSource::Image(image_mask, ImageMaskKind::StackingContext(mask.mode)),
+            ]));
+
+            let mask_prim = BrushPrimitive::new_picture(mask_pic_index);
+            let mask_prim_index = self.prim_store.add_primitive(
+                &LayoutRect::zero(),
+                &max_clip,
+                is_backface_visible,
+                Some(clip_sources),
+                None,
+                PrimitiveContainer::Brush(mask_prim),
+            );
+
+            let parent_pic = &mut self.prim_store.pictures[parent_pic_index.0];
+            parent_pic_index = mask_pic_index;
+            parent_pic.add_primitive(mask_prim_index, clip_and_scroll);
+
+            self.picture_stack.push(mask_pic_index);
+        }
+
+        // The filter graph is applied to the output of the other filters,
+        // so its picture encloses theirs.
+        if !composite_ops.filter_primitives.is_empty() {
+            let src_pic_index = self.prim_store.add_image_picture(
+                Some(PictureCompositeMode::SvgFilter(composite_ops.filter_primitives.clone())),
+                false,
+                pipeline_id,
+                current_reference_frame_index,
+                None,
+                true,
+            );
+
+            let src_prim = BrushPrimitive::new_picture(src_pic_index);
+            let src_prim_index = self.prim_store.add_primitive(
+                &LayoutRect::zero(),
+                &max_clip,
+                is_backface_visible,
+                None,
+                None,
+                PrimitiveContainer::Brush(src_prim),
+            );
+
+            let parent_pic = &mut self.prim_store.pictures[parent_pic_index.0];
+            parent_pic_index = src_pic_index;
+
+            parent_pic.add_primitive(src_prim_index, clip_and_scroll);
+
+            self.picture_stack.push(src_pic_index);
+        }
+
         // For each filter, create a new image with that composite mode.
         for filter in composite_ops.filters.iter().rev() {
             let src_pic_index = self.prim_store.add_image_picture(
@@ -1161,6 +1390,9 @@ impl<'a> DisplayListFlattener<'a> {
         // Add this as the top-most picture for primitives to be added to.
         self.picture_stack.push(pic_index);
 
+        // The mask image is drawn by the pictures of this stacking context.
+        let image_keys = composite_ops.mask.iter().map(|mask| mask.image).collect();
+
         // Push the SC onto the stack, so we know how to handle things in
         // pop_stacking_context.
         let sc = FlattenedStackingContext {
@@ -1168,15 +1400,31 @@ impl<'a> DisplayListFlattener<'a> {
             is_backface_visible,
             pipeline_id,
             transform_style,
+            participating_in_3d_context,
             rendering_context_3d_pic_index,
             glyph_raster_space,
+            iframe_content_hashes: Vec::new(),
+            image_keys,
+            font_instance_keys: FastHashSet::default(),
         };
 
         self.sc_stack.push(sc);
     }
 
-    pub fn pop_stacking_context(&mut self) {
+    /// Pop the current stacking context. If the serialized items it contains
+    /// are given, its pictures are identified by a hash of them, which lets
+    /// cached pictures be reused by later display lists with the same content.
+    pub fn pop_stacking_context(&mut self, content: Option<&[u8]>) {
         let sc = self.sc_stack.pop().unwrap();
+        let content_hash = content.map(|content| sc.content_hash(content, &self.font_instances));
+        let mut image_keys: Vec<_> = sc.image_keys.iter().cloned().collect();
+        image_keys.sort_by_key(|key| ((key.0).0, key.1));
+
+        // The content of this stacking context is also content of the parent.
+        if let Some(parent_sc) = self.sc_stack.last_mut() {
+            parent_sc.image_keys.extend(sc.image_keys.iter().cloned());
+            parent_sc.font_instance_keys.extend(sc.font_instance_keys.iter().cloned());
+        }
 
         // Always pop at least the main picture for this stacking context.
         let mut pop_count = 1;
@@ -1189,11 +1437,23 @@ impl<'a> DisplayListFlattener<'a> {
             pop_count += 1;
         }
 
-        for _ in 0 .. pop_count {
+        for i in 0 .. pop_count {
             let pic_index = self
                 .picture_stack
                 .pop()
                 .expect("bug: mismatched picture stack");
+
+            // Each of the pictures for the stacking context draws something
+            // different, so they need distinct hashes.
+            if let Some(content_hash) = content_hash {
+                let mut hasher = DefaultHasher::new();
+                content_hash.hash(&mut hasher);
+                i.hash(&mut hasher);
+                let picture = &mut self.prim_store.pictures[pic_index.0];
+                picture.content_hash = Some(hasher.finish());
+                picture.image_keys = image_keys.clone();
+            }
+
             self.prim_store.optimize_picture_if_possible(pic_index);
         }
 
@@ -1685,6 +1945,7 @@ impl<'a> DisplayListFlattener<'a> {
 
                 let brush_kind = match border.source {
                     NinePatchBorderSource::Image(image_key) => {
+                        self.add_image_dependency(image_key);
                         BrushKind::Border {
                             source: BorderSource::Image(ImageRequest {
                                 key: image_key,
@@ -1717,6 +1978,19 @@ impl<'a> DisplayListFlattener<'a> {
                             LayoutSize::zero(),
                         )
                     }
+                    NinePatchBorderSource::ConicGradient(gradient) => {
+                        self.create_brush_kind_for_conic_gradient(
+                            &info,
+                            gradient.center,
+                            gradient.angle,
+                            gradient.start_offset,
+                            gradient.end_offset,
+                            gradient_stops,
+                            gradient.extend_mode,
+                            LayoutSize::new(border.height as f32, border.width as f32),
+                            LayoutSize::zero(),
+                        )
+                    }
                 };
 
                 let prim = PrimitiveContainer::Brush(
@@ -1803,6 +2077,35 @@ impl<'a> DisplayListFlattener<'a> {
         }
     }
 
+    pub fn create_brush_kind_for_conic_gradient(
+        &mut self,
+        info: &LayoutPrimitiveInfo,
+        center: LayoutPoint,
+        angle: f32,
+        start_offset: f32,
+        end_offset: f32,
+        stops: ItemRange<GradientStop>,
+        extend_mode: ExtendMode,
+        stretch_size: LayoutSize,
+        mut tile_spacing: LayoutSize,
+    ) -> BrushKind {
+        let mut prim_rect = info.rect;
+        simplify_repeated_primitive(&stretch_size, &mut tile_spacing, &mut prim_rect);
+
+        BrushKind::ConicGradient {
+            stops_range: stops,
+            extend_mode,
+            center,
+            angle,
+            start_offset,
+            end_offset,
+            stops_handle: GpuCacheHandle::new(),
+            stretch_size,
+            tile_spacing,
+            visible_tiles: Vec::new(),
+        }
+    }
+
     pub fn add_text(
         &mut self,
         clip_and_scroll: ScrollNodeAndClipChain,
@@ -1810,9 +2113,13 @@ impl<'a> DisplayListFlattener<'a> {
         prim_info: &LayoutPrimitiveInfo,
         font_instance_key: &FontInstanceKey,
         text_color: &ColorF,
-        glyph_range: ItemRange<GlyphInstance>,
+        glyphs: TextRunGlyphs,
         glyph_options: Option<GlyphOptions>,
     ) {
+        if let Some(sc) = self.sc_stack.last_mut() {
+            sc.font_instance_keys.insert(*font_instance_key);
+        }
+
         let prim = {
             let instance_map = self.font_instances.read().unwrap();
             let font_instance = match instance_map.get(font_instance_key) {
@@ -1854,13 +2161,14 @@ impl<'a> DisplayListFlattener<'a> {
                 render_mode,
                 flags,
                 font_instance.synthetic_italics,
+                font_instance.color_palette,
                 font_instance.platform_options,
                 font_instance.variations.clone(),
             );
             TextRunPrimitiveCpu::new(
                 prim_font,
                 run_offset,
-                glyph_range,
+                glyphs,
                 Vec::new(),
                 false,
                 glyph_raster_space,
@@ -1875,6 +2183,218 @@ impl<'a> DisplayListFlattener<'a> {
         );
     }
 
+    pub fn add_text_decoration(
+        &mut self,
+        clip_and_scroll: ScrollNodeAndClipChain,
+        run_offset: LayoutVector2D,
+        prim_info: &LayoutPrimitiveInfo,
+        font_instance_key: &FontInstanceKey,
+        glyphs: &[GlyphInstance],
+        decoration: &TextDecoration,
+        skip_ink: bool,
+    ) {
+        if glyphs.is_empty() || decoration.thickness <= 0.0 {
+            return;
+        }
+
+        let font = match self.get_measured_font(font_instance_key) {
+            Some(font) => font,
+            None => return,
+        };
+
+        let height = match decoration.style {
+            LineStyle::Wavy => decoration.thickness * 3.0,
+            LineStyle::Solid | LineStyle::Dotted | LineStyle::Dashed => decoration.thickness,
+        };
+        let (top, bottom) = (decoration.offset, decoration.offset + height);
+
+        // Measure the advances of the glyphs, and where their ink crosses the
+        // line. The gaps around the ink are as wide as the line is thick.
+        let mut start = f32::MAX;
+        let mut end = f32::MIN;
+        let mut gaps = Vec::new();
+        let mut extents = FastHashMap::default();
+        for glyph in glyphs {
+            let font_contexts = &self.font_contexts;
+            let (advance, ink) = *extents.entry(glyph.index).or_insert_with(|| {
+                if !skip_ink {
+                    let advance = font_contexts
+                        .get_glyph_dimensions(&font, glyph.index)
+                        .map_or(0.0, |dimensions| dimensions.advance);
+                    return (advance, None);
+                }
+                match font_contexts.get_glyph_ink(*font_instance_key, &font, glyph.index) {
+                    Some(glyph_ink) => (glyph_ink.advance(), glyph_ink.extent(top, bottom)),
+                    None => (0.0, None),
+                }
+            });
+            start = start.min(glyph.point.x);
+            end = end.max(glyph.point.x + advance);
+            if let Some((ink_start, ink_end)) = ink {
+                gaps.push((
+                    glyph.point.x + ink_start - decoration.thickness,
+                    glyph.point.x + ink_end + decoration.thickness,
+                ));
+            }
+        }
+        gaps.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(cmp::Ordering::Equal));
+
+        let mut segments = Vec::new();
+        let mut segment_start = start;
+        for (gap_start, gap_end) in gaps {
+            if gap_start > segment_start {
+                segments.push((segment_start, gap_start.min(end)));
+            }
+            segment_start = segment_start.max(gap_end);
+        }
+        segments.push((segment_start, end));
+
+        // Every segment is a line along the whole run, clipped to the segment,
+        // so that the dashes, dots and waves keep their phase across the gaps.
+        let baseline = glyphs[0].point.y;
+        let rect = LayoutRect::new(
+            LayoutPoint::new(start, baseline + top) + run_offset,
+            LayoutSize::new(end - start, height),
+        );
+        for (segment_start, segment_end) in segments {
+            if segment_end - segment_start <= 0.0 {
+                continue;
+            }
+            let segment_rect = LayoutRect::new(
+                LayoutPoint::new(segment_start, baseline + top) + run_offset,
+                LayoutSize::new(segment_end - segment_start, height),
+            );
+            let clip_rect = match segment_rect.intersection(&prim_info.clip_rect) {
+                Some(clip_rect) => clip_rect,
+                None => continue,
+            };
+            let info = LayoutPrimitiveInfo {
+                rect,
+                clip_rect,
+                .. *prim_info
+            };
+            self.add_line(
+                clip_and_scroll,
+                &info,
+                decoration.thickness,
+                LineOrientation::Horizontal,
+                &decoration.color,
+                decoration.style,
+            );
+        }
+    }
+
+    pub fn add_text_emphasis(
+        &mut self,
+        clip_and_scroll: ScrollNodeAndClipChain,
+        run_offset: LayoutVector2D,
+        prim_info: &LayoutPrimitiveInfo,
+        font_instance_key: &FontInstanceKey,
+        glyphs: &[GlyphInstance],
+        emphasis: &TextEmphasis,
+    ) {
+        let (font, mark_font) = match (
+            self.get_measured_font(font_instance_key),
+            self.get_measured_font(&emphasis.font_key),
+        ) {
+            (Some(font), Some(mark_font)) => (font, mark_font),
+            _ => return,
+        };
+        let mark = match self.font_contexts.get_glyph_dimensions(&mark_font, emphasis.mark) {
+            Some(mark) => mark,
+            None => return,
+        };
+
+        // Center a mark on the advance of each glyph that has ink, so that
+        // spaces aren't marked.
+        let mut marks = Vec::new();
+        let mut bounds: Option<LayoutRect> = None;
+        let mut advances = FastHashMap::default();
+        for glyph in glyphs {
+            let font_contexts = &self.font_contexts;
+            let advance = *advances.entry(glyph.index).or_insert_with(|| {
+                match font_contexts.get_glyph_dimensions(&font, glyph.index) {
+                    Some(ref dimensions) if dimensions.width > 0 && dimensions.height > 0 => {
+                        Some(dimensions.advance)
+                    }
+                    _ => None,
+                }
+            });
+            let advance = match advance {
+                Some(advance) => advance,
+                None => continue,
+            };
+            let point = LayoutPoint::new(
+                glyph.point.x + 0.5 * (advance - mark.advance),
+                glyph.point.y + emphasis.offset,
+            );
+            let mark_rect = LayoutRect::new(
+                LayoutPoint::new(point.x + mark.left as f32, point.y - mark.top as f32),
+                LayoutSize::new(mark.width as f32, mark.height as f32),
+            );
+            bounds = Some(bounds.map_or(mark_rect, |bounds| bounds.union(&mark_rect)));
+            marks.push(GlyphInstance {
+                index: emphasis.mark,
+                point,
+            });
+        }
+
+        let rect = match bounds {
+            Some(bounds) => bounds.translate(&run_offset),
+            None => return,
+        };
+        let clip_rect = match rect.intersection(&prim_info.clip_rect) {
+            Some(clip_rect) => clip_rect,
+            None => return,
+        };
+        let info = LayoutPrimitiveInfo {
+            rect,
+            clip_rect,
+            .. *prim_info
+        };
+        self.add_text(
+            clip_and_scroll,
+            run_offset,
+            &info,
+            &emphasis.font_key,
+            &emphasis.color,
+            TextRunGlyphs::Owned(marks),
+            None,
+        );
+    }
+
+    // Get the font of an instance that glyphs are measured with, to lay out
+    // text decorations and emphasis marks.
+    fn get_measured_font(&self, font_instance_key: &FontInstanceKey) -> Option<FontInstance> {
+        let font = match self.font_instances.read().unwrap().get(font_instance_key) {
+            Some(instance) => FontInstance::new(
+                instance.font_key,
+                instance.size,
+                ColorF::new(0.0, 0.0, 0.0, 1.0),
+                instance.bg_color,
+                FontRenderMode::Alpha,
+                instance.flags,
+                instance.synthetic_italics,
+                instance.color_palette,
+                instance.platform_options,
+                instance.variations.clone(),
+            ),
+            None => return None,
+        };
+        if font.size.0 <= 0 {
+            return None;
+        }
+        Some(font)
+    }
+
+    /// Record that an image is drawn in the current stacking context,
+    /// so that its updates invalidate the cached pictures showing it.
+    fn add_image_dependency(&mut self, image_key: ImageKey) {
+        if let Some(sc) = self.sc_stack.last_mut() {
+            sc.image_keys.insert(image_key);
+        }
+    }
+
     pub fn add_image(
         &mut self,
         clip_and_scroll: ScrollNodeAndClipChain,
@@ -1886,6 +2406,8 @@ impl<'a> DisplayListFlattener<'a> {
         image_rendering: ImageRendering,
         alpha_type: AlphaType,
     ) {
+        self.add_image_dependency(image_key);
+
         let mut prim_rect = info.rect;
         simplify_repeated_primitive(&stretch_size, &mut tile_spacing, &mut prim_rect);
         let info = LayoutPrimitiveInfo {
@@ -1946,6 +2468,9 @@ impl<'a> DisplayListFlattener<'a> {
             YuvData::PlanarYCbCr(plane_0, plane_1, plane_2) => [plane_0, plane_1, plane_2],
             YuvData::InterleavedYCbCr(plane_0) => [plane_0, ImageKey::DUMMY, ImageKey::DUMMY],
         };
+        for &image_key in yuv_key.iter().filter(|&&key| key != ImageKey::DUMMY) {
+            self.add_image_dependency(image_key);
+        }
 
         let prim = BrushPrimitive::new(
             BrushKind::YuvImage {
@@ -2001,6 +2526,7 @@ pub fn build_scene(config: &FrameBuilderConfig, request: SceneRequest) -> BuiltS
         &request.scene,
         &mut clip_scroll_tree,
         request.font_instances,
+        request.font_contexts,
         &request.view,
         &request.output_pipelines,
         config,
@@ -2036,10 +2562,60 @@ struct FlattenedStackingContext {
     /// CSS transform-style property.
     transform_style: TransformStyle,
 
+    /// If true, this stacking context is a plane in a 3d rendering context.
+    participating_in_3d_context: bool,
+
     /// If Some(..), this stacking context establishes a new
     /// 3d rendering context, and the value is the picture
     // index of the 3d context container.
     rendering_context_3d_pic_index: Option<PictureIndex>,
+
+    /// Hashes of the display lists of any iframes inside this
+    /// stacking context, which contribute to its content hash.
+    iframe_content_hashes: Vec<u64>,
+
+    /// The images drawn in this stacking context, including the ones of
+    /// its children. They can be updated without a new display list, so
+    /// their generations are checked when its pictures are cached.
+    image_keys: FastHashSet<ImageKey>,
+
+    /// The font instances of the text in this stacking context, including
+    /// the ones of its children.
+    font_instance_keys: FastHashSet<FontInstanceKey>,
+}
+
+impl FlattenedStackingContext {
+    /// Hash the content of this stacking context, given its serialized
+    /// items. This doesn't depend on where the stacking context is in
+    /// the display list, so identical content gets the same hash.
+    fn content_hash(&self, content: &[u8], font_instances: &FontInstanceMap) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.pipeline_id.hash(&mut hasher);
+
+        // The composite ops aren't part of the items, but determine
+        // what is drawn into the pictures for the stacking context.
+        let composite_ops = serialize(&(
+            &self.composite_ops.filters,
+            &self.composite_ops.mix_blend_mode,
+            &self.composite_ops.mask,
+            &self.composite_ops.filter_primitives,
+        )).expect("bug: failed to serialize composite ops");
+        composite_ops.hash(&mut hasher);
+
+        content.hash(&mut hasher);
+        self.iframe_content_hashes.hash(&mut hasher);
+
+        // The text items only refer to their font instances by key, and
+        // the instance data is looked up when they are flattened.
+        let mut font_instance_keys: Vec<_> = self.font_instance_keys.iter().cloned().collect();
+        font_instance_keys.sort();
+        let instance_map = font_instances.read().unwrap();
+        for font_instance_key in font_instance_keys {
+            instance_map.get(&font_instance_key).hash(&mut hasher);
+        }
+
+        hasher.finish()
+    }
 }
 
 #[derive(Debug)]
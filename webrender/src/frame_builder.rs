@@ -3,7 +3,8 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use api::{BuiltDisplayList, ColorF, DeviceIntPoint, DeviceIntRect, DevicePixelScale};
-use api::{DeviceUintPoint, DeviceUintRect, DeviceUintSize, DocumentLayer, FontRenderMode};
+use api::{DeviceUintPoint, DeviceUintRect, DeviceUintSize, DocumentId, DocumentLayer};
+use api::FontRenderMode;
 use api::{LayoutPoint, LayoutRect, LayoutSize, PipelineId, WorldPoint};
 use clip::{ClipChain, ClipStore};
 use clip_scroll_tree::{ClipScrollTree, SpatialNodeIndex};
@@ -14,13 +15,16 @@ use hit_test::{HitTester, HitTestingRun};
 use internal_types::{FastHashMap};
 use picture::PictureSurface;
 use prim_store::{PrimitiveIndex, PrimitiveRun, PrimitiveStore, Transform};
-use profiler::{FrameProfileCounters, GpuCacheProfileCounters, TextureCacheProfileCounters};
+use profiler::{FrameProfileCounters, GlyphCacheProfileCounters, GpuCacheProfileCounters};
+use profiler::TextureCacheProfileCounters;
 use render_backend::FrameId;
 use render_task::{RenderTask, RenderTaskId, RenderTaskLocation, RenderTaskTree};
 use resource_cache::{ResourceCache};
 use scene::{ScenePipeline, SceneProperties};
 use spatial_node::SpatialNode;
 use std::{mem, f32};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
 use std::sync::Arc;
 use tiling::{Frame, RenderPass, RenderPassKind, RenderTargetContext};
 use tiling::{ScrollbarPrimitive, SpecialRenderPasses};
@@ -99,7 +103,12 @@ pub struct PictureContext<'a> {
 
 pub struct PictureState {
     pub tasks: Vec<RenderTaskId>,
-    pub has_non_root_coord_system: bool,
+    /// If any content of the picture isn't 2D axis-aligned,
+    /// the picture isn't cached.
+    pub has_non_axis_aligned_content: bool,
+    /// Hashes the transforms and clips of the content relative
+    /// to the picture, which is part of the picture cache key.
+    pub layout_hasher: DefaultHasher,
     pub local_rect_changed: bool,
 }
 
@@ -107,10 +116,19 @@ impl PictureState {
     pub fn new() -> PictureState {
         PictureState {
             tasks: Vec::new(),
-            has_non_root_coord_system: false,
+            has_non_axis_aligned_content: false,
+            layout_hasher: DefaultHasher::new(),
             local_rect_changed: false,
         }
     }
+
+    /// Add transform components to the layout hash. They are compared
+    /// exactly, so that any change to the layout misses the cache.
+    pub fn add_to_layout_hash(&mut self, components: &[f32]) {
+        for component in components {
+            component.to_bits().hash(&mut self.layout_hasher);
+        }
+    }
 }
 
 pub struct PrimitiveRunContext<'a> {
@@ -307,12 +325,14 @@ impl FrameBuilder {
         resource_cache: &mut ResourceCache,
         gpu_cache: &mut GpuCache,
         frame_id: FrameId,
+        document_id: DocumentId,
         clip_scroll_tree: &mut ClipScrollTree,
         pipelines: &FastHashMap<PipelineId, Arc<ScenePipeline>>,
         device_pixel_scale: DevicePixelScale,
         layer: DocumentLayer,
         pan: WorldPoint,
         texture_cache_profile: &mut TextureCacheProfileCounters,
+        glyph_cache_profile: &mut GlyphCacheProfileCounters,
         gpu_cache_profile: &mut GpuCacheProfileCounters,
         scene_properties: &SceneProperties,
     ) -> Frame {
@@ -327,8 +347,8 @@ impl FrameBuilder {
             .total_primitives
             .set(self.prim_store.prim_count());
 
-        resource_cache.begin_frame(frame_id);
-        gpu_cache.begin_frame();
+        resource_cache.begin_frame(frame_id, document_id);
+        gpu_cache.begin_frame(document_id);
 
         let transform_palette = clip_scroll_tree.update_tree(
             &self.screen_rect.to_i32(),
@@ -362,7 +382,8 @@ impl FrameBuilder {
 
         resource_cache.block_until_all_resources_added(gpu_cache,
                                                        &mut render_tasks,
-                                                       texture_cache_profile);
+                                                       texture_cache_profile,
+                                                       glyph_cache_profile);
 
         let mut passes = vec![
             special_render_passes.alpha_glyph_pass,
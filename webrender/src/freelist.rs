@@ -2,6 +2,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use util::recycle_vec;
 
@@ -9,7 +10,7 @@ use util::recycle_vec;
 //           iteration of the occupied list to implement
 //           retain() style functionality.
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, Hash, PartialEq)]
 #[cfg_attr(feature = "capture", derive(Serialize))]
 #[cfg_attr(feature = "replay", derive(Deserialize))]
 struct Epoch(u32);
@@ -52,6 +53,23 @@ pub struct WeakFreeListHandle<M> {
     _marker: PhantomData<M>,
 }
 
+// Implemented by hand, since deriving would require the marker
+// type to implement these traits as well.
+impl<M> PartialEq for WeakFreeListHandle<M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.epoch == other.epoch
+    }
+}
+
+impl<M> Eq for WeakFreeListHandle<M> {}
+
+impl<M> Hash for WeakFreeListHandle<M> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.epoch.hash(state);
+    }
+}
+
 #[derive(Debug)]
 #[cfg_attr(feature = "capture", derive(Serialize))]
 #[cfg_attr(feature = "replay", derive(Deserialize))]
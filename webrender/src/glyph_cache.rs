@@ -4,16 +4,17 @@
 
 #[cfg(feature = "pathfinder")]
 use api::DeviceIntPoint;
+use api::DocumentId;
 use glyph_rasterizer::{FontInstance, GlyphFormat, GlyphKey};
-use internal_types::FastHashMap;
+use internal_types::{FastHashMap, FastHashSet};
+use profiler::GlyphCacheProfileCounters;
 use render_task::RenderTaskCache;
 #[cfg(feature = "pathfinder")]
 use render_task::RenderTaskCacheKey;
 use resource_cache::ResourceClassCache;
+use std::mem;
 use std::sync::Arc;
-use texture_cache::{EvictionNotice, TextureCache};
-#[cfg(not(feature = "pathfinder"))]
-use texture_cache::TextureCacheHandle;
+use texture_cache::{EvictionNotice, TextureCache, TextureCacheHandle};
 
 #[cfg(feature = "pathfinder")]
 #[cfg_attr(feature = "capture", derive(Serialize))]
@@ -73,6 +74,26 @@ impl GlyphCacheEntry {
             GlyphCacheEntry::Blank => false,
         }
     }
+
+    #[cfg(feature = "pathfinder")]
+    fn texture_cache_handle<'a>(&self, render_task_cache: &'a RenderTaskCache)
+                                -> Option<&'a TextureCacheHandle> {
+        match *self {
+            GlyphCacheEntry::Cached(ref glyph) => {
+                render_task_cache.get_texture_cache_handle(&glyph.render_task_cache_key)
+            }
+            GlyphCacheEntry::Pending | GlyphCacheEntry::Blank => None,
+        }
+    }
+
+    #[cfg(not(feature = "pathfinder"))]
+    fn texture_cache_handle<'a>(&'a self, _: &'a RenderTaskCache)
+                                -> Option<&'a TextureCacheHandle> {
+        match *self {
+            GlyphCacheEntry::Cached(ref glyph) => Some(&glyph.texture_cache_handle),
+            GlyphCacheEntry::Pending | GlyphCacheEntry::Blank => None,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -84,11 +105,30 @@ pub enum CachedGlyphData {
     Gpu,
 }
 
-pub type GlyphKeyCache = ResourceClassCache<GlyphKey, GlyphCacheEntry, EvictionNotice>;
+#[derive(Default)]
+#[cfg_attr(feature = "capture", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub struct GlyphKeyCacheInfo {
+    eviction_notice: EvictionNotice,
+    // The glyph requests since the last frame that were found in the
+    // cache, and the ones that had to be rasterized.
+    hits: usize,
+    misses: usize,
+    // The documents that requested glyphs from the cache since their
+    // last frame.
+    requested_by: FastHashSet<DocumentId>,
+}
+
+pub type GlyphKeyCache = ResourceClassCache<GlyphKey, GlyphCacheEntry, GlyphKeyCacheInfo>;
 
 impl GlyphKeyCache {
     pub fn eviction_notice(&self) -> &EvictionNotice {
-        &self.user_data
+        &self.user_data.eviction_notice
+    }
+
+    pub fn count_requests(&mut self, hits: usize, misses: usize) {
+        self.user_data.hits += hits;
+        self.user_data.misses += misses;
     }
 }
 
@@ -96,19 +136,31 @@ impl GlyphKeyCache {
 #[cfg_attr(feature = "replay", derive(Deserialize))]
 pub struct GlyphCache {
     glyph_key_caches: FastHashMap<FontInstance, GlyphKeyCache>,
+    // The number of glyph key caches removed since the last frame, for the profiler.
+    evicted_font_count: usize,
+    // The document whose frame is being built, which the glyph key
+    // caches accessed during the frame are marked as requested by.
+    current_document: Option<DocumentId>,
 }
 
 impl GlyphCache {
     pub fn new() -> Self {
         GlyphCache {
             glyph_key_caches: FastHashMap::default(),
+            evicted_font_count: 0,
+            current_document: None,
         }
     }
 
     pub fn get_glyph_key_cache_for_font_mut(&mut self, font: FontInstance) -> &mut GlyphKeyCache {
-        self.glyph_key_caches
+        let current_document = self.current_document;
+        let cache = self.glyph_key_caches
             .entry(font)
-            .or_insert_with(|| GlyphKeyCache::new())
+            .or_insert_with(|| GlyphKeyCache::new());
+        if let Some(document_id) = current_document {
+            cache.user_data.requested_by.insert(document_id);
+        }
+        cache
     }
 
     pub fn get_glyph_key_cache_for_font(&self, font: &FontInstance) -> &GlyphKeyCache {
@@ -141,6 +193,36 @@ impl GlyphCache {
         }
     }
 
+    // Remove the caches of the intermediate steps of animated font
+    // variations that no document requested glyphs from since its last
+    // frame, and free their glyphs from the texture cache right away.
+    fn clear_unused_intermediate_variations<F>(&mut self,
+                                               texture_cache: &mut TextureCache,
+                                               render_task_cache: &RenderTaskCache,
+                                               is_intermediate_variation: F)
+    where
+        F: Fn(&FontInstance) -> bool,
+    {
+        let fonts_to_evict = self.glyph_key_caches
+            .iter()
+            .filter(|&(font, cache)| {
+                cache.user_data.requested_by.is_empty() && is_intermediate_variation(font)
+            })
+            .map(|(font, _)| font.clone())
+            .collect::<Vec<_>>();
+
+        for font in fonts_to_evict {
+            let mut cache = self.glyph_key_caches.remove(&font).unwrap();
+            texture_cache.free_handles(
+                cache
+                    .values()
+                    .filter_map(|entry| entry.texture_cache_handle(render_task_cache)),
+            );
+            cache.clear();
+            self.evicted_font_count += 1;
+        }
+    }
+
     // Clear out evicted entries from glyph key caches and, if possible,
     // also remove entirely any subsequently empty glyph key caches.
     fn clear_evicted(&mut self,
@@ -165,9 +247,152 @@ impl GlyphCache {
         });
     }
 
-    pub fn begin_frame(&mut self,
-                       texture_cache: &TextureCache,
-                       render_task_cache: &RenderTaskCache) {
+    pub fn begin_frame<F>(&mut self,
+                          document_id: DocumentId,
+                          texture_cache: &mut TextureCache,
+                          render_task_cache: &RenderTaskCache,
+                          is_intermediate_variation: F)
+    where
+        F: Fn(&FontInstance) -> bool,
+    {
+        self.clear_unused_intermediate_variations(
+            texture_cache,
+            render_task_cache,
+            is_intermediate_variation,
+        );
+        // The requests of the document are collected again for this frame.
+        self.remove_document(document_id);
+        self.current_document = Some(document_id);
         self.clear_evicted(texture_cache, render_task_cache);
     }
+
+    // Forget the requests of a document, e.g. when it's deleted.
+    pub fn remove_document(&mut self, document_id: DocumentId) {
+        for cache in self.glyph_key_caches.values_mut() {
+            cache.user_data.requested_by.remove(&document_id);
+        }
+    }
+
+    pub fn end_frame(&mut self, profile_counters: &mut GlyphCacheProfileCounters) {
+        let (mut hits, mut misses) = (0, 0);
+        for cache in self.glyph_key_caches.values_mut() {
+            hits += mem::replace(&mut cache.user_data.hits, 0);
+            misses += mem::replace(&mut cache.user_data.misses, 0);
+        }
+        profile_counters.hits.set(hits);
+        profile_counters.misses.set(misses);
+        profile_counters.fonts.set(self.glyph_key_caches.len());
+        profile_counters.evicted_fonts.set(mem::replace(&mut self.evicted_font_count, 0));
+        self.current_document = None;
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "pathfinder"))]
+mod test_glyph_cache {
+    #[test]
+    fn evict_intermediate_variations_unused_by_all_documents() {
+        use api::{ColorF, ColorU, DevicePoint, DocumentId, FontKey, FontRenderMode};
+        use api::{IdNamespace, ImageData, ImageDescriptor, ImageFormat};
+        use app_units::Au;
+        use device::TextureFilter;
+        use glyph_cache::{CachedGlyphInfo, GlyphCache, GlyphCacheEntry};
+        use glyph_rasterizer::{FontInstance, GlyphFormat, GlyphKey, SubpixelDirection};
+        use gpu_cache::GpuCache;
+        use gpu_types::UvRectKind;
+        use profiler::GlyphCacheProfileCounters;
+        use render_backend::FrameId;
+        use render_task::RenderTaskCache;
+        use texture_cache::{EvictionNotice, TextureCache, TextureCacheHandle};
+
+        let mut glyph_cache = GlyphCache::new();
+        let mut gpu_cache = GpuCache::new();
+        let mut texture_cache = TextureCache::new(2048, None, Vec::new());
+        let render_task_cache = RenderTaskCache::new();
+        let mut profile_counters = GlyphCacheProfileCounters::new();
+        let eviction_notice = EvictionNotice::default();
+
+        let document_a = DocumentId(IdNamespace(0), 0);
+        let document_b = DocumentId(IdNamespace(0), 1);
+        let font = FontInstance::new(
+            FontKey::new(IdNamespace(0), 0),
+            Au::from_px(32),
+            ColorF::new(0.0, 0.0, 0.0, 1.0),
+            ColorU::new(0, 0, 0, 0),
+            FontRenderMode::Alpha,
+            Default::default(),
+            Default::default(),
+            0,
+            None,
+            Vec::new(),
+        );
+        let is_intermediate_variation = |f: &FontInstance| *f == font;
+
+        // Document A rasterizes a glyph of the font.
+        texture_cache.begin_frame(FrameId(1));
+        glyph_cache.begin_frame(
+            document_a,
+            &mut texture_cache,
+            &render_task_cache,
+            &is_intermediate_variation,
+        );
+        {
+            let mut handle = TextureCacheHandle::new();
+            texture_cache.update(
+                &mut handle,
+                ImageDescriptor::new(8, 8, ImageFormat::R8, false, false),
+                TextureFilter::Linear,
+                Some(ImageData::new(vec![0; 64])),
+                [0.0; 3],
+                None,
+                &mut gpu_cache,
+                Some(&eviction_notice),
+                UvRectKind::Rect,
+            );
+            let glyph_key_cache = glyph_cache.get_glyph_key_cache_for_font_mut(font.clone());
+            glyph_key_cache.insert(
+                GlyphKey::new(0, DevicePoint::zero(), SubpixelDirection::None),
+                GlyphCacheEntry::Cached(CachedGlyphInfo {
+                    texture_cache_handle: handle,
+                    format: GlyphFormat::Alpha,
+                }),
+            );
+        }
+        glyph_cache.end_frame(&mut profile_counters);
+
+        // A frame of document B doesn't evict the glyphs that document A uses.
+        texture_cache.begin_frame(FrameId(1));
+        glyph_cache.begin_frame(
+            document_b,
+            &mut texture_cache,
+            &render_task_cache,
+            &is_intermediate_variation,
+        );
+        glyph_cache.end_frame(&mut profile_counters);
+        assert!(glyph_cache.glyph_key_caches.contains_key(&font));
+
+        // Document A stops using the font.
+        texture_cache.begin_frame(FrameId(2));
+        glyph_cache.begin_frame(
+            document_a,
+            &mut texture_cache,
+            &render_task_cache,
+            &is_intermediate_variation,
+        );
+        glyph_cache.end_frame(&mut profile_counters);
+        assert!(glyph_cache.glyph_key_caches.contains_key(&font));
+        assert!(!eviction_notice.check());
+
+        // Once no document uses the font, its glyphs are freed.
+        texture_cache.begin_frame(FrameId(2));
+        glyph_cache.begin_frame(
+            document_b,
+            &mut texture_cache,
+            &render_task_cache,
+            &is_intermediate_variation,
+        );
+        glyph_cache.end_frame(&mut profile_counters);
+        assert!(!glyph_cache.glyph_key_caches.contains_key(&font));
+        assert!(eviction_notice.check());
+    }
 }
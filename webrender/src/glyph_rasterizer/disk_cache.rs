@@ -0,0 +1,700 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+//! A persistent cache of rasterized glyphs, so that the glyphs of a previous
+//! session are loaded from disk rather than rasterized again.
+//!
+//! Each glyph is a file in a directory named after the version of the cache,
+//! keyed by the hash of the font data, the font instance and the glyph key.
+//! When the files exceed the size limit, the least recently used ones are
+//! removed. All of the file I/O, including hashing the font data, happens on
+//! a dedicated thread, which the glyph rasterization jobs send their glyphs
+//! to in batches.
+
+use api::{FontInstancePlatformOptions, FontKey, FontTemplate};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use glyph_rasterizer::{FontInstance, GlyphDiskCacheOptions, GlyphFormat, GlyphKey};
+use glyph_rasterizer::RasterizedGlyph;
+use internal_types::FastHashMap;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hasher;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::SystemTime;
+
+// Bump this when the layout of the glyph files or the keys change.
+const FORMAT_VERSION: u32 = 2;
+const MAGIC: &[u8; 4] = b"WRGC";
+// The magic, the version, left, top, scale, width, height and the format.
+const HEADER_SIZE: u64 = 4 + 4 + 4 * 3 + 4 * 2 + 1;
+const VERSION_DIR_PREFIX: &str = "glyphs-v";
+const GLYPH_FILE_EXTENSION: &str = "glyph";
+
+// FNV-1a, which unlike the hashers of the hash maps gives the same
+// hashes in every session.
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> Self {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+}
+
+/// The data of a font, which identifies its glyphs across sessions unlike
+/// its key. It is only hashed on the disk cache thread, the first time that
+/// glyphs of the font are loaded or stored.
+enum FontData {
+    Raw(Arc<Vec<u8>>, u32),
+    File(PathBuf, u32),
+    Hashed(Option<u64>),
+}
+
+impl FontData {
+    /// Returns `None` for the fonts whose data can't be read, whose glyphs
+    /// aren't cached.
+    fn new(template: &FontTemplate) -> Option<FontData> {
+        match *template {
+            FontTemplate::Raw(ref bytes, index) => Some(FontData::Raw(Arc::clone(bytes), index)),
+            #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+            FontTemplate::Native(ref native_font_handle) => Some(FontData::File(
+                PathBuf::from(&native_font_handle.pathname),
+                native_font_handle.index,
+            )),
+            #[cfg(any(target_os = "macos", target_os = "windows"))]
+            FontTemplate::Native(..) => None,
+        }
+    }
+
+    fn hash(&mut self) -> Option<u64> {
+        let hash = match *self {
+            FontData::Raw(ref bytes, index) => Some(hash_font_bytes(bytes, index)),
+            FontData::File(ref path, index) => match fs::read(path) {
+                Ok(bytes) => Some(hash_font_bytes(&bytes, index)),
+                Err(err) => {
+                    warn!("Unable to read the font {:?} for the glyph disk cache: {:?}", path, err);
+                    None
+                }
+            },
+            FontData::Hashed(hash) => return hash,
+        };
+        *self = FontData::Hashed(hash);
+        hash
+    }
+}
+
+fn hash_font_bytes(bytes: &[u8], index: u32) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write(bytes);
+    hasher.write_u32(index);
+    hasher.finish()
+}
+
+/// Hashes a prepared font instance along with the hash of its font data.
+/// Every field is hashed explicitly, so that the hashes don't depend on
+/// how the types derive `Hash`.
+fn hash_font_instance(font_hash: u64, font: &FontInstance) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write_u64(font_hash);
+    // The font key is different in every session, so it is left out.
+    hasher.write_i32(font.size.0);
+    for color in &[font.color, font.bg_color] {
+        hasher.write(&[color.r, color.g, color.b, color.a]);
+    }
+    hasher.write_u8(font.render_mode as u8);
+    hasher.write_u32(font.flags.bits());
+    hasher.write_i16(font.synthetic_italics.angle);
+    hasher.write_u16(font.color_palette);
+    match font.platform_options {
+        Some(ref options) => {
+            hasher.write_u8(1);
+            hash_platform_options(options, &mut hasher);
+        }
+        None => hasher.write_u8(0),
+    }
+    hasher.write_u64(font.variations.len() as u64);
+    for variation in &font.variations {
+        hasher.write_u32(variation.tag);
+        hasher.write_u32(variation.value.to_bits());
+    }
+    let transform = &font.transform;
+    for value in &[transform.scale_x, transform.skew_x, transform.skew_y, transform.scale_y] {
+        hasher.write_u32(value.to_bits());
+    }
+    hasher.finish()
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn hash_platform_options(options: &FontInstancePlatformOptions, hasher: &mut StableHasher) {
+    hasher.write_u8(options.lcd_filter as u8);
+    hasher.write_u8(options.hinting as u8);
+}
+
+#[cfg(target_os = "macos")]
+fn hash_platform_options(options: &FontInstancePlatformOptions, hasher: &mut StableHasher) {
+    hasher.write_u32(options.unused);
+}
+
+#[cfg(target_os = "windows")]
+fn hash_platform_options(options: &FontInstancePlatformOptions, hasher: &mut StableHasher) {
+    hasher.write_u16(options.gamma);
+    hasher.write_u16(options.contrast);
+}
+
+enum DiskCacheMsg {
+    AddFont(FontKey, FontData),
+    DeleteFont(FontKey),
+    // Loads a batch of glyphs, and replies with them in the same order.
+    Load(FontInstance, Vec<GlyphKey>, Sender<Vec<Option<RasterizedGlyph>>>),
+    // Stores a batch of glyphs, already serialized.
+    Store(FontInstance, Vec<(GlyphKey, Vec<u8>)>),
+    Exit,
+}
+
+/// The persistent glyph cache, which owns the thread that does its file I/O.
+pub struct GlyphDiskCache {
+    tx: Sender<DiskCacheMsg>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl GlyphDiskCache {
+    pub fn new(options: GlyphDiskCacheOptions) -> io::Result<Self> {
+        let (tx, rx) = channel();
+        let thread = thread::Builder::new()
+            .name("WRGlyphDiskCache".to_string())
+            .spawn(move || run_disk_cache_thread(options, rx))?;
+
+        Ok(GlyphDiskCache {
+            tx,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn add_font(&self, font_key: FontKey, template: &FontTemplate) {
+        if let Some(data) = FontData::new(template) {
+            self.tx.send(DiskCacheMsg::AddFont(font_key, data)).ok();
+        }
+    }
+
+    pub fn delete_font(&self, font_key: FontKey) {
+        self.tx.send(DiskCacheMsg::DeleteFont(font_key)).ok();
+    }
+
+    /// Returns a handle that the glyph rasterization jobs use to load and
+    /// store glyphs.
+    pub fn client(&self) -> GlyphDiskCacheClient {
+        GlyphDiskCacheClient {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl Drop for GlyphDiskCache {
+    fn drop(&mut self) {
+        // Let the thread finish writing the glyphs that are already queued.
+        self.tx.send(DiskCacheMsg::Exit).ok();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+pub struct GlyphDiskCacheClient {
+    tx: Sender<DiskCacheMsg>,
+}
+
+impl GlyphDiskCacheClient {
+    /// Loads the glyphs of a font instance that are in the cache, waiting
+    /// for the disk cache thread to read them. The glyphs that aren't in the
+    /// cache are `None`.
+    pub fn load(&self, font: &FontInstance, keys: &[GlyphKey]) -> Vec<Option<RasterizedGlyph>> {
+        let (reply_tx, reply_rx) = channel();
+        let msg = DiskCacheMsg::Load(font.clone(), keys.to_vec(), reply_tx);
+        match self.tx.send(msg).ok().and_then(|_| reply_rx.recv().ok()) {
+            Some(glyphs) => glyphs,
+            None => keys.iter().map(|_| None).collect(),
+        }
+    }
+
+    /// Queues rasterized glyphs of a font instance to be written to the
+    /// cache, without waiting for them to be written.
+    pub fn store(&self, font: FontInstance, glyphs: &[(GlyphKey, &RasterizedGlyph)]) {
+        if glyphs.is_empty() {
+            return;
+        }
+        let glyphs = glyphs
+            .iter()
+            .map(|&(ref key, glyph)| (key.clone(), write_glyph(glyph)))
+            .collect();
+        self.tx.send(DiskCacheMsg::Store(font, glyphs)).ok();
+    }
+}
+
+fn run_disk_cache_thread(options: GlyphDiskCacheOptions, rx: Receiver<DiskCacheMsg>) {
+    // When the cache can't be opened, the thread exits, and every glyph
+    // is then missing from the cache.
+    let mut files = match GlyphFiles::open(options) {
+        Ok(files) => files,
+        Err(err) => {
+            error!("Unable to open the glyph disk cache: {:?}", err);
+            return;
+        }
+    };
+    let mut fonts: FastHashMap<FontKey, FontData> = FastHashMap::default();
+
+    for msg in rx {
+        match msg {
+            DiskCacheMsg::AddFont(font_key, data) => {
+                fonts.insert(font_key, data);
+            }
+            DiskCacheMsg::DeleteFont(font_key) => {
+                fonts.remove(&font_key);
+            }
+            DiskCacheMsg::Load(font, keys, reply_tx) => {
+                let instance_hash = fonts
+                    .get_mut(&font.font_key)
+                    .and_then(|data| data.hash())
+                    .map(|font_hash| hash_font_instance(font_hash, &font));
+                let glyphs = keys
+                    .iter()
+                    .map(|key| instance_hash.and_then(|hash| files.load(hash, key)))
+                    .collect();
+                reply_tx.send(glyphs).ok();
+            }
+            DiskCacheMsg::Store(font, glyphs) => {
+                let font_hash = fonts.get_mut(&font.font_key).and_then(|data| data.hash());
+                if let Some(font_hash) = font_hash {
+                    let instance_hash = hash_font_instance(font_hash, &font);
+                    for (key, data) in glyphs {
+                        files.store(instance_hash, &key, &data);
+                    }
+                }
+            }
+            DiskCacheMsg::Exit => break,
+        }
+    }
+}
+
+/// The glyph files of the cache, which are only accessed from the disk
+/// cache thread.
+struct GlyphFiles {
+    dir: PathBuf,
+    max_size: u64,
+    // The number of bytes of the glyph files in the cache.
+    size: u64,
+    next_temp_file_id: usize,
+}
+
+impl GlyphFiles {
+    fn open(options: GlyphDiskCacheOptions) -> io::Result<Self> {
+        let version = format!(
+            "{}{}-{}",
+            VERSION_DIR_PREFIX,
+            FORMAT_VERSION,
+            env!("CARGO_PKG_VERSION"),
+        );
+
+        // The glyphs of other versions can't be loaded, so remove them.
+        if let Ok(entries) = fs::read_dir(&options.path) {
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                if name.starts_with(VERSION_DIR_PREFIX) && name != version {
+                    fs::remove_dir_all(entry.path()).ok();
+                }
+            }
+        }
+
+        let dir = options.path.join(version);
+        fs::create_dir_all(&dir)?;
+
+        let mut files = GlyphFiles {
+            dir,
+            max_size: options.max_size,
+            size: 0,
+            next_temp_file_id: 0,
+        };
+        let max_size = files.max_size;
+        files.size = files.shrink_to(max_size);
+
+        Ok(files)
+    }
+
+    fn glyph_path(&self, instance_hash: u64, key: &GlyphKey) -> PathBuf {
+        self.dir.join(format!(
+            "{:016x}-{:08x}.{}",
+            instance_hash,
+            key.0,
+            GLYPH_FILE_EXTENSION,
+        ))
+    }
+
+    fn load(&mut self, instance_hash: u64, key: &GlyphKey) -> Option<RasterizedGlyph> {
+        let path = self.glyph_path(instance_hash, key);
+        let mut file = File::open(&path).ok()?;
+        let len = file.metadata().ok()?.len();
+        match read_glyph(&mut file, len) {
+            Ok(glyph) => {
+                // Rewrite the magic to update the modification time, which
+                // orders the files from the least recently used.
+                let touched = OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|mut file| file.write_all(MAGIC));
+                if let Err(err) = touched {
+                    warn!("Unable to touch a glyph in the disk cache {:?}: {:?}", path, err);
+                }
+                Some(glyph)
+            }
+            Err(err) => {
+                warn!("Invalid glyph in the disk cache {:?}: {:?}", path, err);
+                None
+            }
+        }
+    }
+
+    fn store(&mut self, instance_hash: u64, key: &GlyphKey, data: &[u8]) {
+        let path = self.glyph_path(instance_hash, key);
+        // Write to a temporary file first, so that a partially written glyph
+        // is never loaded by another session.
+        let temp_path = path.with_extension(format!(
+            "{}-{}.tmp",
+            process::id(),
+            self.next_temp_file_id,
+        ));
+        self.next_temp_file_id += 1;
+
+        let result = File::create(&temp_path)
+            .and_then(|mut file| file.write_all(data))
+            .and_then(|_| fs::rename(&temp_path, &path));
+        if let Err(err) = result {
+            warn!("Unable to store a glyph in the disk cache {:?}: {:?}", path, err);
+            fs::remove_file(&temp_path).ok();
+            return;
+        }
+
+        self.size += data.len() as u64;
+        if self.size > self.max_size {
+            // Leave some room, so that the next glyphs don't immediately
+            // scan the directory again.
+            let max_size = self.max_size / 4 * 3;
+            self.size = self.shrink_to(max_size);
+        }
+    }
+
+    // Removes the least recently used glyph files until they fit in the
+    // given number of bytes, and returns the number of bytes of the
+    // remaining files.
+    fn shrink_to(&self, max_size: u64) -> u64 {
+        let mut files = list_glyph_files(&self.dir);
+        let mut size: u64 = files.iter().map(|&(_, len, _)| len).sum();
+        if size <= max_size {
+            return size;
+        }
+
+        files.sort_by_key(|&(_, _, modified)| modified);
+        for (path, len, _) in files {
+            if size <= max_size {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                size -= len;
+            }
+        }
+
+        size
+    }
+}
+
+// Lists the paths, sizes and modification times of the glyph files.
+fn list_glyph_files(dir: &Path) -> Vec<(PathBuf, u64, Option<SystemTime>)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(..) => return Vec::new(),
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().map_or(false, |ext| ext == GLYPH_FILE_EXTENSION))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((entry.path(), metadata.len(), metadata.modified().ok()))
+        })
+        .collect()
+}
+
+fn write_glyph(glyph: &RasterizedGlyph) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_SIZE as usize + glyph.bytes.len());
+    data.extend_from_slice(MAGIC);
+    data.write_u32::<LittleEndian>(FORMAT_VERSION).unwrap();
+    data.write_f32::<LittleEndian>(glyph.left).unwrap();
+    data.write_f32::<LittleEndian>(glyph.top).unwrap();
+    data.write_f32::<LittleEndian>(glyph.scale).unwrap();
+    data.write_u32::<LittleEndian>(glyph.width).unwrap();
+    data.write_u32::<LittleEndian>(glyph.height).unwrap();
+    data.write_u8(glyph.format as u8).unwrap();
+    data.extend_from_slice(&glyph.bytes);
+    data
+}
+
+fn read_glyph<R: Read>(reader: &mut R, len: u64) -> io::Result<RasterizedGlyph> {
+    let invalid = |what| io::Error::new(io::ErrorKind::InvalidData, what);
+
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC || reader.read_u32::<LittleEndian>()? != FORMAT_VERSION {
+        return Err(invalid("unknown file format"));
+    }
+
+    let left = reader.read_f32::<LittleEndian>()?;
+    let top = reader.read_f32::<LittleEndian>()?;
+    let scale = reader.read_f32::<LittleEndian>()?;
+    let width = reader.read_u32::<LittleEndian>()?;
+    let height = reader.read_u32::<LittleEndian>()?;
+    let format = match reader.read_u8()? {
+        0 => GlyphFormat::Alpha,
+        1 => GlyphFormat::TransformedAlpha,
+        2 => GlyphFormat::Subpixel,
+        3 => GlyphFormat::TransformedSubpixel,
+        4 => GlyphFormat::Bitmap,
+        5 => GlyphFormat::ColorBitmap,
+        _ => return Err(invalid("unknown glyph format")),
+    };
+
+    // Check the size before allocating, in case the header is corrupt.
+    // We always render glyphs in 32 bits RGBA format.
+    let bytes_len = width as u64 * height as u64 * 4;
+    if HEADER_SIZE + bytes_len != len {
+        return Err(invalid("unexpected file size"));
+    }
+    let mut bytes = vec![0; bytes_len as usize];
+    reader.read_exact(&mut bytes)?;
+
+    Ok(RasterizedGlyph {
+        top,
+        left,
+        width,
+        height,
+        scale,
+        format,
+        bytes,
+    })
+}
+
+#[cfg(test)]
+mod test_glyph_disk_cache {
+    use api::{ColorF, ColorU, DevicePoint, FontInstanceFlags, FontKey, FontRenderMode};
+    use api::{FontTemplate, FontVariation, IdNamespace};
+    use app_units::Au;
+    use glyph_rasterizer::{FontInstance, GlyphDiskCacheOptions, GlyphFormat, GlyphKey};
+    use glyph_rasterizer::{RasterizedGlyph, SubpixelDirection};
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use super::{GlyphDiskCache, GlyphFiles, HEADER_SIZE, hash_font_instance, write_glyph};
+
+    fn cache_options(name: &str, max_size: u64) -> GlyphDiskCacheOptions {
+        let path = env::temp_dir().join(format!("wr-glyph-cache-{}-{}", name, process::id()));
+        fs::remove_dir_all(&path).ok();
+        GlyphDiskCacheOptions { path, max_size }
+    }
+
+    fn glyph(width: u32, height: u32) -> RasterizedGlyph {
+        RasterizedGlyph {
+            top: 12.0,
+            left: -1.0,
+            width,
+            height,
+            scale: 1.0,
+            format: GlyphFormat::Subpixel,
+            bytes: (0 .. width * height * 4).map(|i| i as u8).collect(),
+        }
+    }
+
+    fn key(index: u32) -> GlyphKey {
+        GlyphKey::new(index, DevicePoint::zero(), SubpixelDirection::None)
+    }
+
+    fn font(font_key: FontKey) -> FontInstance {
+        FontInstance::new(
+            font_key,
+            Au::from_px(16),
+            ColorF::new(0.0, 0.0, 0.0, 1.0),
+            ColorU::new(0, 0, 0, 0),
+            FontRenderMode::Alpha,
+            FontInstanceFlags::empty(),
+            Default::default(),
+            0,
+            None,
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn store_and_load() {
+        let options = cache_options("store", 1 << 20);
+        let mut files = GlyphFiles::open(options.clone()).unwrap();
+
+        assert!(files.load(1, &key(3)).is_none());
+        files.store(1, &key(3), &write_glyph(&glyph(4, 5)));
+
+        // The glyph is there in the next session, but not for other
+        // font instances or glyphs.
+        let mut files = GlyphFiles::open(options.clone()).unwrap();
+        let loaded = files.load(1, &key(3)).unwrap();
+        let expected = glyph(4, 5);
+        assert_eq!((loaded.left, loaded.top, loaded.scale), (-1.0, 12.0, 1.0));
+        assert_eq!((loaded.width, loaded.height), (4, 5));
+        assert_eq!(loaded.format, GlyphFormat::Subpixel);
+        assert_eq!(loaded.bytes, expected.bytes);
+        assert!(files.load(2, &key(3)).is_none());
+        assert!(files.load(1, &key(4)).is_none());
+
+        // Truncated files are ignored.
+        let path = files.glyph_path(1, &key(3));
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[.. data.len() - 1]).unwrap();
+        assert!(files.load(1, &key(3)).is_none());
+
+        fs::remove_dir_all(&options.path).unwrap();
+    }
+
+    #[test]
+    fn remove_other_versions() {
+        let options = cache_options("versions", 1 << 20);
+        let old_version = options.path.join("glyphs-v0");
+        let unrelated = options.path.join("unrelated");
+        fs::create_dir_all(&old_version).unwrap();
+        fs::create_dir_all(&unrelated).unwrap();
+
+        GlyphFiles::open(options.clone()).unwrap();
+        assert!(!old_version.exists());
+        assert!(unrelated.exists());
+
+        fs::remove_dir_all(&options.path).unwrap();
+    }
+
+    #[test]
+    fn limit_size() {
+        let glyph_size = HEADER_SIZE + 8 * 8 * 4;
+        let options = cache_options("size", glyph_size * 4);
+        let mut files = GlyphFiles::open(options.clone()).unwrap();
+
+        // Space the glyphs out, so that their modification times differ.
+        let store = |files: &mut GlyphFiles, index| {
+            files.store(1, &key(index), &write_glyph(&glyph(8, 8)));
+            thread::sleep(Duration::from_millis(20));
+        };
+        for i in 0 .. 4 {
+            store(&mut files, i);
+        }
+        assert_eq!(files.size, glyph_size * 4);
+
+        // Loading the oldest glyph makes it the most recently used.
+        assert!(files.load(1, &key(0)).is_some());
+        thread::sleep(Duration::from_millis(20));
+
+        // Going over the limit leaves three quarters of it, without the
+        // least recently used glyphs.
+        store(&mut files, 4);
+        assert_eq!(files.size, glyph_size * 3);
+        let remaining: Vec<u32> = (0 .. 5).filter(|&i| files.load(1, &key(i)).is_some()).collect();
+        assert_eq!(remaining, vec![0, 3, 4]);
+
+        // A smaller limit shrinks the cache of the next session.
+        let files = GlyphFiles::open(GlyphDiskCacheOptions {
+            max_size: glyph_size,
+            ..options.clone()
+        }).unwrap();
+        assert_eq!(files.size, glyph_size);
+
+        fs::remove_dir_all(&options.path).unwrap();
+    }
+
+    #[test]
+    fn hash_every_field() {
+        let base = font(FontKey::new(IdNamespace(1), 1));
+        let hash = hash_font_instance(7, &base);
+
+        // The font key changes in every session, unlike the font data.
+        let other_key = font(FontKey::new(IdNamespace(2), 5));
+        assert_eq!(hash_font_instance(7, &other_key), hash);
+        assert_ne!(hash_font_instance(8, &base), hash);
+
+        let mut changed = Vec::new();
+        changed.push(FontInstance { size: Au::from_px(17), ..base.clone() });
+        changed.push(FontInstance { color: ColorU::new(255, 0, 0, 255), ..base.clone() });
+        changed.push(FontInstance { bg_color: ColorU::new(0, 0, 0, 255), ..base.clone() });
+        changed.push(FontInstance { render_mode: FontRenderMode::Mono, ..base.clone() });
+        changed.push(FontInstance { flags: FontInstanceFlags::SYNTHETIC_BOLD, ..base.clone() });
+        changed.push(FontInstance { color_palette: 1, ..base.clone() });
+        changed.push(FontInstance {
+            platform_options: Some(Default::default()),
+            ..base.clone()
+        });
+        changed.push(FontInstance {
+            variations: vec![FontVariation { tag: 1, value: 0.5 }],
+            ..base.clone()
+        });
+        let mut transformed = base.clone();
+        transformed.transform.skew_x = 0.25;
+        changed.push(transformed);
+
+        for font in &changed {
+            assert_ne!(hash_font_instance(7, font), hash);
+        }
+    }
+
+    #[test]
+    fn load_and_store_on_thread() {
+        let options = cache_options("thread", 1 << 20);
+        let font_key = FontKey::new(IdNamespace(1), 1);
+        let template = FontTemplate::Raw(Arc::new(vec![1, 2, 3]), 0);
+        let keys = [key(1), key(2)];
+
+        let cache = GlyphDiskCache::new(options.clone()).unwrap();
+        cache.add_font(font_key, &template);
+        let client = cache.client();
+        assert!(client.load(&font(font_key), &keys).iter().all(|glyph| glyph.is_none()));
+        let glyph = glyph(2, 2);
+        client.store(font(font_key), &[(key(1), &glyph)]);
+
+        // Dropping the cache waits for the queued glyphs to be written, and
+        // the next session finds them under a different font key.
+        drop(client);
+        drop(cache);
+        let font_key = FontKey::new(IdNamespace(2), 7);
+        let cache = GlyphDiskCache::new(options.clone()).unwrap();
+        cache.add_font(font_key, &template);
+        let loaded = cache.client().load(&font(font_key), &keys);
+        assert_eq!(loaded[0].as_ref().map(|glyph| glyph.bytes.clone()), Some(glyph.bytes));
+        assert!(loaded[1].is_none());
+
+        // Fonts that aren't known to the cache have no glyphs in it.
+        cache.delete_font(font_key);
+        assert!(cache.client().load(&font(font_key), &keys)[0].is_none());
+
+        drop(cache);
+        fs::remove_dir_all(&options.path).unwrap();
+    }
+}
@@ -3,21 +3,27 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use api::{ColorF, ColorU, DevicePoint};
-use api::{FontInstanceFlags, FontInstancePlatformOptions};
-use api::{FontKey, FontRenderMode, FontTemplate, FontVariation};
-use api::{GlyphIndex, GlyphDimensions, SyntheticItalics};
+use api::{FontInstanceFlags, FontInstanceKey, FontInstancePlatformOptions};
+use api::{FontKey, FontRenderMode, FontTemplate, FontVariation, FontVariationAnimation};
+use api::{GlyphIndex, GlyphDimensions, GlyphOutline, SyntheticItalics};
 use api::{LayoutPoint, LayoutToWorldTransform, WorldPoint};
 use app_units::Au;
 use euclid::approxeq::ApproxEq;
-use internal_types::ResourceCacheError;
+use internal_types::{FastHashMap, ResourceCacheError};
 use platform::font::FontContext;
 use rayon::ThreadPool;
 use std::cmp;
 use std::hash::{Hash, Hasher};
 use std::mem;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::sync::mpsc::{channel, Receiver, Sender};
 
+#[cfg(not(feature = "pathfinder"))]
+mod disk_cache;
+#[cfg(not(feature = "pathfinder"))]
+use self::disk_cache::GlyphDiskCache;
+
 #[cfg(feature = "pathfinder")]
 mod pathfinder;
 #[cfg(feature = "pathfinder")]
@@ -28,6 +34,15 @@ pub use self::pathfinder::{ThreadSafePathfinderFontContext, NativeFontHandleWrap
 #[cfg(not(feature = "pathfinder"))]
 mod no_pathfinder;
 
+/// The location and the size limit of the persistent glyph cache.
+#[derive(Clone, Debug)]
+pub struct GlyphDiskCacheOptions {
+    /// The directory of the cache, which is created if needed.
+    pub path: PathBuf,
+    /// The maximum number of bytes of the glyph files.
+    pub max_size: u64,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 #[cfg_attr(feature = "capture", derive(Serialize))]
 #[cfg_attr(feature = "replay", derive(Deserialize))]
@@ -178,6 +193,7 @@ pub struct FontInstance {
     pub render_mode: FontRenderMode,
     pub flags: FontInstanceFlags,
     pub synthetic_italics: SyntheticItalics,
+    pub color_palette: u16,
     pub platform_options: Option<FontInstancePlatformOptions>,
     pub variations: Vec<FontVariation>,
     pub transform: FontTransform,
@@ -192,6 +208,7 @@ impl FontInstance {
         render_mode: FontRenderMode,
         flags: FontInstanceFlags,
         synthetic_italics: SyntheticItalics,
+        color_palette: u16,
         platform_options: Option<FontInstancePlatformOptions>,
         variations: Vec<FontVariation>,
     ) -> Self {
@@ -207,6 +224,7 @@ impl FontInstance {
             render_mode,
             flags,
             synthetic_italics,
+            color_palette,
             platform_options,
             variations,
             transform: FontTransform::identity(),
@@ -221,6 +239,20 @@ impl FontInstance {
         if self.transform.is_identity() { GlyphFormat::Subpixel } else { GlyphFormat::TransformedSubpixel }
     }
 
+    // Snap the animated variation axes to the nearest step of their
+    // animation, so that an animation only creates a bounded set of
+    // font instances. Returns whether an axis is at an intermediate step.
+    pub fn snap_variations(&mut self, animations: &[FontVariationAnimation]) -> bool {
+        let mut is_intermediate = false;
+        for variation in &mut self.variations {
+            if let Some(animation) = animations.iter().find(|a| a.tag == variation.tag) {
+                variation.value = animation.snap(variation.value);
+                is_intermediate |= animation.is_intermediate(variation.value);
+            }
+        }
+        is_intermediate
+    }
+
     pub fn disable_subpixel_aa(&mut self) {
         self.render_mode = self.render_mode.limit_by(FontRenderMode::Alpha);
     }
@@ -448,6 +480,10 @@ pub struct FontContexts {
     shared_context: Mutex<FontContext>,
     #[cfg(feature = "pathfinder")]
     pathfinder_context: Box<ThreadSafePathfinderFontContext>,
+    // The ink of the glyphs that text decorations were laid out along, for
+    // the current variations of each font instance. It's kept across scene
+    // builds, since measuring it rasterizes the glyphs.
+    glyph_inks: Mutex<FastHashMap<FontInstanceKey, GlyphInks>>,
     // Stored here as a convenience to get the current thread index.
     #[allow(dead_code)]
     workers: Arc<ThreadPool>,
@@ -476,6 +512,184 @@ impl FontContexts {
     pub fn num_worker_contexts(&self) -> usize {
         self.worker_contexts.len()
     }
+
+    /// Get the dimensions of a glyph, to lay out text decorations that
+    /// don't need its ink.
+    pub fn get_glyph_dimensions(
+        &self,
+        font: &FontInstance,
+        glyph_index: GlyphIndex,
+    ) -> Option<GlyphDimensions> {
+        let mut context = self.lock_shared_context();
+        if !context.has_font(&font.font_key) {
+            return None;
+        }
+        let key = GlyphKey::new(glyph_index, DevicePoint::zero(), SubpixelDirection::None);
+        context.get_glyph_dimensions(font, &key)
+    }
+
+    /// Get the advance and the ink of a glyph of the font of an instance,
+    /// to lay out text decorations. The glyph is only measured the first
+    /// time it's requested.
+    pub fn get_glyph_ink(
+        &self,
+        instance_key: FontInstanceKey,
+        font: &FontInstance,
+        glyph_index: GlyphIndex,
+    ) -> Option<Arc<GlyphInk>> {
+        if let Some(ink) = self.glyph_inks
+            .lock()
+            .unwrap()
+            .get(&instance_key)
+            .filter(|inks| inks.font == *font)
+            .and_then(|inks| inks.inks.get(&glyph_index))
+        {
+            return ink.clone();
+        }
+
+        let ink = {
+            let mut context = self.lock_shared_context();
+            // The font may not have reached the context yet, so don't
+            // remember that it couldn't be measured.
+            if !context.has_font(&font.font_key) {
+                return None;
+            }
+            let key = GlyphKey::new(glyph_index, DevicePoint::zero(), SubpixelDirection::None);
+            measure_glyph_ink(&mut context, font, &key).map(Arc::new)
+        };
+
+        let mut glyph_inks = self.glyph_inks.lock().unwrap();
+        let inks = glyph_inks.entry(instance_key).or_insert_with(|| GlyphInks {
+            font: font.clone(),
+            inks: FastHashMap::default(),
+        });
+        // The variations of the instance were animated, so the ink of the
+        // previous ones won't be needed again.
+        if inks.font != *font {
+            inks.font = font.clone();
+            inks.inks.clear();
+        }
+        inks.inks.insert(glyph_index, ink.clone());
+        ink
+    }
+
+    /// Forget the ink of the glyphs of a deleted font instance.
+    pub fn delete_font_instance(&self, instance_key: FontInstanceKey) {
+        self.glyph_inks.lock().unwrap().remove(&instance_key);
+    }
+}
+
+// The ink of the glyphs of a font instance, with its current variations.
+struct GlyphInks {
+    font: FontInstance,
+    inks: FastHashMap<GlyphIndex, Option<Arc<GlyphInk>>>,
+}
+
+/// The measurements of a glyph that text decorations are laid out with,
+/// in the pixels of the size of the font.
+#[derive(Debug)]
+pub struct GlyphInk {
+    advance: f32,
+    // The offset of the first column from the origin of the glyph, and of
+    // the first row from the baseline (positive downwards).
+    left: f32,
+    top: f32,
+    column_width: f32,
+    row_height: f32,
+    // The range of columns the ink covers in each row.
+    rows: Vec<Option<(u32, u32)>>,
+}
+
+impl GlyphInk {
+    pub fn advance(&self) -> f32 {
+        self.advance
+    }
+
+    /// Get the horizontal extent of the ink between two offsets from the
+    /// baseline (positive downwards), relative to the origin of the glyph.
+    pub fn extent(&self, top: f32, bottom: f32) -> Option<(f32, f32)> {
+        let mut columns: Option<(u32, u32)> = None;
+        for (row, row_columns) in self.rows.iter().enumerate() {
+            let row_top = self.top + row as f32 * self.row_height;
+            if row_top + self.row_height <= top || row_top >= bottom {
+                continue;
+            }
+            if let Some((start, end)) = *row_columns {
+                columns = Some(match columns {
+                    Some((min_start, max_end)) => {
+                        (cmp::min(min_start, start), cmp::max(max_end, end))
+                    }
+                    None => (start, end),
+                });
+            }
+        }
+        columns.map(|(start, end)| (
+            self.left + start as f32 * self.column_width,
+            self.left + end as f32 * self.column_width,
+        ))
+    }
+}
+
+// Scan each row of the rasterized glyph for coverage.
+#[cfg(not(feature = "pathfinder"))]
+fn measure_glyph_ink(
+    context: &mut FontContext,
+    font: &FontInstance,
+    key: &GlyphKey,
+) -> Option<GlyphInk> {
+    let advance = context.get_glyph_dimensions(font, key)?.advance;
+    let glyph = match context.rasterize_glyph(font, key) {
+        GlyphRasterResult::Bitmap(glyph) => glyph,
+        // The glyph has no ink.
+        GlyphRasterResult::LoadFailed => return Some(GlyphInk {
+            advance,
+            left: 0.0,
+            top: 0.0,
+            column_width: 0.0,
+            row_height: 0.0,
+            rows: Vec::new(),
+        }),
+    };
+    let rows = (0 .. glyph.height).map(|row| {
+        let mut columns = (0 .. glyph.width).filter(|column| {
+            glyph.bytes[((row * glyph.width + column) * 4 + 3) as usize] != 0
+        });
+        columns.next().map(|start| {
+            let end = columns.last().unwrap_or(start);
+            (start, end + 1)
+        })
+    }).collect();
+    Some(GlyphInk {
+        advance,
+        left: glyph.left * glyph.scale,
+        top: -glyph.top * glyph.scale,
+        column_width: glyph.scale,
+        row_height: glyph.scale,
+        rows,
+    })
+}
+
+// Pathfinder doesn't rasterize glyphs on the CPU, so use the bounds of the glyph.
+#[cfg(feature = "pathfinder")]
+fn measure_glyph_ink(
+    context: &mut FontContext,
+    font: &FontInstance,
+    key: &GlyphKey,
+) -> Option<GlyphInk> {
+    let dimensions = context.get_glyph_dimensions(font, key)?;
+    let rows = if dimensions.width == 0 || dimensions.height == 0 {
+        Vec::new()
+    } else {
+        vec![Some((0, 1))]
+    };
+    Some(GlyphInk {
+        advance: dimensions.advance,
+        left: dimensions.left as f32,
+        top: -dimensions.top as f32,
+        column_width: dimensions.width as f32,
+        row_height: dimensions.height as f32,
+        rows,
+    })
 }
 
 pub struct GlyphRasterizer {
@@ -504,6 +718,10 @@ pub struct GlyphRasterizer {
     //   a frame where it is used (although it seems unlikely).
     fonts_to_remove: Vec<FontKey>,
 
+    // The persistent cache of rasterized glyphs, if enabled.
+    #[cfg(not(feature = "pathfinder"))]
+    disk_cache: Option<GlyphDiskCache>,
+
     #[allow(dead_code)]
     next_gpu_glyph_cache_key: GpuGlyphCacheKey,
 }
@@ -526,6 +744,7 @@ impl GlyphRasterizer {
                 shared_context: Mutex::new(shared_context),
                 #[cfg(feature = "pathfinder")]
                 pathfinder_context: create_pathfinder_font_context()?,
+                glyph_inks: Mutex::new(FastHashMap::default()),
                 workers: Arc::clone(&workers),
         };
 
@@ -536,11 +755,38 @@ impl GlyphRasterizer {
             glyph_tx,
             workers,
             fonts_to_remove: Vec::new(),
+            #[cfg(not(feature = "pathfinder"))]
+            disk_cache: None,
             next_gpu_glyph_cache_key: GpuGlyphCacheKey(0),
         })
     }
 
+    /// Keep the rasterized glyphs in a persistent cache on disk, so that
+    /// they are not rasterized again in later sessions. This only applies
+    /// to the fonts that are added afterwards.
+    #[cfg(not(feature = "pathfinder"))]
+    pub fn enable_disk_cache(&mut self, options: GlyphDiskCacheOptions) {
+        match GlyphDiskCache::new(options) {
+            Ok(disk_cache) => self.disk_cache = Some(disk_cache),
+            Err(err) => error!("Unable to start the glyph disk cache: {:?}", err),
+        }
+    }
+
+    /// Pathfinder renders glyphs on the GPU, so there are no rasterized
+    /// glyphs to keep.
+    #[cfg(feature = "pathfinder")]
+    pub fn enable_disk_cache(&mut self, _: GlyphDiskCacheOptions) {
+        warn!("The glyph disk cache isn't supported with pathfinder");
+    }
+
     pub fn add_font(&mut self, font_key: FontKey, template: FontTemplate) {
+        #[cfg(not(feature = "pathfinder"))]
+        {
+            if let Some(ref disk_cache) = self.disk_cache {
+                disk_cache.add_font(font_key, &template);
+            }
+        }
+
         let font_contexts = Arc::clone(&self.font_contexts);
         // It's important to synchronously add the font for the shared context because
         // we use it to check that fonts have been properly added when requesting glyphs.
@@ -564,12 +810,37 @@ impl GlyphRasterizer {
         self.add_font_to_pathfinder(&font_key, &template);
     }
 
+    /// Get the font contexts, for measuring glyphs outside of the render backend.
+    pub fn get_font_contexts(&self) -> Arc<FontContexts> {
+        Arc::clone(&self.font_contexts)
+    }
+
     pub fn delete_font(&mut self, font_key: FontKey) {
         self.fonts_to_remove.push(font_key);
+        #[cfg(not(feature = "pathfinder"))]
+        {
+            if let Some(ref disk_cache) = self.disk_cache {
+                disk_cache.delete_font(font_key);
+            }
+        }
+    }
+
+    #[cfg(not(any(target_os = "android", all(unix, not(target_os = "macos")))))]
+    pub fn prepare_font(&self, font: &mut FontInstance) {
+        FontContext::prepare_font(font);
     }
 
+    #[cfg(any(target_os = "android", all(unix, not(target_os = "macos"))))]
     pub fn prepare_font(&self, font: &mut FontInstance) {
+        let color = font.color;
         FontContext::prepare_font(font);
+
+        // FreeType draws the color glyph layers that use the text color
+        // itself, so the glyphs of those fonts depend on the color.
+        if font.render_mode != FontRenderMode::Mono &&
+           self.font_contexts.lock_shared_context().uses_text_color(&font.font_key) {
+            font.color = ColorU::new(color.r, color.g, color.b, 0xFF);
+        }
     }
 
     pub fn get_glyph_dimensions(
@@ -588,6 +859,31 @@ impl GlyphRasterizer {
             .get_glyph_dimensions(font, &glyph_key)
     }
 
+    pub fn get_glyph_outlines(
+        &mut self,
+        font: &FontInstance,
+        glyph_indices: &[GlyphIndex],
+    ) -> Vec<Option<GlyphOutline>> {
+        let mut font_context = self.font_contexts.lock_shared_context();
+        if !font_context.has_font(&font.font_key) {
+            return Vec::new();
+        }
+
+        glyph_indices
+            .iter()
+            .enumerate()
+            .map(|(i, &glyph_index)| {
+                let glyph_key = GlyphKey::new(
+                    glyph_index,
+                    DevicePoint::zero(),
+                    SubpixelDirection::None,
+                );
+                let next_glyph = glyph_indices.get(i + 1).cloned();
+                font_context.get_glyph_outline(font, &glyph_key, next_glyph)
+            })
+            .collect()
+    }
+
     pub fn get_glyph_index(&mut self, font_key: FontKey, ch: char) -> Option<u32> {
         self.font_contexts
             .lock_shared_context()
@@ -603,6 +899,10 @@ impl GlyphRasterizer {
         let fonts_to_remove = mem::replace(&mut self.fonts_to_remove, Vec::new());
 
         self.workers.spawn(move || {
+            font_contexts.glyph_inks
+                .lock()
+                .unwrap()
+                .retain(|_, inks| !fonts_to_remove.contains(&inks.font.font_key));
             for font_key in &fonts_to_remove {
                 font_contexts.lock_shared_context().delete_font(font_key);
             }
@@ -620,6 +920,7 @@ impl GlyphRasterizer {
         //TODO: any signals need to be sent to the workers?
         self.pending_glyphs = 0;
         self.fonts_to_remove.clear();
+        self.font_contexts.glyph_inks.lock().unwrap().clear();
     }
 }
 
@@ -698,7 +999,7 @@ mod test_glyph_rasterizer {
         let mut glyph_rasterizer = GlyphRasterizer::new(workers).unwrap();
         let mut glyph_cache = GlyphCache::new();
         let mut gpu_cache = GpuCache::new();
-        let mut texture_cache = TextureCache::new(2048);
+        let mut texture_cache = TextureCache::new(2048, None, Vec::new());
         let mut render_task_cache = RenderTaskCache::new();
         let mut render_task_tree = RenderTaskTree::new(FrameId(0));
         let mut special_render_passes = SpecialRenderPasses::new(&DeviceIntSize::new(1366, 768));
@@ -721,6 +1022,7 @@ mod test_glyph_rasterizer {
             FontRenderMode::Subpixel,
             Default::default(),
             Default::default(),
+            0,
             None,
             Vec::new(),
         );
@@ -752,7 +1054,7 @@ mod test_glyph_rasterizer {
 
         glyph_rasterizer.resolve_glyphs(
             &mut glyph_cache,
-            &mut TextureCache::new(4096),
+            &mut TextureCache::new(4096, None, Vec::new()),
             &mut gpu_cache,
             &mut render_task_cache,
             &mut render_task_tree,
@@ -760,6 +1062,130 @@ mod test_glyph_rasterizer {
         );
     }
 
+    #[test]
+    fn glyph_ink_extent() {
+        use glyph_rasterizer::GlyphInk;
+
+        // Two rows of 2px, whose ink covers the columns 1..3 and 0..2.
+        let ink = GlyphInk {
+            advance: 10.0,
+            left: 1.0,
+            top: -4.0,
+            column_width: 2.0,
+            row_height: 2.0,
+            rows: vec![Some((1, 3)), None, Some((0, 2))],
+        };
+        assert_eq!(ink.advance(), 10.0);
+        assert_eq!(ink.extent(-4.0, -2.0), Some((3.0, 7.0)));
+        assert_eq!(ink.extent(-2.0, 0.0), None);
+        assert_eq!(ink.extent(-3.0, 1.0), Some((1.0, 7.0)));
+        assert_eq!(ink.extent(2.0, 4.0), None);
+    }
+
+    #[test]
+    fn snap_animated_variations() {
+        use api::{ColorF, ColorU, FontKey, FontRenderMode, FontVariation};
+        use api::{FontVariationAnimation, IdNamespace};
+        use app_units::Au;
+        use glyph_rasterizer::FontInstance;
+
+        let wght = 0x77676874;
+        let font_key = FontKey::new(IdNamespace(0), 0);
+        let animations = [
+            FontVariationAnimation { tag: wght, min: 100.0, max: 900.0, steps: 5 },
+        ];
+
+        let font_with_weight = |weight| FontInstance::new(
+            font_key,
+            Au::from_px(32),
+            ColorF::new(0.0, 0.0, 0.0, 1.0),
+            ColorU::new(0, 0, 0, 0),
+            FontRenderMode::Alpha,
+            Default::default(),
+            Default::default(),
+            0,
+            None,
+            vec![FontVariation { tag: wght, value: weight }],
+        );
+
+        let mut font = font_with_weight(320.0);
+        assert!(font.snap_variations(&animations));
+        assert_eq!(font.variations[0].value, 300.0);
+
+        let mut font = font_with_weight(880.0);
+        assert!(!font.snap_variations(&animations));
+        assert_eq!(font.variations[0].value, 900.0);
+
+        let mut font = font_with_weight(0.0);
+        assert!(!font.snap_variations(&animations));
+        assert_eq!(font.variations[0].value, 100.0);
+
+        let mut font = font_with_weight(320.0);
+        assert!(!font.snap_variations(&[]));
+        assert_eq!(font.variations[0].value, 320.0);
+    }
+
+    #[test]
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn glyph_outlines() {
+        use rayon::ThreadPoolBuilder;
+        use std::fs::File;
+        use std::io::Read;
+        use std::sync::Arc;
+        use api::{ColorF, ColorU, FontKey, FontRenderMode, FontTemplate};
+        use api::{IdNamespace, PathOp};
+        use app_units::Au;
+        use glyph_rasterizer::{FontInstance, GlyphRasterizer};
+
+        let workers = Arc::new(ThreadPoolBuilder::new().build().unwrap());
+        let mut glyph_rasterizer = GlyphRasterizer::new(workers).unwrap();
+
+        let mut font_data = vec![];
+        File::open("../wrench/reftests/text/Ahem.ttf")
+            .expect("Couldn't open font file")
+            .read_to_end(&mut font_data)
+            .expect("failed to read font file");
+
+        let font_key = FontKey::new(IdNamespace(0), 0);
+        let font = FontInstance::new(
+            font_key,
+            Au::from_px(40),
+            ColorF::new(0.0, 0.0, 0.0, 1.0),
+            ColorU::new(0, 0, 0, 0),
+            FontRenderMode::Alpha,
+            Default::default(),
+            Default::default(),
+            0,
+            None,
+            Vec::new(),
+        );
+
+        // The font hasn't been added yet.
+        assert!(glyph_rasterizer.get_glyph_outlines(&font, &[58]).is_empty());
+
+        glyph_rasterizer.add_font(font_key, FontTemplate::Raw(Arc::new(font_data), 0));
+
+        // The 'X' of Ahem is a square covering the ascent and the descent.
+        let outlines = glyph_rasterizer.get_glyph_outlines(&font, &[58, 58]);
+        assert_eq!(outlines.len(), 2);
+        for outline in &outlines {
+            let outline = outline.as_ref().unwrap();
+            assert_eq!(outline.advance, 40.0);
+            assert_eq!(outline.kerning, 0.0);
+            match outline.commands.first() {
+                Some(&PathOp::MoveTo(..)) => {}
+                first => panic!("unexpected first command {:?}", first),
+            }
+            assert_eq!(outline.commands.last(), Some(&PathOp::Close));
+            for command in &outline.commands {
+                if let PathOp::LineTo(point) = *command {
+                    assert!(point.x == 0.0 || point.x == 40.0);
+                    assert!(point.y == -32.0 || point.y == 8.0);
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_subpx_quantize() {
         use glyph_rasterizer::SubpixelOffset;
@@ -12,7 +12,7 @@ use gpu_types::UvRectKind;
 use rayon::prelude::*;
 use std::sync::{Arc, MutexGuard};
 use platform::font::FontContext;
-use glyph_rasterizer::{FontInstance, FontContexts, GlyphKey};
+use glyph_rasterizer::{FontInstance, FontContexts, GlyphKey, RasterizedGlyph};
 use glyph_rasterizer::{GlyphRasterizer, GlyphRasterJob, GlyphRasterJobs, GlyphRasterResult};
 use glyph_cache::{GlyphCache, CachedGlyphInfo, GlyphCacheEntry};
 use texture_cache::{TextureCache, TextureCacheHandle};
@@ -86,6 +86,8 @@ impl GlyphRasterizer {
             }
         }
 
+        glyph_key_cache.count_requests(glyph_keys.len() - new_glyphs.len(), new_glyphs.len());
+
         if new_glyphs.is_empty() {
             return;
         }
@@ -98,19 +100,32 @@ impl GlyphRasterizer {
     pub(in super) fn request_glyphs_from_backend(&mut self, font: FontInstance, glyphs: Vec<GlyphKey>) {
         let font_contexts = Arc::clone(&self.font_contexts);
         let glyph_tx = self.glyph_tx.clone();
+        let disk_cache = self.disk_cache.as_ref().map(|disk_cache| disk_cache.client());
 
         // spawn an async task to get off of the render backend thread as early as
         // possible and in that task use rayon's fork join dispatch to rasterize the
         // glyphs in the thread pool.
         self.workers.spawn(move || {
-            let jobs = glyphs
+            // Glyphs that are in the disk cache don't need to be rasterized.
+            // They are read in one batch by the disk cache thread.
+            let cached = match disk_cache {
+                Some(ref disk_cache) => disk_cache.load(&font, &glyphs),
+                None => glyphs.iter().map(|_| None).collect(),
+            };
+            let from_disk_cache: Vec<bool> = cached.iter().map(Option::is_some).collect();
+
+            let jobs: Vec<GlyphRasterJob> = glyphs
                 .par_iter()
-                .map(|key: &GlyphKey| {
+                .zip(cached)
+                .map(|(key, cached): (&GlyphKey, Option<RasterizedGlyph>)| {
                     profile_scope!("glyph-raster");
-                    let mut context = font_contexts.lock_current_context();
+                    let result = match cached {
+                        Some(glyph) => GlyphRasterResult::Bitmap(glyph),
+                        None => font_contexts.lock_current_context().rasterize_glyph(&font, key),
+                    };
                     let job = GlyphRasterJob {
                         key: key.clone(),
-                        result: context.rasterize_glyph(&font, key),
+                        result,
                     };
 
                     // Sanity check.
@@ -126,6 +141,20 @@ impl GlyphRasterizer {
                 })
                 .collect();
 
+            // Queue the newly rasterized glyphs to be written in one batch.
+            if let Some(ref disk_cache) = disk_cache {
+                let rasterized: Vec<_> = jobs
+                    .iter()
+                    .zip(from_disk_cache)
+                    .filter(|&(_, from_disk_cache)| !from_disk_cache)
+                    .filter_map(|(job, _)| match job.result {
+                        GlyphRasterResult::Bitmap(ref glyph) => Some((job.key.clone(), glyph)),
+                        GlyphRasterResult::LoadFailed => None,
+                    })
+                    .collect();
+                disk_cache.store(font.clone(), &rasterized);
+            }
+
             glyph_tx.send(GlyphRasterJobs { font, jobs }).unwrap();
         });
     }
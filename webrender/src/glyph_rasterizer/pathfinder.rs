@@ -168,6 +168,8 @@ impl GlyphRasterizer {
         let (x_scale, y_scale) = font.transform.compute_scale().unwrap_or((1.0, 1.0));
         let scale = font.oversized_scale_factor(x_scale, y_scale) as f32;
 
+        let mut misses = 0;
+
         // select glyphs that have not been requested yet.
         for glyph_key in glyph_keys {
             let mut cached_glyph_info = None;
@@ -185,6 +187,7 @@ impl GlyphRasterizer {
             }
 
             if cached_glyph_info.is_none() {
+                misses += 1;
                 let mut pathfinder_font_context = self.font_contexts.lock_pathfinder_context();
 
                 let pathfinder_font_instance = pathfinder_font_renderer::FontInstance {
@@ -237,6 +240,8 @@ impl GlyphRasterizer {
 
             glyph_key_cache.insert(glyph_key.clone(), handle);
         }
+
+        glyph_key_cache.count_requests(glyph_keys.len() - misses, misses);
     }
 
     pub fn resolve_glyphs(
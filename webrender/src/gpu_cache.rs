@@ -23,13 +23,25 @@
 //! use the ```get_address``` API to get the allocated
 //! address in the GPU cache of a given resource slot
 //! for this frame.
-
-use api::{PremultipliedColorF, TexelRect};
+//!
+//! The cache texture grows as more rows are needed. When
+//! enough blocks have been evicted, ```begin_frame```
+//! compacts the cache by relocating the blocks of the last
+//! rows into the free space of the first ones, so that the
+//! texture can shrink again. Handles refer to blocks rather
+//! than addresses, so they are not affected by this. The
+//! frames that were already built keep reading the moved
+//! blocks at their previous addresses though, so the vacated
+//! rows are only dropped once every document has built a
+//! frame since the compaction.
+
+use api::{DocumentId, PremultipliedColorF, TexelRect};
 use device::FrameId;
 use euclid::TypedRect;
+use internal_types::FastHashMap;
 use profiler::GpuCacheProfileCounters;
 use renderer::MAX_VERTEX_TEXTURE_WIDTH;
-use std::{mem, u16, u32};
+use std::{cmp, mem, u16, u32};
 use std::ops::Add;
 
 
@@ -228,6 +240,13 @@ pub enum GpuCacheUpdate {
         block_count: usize,
         address: GpuCacheAddress,
     },
+    // Move blocks already in the texture to a new address,
+    // as part of a compaction of the cache.
+    Move {
+        src: GpuCacheAddress,
+        dst: GpuCacheAddress,
+        block_count: usize,
+    },
 }
 
 #[must_use]
@@ -297,6 +316,35 @@ impl FreeBlockLists {
             _ => panic!("Can't allocate > MAX_VERTEX_TEXTURE_WIDTH per resource!"),
         }
     }
+
+    fn heads(&self) -> [Option<BlockIndex>; 9] {
+        [
+            self.free_list_1,
+            self.free_list_2,
+            self.free_list_4,
+            self.free_list_8,
+            self.free_list_16,
+            self.free_list_32,
+            self.free_list_64,
+            self.free_list_128,
+            self.free_list_large,
+        ]
+    }
+}
+
+// The rows vacated by a compaction, which can't be
+// dropped while older frames may still read from them.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "capture", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+struct PendingShrink {
+    // The frame during which the blocks were moved.
+    frame_id: FrameId,
+    // The number of rows before and after the compaction.
+    row_count: usize,
+    kept_row_count: usize,
+    // The height of the texture once the rows are dropped.
+    height: u32,
 }
 
 // CPU-side representation of the GPU resource cache texture.
@@ -322,8 +370,15 @@ struct Texture {
     pending_blocks: Vec<GpuBlockData>,
     // Pending update commands.
     updates: Vec<GpuCacheUpdate>,
+    // Blocks that were left out of any row by a compaction.
+    // They are reused when new rows are created, so that
+    // stale handles referring to them remain in bounds.
+    unused_blocks: Vec<BlockIndex>,
+    // The last compaction, if its vacated rows are still in use.
+    pending_shrink: Option<PendingShrink>,
     // Profile stats
     allocated_block_count: usize,
+    moved_block_count: usize,
 }
 
 impl Texture {
@@ -336,7 +391,10 @@ impl Texture {
             pending_blocks: Vec::new(),
             updates: Vec::new(),
             occupied_list_head: None,
+            unused_blocks: Vec::new(),
+            pending_shrink: None,
             allocated_block_count: 0,
+            moved_block_count: 0,
         }
     }
 
@@ -360,23 +418,23 @@ impl Texture {
             }
 
             // Create a new row.
-            let items_per_row = MAX_VERTEX_TEXTURE_WIDTH / alloc_size;
             let row_index = self.rows.len();
             self.rows.push(Row::new(alloc_size));
 
             // Create a ```Block``` for each possible allocation address
             // in this row, and link it in to the free-list for this
             // block size.
-            let mut prev_block_index = None;
-            for i in 0 .. items_per_row {
-                let address = GpuCacheAddress::new(i * alloc_size, row_index);
-                let block_index = BlockIndex(self.blocks.len());
-                let block = Block::new(address, prev_block_index, frame_id);
-                self.blocks.push(block);
-                prev_block_index = Some(block_index);
+            let row_blocks = create_row_blocks(
+                &mut self.blocks,
+                &mut self.unused_blocks,
+                row_index,
+                alloc_size,
+                frame_id,
+            );
+            for block_index in row_blocks {
+                self.blocks[block_index.0].next = *free_list;
+                *free_list = Some(block_index);
             }
-
-            *free_list = prev_block_index;
         }
 
         // Given the code above, it's now guaranteed that there is a block
@@ -466,6 +524,249 @@ impl Texture {
             current_block = next_block;
         }
     }
+
+    // Move the blocks of the last rows into the free space of
+    // the first ones, if that allows the texture to shrink. This
+    // only changes the addresses of the blocks, so the handles
+    // remain valid, and the data is copied on the GPU side with
+    // ```GpuCacheUpdate::Move``` commands. The vacated rows are
+    // left untouched until ```shrink``` is called.
+    fn compact(&mut self, frame_id: FrameId) {
+        self.moved_block_count = 0;
+
+        // Early out if the previous compaction isn't done, or if the
+        // allocated blocks could not fit in a smaller texture, however
+        // they are packed.
+        if self.pending_shrink.is_some() ||
+            self.height <= GPU_CACHE_INITIAL_HEIGHT ||
+            self.allocated_block_count >
+                (self.height - NEW_ROWS_PER_RESIZE) as usize * MAX_VERTEX_TEXTURE_WIDTH {
+            return;
+        }
+
+        let mut live_counts = vec![0usize; self.rows.len()];
+        let mut current_block = self.occupied_list_head;
+        while let Some(index) = current_block {
+            let block = &self.blocks[index.0];
+            live_counts[block.address.v as usize] += 1;
+            current_block = block.next;
+        }
+
+        // Find the smallest number of rows that can hold all the live blocks,
+        // given that the blocks of these rows stay in place, and that the
+        // empty ones can be reused for any block size.
+        let size_class_count = size_class(MAX_VERTEX_TEXTURE_WIDTH) + 1;
+        let mut outside_live_blocks = vec![0usize; size_class_count];
+        let mut inside_free_blocks = vec![0usize; size_class_count];
+        let mut inside_empty_rows = 0;
+        for (row, &live_count) in self.rows.iter().zip(&live_counts) {
+            outside_live_blocks[size_class(row.block_count_per_item)] += live_count;
+        }
+
+        let mut required_row_count = 0;
+        loop {
+            let missing_row_count: usize = (0 .. size_class_count)
+                .map(|class| {
+                    let items_per_row = MAX_VERTEX_TEXTURE_WIDTH >> class;
+                    let missing_blocks = outside_live_blocks[class]
+                        .saturating_sub(inside_free_blocks[class]);
+                    (missing_blocks + items_per_row - 1) / items_per_row
+                })
+                .sum();
+            if missing_row_count <= inside_empty_rows {
+                break;
+            }
+
+            let row = &self.rows[required_row_count];
+            let live_count = live_counts[required_row_count];
+            if live_count == 0 {
+                inside_empty_rows += 1;
+            } else {
+                let class = size_class(row.block_count_per_item);
+                outside_live_blocks[class] -= live_count;
+                inside_free_blocks[class] +=
+                    MAX_VERTEX_TEXTURE_WIDTH / row.block_count_per_item - live_count;
+            }
+            required_row_count += 1;
+        }
+
+        let extra_rows = (required_row_count as u32).saturating_sub(GPU_CACHE_INITIAL_HEIGHT);
+        let new_height = GPU_CACHE_INITIAL_HEIGHT +
+            (extra_rows + NEW_ROWS_PER_RESIZE - 1) / NEW_ROWS_PER_RESIZE * NEW_ROWS_PER_RESIZE;
+        if new_height >= self.height {
+            return;
+        }
+
+        // Keep as many rows as the new height allows, to move fewer blocks.
+        let kept_row_count = cmp::min(self.rows.len(), new_height as usize);
+
+        // Sort the blocks between the ones to move, the free ones
+        // of the kept rows, and the ones that are not needed anymore.
+        let mut blocks_to_move = vec![Vec::new(); size_class_count];
+        let mut current_block = self.occupied_list_head;
+        while let Some(index) = current_block {
+            let block = &self.blocks[index.0];
+            let row_index = block.address.v as usize;
+            if row_index >= kept_row_count {
+                let class = size_class(self.rows[row_index].block_count_per_item);
+                blocks_to_move[class].push(index);
+            }
+            current_block = block.next;
+        }
+
+        let mut free_blocks = vec![Vec::new(); size_class_count];
+        let mut empty_row_blocks = vec![Vec::new(); kept_row_count];
+        let free_lists = mem::replace(&mut self.free_lists, FreeBlockLists::new());
+        for &free_list_head in free_lists.heads().iter() {
+            let mut current_block = free_list_head;
+            while let Some(index) = current_block {
+                let block = &self.blocks[index.0];
+                let row_index = block.address.v as usize;
+                if row_index >= kept_row_count {
+                    self.unused_blocks.push(index);
+                } else if live_counts[row_index] == 0 {
+                    empty_row_blocks[row_index].push(index);
+                } else {
+                    let class = size_class(self.rows[row_index].block_count_per_item);
+                    free_blocks[class].push(index);
+                }
+                current_block = block.next;
+            }
+        }
+
+        // Move the blocks, using the empty rows once there is no
+        // room left in the rows of their size.
+        let mut empty_rows = (0 .. kept_row_count).filter(|&row_index| live_counts[row_index] == 0);
+        for (class, blocks) in blocks_to_move.into_iter().enumerate() {
+            let block_count_per_item = 1 << class;
+            for index in blocks {
+                if free_blocks[class].is_empty() {
+                    let row_index = empty_rows
+                        .next()
+                        .expect("Not enough room left to compact the GPU cache!");
+                    self.unused_blocks.extend(empty_row_blocks[row_index].drain(..));
+                    self.rows[row_index].block_count_per_item = block_count_per_item;
+                    free_blocks[class] = create_row_blocks(
+                        &mut self.blocks,
+                        &mut self.unused_blocks,
+                        row_index,
+                        block_count_per_item,
+                        frame_id,
+                    );
+                }
+
+                let target = free_blocks[class].pop().unwrap();
+                let dst = self.blocks[target.0].address;
+                let src = mem::replace(&mut self.blocks[index.0].address, dst);
+                self.unused_blocks.push(target);
+                self.updates.push(GpuCacheUpdate::Move {
+                    src,
+                    dst,
+                    block_count: block_count_per_item,
+                });
+                self.moved_block_count += block_count_per_item;
+            }
+        }
+
+        // Link the remaining blocks of the kept rows into the free lists.
+        let remaining_blocks = free_blocks
+            .into_iter()
+            .chain(empty_row_blocks.into_iter())
+            .flat_map(|blocks| blocks.into_iter());
+        for index in remaining_blocks {
+            let row_index = self.blocks[index.0].address.v as usize;
+            let (_, free_list) = self.free_lists
+                .get_actual_block_count_and_free_list(self.rows[row_index].block_count_per_item);
+            self.blocks[index.0].next = *free_list;
+            *free_list = Some(index);
+        }
+
+        self.pending_shrink = Some(PendingShrink {
+            frame_id,
+            row_count: self.rows.len(),
+            kept_row_count,
+            height: new_height,
+        });
+    }
+
+    // Drop the rows vacated by the last compaction, once no frame
+    // reads from them anymore. If new rows were added in the meantime,
+    // the vacated rows are reused instead.
+    fn shrink(&mut self, frame_id: FrameId) {
+        let shrink = match self.pending_shrink.take() {
+            Some(shrink) => shrink,
+            None => return,
+        };
+
+        if self.rows.len() == shrink.row_count {
+            self.rows.truncate(shrink.kept_row_count);
+            self.height = shrink.height;
+            return;
+        }
+
+        for row_index in shrink.kept_row_count .. shrink.row_count {
+            self.rows[row_index] = Row::new(1);
+            let row_blocks = create_row_blocks(
+                &mut self.blocks,
+                &mut self.unused_blocks,
+                row_index,
+                1,
+                frame_id,
+            );
+            let (_, free_list) = self.free_lists.get_actual_block_count_and_free_list(1);
+            for block_index in row_blocks {
+                self.blocks[block_index.0].next = *free_list;
+                *free_list = Some(block_index);
+            }
+        }
+    }
+
+    // The percentage of free blocks in the allocated rows.
+    fn fragmentation(&self) -> usize {
+        let total_block_count = self.rows.len() * MAX_VERTEX_TEXTURE_WIDTH;
+        if total_block_count == 0 {
+            return 0;
+        }
+        100 * (total_block_count - self.allocated_block_count) / total_block_count
+    }
+}
+
+// Returns the index of the size class of a block, knowing
+// that block sizes are powers of two.
+fn size_class(block_count_per_item: usize) -> usize {
+    debug_assert!(block_count_per_item.is_power_of_two());
+    block_count_per_item.trailing_zeros() as usize
+}
+
+// Create a block for each possible allocation address in a row,
+// taking unused blocks first. The reused blocks keep their epoch,
+// so that the handles that referred to them remain invalid.
+fn create_row_blocks(
+    blocks: &mut Vec<Block>,
+    unused_blocks: &mut Vec<BlockIndex>,
+    row_index: usize,
+    block_count_per_item: usize,
+    frame_id: FrameId,
+) -> Vec<BlockIndex> {
+    let items_per_row = MAX_VERTEX_TEXTURE_WIDTH / block_count_per_item;
+    (0 .. items_per_row)
+        .map(|i| {
+            let address = GpuCacheAddress::new(i * block_count_per_item, row_index);
+            match unused_blocks.pop() {
+                Some(index) => {
+                    let block = &mut blocks[index.0];
+                    block.address = address;
+                    block.next = None;
+                    block.last_access_time = frame_id;
+                    index
+                }
+                None => {
+                    blocks.push(Block::new(address, None, frame_id));
+                    BlockIndex(blocks.len() - 1)
+                }
+            }
+        })
+        .collect()
 }
 
 
@@ -516,6 +817,8 @@ impl<'a> Drop for GpuDataRequest<'a> {
 pub struct GpuCache {
     /// Current frame ID.
     frame_id: FrameId,
+    /// The frame ID of the last frame built for each document.
+    document_frames: FastHashMap<DocumentId, FrameId>,
     /// CPU-side texture allocator.
     texture: Texture,
     /// Number of blocks requested this frame that don't
@@ -527,19 +830,38 @@ impl GpuCache {
     pub fn new() -> Self {
         GpuCache {
             frame_id: FrameId::new(0),
+            document_frames: FastHashMap::default(),
             texture: Texture::new(),
             saved_block_count: 0,
         }
     }
 
-    /// Begin a new frame.
-    pub fn begin_frame(&mut self) {
+    /// Begin a new frame of a document.
+    pub fn begin_frame(&mut self, document_id: DocumentId) {
         debug_assert!(self.texture.pending_blocks.is_empty());
         self.frame_id = self.frame_id + 1;
+
+        // The previous frame of this document is still drawn until
+        // the new one is published, so it counts as well.
+        let document_frames = &self.document_frames;
+        let can_shrink = self.texture.pending_shrink.map_or(false, |shrink| {
+            document_frames.values().all(|&frame_id| frame_id >= shrink.frame_id)
+        });
+        if can_shrink {
+            self.texture.shrink(self.frame_id);
+        }
+        self.document_frames.insert(document_id, self.frame_id);
+
         self.texture.evict_old_blocks(self.frame_id);
+        self.texture.compact(self.frame_id);
         self.saved_block_count = 0;
     }
 
+    /// Forget about a document whose frames are not drawn anymore.
+    pub fn remove_document(&mut self, document_id: DocumentId) {
+        self.document_frames.remove(&document_id);
+    }
+
     // Invalidate a (possibly) existing block in the cache.
     // This means the next call to request() for this location
     // will rebuild the data and upload it to the GPU.
@@ -560,7 +882,10 @@ impl GpuCache {
         // Check if the allocation for this handle is still valid.
         if let Some(ref location) = handle.location {
             let block = &mut self.texture.blocks[location.block_index.0];
-            max_block_count = self.texture.rows[block.address.v as usize].block_count_per_item;
+            // The row of a stale block may have been removed by a compaction.
+            if let Some(row) = self.texture.rows.get(block.address.v as usize) {
+                max_block_count = row.block_count_per_item;
+            }
             if block.epoch == location.epoch {
                 if block.last_access_time != self.frame_id {
                     // Mark last access time to avoid evicting this block.
@@ -621,6 +946,12 @@ impl GpuCache {
         profile_counters
             .saved_blocks
             .set(self.saved_block_count);
+        profile_counters
+            .moved_blocks
+            .set(self.texture.moved_block_count);
+        profile_counters
+            .fragmentation
+            .set(self.texture.fragmentation());
         self.frame_id
     }
 
@@ -646,3 +977,114 @@ impl GpuCache {
         block.address
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use api::IdNamespace;
+    use std::collections::HashSet;
+
+    fn count_moves(updates: &GpuCacheUpdateList) -> usize {
+        updates.updates
+            .iter()
+            .filter(|update| match **update {
+                GpuCacheUpdate::Move { .. } => true,
+                GpuCacheUpdate::Copy { .. } => false,
+            })
+            .count()
+    }
+
+    fn build_frame(
+        cache: &mut GpuCache,
+        document_id: DocumentId,
+        handles: &mut [GpuCacheHandle],
+    ) -> GpuCacheUpdateList {
+        cache.begin_frame(document_id);
+        for handle in handles.iter_mut() {
+            if let Some(mut request) = cache.request(handle) {
+                request.push([1.0f32, 2.0, 3.0, 4.0]);
+            }
+        }
+        cache.end_frame(&mut GpuCacheProfileCounters::new());
+        cache.extract_updates()
+    }
+
+    #[test]
+    fn compaction() {
+        let document_id = DocumentId(IdNamespace(0), 0);
+        let mut cache = GpuCache::new();
+        let mut handles = vec![GpuCacheHandle::new(); 700 * MAX_VERTEX_TEXTURE_WIDTH];
+        let updates = build_frame(&mut cache, document_id, &mut handles);
+        assert_eq!(updates.height, GPU_CACHE_INITIAL_HEIGHT + NEW_ROWS_PER_RESIZE);
+
+        // Keep one block out of 64 alive, and let the others be evicted.
+        let mut handles: Vec<_> = handles.into_iter().step_by(64).collect();
+        let mut move_count = 0;
+        let mut height = updates.height;
+        for _ in 0 .. FRAMES_BEFORE_EVICTION + 2 {
+            let updates = build_frame(&mut cache, document_id, &mut handles);
+            // The blocks kept alive are never uploaded again.
+            assert!(updates.blocks.is_empty());
+            move_count += count_moves(&updates);
+            height = updates.height;
+        }
+
+        assert_eq!(height, GPU_CACHE_INITIAL_HEIGHT);
+        assert_eq!(cache.texture.rows.len(), GPU_CACHE_INITIAL_HEIGHT as usize);
+        assert_eq!(move_count, handles.len() - GPU_CACHE_INITIAL_HEIGHT as usize * 16);
+
+        let mut addresses = HashSet::new();
+        for handle in &handles {
+            let address = cache.get_address(handle);
+            assert!((address.v as u32) < height);
+            assert!(addresses.insert((address.u, address.v)));
+        }
+    }
+    #[test]
+    fn compaction_waits_for_the_frames_of_all_documents() {
+        let document_a = DocumentId(IdNamespace(0), 0);
+        let document_b = DocumentId(IdNamespace(0), 1);
+        let mut cache = GpuCache::new();
+        let mut handles = vec![GpuCacheHandle::new(); 700 * MAX_VERTEX_TEXTURE_WIDTH];
+        let updates = build_frame(&mut cache, document_a, &mut handles);
+        let initial_height = updates.height;
+
+        // The frame of document B is built before the compaction,
+        // so it's still drawn with the blocks at their old addresses.
+        build_frame(&mut cache, document_b, &mut []);
+
+        let mut handles: Vec<_> = handles.into_iter().step_by(64).collect();
+        let mut move_count = 0;
+        for _ in 0 .. FRAMES_BEFORE_EVICTION + 2 {
+            let updates = build_frame(&mut cache, document_a, &mut handles);
+            move_count += count_moves(&updates);
+            assert_eq!(updates.height, initial_height);
+        }
+
+        // The blocks were moved for the frames of document A.
+        assert!(move_count > 0);
+        for handle in &handles {
+            assert!((cache.get_address(handle).v as u32) < GPU_CACHE_INITIAL_HEIGHT);
+        }
+
+        // The previous frame of document B is drawn until the new one replaces it.
+        let updates = build_frame(&mut cache, document_b, &mut []);
+        assert_eq!(updates.height, initial_height);
+
+        let updates = build_frame(&mut cache, document_a, &mut handles);
+        assert_eq!(updates.height, GPU_CACHE_INITIAL_HEIGHT);
+        assert_eq!(cache.texture.rows.len(), GPU_CACHE_INITIAL_HEIGHT as usize);
+
+        // Nothing can be moved until the rows of a compaction are dropped, but
+        // the documents that are not drawn anymore don't hold them back.
+        cache.texture.pending_shrink = Some(PendingShrink {
+            frame_id: cache.frame_id,
+            row_count: cache.texture.rows.len(),
+            kept_row_count: cache.texture.rows.len(),
+            height: GPU_CACHE_INITIAL_HEIGHT,
+        });
+        cache.remove_document(document_b);
+        build_frame(&mut cache, document_a, &mut handles);
+        assert!(cache.texture.pending_shrink.is_none());
+    }
+}
@@ -76,6 +76,38 @@ pub struct BlurInstance {
     pub blur_direction: BlurDirection,
 }
 
+// Note: has to match `SVG_FILTER_*` in cs_svg_filter.glsl.
+#[repr(u16)]
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "capture", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub enum SvgFilterKind {
+    Identity = 0,
+    Flood = 1,
+    Offset = 2,
+    ComponentTransfer = 3,
+    Composite = 4,
+}
+
+// Draws one SVG filter primitive into its render task. A merge
+// is drawn as an identity instance per input, blended in order.
+#[derive(Debug)]
+#[repr(C)]
+#[cfg_attr(feature = "capture", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub struct SvgFilterInstance {
+    pub task_address: RenderTaskAddress,
+    pub input_1_task_address: RenderTaskAddress,
+    pub input_2_task_address: RenderTaskAddress,
+    pub kind: SvgFilterKind,
+    // Bit 0 (1) is set if only the alpha of the first (second) input is read.
+    pub input_flags: u16,
+    // The composite operator, for composite primitives.
+    pub generic_int: u16,
+    pub padding: u16,
+    pub extra_data_address: GpuCacheAddress,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 #[repr(C)]
 #[cfg_attr(feature = "capture", derive(Serialize))]
@@ -2,15 +2,27 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use api::{BorderRadius, ClipMode, HitTestFlags, HitTestItem, HitTestResult, ItemTag, LayoutPoint};
-use api::{LayoutPrimitiveInfo, LayoutRect, PipelineId, WorldPoint};
-use clip::{ClipSource, ClipStore, rounded_rectangle_contains_point};
+use api::{BorderRadius, ClipMode, ExternalScrollId, HitTestArea, HitTestFlags, HitTestItem};
+use api::{HitTestResult, ItemTag, LayoutPoint, LayoutPrimitiveInfo, LayoutRect, PipelineId};
+use api::{WorldPoint, WorldRect, WorldSize};
+use clip::{ClipSource, ClipStore, PathClipSource, rounded_rectangle_contains_point};
 use clip_node::ClipNode;
 use clip_scroll_tree::{ClipChainIndex, ClipNodeIndex, SpatialNodeIndex, ClipScrollTree};
-use internal_types::FastHashMap;
-use prim_store::ScrollNodeAndClipChain;
+use internal_types::{FastHashMap, FastHashSet};
+use prim_store::{PictureIndex, ScrollNodeAndClipChain};
+use spatial_node::SpatialNodeType;
+use std::cmp::Ordering;
 use util::LayoutToWorldFastTransform;
 
+/// The number of columns the area of a circle is integrated over when
+/// computing the coverage of an area hit test.
+const CIRCLE_COVERAGE_COLUMNS: usize = 64;
+
+/// The number of rows and columns of points that the clips of an item are
+/// sampled at when computing its coverage of an area, if they aren't just
+/// rectangles.
+const CLIP_COVERAGE_SAMPLES: usize = 16;
+
 /// A copy of important clip scroll node data to use during hit testing. This a copy of
 /// data from the ClipScrollTree that will persist as a new frame is under construction,
 /// allowing hit tests consistent with the currently rendered frame.
@@ -23,6 +35,10 @@ pub struct HitTestSpatialNode {
 
     /// World viewport transform for content transformed by this node.
     world_viewport_transform: LayoutToWorldFastTransform,
+
+    /// The external id of the innermost scroll frame that this node is, or
+    /// is positioned within, if any.
+    scroll_id: Option<ExternalScrollId>,
 }
 
 pub struct HitTestClipNode {
@@ -73,12 +89,20 @@ impl HitTestingItem {
     }
 }
 
+/// A run of hit testing items sharing the same positioning and clipping. The last
+/// field is the container picture of the preserve-3d rendering context that the
+/// items participate in, if any, which is used to sort hits by depth.
 #[derive(Clone)]
-pub struct HitTestingRun(pub Vec<HitTestingItem>, pub ScrollNodeAndClipChain);
+pub struct HitTestingRun(
+    pub Vec<HitTestingItem>,
+    pub ScrollNodeAndClipChain,
+    pub Option<PictureIndex>,
+);
 
 enum HitTestRegion {
     Rectangle(LayoutRect, ClipMode),
     RoundedRectangle(LayoutRect, BorderRadius, ClipMode),
+    Path(PathClipSource),
 }
 
 impl HitTestRegion {
@@ -92,6 +116,8 @@ impl HitTestRegion {
                 rounded_rectangle_contains_point(point, &rect, &radii),
             HitTestRegion::RoundedRectangle(rect, radii, ClipMode::ClipOut) =>
                 !rounded_rectangle_contains_point(point, &rect, &radii),
+            HitTestRegion::Path(ref path) =>
+                path.contains(point),
         }
     }
 }
@@ -144,6 +170,7 @@ impl HitTester {
                 pipeline_id: node.pipeline_id,
                 world_content_transform: node.world_content_transform,
                 world_viewport_transform: node.world_viewport_transform,
+                scroll_id: get_enclosing_scroll_id(index, clip_scroll_tree),
             });
         }
 
@@ -232,10 +259,103 @@ impl HitTester {
         true
     }
 
+    /// Clips a world rect by the clips of a clip chain and its parents. Returns
+    /// the clipped rect, and whether it's exactly the part of the rect that is
+    /// clipped in, rather than just its bounds. It's only exact if the clips are
+    /// rectangles that are axis-aligned in world space, such as scroll frames.
+    fn clip_rect_by_clip_chain(
+        &self,
+        rect: WorldRect,
+        clip_chain_index: ClipChainIndex,
+    ) -> Option<(WorldRect, bool)> {
+        let mut rect = rect;
+        let mut is_exact = true;
+        let mut current = Some(clip_chain_index);
+        while let Some(index) = current {
+            let descriptor = &self.clip_chains[index.0];
+            for clip_node_index in &descriptor.clips {
+                let node = &self.clip_nodes[clip_node_index.0];
+                let transform = &self.spatial_nodes[node.spatial_node.0].world_viewport_transform;
+                for region in &node.regions {
+                    let local_bounds = match *region {
+                        HitTestRegion::Rectangle(bounds, ClipMode::Clip) => bounds,
+                        HitTestRegion::RoundedRectangle(bounds, _, ClipMode::Clip) => {
+                            is_exact = false;
+                            bounds
+                        }
+                        HitTestRegion::Path(ref path) => {
+                            is_exact = false;
+                            path.bounds
+                        }
+                        HitTestRegion::Rectangle(_, ClipMode::ClipOut) |
+                        HitTestRegion::RoundedRectangle(_, _, ClipMode::ClipOut) => {
+                            is_exact = false;
+                            continue;
+                        }
+                    };
+                    is_exact &= transform.preserves_2d_axis_alignment();
+                    let world_bounds = transform.transform_rect(&local_bounds)?;
+                    rect = rect.intersection(&world_bounds)?;
+                }
+            }
+            current = descriptor.parent;
+        }
+        Some((rect, is_exact))
+    }
+
+    /// Estimates the part of the area that is inside a rect and clipped in by
+    /// a clip chain, by testing the clips at the centers of a grid of cells
+    /// over the rect. Also returns the center of a cell that is clipped in and
+    /// inside the area, closest to `target`, if there is one.
+    fn sample_clipped_coverage(
+        &self,
+        area: &HitTestArea,
+        rect: &WorldRect,
+        target: WorldPoint,
+        clip_chain_index: ClipChainIndex,
+        pipeline_id: Option<PipelineId>,
+        flags: HitTestFlags,
+    ) -> (f32, Option<WorldPoint>) {
+        let cell_size = WorldSize::new(
+            rect.size.width / CLIP_COVERAGE_SAMPLES as f32,
+            rect.size.height / CLIP_COVERAGE_SAMPLES as f32,
+        );
+        let mut covered = 0.0;
+        let mut closest: Option<(WorldPoint, f32)> = None;
+        for row in 0 .. CLIP_COVERAGE_SAMPLES {
+            for column in 0 .. CLIP_COVERAGE_SAMPLES {
+                let cell = WorldRect::new(
+                    WorldPoint::new(
+                        rect.origin.x + column as f32 * cell_size.width,
+                        rect.origin.y + row as f32 * cell_size.height,
+                    ),
+                    cell_size,
+                );
+                let point = WorldPoint::new(
+                    cell.origin.x + 0.5 * cell_size.width,
+                    cell.origin.y + 0.5 * cell_size.height,
+                );
+                let mut test = HitTest::new(pipeline_id, point, flags);
+                if !self.is_point_clipped_in_for_clip_chain(point, clip_chain_index, &mut test) {
+                    continue;
+                }
+
+                covered += area_coverage(area, &cell);
+                if area.contains(&point) {
+                    let distance = (point - target).square_length();
+                    if closest.map_or(true, |(_, closest_distance)| distance < closest_distance) {
+                        closest = Some((point, distance));
+                    }
+                }
+            }
+        }
+        (covered, closest.map(|(point, _)| point))
+    }
+
     pub fn find_node_under_point(&self, mut test: HitTest) -> Option<SpatialNodeIndex> {
         let point = test.get_absolute_point(self);
 
-        for &HitTestingRun(ref items, ref clip_and_scroll) in self.runs.iter().rev() {
+        for &HitTestingRun(ref items, ref clip_and_scroll, _) in self.runs.iter().rev() {
             let spatial_node_index = clip_and_scroll.spatial_node_index;
             let scroll_node = &self.spatial_nodes[spatial_node_index.0];
             let transform = scroll_node.world_content_transform;
@@ -271,8 +391,13 @@ impl HitTester {
     pub fn hit_test(&self, mut test: HitTest) -> HitTestResult {
         let point = test.get_absolute_point(self);
 
+        // When sorting by depth, every hit is needed before the front-most one is known.
+        let sort_by_depth = test.flags.contains(HitTestFlags::SORT_BY_DEPTH);
+        let find_all = test.flags.contains(HitTestFlags::FIND_ALL) || sort_by_depth;
+
         let mut result = HitTestResult::default();
-        for &HitTestingRun(ref items, ref clip_and_scroll) in self.runs.iter().rev() {
+        let mut depths = Vec::new();
+        for &HitTestingRun(ref items, ref clip_and_scroll, context_3d) in self.runs.iter().rev() {
             let spatial_node_index = clip_and_scroll.spatial_node_index;
             let scroll_node = &self.spatial_nodes[spatial_node_index.0];
             let pipeline_id = scroll_node.pipeline_id;
@@ -330,10 +455,25 @@ impl HitTester {
                     tag: item.tag,
                     point_in_viewport,
                     point_relative_to_item: point_in_layer - item.rect.origin.to_vector(),
+                    scroll_node: scroll_node.scroll_id,
+                    coverage: 1.0,
                 });
-                if !test.flags.contains(HitTestFlags::FIND_ALL) {
+                if !find_all {
                     return result;
                 }
+
+                if sort_by_depth {
+                    let position = transform.transform_point2d_homogeneous(&point_in_layer);
+                    let depth = if position.w != 0.0 { position.z / position.w } else { 0.0 };
+                    depths.push((context_3d, depth));
+                }
+            }
+        }
+
+        if sort_by_depth {
+            sort_hits_by_depth(&mut result.items, &depths);
+            if !test.flags.contains(HitTestFlags::FIND_ALL) {
+                result.items.truncate(1);
             }
         }
 
@@ -341,11 +481,268 @@ impl HitTester {
         result
     }
 
+    /// Hit test all items against an area in a single pass over the items. The coverage
+    /// of each item is the fraction of the area covered by its rect, clipped by its clip
+    /// chain. Clips other than axis-aligned rectangles are sampled on a grid. The point
+    /// reported for a hit is the point of the clipped item closest to the center of the
+    /// area, where backface visibility is also checked.
+    pub fn hit_test_area(
+        &self,
+        pipeline_id: Option<PipelineId>,
+        area: HitTestArea,
+        flags: HitTestFlags,
+    ) -> HitTestResult {
+        let area = match self.get_absolute_area(pipeline_id, area, flags) {
+            Some(area) => area,
+            None => return HitTestResult::default(),
+        };
+        let bounds = area.bounding_rect();
+        let area_size = area_coverage(&area, &bounds);
+        if area_size <= 0.0 {
+            return HitTestResult::default();
+        }
+
+        let center = WorldPoint::new(
+            bounds.origin.x + 0.5 * bounds.size.width,
+            bounds.origin.y + 0.5 * bounds.size.height,
+        );
+
+        let mut result = HitTestResult::default();
+        let mut seen = FastHashSet::default();
+        for &HitTestingRun(ref items, ref clip_and_scroll, _) in self.runs.iter().rev() {
+            let scroll_node = &self.spatial_nodes[clip_and_scroll.spatial_node_index.0];
+            let pipeline = scroll_node.pipeline_id;
+            if pipeline_id.map_or(false, |id| id != pipeline) {
+                continue;
+            }
+
+            let transform = scroll_node.world_content_transform;
+            let inverse = match transform.inverse() {
+                Some(inverse) => inverse,
+                None => continue,
+            };
+            let mut facing_backwards: Option<bool> = None;  // will be computed on first use
+
+            for item in items.iter().rev() {
+                if seen.contains(&(pipeline, item.tag)) {
+                    continue;
+                }
+
+                let clip_chain_index = clip_and_scroll.clip_chain_index;
+                let (world_rect, is_exact) = match item.rect
+                    .intersection(&item.clip_rect)
+                    .and_then(|rect| transform.transform_rect(&rect))
+                    .and_then(|rect| rect.intersection(&bounds))
+                    .and_then(|rect| self.clip_rect_by_clip_chain(rect, clip_chain_index))
+                {
+                    Some(clipped) => clipped,
+                    None => continue,
+                };
+
+                // The point of the visible rect closest to the center of the area is within
+                // the area whenever the two intersect.
+                let closest_point = WorldPoint::new(
+                    center.x.max(world_rect.min_x()).min(world_rect.max_x()),
+                    center.y.max(world_rect.min_y()).min(world_rect.max_y()),
+                );
+                let (covered, point) = if is_exact {
+                    if !area.contains(&closest_point) {
+                        continue;
+                    }
+                    (area_coverage(&area, &world_rect), closest_point)
+                } else {
+                    let (covered, sampled_point) = self.sample_clipped_coverage(
+                        &area,
+                        &world_rect,
+                        center,
+                        clip_chain_index,
+                        pipeline_id,
+                        flags,
+                    );
+                    // Prefer the closest point of the rect when the clips don't cut it off.
+                    let mut test = HitTest::new(pipeline_id, closest_point, flags);
+                    let point = if area.contains(&closest_point) &&
+                        self.is_point_clipped_in_for_clip_chain(
+                            closest_point,
+                            clip_chain_index,
+                            &mut test,
+                        ) {
+                        closest_point
+                    } else {
+                        match sampled_point {
+                            Some(point) => point,
+                            None => continue,
+                        }
+                    };
+                    (covered, point)
+                };
+
+                // Don't hit items with backface-visibility:hidden if they are facing the back.
+                if !item.is_backface_visible {
+                    if *facing_backwards.get_or_insert_with(|| transform.is_backface_visible()) {
+                        continue;
+                    }
+                }
+
+                let point_in_layer = match inverse.transform_point2d(&point) {
+                    Some(point) => point,
+                    None => continue,
+                };
+                let root_node = &self.spatial_nodes[self.pipeline_root_nodes[&pipeline].0];
+                let point_in_viewport = match root_node.world_viewport_transform
+                    .inverse()
+                    .and_then(|inverted| inverted.transform_point2d(&point))
+                {
+                    Some(point) => point,
+                    None => continue,
+                };
+
+                seen.insert((pipeline, item.tag));
+                result.items.push(HitTestItem {
+                    pipeline,
+                    tag: item.tag,
+                    point_in_viewport,
+                    point_relative_to_item: point_in_layer - item.rect.origin.to_vector(),
+                    scroll_node: scroll_node.scroll_id,
+                    coverage: (covered / area_size).min(1.0),
+                });
+            }
+        }
+
+        // A stable sort keeps paint order, front to back, for equal coverage.
+        result.items.sort_by(|a, b| {
+            b.coverage.partial_cmp(&a.coverage).unwrap_or(Ordering::Equal)
+        });
+        if !flags.contains(HitTestFlags::FIND_ALL) {
+            result.items.truncate(1);
+        }
+
+        result
+    }
+
+    fn get_absolute_area(
+        &self,
+        pipeline_id: Option<PipelineId>,
+        area: HitTestArea,
+        flags: HitTestFlags,
+    ) -> Option<HitTestArea> {
+        if !flags.contains(HitTestFlags::POINT_RELATIVE_TO_PIPELINE_VIEWPORT) {
+            return Some(area);
+        }
+
+        let transform = match pipeline_id {
+            Some(id) => self.get_pipeline_root(id).world_viewport_transform,
+            None => return Some(area),
+        };
+
+        match area {
+            HitTestArea::Rect(rect) => {
+                transform
+                    .transform_rect(&LayoutRect::from_untyped(&rect.to_untyped()))
+                    .map(HitTestArea::Rect)
+            }
+            HitTestArea::Circle(center, radius) => {
+                let transformed_center =
+                    transform.transform_point2d(&LayoutPoint::new(center.x, center.y))?;
+                let x_axis =
+                    transform.transform_point2d(&LayoutPoint::new(center.x + radius, center.y))?;
+                let y_axis =
+                    transform.transform_point2d(&LayoutPoint::new(center.x, center.y + radius))?;
+                // Non-uniform scales turn the circle into an ellipse, which is approximated
+                // by a circle with the mean of its radii.
+                let radius = 0.5 * ((x_axis - transformed_center).length() +
+                    (y_axis - transformed_center).length());
+                Some(HitTestArea::Circle(transformed_center, radius))
+            }
+        }
+    }
+
     pub fn get_pipeline_root(&self, pipeline_id: PipelineId) -> &HitTestSpatialNode {
         &self.spatial_nodes[self.pipeline_root_nodes[&pipeline_id].0]
     }
 }
 
+fn get_enclosing_scroll_id(
+    index: SpatialNodeIndex,
+    clip_scroll_tree: &ClipScrollTree,
+) -> Option<ExternalScrollId> {
+    let mut current = Some(index);
+    while let Some(index) = current {
+        let node = &clip_scroll_tree.spatial_nodes[index.0];
+        if let SpatialNodeType::ScrollFrame(ref info) = node.node_type {
+            if info.external_id.is_some() {
+                return info.external_id;
+            }
+        }
+        current = node.parent;
+    }
+    None
+}
+
+/// Returns the part of the area that is inside the rect. For circles, the area is
+/// integrated numerically over a fixed number of columns, so the result is only
+/// meaningful relative to other results for the same circle.
+fn area_coverage(area: &HitTestArea, rect: &WorldRect) -> f32 {
+    match *area {
+        HitTestArea::Rect(ref area_rect) => {
+            area_rect.intersection(rect).map_or(0.0, |r| r.size.width * r.size.height)
+        }
+        HitTestArea::Circle(center, radius) => {
+            let column_width = 2.0 * radius / CIRCLE_COVERAGE_COLUMNS as f32;
+            let mut covered = 0.0;
+            for i in 0 .. CIRCLE_COVERAGE_COLUMNS {
+                let x0 = center.x - radius + i as f32 * column_width;
+                let width = (x0 + column_width).min(rect.max_x()) - x0.max(rect.min_x());
+                if width <= 0.0 {
+                    continue;
+                }
+                let dx = x0 + 0.5 * column_width - center.x;
+                let half_height = (radius * radius - dx * dx).max(0.0).sqrt();
+                let height = (center.y + half_height).min(rect.max_y()) -
+                    (center.y - half_height).max(rect.min_y());
+                if height > 0.0 {
+                    covered += width * height;
+                }
+            }
+            covered
+        }
+    }
+}
+
+/// Reorder runs of hits that belong to the same preserve-3d rendering context so
+/// that the hits closest to the viewer come first. Hits outside of any 3d context
+/// keep their paint order.
+fn sort_hits_by_depth(
+    items: &mut Vec<HitTestItem>,
+    depths: &[(Option<PictureIndex>, f32)],
+) {
+    debug_assert_eq!(items.len(), depths.len());
+
+    let mut start = 0;
+    while start < items.len() {
+        let context_3d = depths[start].0;
+        let mut end = start + 1;
+        while end < items.len() && depths[end].0 == context_3d {
+            end += 1;
+        }
+
+        if context_3d.is_some() && end - start > 1 {
+            let mut group: Vec<(HitTestItem, f32)> = items[start .. end]
+                .iter()
+                .cloned()
+                .zip(depths[start .. end].iter().map(|&(_, depth)| depth))
+                .collect();
+            // A stable sort keeps paint order for coplanar items.
+            group.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+            for (slot, (item, _)) in items[start .. end].iter_mut().zip(group.into_iter()) {
+                *slot = item;
+            }
+        }
+
+        start = end;
+    }
+}
+
 fn get_regions_for_clip_node(
     node: &ClipNode,
     clip_store: &ClipStore
@@ -364,7 +761,8 @@ fn get_regions_for_clip_node(
             ClipSource::Rectangle(ref rect, mode) => HitTestRegion::Rectangle(*rect, mode),
             ClipSource::RoundedRectangle(ref rect, ref radii, ref mode) =>
                 HitTestRegion::RoundedRectangle(*rect, *radii, *mode),
-            ClipSource::Image(ref mask) => HitTestRegion::Rectangle(mask.rect, ClipMode::Clip),
+            ClipSource::Image(ref mask, _) => HitTestRegion::Rectangle(mask.rect, ClipMode::Clip),
+            ClipSource::Path(ref path) => HitTestRegion::Path(path.clone()),
             ClipSource::LineDecoration(_) |
             ClipSource::BoxShadow(_) => {
                 unreachable!("Didn't expect to hit test against BorderCorner / BoxShadow / LineDecoration");
@@ -3,7 +3,7 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use api::{DebugCommand, DeviceUintRect, DocumentId, ExternalImageData, ExternalImageId};
-use api::ImageFormat;
+use api::{DocumentPriority, ImageFormat};
 use device::TextureFilter;
 use renderer::PipelineInfo;
 use gpu_cache::GpuCacheUpdateList;
@@ -60,6 +60,10 @@ impl SavedTargetIndex {
 pub enum SourceTexture {
     Invalid,
     TextureCache(CacheTextureId),
+    // A standalone texture cache entry in a compressed format. Those are
+    // stored in 2D textures, since compressed data can't be uploaded to
+    // texture arrays, and can't be rendered to nor read back.
+    CompressedTextureCache(CacheTextureId),
     External(ExternalImageData),
     CacheA8,
     CacheRGBA8,
@@ -133,6 +137,7 @@ impl TextureUpdateList {
 pub struct RenderedDocument {
     pub frame: tiling::Frame,
     pub is_new_scene: bool,
+    pub priority: DocumentPriority,
 }
 
 pub enum DebugOutput {
@@ -160,6 +165,7 @@ pub enum ResultMsg {
         TextureUpdateList,
         BackendProfileCounters,
     ),
+    HideDocument(DocumentId),
 }
 
 #[derive(Clone, Debug)]
@@ -62,6 +62,7 @@ mod capture;
 mod clip;
 mod clip_node;
 mod clip_scroll_tree;
+mod compressed_texture;
 mod debug_colors;
 #[cfg(feature = "debug_renderer")]
 mod debug_font_data;
@@ -183,6 +184,7 @@ pub extern crate webrender_api;
 pub use device::{build_shader_strings, ReadPixelsFormat, UploadMethod, VertexUsageHint};
 pub use device::{ProgramBinary, ProgramCache, ProgramCacheObserver, ProgramSources};
 pub use frame_builder::ChasePrimitive;
+pub use glyph_rasterizer::GlyphDiskCacheOptions;
 pub use renderer::{AsyncPropertySampler, CpuProfile, DebugFlags, OutputImageHandler, RendererKind};
 pub use renderer::{ExternalImage, ExternalImageHandler, ExternalImageSource, GpuProfile};
 pub use renderer::{GraphicsApi, GraphicsApiInfo, PipelineInfo, Renderer, RendererOptions};
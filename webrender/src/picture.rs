@@ -2,9 +2,9 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use api::{DeviceRect, FilterOp, MixBlendMode, PipelineId, PremultipliedColorF};
+use api::{DeviceRect, FilterOp, FilterPrimitive, MixBlendMode, PipelineId, PremultipliedColorF};
 use api::{DeviceIntRect, DeviceIntSize, DevicePoint, LayoutPoint, LayoutRect};
-use api::{DevicePixelScale, PictureIntPoint, PictureIntRect, PictureIntSize};
+use api::{DevicePixelScale, ImageKey, PictureIntPoint, PictureIntRect, PictureIntSize};
 use box_shadow::{BLUR_SAMPLE_SCALE};
 use clip_scroll_tree::SpatialNodeIndex;
 use frame_builder::{FrameBuildingContext, FrameBuildingState, PictureState, PrimitiveRunContext};
@@ -15,6 +15,8 @@ use prim_store::{PrimitiveMetadata, ScrollNodeAndClipChain, Transform};
 use render_task::{ClearMode, RenderTask, RenderTaskCacheEntryHandle};
 use render_task::{RenderTaskCacheKey, RenderTaskCacheKeyKind, RenderTaskId, RenderTaskLocation};
 use scene::{FilterOpHelpers, SceneProperties};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::mem;
 use tiling::RenderTargetKind;
 use util::TransformedRectKind;
@@ -31,7 +33,7 @@ use util::TransformedRectKind;
 
 /// Specifies how this Picture should be composited
 /// onto the target it belongs to.
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum PictureCompositeMode {
     /// Apply CSS mix-blend-mode effect.
     MixBlend(MixBlendMode),
@@ -40,6 +42,14 @@ pub enum PictureCompositeMode {
     /// Draw to intermediate surface, copy straight across. This
     /// is used for CSS isolation, and plane splitting.
     Blit,
+    /// Draw to intermediate surface, and copy it across through
+    /// the stacking context mask. The mask image is attached to
+    /// the picture primitive as an image clip source, so that it
+    /// is applied by the clip mask when compositing.
+    Mask,
+    /// Apply an SVG-style filter graph, with one render
+    /// task per primitive.
+    SvgFilter(Vec<FilterPrimitive>),
 }
 
 // Stores the location of the picture if it is drawn to
@@ -52,15 +62,29 @@ pub enum PictureSurface {
     TextureCache(RenderTaskCacheEntryHandle),
 }
 
-// A unique identifier for a Picture. Once we start
-// doing deep compares of picture content, these
-// may be the same across display lists, but that's
-// not currently supported.
+// A unique identifier for a Picture within a scene.
+// Pictures that are compared across display lists
+// are identified by their content hash instead.
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 #[cfg_attr(feature = "capture", derive(Serialize))]
 #[cfg_attr(feature = "replay", derive(Deserialize))]
 pub struct PictureId(pub u64);
 
+// Identifies what is drawn in a cached picture.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "capture", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub enum PictureContentId {
+    // A hash of the display items the picture was built
+    // from. This is the same for identical content in any
+    // display list, so the cache survives new display lists.
+    Hash(u64),
+    // A picture that wasn't built from a stacking context
+    // (e.g. a text shadow). The scene_id is globally unique,
+    // so this is only valid until the next display list.
+    Scene(u64, PictureId),
+}
+
 // Cache key that determines whether a pre-existing
 // picture in the texture cache matches the content
 // of the current picture.
@@ -74,25 +98,18 @@ pub struct PictureCacheKey {
     //       we want the cache to remain valid as it
     //       is scrolled and/or translated by animation.
     //       This is valid while we have the restriction
-    //       in place that only pictures that use the
-    //       root coordinate system are cached - once
-    //       we relax that, we'll need to consider some
-    //       extra parameters, depending on transform.
-
-    // This is a globally unique id of the scene this picture 
-    // is associated with, to avoid picture id collisions.
-    scene_id: u64,
-
-    // The unique (for the scene_id) identifier for this picture.
-    // TODO(gw): Currently, these will not be
-    //           shared across new display lists,
-    //           so will only remain valid during
-    //           scrolling. Next step will be to
-    //           allow deep comparisons on pictures
-    //           between display lists, allowing
-    //           pictures that are the same to be
-    //           cached across display lists!
-    picture_id: PictureId,
+    //       in place that only pictures with 2D axis
+    //       aligned transforms are cached. Any scale is
+    //       part of the layout hash below.
+
+    // What is drawn in this picture.
+    content_id: PictureContentId,
+
+    // A hash of the transforms and clips of the content relative
+    // to the picture, and the scale of the picture itself. This
+    // includes clips defined outside of the stacking context, as
+    // the content of the picture is clipped by them too.
+    layout_hash: u64,
 
     // Store the rect within the unclipped device
     // rect that we are actually rendering. This ensures
@@ -158,6 +175,15 @@ pub struct PicturePrimitive {
 
     // Unique identifier for this picture.
     pub id: PictureId,
+
+    // A hash of the content of this picture, if it was built
+    // from a stacking context. Used to match cached pictures
+    // across display lists.
+    pub content_hash: Option<u64>,
+
+    // The images drawn in this picture, if it has a content
+    // hash. Their current generations are part of the cache key.
+    pub image_keys: Vec<ImageKey>,
 }
 
 impl PicturePrimitive {
@@ -199,6 +225,8 @@ impl PicturePrimitive {
             apply_local_clip_rect,
             pipeline_id,
             id,
+            content_hash: None,
+            image_keys: Vec::new(),
         }
     }
 
@@ -261,6 +289,8 @@ impl PicturePrimitive {
                 filter.is_noop()
             }
             Some(PictureCompositeMode::Blit) |
+            Some(PictureCompositeMode::Mask) |
+            Some(PictureCompositeMode::SvgFilter(..)) |
             Some(PictureCompositeMode::MixBlend(..)) => {
                 false
             }
@@ -289,6 +319,11 @@ impl PicturePrimitive {
                                 .screen_rect
                                 .as_ref()
                                 .expect("bug: trying to draw an off-screen picture!?");
+
+        // The content of this picture is also content of the parent.
+        pic_state.has_non_axis_aligned_content |= pic_state_for_children.has_non_axis_aligned_content;
+        pic_state.layout_hasher.write_u64(pic_state_for_children.layout_hasher.finish());
+
         if self.can_draw_directly_to_parent_surface() {
             pic_state.tasks.extend(pic_state_for_children.tasks);
             self.surface = None;
@@ -327,13 +362,12 @@ impl PicturePrimitive {
                     frame_context.device_pixel_scale,
                 );
 
-                // If we are drawing a blur that has primitives or clips that contain
-                // a complex coordinate system, don't bother caching them (for now).
-                // It's likely that they are animating and caching may not help here
-                // anyway. In the future we should relax this a bit, so that we can
-                // cache tasks with complex coordinate systems if we detect the
-                // relevant transforms haven't changed from frame to frame.
-                let surface = if pic_state_for_children.has_non_root_coord_system {
+                // If we are drawing a blur that has primitives or clips that aren't
+                // 2D axis-aligned, don't bother caching them (for now). It's likely
+                // that they are animating and caching may not help here anyway.
+                // Axis-aligned transforms are part of the layout hash, so a change
+                // to them (e.g. a scale or an inner scroll offset) misses the cache.
+                let surface = if pic_state_for_children.has_non_axis_aligned_content {
                     let picture_task = RenderTask::new_picture(
                         RenderTaskLocation::Dynamic(None, Some(device_rect.size)),
                         prim_index,
@@ -371,14 +405,31 @@ impl PicturePrimitive {
                         ),
                     );
 
+                    let content_id = match self.content_hash {
+                        Some(content_hash) => {
+                            // The images can be updated without a new display list.
+                            let mut hasher = DefaultHasher::new();
+                            content_hash.hash(&mut hasher);
+                            for image_key in &self.image_keys {
+                                frame_state.resource_cache
+                                    .get_image_generation(*image_key)
+                                    .hash(&mut hasher);
+                            }
+                            PictureContentId::Hash(hasher.finish())
+                        }
+                        None => PictureContentId::Scene(frame_context.scene_id, self.id),
+                    };
+                    let layout_hash = pic_state_for_children.layout_hasher.finish();
+                    let mut is_invalidated = false;
+
                     // Request a render task that will cache the output in the
                     // texture cache.
                     let cache_item = frame_state.resource_cache.request_render_task(
                         RenderTaskCacheKey {
                             size: device_rect.size,
                             kind: RenderTaskCacheKeyKind::Picture(PictureCacheKey {
-                                scene_id: frame_context.scene_id,
-                                picture_id: self.id,
+                                content_id,
+                                layout_hash,
                                 unclipped_size: prim_screen_rect.unclipped.size,
                                 pic_relative_render_rect,
                             }),
@@ -388,6 +439,7 @@ impl PicturePrimitive {
                         None,
                         false,
                         |render_tasks| {
+                            is_invalidated = true;
                             let child_tasks = mem::replace(&mut pic_state_for_children.tasks, Vec::new());
 
                             let picture_task = RenderTask::new_picture(
@@ -416,6 +468,11 @@ impl PicturePrimitive {
                         }
                     );
 
+                    frame_state.profile_counters.cached_pictures.inc();
+                    if is_invalidated {
+                        frame_state.profile_counters.invalidated_pictures.inc();
+                    }
+
                     PictureSurface::TextureCache(cache_item)
                 };
 
@@ -562,7 +619,37 @@ impl PicturePrimitive {
                 pic_state.tasks.push(render_task_id);
                 self.surface = Some(PictureSurface::RenderTask(render_task_id));
             }
-            Some(PictureCompositeMode::Blit) | None => {
+            Some(PictureCompositeMode::SvgFilter(ref primitives)) => {
+                let uv_rect_kind = calculate_uv_rect_kind(
+                    &prim_metadata.local_rect,
+                    &prim_run_context.transform,
+                    &prim_screen_rect.clipped,
+                    frame_context.device_pixel_scale,
+                );
+
+                let picture_task = RenderTask::new_picture(
+                    RenderTaskLocation::Dynamic(None, Some(prim_screen_rect.clipped.size)),
+                    prim_index,
+                    prim_screen_rect.clipped.origin,
+                    pic_state_for_children.tasks,
+                    uv_rect_kind,
+                );
+
+                let picture_task_id = frame_state.render_tasks.add(picture_task);
+
+                let render_task_id = RenderTask::new_svg_filter_graph(
+                    primitives,
+                    picture_task_id,
+                    frame_state.render_tasks,
+                    frame_context.device_pixel_scale,
+                );
+
+                pic_state.tasks.push(render_task_id);
+                self.surface = Some(PictureSurface::RenderTask(render_task_id));
+            }
+            Some(PictureCompositeMode::Blit) |
+            Some(PictureCompositeMode::Mask) |
+            None => {
                 let uv_rect_kind = calculate_uv_rect_kind(
                     &prim_metadata.local_rect,
                     &prim_run_context.transform,
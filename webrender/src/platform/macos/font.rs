@@ -3,7 +3,7 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use api::{ColorU, FontKey, FontRenderMode, GlyphDimensions};
-use api::{FontInstanceFlags, FontVariation, NativeFontHandle};
+use api::{FontInstanceFlags, FontVariation, GlyphIndex, GlyphOutline, NativeFontHandle};
 use app_units::Au;
 use core_foundation::array::{CFArray, CFArrayRef};
 use core_foundation::base::TCFType;
@@ -415,6 +415,16 @@ impl FontContext {
             })
     }
 
+    // Outlines are only extracted with FreeType so far.
+    pub fn get_glyph_outline(
+        &mut self,
+        _font: &FontInstance,
+        _key: &GlyphKey,
+        _next_glyph: Option<GlyphIndex>,
+    ) -> Option<GlyphOutline> {
+        None
+    }
+
     // Assumes the pixels here are linear values from CG
     #[cfg(not(feature = "pathfinder"))]
     fn gamma_correct_pixels(
@@ -3,8 +3,13 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use api::{ColorU, GlyphDimensions, FontKey, FontRenderMode};
+use api::{GlyphIndex, GlyphOutline, LayoutPoint, PathOp};
 use api::{FontInstancePlatformOptions, FontLCDFilter, FontHinting};
 use api::{FontInstanceFlags, NativeFontHandle};
+#[cfg(not(feature = "pathfinder"))]
+use api::DevicePoint;
+#[cfg(not(feature = "pathfinder"))]
+use app_units::Au;
 use freetype::freetype::{FT_BBox, FT_Outline_Translate, FT_Pixel_Mode, FT_Render_Mode};
 use freetype::freetype::{FT_Done_Face, FT_Error, FT_Get_Char_Index, FT_Int32};
 use freetype::freetype::{FT_Done_FreeType, FT_Library_SetLcdFilter, FT_Pos};
@@ -12,13 +17,16 @@ use freetype::freetype::{FT_F26Dot6, FT_Face, FT_Glyph_Format, FT_Long, FT_UInt}
 use freetype::freetype::{FT_GlyphSlot, FT_LcdFilter, FT_New_Face, FT_New_Memory_Face};
 use freetype::freetype::{FT_Init_FreeType, FT_Load_Glyph, FT_Render_Glyph};
 use freetype::freetype::{FT_Library, FT_Outline_Get_CBox, FT_Set_Char_Size, FT_Select_Size};
-use freetype::freetype::{FT_Fixed, FT_Matrix, FT_Set_Transform};
+use freetype::freetype::{FT_Byte, FT_Fixed, FT_Matrix, FT_Set_Transform, FT_ULong};
+use freetype::freetype::{FT_Outline, FT_Vector};
 use freetype::freetype::{FT_LOAD_COLOR, FT_LOAD_DEFAULT, FT_LOAD_FORCE_AUTOHINT};
 use freetype::freetype::{FT_LOAD_IGNORE_GLOBAL_ADVANCE_WIDTH, FT_LOAD_NO_AUTOHINT};
 use freetype::freetype::{FT_LOAD_NO_BITMAP, FT_LOAD_NO_HINTING, FT_LOAD_VERTICAL_LAYOUT};
 use freetype::freetype::{FT_FACE_FLAG_SCALABLE, FT_FACE_FLAG_FIXED_SIZES};
 use freetype::succeeded;
 use glyph_rasterizer::{FontInstance, GlyphFormat, GlyphKey, GlyphRasterResult, RasterizedGlyph};
+#[cfg(not(feature = "pathfinder"))]
+use glyph_rasterizer::{FontTransform, SubpixelDirection};
 #[cfg(feature = "pathfinder")]
 use glyph_rasterizer::NativeFontHandleWrapper;
 use internal_types::{FastHashMap, ResourceCacheError};
@@ -27,6 +35,7 @@ use pathfinder_font_renderer::freetype as pf_freetype;
 use std::{cmp, mem, ptr, slice};
 use std::cmp::max;
 use std::ffi::CString;
+use std::os::raw::{c_int, c_void};
 use std::sync::Arc;
 
 // These constants are not present in the freetype
@@ -37,14 +46,144 @@ const FT_LOAD_TARGET_LIGHT: FT_UInt  = 1 << 16;
 const FT_LOAD_TARGET_MONO: FT_UInt   = 2 << 16;
 const FT_LOAD_TARGET_LCD: FT_UInt    = 3 << 16;
 const FT_LOAD_TARGET_LCD_V: FT_UInt  = 4 << 16;
+const FT_FACE_FLAG_COLOR: FT_Long    = 1 << 14;
+const FT_KERNING_UNFITTED: FT_UInt  = 1;
+
+const COLR_TABLE_TAG: FT_ULong = 0x434F4C52;
+#[cfg(not(feature = "pathfinder"))]
+const CPAL_TABLE_TAG: FT_ULong = 0x4350414C;
+
+// The palette entry of COLR layers that are drawn in the text color.
+const TEXT_COLOR_PALETTE_ENTRY: u16 = 0xFFFF;
 
 struct Face {
     face: FT_Face,
+    color_layers: Option<ColorLayers>,
     // Raw byte data has to live until the font is deleted, according to
     // https://www.freetype.org/freetype2/docs/reference/ft2-base_interface.html#FT_New_Memory_Face
     _bytes: Option<Arc<Vec<u8>>>,
 }
 
+fn load_sfnt_table(face: FT_Face, tag: FT_ULong) -> Option<Vec<u8>> {
+    let mut length: FT_ULong = 0;
+    let result = unsafe { FT_Load_Sfnt_Table(face, tag, 0, ptr::null_mut(), &mut length) };
+    if !succeeded(result) || length == 0 {
+        return None;
+    }
+    let mut table = vec![0u8; length as usize];
+    let result = unsafe { FT_Load_Sfnt_Table(face, tag, 0, table.as_mut_ptr(), &mut length) };
+    if succeeded(result) {
+        Some(table)
+    } else {
+        None
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset .. offset + 2).map(|b| (b[0] as u16) << 8 | b[1] as u16)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset .. offset + 4).map(|b| {
+        (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32
+    })
+}
+
+// The color glyphs of a font, which are stacks of layers of other glyphs
+// in the COLR table, filled with colors from the palettes in the CPAL
+// table. Only the version 0 parts of the tables are used.
+struct ColorLayers {
+    // The layers are only read when rasterizing glyphs with FreeType.
+    #[cfg(not(feature = "pathfinder"))]
+    colr: Vec<u8>,
+    #[cfg(not(feature = "pathfinder"))]
+    cpal: Vec<u8>,
+    uses_text_color: bool,
+}
+
+impl ColorLayers {
+    fn new(face: FT_Face) -> Option<ColorLayers> {
+        let colr = load_sfnt_table(face, COLR_TABLE_TAG)?;
+        #[cfg(not(feature = "pathfinder"))]
+        let cpal = load_sfnt_table(face, CPAL_TABLE_TAG)?;
+
+        let layers_offset = read_u32(&colr, 8)? as usize;
+        let num_layers = read_u16(&colr, 12)? as usize;
+        let uses_text_color = (0 .. num_layers).any(|i| {
+            read_u16(&colr, layers_offset + i * 4 + 2) == Some(TEXT_COLOR_PALETTE_ENTRY)
+        });
+
+        Some(ColorLayers {
+            #[cfg(not(feature = "pathfinder"))]
+            colr,
+            #[cfg(not(feature = "pathfinder"))]
+            cpal,
+            uses_text_color,
+        })
+    }
+}
+
+#[cfg(not(feature = "pathfinder"))]
+impl ColorLayers {
+    // Get the glyphs and colors of the layers of a color glyph, from the
+    // bottom up, or None if the glyph has no layers.
+    fn get_layers(&self, glyph: u32, palette: u16, text_color: ColorU) -> Option<Vec<(u32, ColorU)>> {
+        let num_base_glyphs = read_u16(&self.colr, 2)? as usize;
+        let base_glyphs_offset = read_u32(&self.colr, 4)? as usize;
+        let layers_offset = read_u32(&self.colr, 8)? as usize;
+        let num_layers = read_u16(&self.colr, 12)? as usize;
+
+        // The base glyph records are sorted by glyph.
+        let (mut low, mut high) = (0, num_base_glyphs);
+        while low < high {
+            let mid = (low + high) / 2;
+            let record = base_glyphs_offset + mid * 6;
+            let base_glyph = read_u16(&self.colr, record)? as u32;
+            if base_glyph < glyph {
+                low = mid + 1;
+            } else if base_glyph > glyph {
+                high = mid;
+            } else {
+                let first_layer = read_u16(&self.colr, record + 2)? as usize;
+                let layer_count = read_u16(&self.colr, record + 4)? as usize;
+                if layer_count == 0 || first_layer + layer_count > num_layers {
+                    return None;
+                }
+                return (first_layer .. first_layer + layer_count)
+                    .map(|i| {
+                        let layer = layers_offset + i * 4;
+                        let layer_glyph = read_u16(&self.colr, layer)? as u32;
+                        let entry = read_u16(&self.colr, layer + 2)?;
+                        let color = if entry == TEXT_COLOR_PALETTE_ENTRY {
+                            text_color
+                        } else {
+                            self.get_palette_color(palette, entry)?
+                        };
+                        Some((layer_glyph, color))
+                    })
+                    .collect();
+            }
+        }
+        None
+    }
+
+    fn get_palette_color(&self, palette: u16, entry: u16) -> Option<ColorU> {
+        let num_entries = read_u16(&self.cpal, 2)?;
+        let num_palettes = read_u16(&self.cpal, 4)?;
+        let records_offset = read_u32(&self.cpal, 8)? as usize;
+        if entry >= num_entries || num_palettes == 0 {
+            return None;
+        }
+
+        let palette = if palette < num_palettes { palette } else { 0 };
+        let first_record = read_u16(&self.cpal, 12 + palette as usize * 2)? as usize;
+        let record = records_offset + (first_record + entry as usize) * 4;
+        // The color records are stored as BGRA.
+        let color = self.cpal.get(record .. record + 4)?;
+        Some(ColorU::new(color[2], color[1], color[0], color[3]))
+    }
+}
+
 pub struct FontContext {
     lib: FT_Library,
     faces: FastHashMap<FontKey, Face>,
@@ -58,8 +197,125 @@ unsafe impl Send for FontContext {}
 
 extern "C" {
     fn FT_GlyphSlot_Embolden(slot: FT_GlyphSlot);
+    fn FT_Load_Sfnt_Table(
+        face: FT_Face,
+        tag: FT_ULong,
+        offset: FT_Long,
+        buffer: *mut FT_Byte,
+        length: *mut FT_ULong,
+    ) -> FT_Error;
+    fn FT_Outline_Decompose(
+        outline: *mut FT_Outline,
+        funcs: *const OutlineFuncs,
+        user: *mut c_void,
+    ) -> FT_Error;
+    fn FT_Get_Kerning(
+        face: FT_Face,
+        left_glyph: FT_UInt,
+        right_glyph: FT_UInt,
+        kern_mode: FT_UInt,
+        kerning: *mut FT_Vector,
+    ) -> FT_Error;
+}
+
+// The callbacks of FT_Outline_Decompose, as in FT_Outline_Funcs.
+#[repr(C)]
+struct OutlineFuncs {
+    move_to: extern "C" fn(to: *const FT_Vector, user: *mut c_void) -> c_int,
+    line_to: extern "C" fn(to: *const FT_Vector, user: *mut c_void) -> c_int,
+    conic_to: extern "C" fn(
+        control: *const FT_Vector,
+        to: *const FT_Vector,
+        user: *mut c_void,
+    ) -> c_int,
+    cubic_to: extern "C" fn(
+        control1: *const FT_Vector,
+        control2: *const FT_Vector,
+        to: *const FT_Vector,
+        user: *mut c_void,
+    ) -> c_int,
+    shift: c_int,
+    delta: FT_Pos,
+}
+
+// Collects the commands of a decomposed outline, flipping the y axis
+// so that it points down like the rest of WebRender.
+struct OutlineBuilder {
+    commands: Vec<PathOp>,
+    scale: f32,
+}
+
+impl OutlineBuilder {
+    fn from_user<'a>(user: *mut c_void) -> &'a mut OutlineBuilder {
+        unsafe { &mut *(user as *mut OutlineBuilder) }
+    }
+
+    fn point(&self, v: *const FT_Vector) -> LayoutPoint {
+        let v = unsafe { &*v };
+        LayoutPoint::new(
+            v.x as f32 / 64.0 * self.scale,
+            -v.y as f32 / 64.0 * self.scale,
+        )
+    }
+
+    fn close(&mut self) {
+        match self.commands.last() {
+            None | Some(&PathOp::Close) => {}
+            Some(_) => self.commands.push(PathOp::Close),
+        }
+    }
+}
+
+extern "C" fn outline_move_to(to: *const FT_Vector, user: *mut c_void) -> c_int {
+    let builder = OutlineBuilder::from_user(user);
+    builder.close();
+    let to = builder.point(to);
+    builder.commands.push(PathOp::MoveTo(to));
+    0
 }
 
+extern "C" fn outline_line_to(to: *const FT_Vector, user: *mut c_void) -> c_int {
+    let builder = OutlineBuilder::from_user(user);
+    let to = builder.point(to);
+    builder.commands.push(PathOp::LineTo(to));
+    0
+}
+
+extern "C" fn outline_conic_to(
+    control: *const FT_Vector,
+    to: *const FT_Vector,
+    user: *mut c_void,
+) -> c_int {
+    let builder = OutlineBuilder::from_user(user);
+    let control = builder.point(control);
+    let to = builder.point(to);
+    builder.commands.push(PathOp::QuadraticTo(control, to));
+    0
+}
+
+extern "C" fn outline_cubic_to(
+    control1: *const FT_Vector,
+    control2: *const FT_Vector,
+    to: *const FT_Vector,
+    user: *mut c_void,
+) -> c_int {
+    let builder = OutlineBuilder::from_user(user);
+    let control1 = builder.point(control1);
+    let control2 = builder.point(control2);
+    let to = builder.point(to);
+    builder.commands.push(PathOp::CubicTo(control1, control2, to));
+    0
+}
+
+const OUTLINE_FUNCS: OutlineFuncs = OutlineFuncs {
+    move_to: outline_move_to,
+    line_to: outline_line_to,
+    conic_to: outline_conic_to,
+    cubic_to: outline_cubic_to,
+    shift: 0,
+    delta: 0,
+};
+
 fn get_skew_bounds(bottom: i32, top: i32, skew_factor: f32) -> (f32, f32) {
     let skew_min = ((bottom as f32 + 0.5) * skew_factor).floor();
     let skew_max = ((top as f32 - 0.5) * skew_factor).ceil();
@@ -196,6 +452,7 @@ impl FontContext {
                     *font_key,
                     Face {
                         face,
+                        color_layers: ColorLayers::new(face),
                         _bytes: Some(bytes),
                     },
                 );
@@ -223,6 +480,7 @@ impl FontContext {
                     *font_key,
                     Face {
                         face,
+                        color_layers: ColorLayers::new(face),
                         _bytes: None,
                     },
                 );
@@ -240,9 +498,22 @@ impl FontContext {
         }
     }
 
+    pub fn uses_text_color(&self, font_key: &FontKey) -> bool {
+        self.faces
+            .get(font_key)
+            .and_then(|face| face.color_layers.as_ref())
+            .map_or(false, |color_layers| color_layers.uses_text_color)
+    }
+
     fn load_glyph(&self, font: &FontInstance, glyph: &GlyphKey) -> Option<(FT_GlyphSlot, f32)> {
         debug_assert!(self.faces.contains_key(&font.font_key));
         let face = self.faces.get(&font.font_key).unwrap();
+        let face_flags = unsafe { (*face.face).face_flags };
+        // The strikes of color bitmap fonts, such as CBDT or sbix emoji,
+        // are the only way to draw their glyphs, rather than a
+        // replacement for the outlines at some sizes.
+        let color_bitmaps = (face_flags & (FT_FACE_FLAG_FIXED_SIZES as FT_Long)) != 0 &&
+                            (face_flags & FT_FACE_FLAG_COLOR) != 0;
 
         let mut load_flags = FT_LOAD_DEFAULT;
         let FontInstancePlatformOptions { mut hinting, .. } = font.platform_options.unwrap_or_default();
@@ -276,7 +547,7 @@ impl FontContext {
         if font.flags.contains(FontInstanceFlags::NO_AUTOHINT) {
             load_flags |= FT_LOAD_NO_AUTOHINT;
         }
-        if !font.flags.contains(FontInstanceFlags::EMBEDDED_BITMAPS) {
+        if !font.flags.contains(FontInstanceFlags::EMBEDDED_BITMAPS) && !color_bitmaps {
             load_flags |= FT_LOAD_NO_BITMAP;
         }
         if font.flags.contains(FontInstanceFlags::VERTICAL_LAYOUT) {
@@ -289,9 +560,11 @@ impl FontContext {
         let (x_scale, y_scale) = font.transform.compute_scale().unwrap_or((1.0, 1.0));
         let scale = font.oversized_scale_factor(x_scale, y_scale);
         let req_size = font.size.to_f64_px();
-        let face_flags = unsafe { (*face.face).face_flags };
+        // Scalable fonts would only use a strike that exactly matches the
+        // size, so color bitmap fonts always select the nearest strike
+        // and scale it.
         let mut result = if (face_flags & (FT_FACE_FLAG_FIXED_SIZES as FT_Long)) != 0 &&
-                            (face_flags & (FT_FACE_FLAG_SCALABLE as FT_Long)) == 0 &&
+                            ((face_flags & (FT_FACE_FLAG_SCALABLE as FT_Long)) == 0 || color_bitmaps) &&
                             (load_flags & FT_LOAD_NO_BITMAP) == 0 {
             unsafe { FT_Set_Transform(face.face, ptr::null_mut(), ptr::null_mut()) };
             self.choose_bitmap_size(face.face, req_size * y_scale / scale)
@@ -514,6 +787,63 @@ impl FontContext {
         slot.and_then(|(slot, scale)| self.get_glyph_dimensions_impl(slot, font, key, Some(scale)))
     }
 
+    pub fn get_glyph_outline(
+        &mut self,
+        font: &FontInstance,
+        key: &GlyphKey,
+        next_glyph: Option<GlyphIndex>,
+    ) -> Option<GlyphOutline> {
+        let (slot, scale) = self.load_glyph(font, key)?;
+        if unsafe { (*slot).format } != FT_Glyph_Format::FT_GLYPH_FORMAT_OUTLINE {
+            return None;
+        }
+
+        let mut builder = OutlineBuilder {
+            commands: Vec::new(),
+            scale,
+        };
+        let result = unsafe {
+            FT_Outline_Decompose(
+                &mut (*slot).outline,
+                &OUTLINE_FUNCS,
+                &mut builder as *mut OutlineBuilder as *mut c_void,
+            )
+        };
+        if !succeeded(result) {
+            error!("Unable to decompose outline of glyph {}", key.index());
+            return None;
+        }
+        builder.close();
+
+        // The kerning table is scaled by the size that load_glyph set on the
+        // face. Only the legacy 'kern' table is supported by FreeType, so
+        // fonts that only kern through GPOS have no kerning here.
+        let kerning = next_glyph.map_or(0.0, |next_glyph| {
+            let face = self.faces.get(&font.font_key).unwrap();
+            let mut kerning = FT_Vector { x: 0, y: 0 };
+            let result = unsafe {
+                FT_Get_Kerning(
+                    face.face,
+                    key.index() as FT_UInt,
+                    next_glyph as FT_UInt,
+                    FT_KERNING_UNFITTED,
+                    &mut kerning,
+                )
+            };
+            if succeeded(result) {
+                kerning.x as f32 / 64.0 * scale
+            } else {
+                0.0
+            }
+        });
+
+        Some(GlyphOutline {
+            commands: builder.commands,
+            advance: unsafe { (*slot).metrics.horiAdvance as f32 / 64.0 } * scale,
+            kerning,
+        })
+    }
+
     fn choose_bitmap_size(&self, face: FT_Face, requested_size: f64) -> FT_Error {
         let mut best_dist = unsafe { *(*face).available_sizes.offset(0) }.y_ppem as f64 / 64.0 - requested_size;
         let mut best_size = 0;
@@ -606,8 +936,134 @@ impl FontContext {
         }
     }
 
+    // Composes a color glyph from its layers, each of which is another glyph
+    // rasterized as an alpha mask and filled with the color of the layer.
+    #[cfg(not(feature = "pathfinder"))]
+    fn rasterize_color_layers(
+        &mut self,
+        font: &FontInstance,
+        layers: &[(u32, ColorU)],
+    ) -> GlyphRasterResult {
+        // Like color bitmaps, the glyph is drawn in local space, so
+        // rasterize the layers without the transform, at a resolution
+        // that suits its scale.
+        let (x_scale, y_scale) = font.transform.compute_scale().unwrap_or((1.0, 1.0));
+        let raster_scale = x_scale.max(y_scale);
+        let mut layer_font = font.clone();
+        layer_font.render_mode = FontRenderMode::Alpha;
+        layer_font.disable_subpixel_position();
+        layer_font.transform = FontTransform::identity();
+        layer_font.size = Au::from_f32_px((font.size.to_f64_px() * raster_scale) as f32);
+
+        let mut scale = 1.0;
+        let mut masks = Vec::with_capacity(layers.len());
+        for &(glyph, color) in layers {
+            let key = GlyphKey::new(glyph, DevicePoint::zero(), SubpixelDirection::None);
+            let (slot, layer_scale) = match self.load_glyph(&layer_font, &key) {
+                Some(val) => val,
+                None => return GlyphRasterResult::LoadFailed,
+            };
+            scale = layer_scale;
+
+            if unsafe { (*slot).format } != FT_Glyph_Format::FT_GLYPH_FORMAT_OUTLINE {
+                return GlyphRasterResult::LoadFailed;
+            }
+            let dimensions = match self.get_glyph_dimensions_impl(slot, &layer_font, &key, None) {
+                Some(val) => val,
+                None => return GlyphRasterResult::LoadFailed,
+            };
+            // Empty layers have nothing to draw.
+            if dimensions.width == 0 || dimensions.height == 0 {
+                continue;
+            }
+            if !self.rasterize_glyph_outline(slot, &layer_font, &key) {
+                return GlyphRasterResult::LoadFailed;
+            }
+
+            let bitmap = unsafe { &(*slot).bitmap };
+            if bitmap.pixel_mode as u32 != FT_Pixel_Mode::FT_PIXEL_MODE_GRAY as u32 {
+                return GlyphRasterResult::LoadFailed;
+            }
+            let (width, height) = (bitmap.width as usize, bitmap.rows as usize);
+            let mut alpha = Vec::with_capacity(width * height);
+            for y in 0 .. height {
+                let row = unsafe {
+                    slice::from_raw_parts(bitmap.buffer.offset(y as isize * bitmap.pitch as isize), width)
+                };
+                alpha.extend_from_slice(row);
+            }
+            let (left, top) = unsafe {
+                (
+                    dimensions.left + (*slot).bitmap_left,
+                    dimensions.top + (*slot).bitmap_top - dimensions.height as i32,
+                )
+            };
+            masks.push((left, top, width, height, alpha, color));
+        }
+
+        if masks.is_empty() {
+            return GlyphRasterResult::LoadFailed;
+        }
+
+        let left = masks.iter().map(|m| m.0).min().unwrap();
+        let right = masks.iter().map(|m| m.0 + m.2 as i32).max().unwrap();
+        let top = masks.iter().map(|m| m.1).max().unwrap();
+        let bottom = masks.iter().map(|m| m.1 - m.3 as i32).min().unwrap();
+        let (width, height) = ((right - left) as usize, (top - bottom) as usize);
+
+        // Draw the layers over each other into premultiplied BGRA8.
+        let mut final_buffer = vec![0u8; width * height * 4];
+        for &(mask_left, mask_top, mask_width, mask_height, ref alpha, color) in &masks {
+            for y in 0 .. mask_height {
+                let dest_row = (top - mask_top) as usize + y;
+                for x in 0 .. mask_width {
+                    let coverage = alpha[y * mask_width + x] as u32;
+                    if coverage == 0 {
+                        continue;
+                    }
+                    let a = coverage * color.a as u32 / 255;
+                    let src = [
+                        color.b as u32 * a / 255,
+                        color.g as u32 * a / 255,
+                        color.r as u32 * a / 255,
+                        a,
+                    ];
+                    let dest = (dest_row * width + (mask_left - left) as usize + x) * 4;
+                    for i in 0 .. 4 {
+                        let d = final_buffer[dest + i] as u32;
+                        final_buffer[dest + i] = (src[i] + d * (255 - a) / 255) as u8;
+                    }
+                }
+            }
+        }
+
+        GlyphRasterResult::Bitmap(RasterizedGlyph {
+            left: left as f32,
+            top: top as f32,
+            width: width as u32,
+            height: height as u32,
+            scale: scale / raster_scale as f32,
+            format: GlyphFormat::ColorBitmap,
+            bytes: final_buffer,
+        })
+    }
+
     #[cfg(not(feature = "pathfinder"))]
     pub fn rasterize_glyph(&mut self, font: &FontInstance, key: &GlyphKey) -> GlyphRasterResult {
+        // Mono text has no use for colors.
+        if font.render_mode != FontRenderMode::Mono {
+            let text_color = ColorU::new(font.color.r, font.color.g, font.color.b, 0xFF);
+            let layers = self.faces
+                .get(&font.font_key)
+                .and_then(|face| face.color_layers.as_ref())
+                .and_then(|color_layers| {
+                    color_layers.get_layers(key.index(), font.color_palette, text_color)
+                });
+            if let Some(layers) = layers {
+                return self.rasterize_color_layers(font, &layers);
+            }
+        }
+
         let (slot, scale) = match self.load_glyph(font, key) {
             Some(val) => val,
             None => return GlyphRasterResult::LoadFailed,
@@ -3,7 +3,7 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use api::{FontInstanceFlags, FontKey, FontRenderMode};
-use api::{ColorU, GlyphDimensions};
+use api::{ColorU, GlyphDimensions, GlyphIndex, GlyphOutline};
 use dwrote;
 use gamma_lut::ColorLut;
 use glyph_rasterizer::{FontInstance, FontTransform, GlyphKey};
@@ -302,6 +302,16 @@ impl FontContext {
             })
     }
 
+    // Outlines are only extracted with FreeType so far.
+    pub fn get_glyph_outline(
+        &mut self,
+        _font: &FontInstance,
+        _key: &GlyphKey,
+        _next_glyph: Option<GlyphIndex>,
+    ) -> Option<GlyphOutline> {
+        None
+    }
+
     // DWrite ClearType gives us values in RGB, but WR expects BGRA.
     #[cfg(not(feature = "pathfinder"))]
     fn convert_to_bgra(
@@ -13,7 +13,7 @@ use border::{BorderCacheKey, BorderRenderTaskInfo};
 use box_shadow::BLUR_SAMPLE_SCALE;
 use clip_scroll_tree::{ClipChainIndex, CoordinateSystemId, SpatialNodeIndex};
 use clip::{ClipChain, ClipChainNode, ClipChainNodeIter, ClipChainNodeRef, ClipSource};
-use clip::{ClipSourcesHandle, ClipWorkItem};
+use clip::{ClipSourcesHandle, ClipWorkItem, ImageMaskChannel};
 use frame_builder::{FrameBuildingContext, FrameBuildingState, PictureContext, PictureState};
 use frame_builder::PrimitiveRunContext;
 use glyph_rasterizer::{FontInstance, FontTransform, GlyphKey, FONT_SIZE_LIMIT};
@@ -32,6 +32,7 @@ use scene::SceneProperties;
 use segment::SegmentBuilder;
 use spatial_node::SpatialNode;
 use std::{mem, usize};
+use std::hash::Hash;
 use std::sync::Arc;
 use util::{MatrixHelpers, calculate_screen_bounding_rect};
 use util::{pack_as_float, recycle_vec, TransformedRectKind};
@@ -324,6 +325,18 @@ pub enum BrushKind {
         tile_spacing: LayoutSize,
         visible_tiles: Vec<VisibleGradientTile>,
     },
+    ConicGradient {
+        stops_handle: GpuCacheHandle,
+        stops_range: ItemRange<GradientStop>,
+        extend_mode: ExtendMode,
+        center: LayoutPoint,
+        angle: f32,
+        start_offset: f32,
+        end_offset: f32,
+        stretch_size: LayoutSize,
+        tile_spacing: LayoutSize,
+        visible_tiles: Vec<VisibleGradientTile>,
+    },
     LinearGradient {
         stops_handle: GpuCacheHandle,
         stops_range: ItemRange<GradientStop>,
@@ -354,6 +367,7 @@ impl BrushKind {
             BrushKind::Solid { .. } |
             BrushKind::YuvImage { .. } |
             BrushKind::RadialGradient { .. } |
+            BrushKind::ConicGradient { .. } |
             BrushKind::Border { .. } |
             BrushKind::LinearGradient { .. } => true,
 
@@ -552,6 +566,20 @@ impl BrushPrimitive {
                     stretch_size.height,
                 ]);
             }
+            BrushKind::ConicGradient { stretch_size, center, angle, start_offset, end_offset, extend_mode, .. } => {
+                request.push([
+                    center.x,
+                    center.y,
+                    start_offset,
+                    end_offset,
+                ]);
+                request.push([
+                    angle,
+                    pack_as_float(extend_mode as u32),
+                    stretch_size.width,
+                    stretch_size.height,
+                ]);
+            }
         }
     }
 }
@@ -775,12 +803,21 @@ impl<'a> GradientGpuBlockBuilder<'a> {
     }
 }
 
+/// The glyphs of a text run.
+#[derive(Debug, Clone)]
+pub enum TextRunGlyphs {
+    /// Glyphs of the display list.
+    DisplayList(ItemRange<GlyphInstance>),
+    /// Glyphs placed while flattening the display list, like emphasis marks.
+    Owned(Vec<GlyphInstance>),
+}
+
 #[derive(Debug, Clone)]
 pub struct TextRunPrimitiveCpu {
     pub specified_font: FontInstance,
     pub used_font: FontInstance,
     pub offset: LayoutVector2D,
-    pub glyph_range: ItemRange<GlyphInstance>,
+    pub glyphs: TextRunGlyphs,
     pub glyph_keys: Vec<GlyphKey>,
     pub glyph_gpu_blocks: Vec<GpuBlockData>,
     pub shadow: bool,
@@ -791,7 +828,7 @@ impl TextRunPrimitiveCpu {
     pub fn new(
         font: FontInstance,
         offset: LayoutVector2D,
-        glyph_range: ItemRange<GlyphInstance>,
+        glyphs: TextRunGlyphs,
         glyph_keys: Vec<GlyphKey>,
         shadow: bool,
         glyph_raster_space: GlyphRasterSpace,
@@ -800,7 +837,7 @@ impl TextRunPrimitiveCpu {
             specified_font: font.clone(),
             used_font: font,
             offset,
-            glyph_range,
+            glyphs,
             glyph_keys,
             glyph_gpu_blocks: Vec::new(),
             shadow,
@@ -887,35 +924,25 @@ impl TextRunPrimitiveCpu {
         //           completely, and just reference the glyphs
         //           directly from the display list.
         if self.glyph_keys.is_empty() || cache_dirty {
-            let subpx_dir = self.used_font.get_subpx_dir();
-            let src_glyphs = display_list.get(self.glyph_range);
-
-            // TODO(gw): If we support chunks() on AuxIter
-            //           in the future, this code below could
-            //           be much simpler...
-            let mut gpu_block = [0.0; 4];
-            for (i, src) in src_glyphs.enumerate() {
-                let world_offset = self.used_font.transform.transform(&src.point);
-                let device_offset = device_pixel_scale.transform_point(&world_offset);
-                let key = GlyphKey::new(src.index, device_offset, subpx_dir);
-                self.glyph_keys.push(key);
-
-                // Two glyphs are packed per GPU block.
-
-                if (i & 1) == 0 {
-                    gpu_block[0] = src.point.x;
-                    gpu_block[1] = src.point.y;
-                } else {
-                    gpu_block[2] = src.point.x;
-                    gpu_block[3] = src.point.y;
-                    self.glyph_gpu_blocks.push(gpu_block.into());
+            match self.glyphs {
+                TextRunGlyphs::DisplayList(glyph_range) => {
+                    cache_text_run_glyphs(
+                        display_list.get(glyph_range),
+                        &self.used_font,
+                        device_pixel_scale,
+                        &mut self.glyph_keys,
+                        &mut self.glyph_gpu_blocks,
+                    );
+                }
+                TextRunGlyphs::Owned(ref glyphs) => {
+                    cache_text_run_glyphs(
+                        glyphs.iter().cloned(),
+                        &self.used_font,
+                        device_pixel_scale,
+                        &mut self.glyph_keys,
+                        &mut self.glyph_gpu_blocks,
+                    );
                 }
-            }
-
-            // Ensure the last block is added in the case
-            // of an odd number of glyphs.
-            if (self.glyph_keys.len() & 1) != 0 {
-                self.glyph_gpu_blocks.push(gpu_block.into());
             }
         }
 
@@ -944,6 +971,45 @@ impl TextRunPrimitiveCpu {
     }
 }
 
+// Build the glyph keys and GPU blocks of the glyphs of a text run.
+fn cache_text_run_glyphs<I: Iterator<Item = GlyphInstance>>(
+    src_glyphs: I,
+    font: &FontInstance,
+    device_pixel_scale: DevicePixelScale,
+    glyph_keys: &mut Vec<GlyphKey>,
+    glyph_gpu_blocks: &mut Vec<GpuBlockData>,
+) {
+    let subpx_dir = font.get_subpx_dir();
+
+    // TODO(gw): If we support chunks() on AuxIter
+    //           in the future, this code below could
+    //           be much simpler...
+    let mut gpu_block = [0.0; 4];
+    for (i, src) in src_glyphs.enumerate() {
+        let world_offset = font.transform.transform(&src.point);
+        let device_offset = device_pixel_scale.transform_point(&world_offset);
+        let key = GlyphKey::new(src.index, device_offset, subpx_dir);
+        glyph_keys.push(key);
+
+        // Two glyphs are packed per GPU block.
+
+        if (i & 1) == 0 {
+            gpu_block[0] = src.point.x;
+            gpu_block[1] = src.point.y;
+        } else {
+            gpu_block[2] = src.point.x;
+            gpu_block[3] = src.point.y;
+            glyph_gpu_blocks.push(gpu_block.into());
+        }
+    }
+
+    // Ensure the last block is added in the case
+    // of an odd number of glyphs.
+    if (glyph_keys.len() & 1) != 0 {
+        glyph_gpu_blocks.push(gpu_block.into());
+    }
+}
+
 #[derive(Debug)]
 #[repr(C)]
 struct ClipRect {
@@ -993,11 +1059,13 @@ impl ClipCorner {
 #[repr(C)]
 pub struct ImageMaskData {
     pub local_rect: LayoutRect,
+    pub channel: ImageMaskChannel,
 }
 
 impl ToGpuBlocks for ImageMaskData {
     fn write_gpu_blocks(&self, mut request: GpuDataRequest) {
         request.push(self.local_rect);
+        request.push([self.channel as u32 as f32, 0.0, 0.0, 0.0]);
     }
 }
 
@@ -1156,6 +1224,7 @@ impl PrimitiveContainer {
                     BrushKind::Image { .. } |
                     BrushKind::YuvImage { .. } |
                     BrushKind::RadialGradient { .. } |
+                    BrushKind::ConicGradient { .. } |
                     BrushKind::Border { .. } |
                     BrushKind::LinearGradient { .. } => {
                         true
@@ -1182,7 +1251,7 @@ impl PrimitiveContainer {
                 PrimitiveContainer::TextRun(TextRunPrimitiveCpu::new(
                     font,
                     info.offset + shadow.offset,
-                    info.glyph_range,
+                    info.glyphs.clone(),
                     info.glyph_keys.clone(),
                     true,
                     info.glyph_raster_space,
@@ -1202,6 +1271,7 @@ impl PrimitiveContainer {
                     BrushKind::YuvImage { .. } |
                     BrushKind::Border { .. } |
                     BrushKind::RadialGradient { .. } |
+                    BrushKind::ConicGradient { .. } |
                     BrushKind::LinearGradient { .. } => {
                         panic!("bug: other brush kinds not expected here yet");
                     }
@@ -1312,6 +1382,7 @@ impl PrimitiveStore {
                     BrushKind::Image { .. } => PrimitiveOpacity::translucent(),
                     BrushKind::YuvImage { .. } => PrimitiveOpacity::opaque(),
                     BrushKind::RadialGradient { .. } => PrimitiveOpacity::translucent(),
+                    BrushKind::ConicGradient { .. } => PrimitiveOpacity::translucent(),
                     BrushKind::LinearGradient { .. } => PrimitiveOpacity::translucent(),
                     BrushKind::Picture { .. } => PrimitiveOpacity::translucent(),
                     BrushKind::Border { .. } => PrimitiveOpacity::translucent(),
@@ -1393,6 +1464,7 @@ impl PrimitiveStore {
                     BrushKind::YuvImage { .. } |
                     BrushKind::LinearGradient { .. } |
                     BrushKind::RadialGradient { .. } |
+                    BrushKind::ConicGradient { .. } |
                     BrushKind::Clear => {}
                 }
             }
@@ -1440,7 +1512,8 @@ impl PrimitiveStore {
                         BrushKind::YuvImage { .. } |
                         BrushKind::Border { .. } |
                         BrushKind::LinearGradient { .. } |
-                        BrushKind::RadialGradient { .. } => {
+                        BrushKind::RadialGradient { .. } |
+                        BrushKind::ConicGradient { .. } => {
                             unreachable!("bug: invalid prim type for opacity collapse");
                         }
                     };
@@ -1791,10 +1864,22 @@ impl PrimitiveStore {
                                     metadata.screen_rect = None;
                                 }
                             } else if request_source_image {
-                                frame_state.resource_cache.request_image(
-                                    request,
-                                    frame_state.gpu_cache,
-                                );
+                                match *source {
+                                    // The image is blitted into the render task cache,
+                                    // which can't be done from compressed textures.
+                                    ImageSource::Cache { .. } => {
+                                        frame_state.resource_cache.request_decoded_image(
+                                            request,
+                                            frame_state.gpu_cache,
+                                        );
+                                    }
+                                    ImageSource::Default => {
+                                        frame_state.resource_cache.request_image(
+                                            request,
+                                            frame_state.gpu_cache,
+                                        );
+                                    }
+                                }
                             }
                         }
                     }
@@ -1887,6 +1972,56 @@ impl PrimitiveStore {
                             );
                         }
                     }
+                    BrushKind::ConicGradient {
+                        stops_range,
+                        center,
+                        angle,
+                        start_offset,
+                        end_offset,
+                        extend_mode,
+                        stretch_size,
+                        tile_spacing,
+                        ref mut stops_handle,
+                        ref mut visible_tiles,
+                        ..
+                    } => {
+                        build_gradient_stops_request(
+                            stops_handle,
+                            stops_range,
+                            false,
+                            frame_state,
+                            pic_context,
+                        );
+
+                        if tile_spacing != LayoutSize::zero() {
+                            is_tiled = true;
+
+                            decompose_repeated_primitive(
+                                visible_tiles,
+                                metadata,
+                                &stretch_size,
+                                &tile_spacing,
+                                prim_run_context,
+                                frame_context,
+                                frame_state,
+                                &mut |rect, mut request| {
+                                    request.push([
+                                        center.x,
+                                        center.y,
+                                        start_offset,
+                                        end_offset,
+                                    ]);
+                                    request.push([
+                                        angle,
+                                        pack_as_float(extend_mode as u32),
+                                        stretch_size.width,
+                                        stretch_size.height,
+                                    ]);
+                                    request.write_segment(*rect, [0.0; 4]);
+                                },
+                            );
+                        }
+                    }
                     BrushKind::LinearGradient {
                         stops_range,
                         reverse_stops,
@@ -2065,7 +2200,7 @@ impl PrimitiveStore {
             // TODO(gw): We can easily extend the segment builder to support these clip sources in
             // the future, but they are rarely used.
             // We must do this check here in case we continue early below.
-            if local_clips.has_image_or_line_decoration_clip {
+            if local_clips.has_mask_only_clip {
                 clip_mask_kind = BrushClipMaskKind::Global;
             }
 
@@ -2117,7 +2252,9 @@ impl PrimitiveStore {
 
                         continue;
                     }
-                    ClipSource::LineDecoration(..) | ClipSource::Image(..) => continue,
+                    ClipSource::LineDecoration(..) |
+                    ClipSource::Image(..) |
+                    ClipSource::Path(..) => continue,
                 };
 
                 segment_builder.push_clip_rect(local_clip_rect, radius, mode);
@@ -2485,9 +2622,18 @@ impl PrimitiveStore {
                         .world_content_transform
                         .inverse();
 
-                    // Mark whether this picture has a complex coordinate system.
-                    pic_state_for_children.has_non_root_coord_system |=
-                        prim_run_context.scroll_node.coordinate_system_id != CoordinateSystemId::root();
+                    // Mark whether this picture has a complex transform, and
+                    // include its scale in the layout of its content.
+                    let world_transform = &prim_run_context.scroll_node.world_content_transform;
+                    pic_state_for_children.has_non_axis_aligned_content |=
+                        !world_transform.preserves_2d_axis_alignment();
+                    let world_transform = world_transform.to_transform();
+                    pic_state_for_children.add_to_layout_hash(&[
+                        world_transform.m11,
+                        world_transform.m12,
+                        world_transform.m21,
+                        world_transform.m22,
+                    ]);
 
                     PictureContext {
                         pipeline_id: pic.pipeline_id,
@@ -2656,11 +2802,63 @@ impl PrimitiveStore {
             let clip_chain = &frame_context
                 .clip_chains[run.clip_and_scroll.clip_chain_index.0];
 
-            // Mark whether this picture contains any complex coordinate
-            // systems, due to either the scroll node or the clip-chain.
-            pic_state.has_non_root_coord_system |=
-                scroll_node.coordinate_system_id != CoordinateSystemId::root();
-            pic_state.has_non_root_coord_system |= clip_chain.has_non_root_coord_system;
+            // Mark whether this picture contains any content that isn't
+            // 2D axis-aligned, due to either the scroll node or the clip-chain.
+            pic_state.has_non_axis_aligned_content |=
+                !scroll_node.world_content_transform.preserves_2d_axis_alignment();
+            if clip_chain.has_non_root_coord_system {
+                let clip_nodes = ClipChainNodeIter { current: clip_chain.nodes.clone() };
+                for clip_node in clip_nodes {
+                    let clip_spatial_node = &frame_context
+                        .clip_scroll_tree
+                        .spatial_nodes[clip_node.work_item.spatial_node_index.0];
+                    pic_state.has_non_axis_aligned_content |=
+                        !clip_spatial_node.world_content_transform.preserves_2d_axis_alignment();
+                }
+            }
+
+            // The position of the run within the picture is part of the
+            // picture content, e.g. if it's in a scroll frame inside it.
+            match pic_context.inv_world_transform {
+                Some(ref inv_world_transform) => {
+                    let relative_transform = inv_world_transform
+                        .pre_mul(&scroll_node.world_content_transform)
+                        .to_transform();
+                    pic_state.add_to_layout_hash(&relative_transform.to_row_major_array());
+                }
+                None => {
+                    pic_state.has_non_axis_aligned_content = true;
+                }
+            }
+
+            // The clips of the run, and where they are relative to the picture,
+            // are part of its content too. The content hash of a picture only
+            // covers the clips defined inside of its stacking context.
+            if let Some(ref inv_world_transform) = pic_context.inv_world_transform {
+                let clip_nodes = ClipChainNodeIter { current: clip_chain.nodes.clone() };
+                for clip_node in clip_nodes {
+                    let clip_spatial_node = &frame_context
+                        .clip_scroll_tree
+                        .spatial_nodes[clip_node.work_item.spatial_node_index.0];
+                    let relative_transform = inv_world_transform
+                        .pre_mul(&clip_spatial_node.world_content_transform)
+                        .to_transform();
+                    pic_state.add_to_layout_hash(&relative_transform.to_row_major_array());
+
+                    let clip_sources = frame_state.clip_store
+                        .get_opt(&clip_node.work_item.clip_sources)
+                        .expect("bug");
+                    for &(ref clip_source, _) in clip_sources.clips() {
+                        clip_source.hash_layout(&mut pic_state.layout_hasher);
+                        // Image masks can be updated without a new display list.
+                        if let ClipSource::Image(ref mask, _) = *clip_source {
+                            frame_state.resource_cache
+                                .get_image_generation(mask.image)
+                                .hash(&mut pic_state.layout_hasher);
+                        }
+                    }
+                }
+            }
 
             if !scroll_node.invertible {
                 if run.is_chasing(self.chase_id) {
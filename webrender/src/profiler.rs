@@ -317,6 +317,8 @@ pub struct FrameProfileCounters {
     pub targets_used: IntProfileCounter,
     pub targets_changed: IntProfileCounter,
     pub targets_created: IntProfileCounter,
+    pub cached_pictures: IntProfileCounter,
+    pub invalidated_pictures: IntProfileCounter,
 }
 
 impl FrameProfileCounters {
@@ -327,6 +329,8 @@ impl FrameProfileCounters {
             targets_used: IntProfileCounter::new("Used targets"),
             targets_changed: IntProfileCounter::new("Changed targets"),
             targets_created: IntProfileCounter::new("Created targets"),
+            cached_pictures: IntProfileCounter::new("Cached pictures"),
+            invalidated_pictures: IntProfileCounter::new("Invalidated pictures"),
         }
     }
     pub fn reset_targets(&mut self) {
@@ -341,6 +345,7 @@ pub struct TextureCacheProfileCounters {
     pub pages_a8_linear: ResourceProfileCounter,
     pub pages_rgba8_linear: ResourceProfileCounter,
     pub pages_rgba8_nearest: ResourceProfileCounter,
+    pub entries: ResourceProfileCounter,
 }
 
 impl TextureCacheProfileCounters {
@@ -349,6 +354,26 @@ impl TextureCacheProfileCounters {
             pages_a8_linear: ResourceProfileCounter::new("Texture A8 cached pages"),
             pages_rgba8_linear: ResourceProfileCounter::new("Texture RGBA8 cached pages (L)"),
             pages_rgba8_nearest: ResourceProfileCounter::new("Texture RGBA8 cached pages (N)"),
+            entries: ResourceProfileCounter::new("Texture cache entries"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GlyphCacheProfileCounters {
+    pub hits: IntProfileCounter,
+    pub misses: IntProfileCounter,
+    pub fonts: IntProfileCounter,
+    pub evicted_fonts: IntProfileCounter,
+}
+
+impl GlyphCacheProfileCounters {
+    pub fn new() -> Self {
+        GlyphCacheProfileCounters {
+            hits: IntProfileCounter::new("Glyph cache hits"),
+            misses: IntProfileCounter::new("Glyph cache misses"),
+            fonts: IntProfileCounter::new("Glyph cache font instances"),
+            evicted_fonts: IntProfileCounter::new("Glyph cache evicted instances"),
         }
     }
 }
@@ -360,6 +385,8 @@ pub struct GpuCacheProfileCounters {
     pub updated_rows: IntProfileCounter,
     pub updated_blocks: IntProfileCounter,
     pub saved_blocks: IntProfileCounter,
+    pub moved_blocks: IntProfileCounter,
+    pub fragmentation: IntProfileCounter,
 }
 
 impl GpuCacheProfileCounters {
@@ -370,6 +397,8 @@ impl GpuCacheProfileCounters {
             allocated_blocks: IntProfileCounter::new("GPU cache blocks: total"),
             updated_blocks: IntProfileCounter::new("GPU cache blocks: updated"),
             saved_blocks: IntProfileCounter::new("GPU cache blocks: saved"),
+            moved_blocks: IntProfileCounter::new("GPU cache blocks: moved"),
+            fragmentation: IntProfileCounter::new("GPU cache fragmentation (%)"),
         }
     }
 }
@@ -386,6 +415,7 @@ pub struct ResourceProfileCounters {
     pub font_templates: ResourceProfileCounter,
     pub image_templates: ResourceProfileCounter,
     pub texture_cache: TextureCacheProfileCounters,
+    pub glyph_cache: GlyphCacheProfileCounters,
     pub gpu_cache: GpuCacheProfileCounters,
 }
 
@@ -427,6 +457,7 @@ impl BackendProfileCounters {
                 font_templates: ResourceProfileCounter::new("Font Templates"),
                 image_templates: ResourceProfileCounter::new("Image Templates"),
                 texture_cache: TextureCacheProfileCounters::new(),
+                glyph_cache: GlyphCacheProfileCounters::new(),
                 gpu_cache: GpuCacheProfileCounters::new(),
             },
             ipc: IpcProfileCounters {
@@ -936,7 +967,11 @@ impl Profiler {
         };
 
         let rect0 = self.draw_bar(
-            &format!("GPU cache rows ({}):", counters.allocated_rows.value),
+            &format!(
+                "GPU cache rows ({}, {}% fragmented):",
+                counters.allocated_rows.value,
+                counters.fragmentation.value,
+            ),
             ColorU::new(0xFF, 0xFF, 0xFF, 0xFF),
             &[
                 (color_updated, &counters.updated_rows),
@@ -996,7 +1031,26 @@ impl Profiler {
             debug_renderer,
         );
 
-        let total_rect = rect0.union(&rect1).inflate(10.0, 10.0);
+        let mut total_rect = rect0.union(&rect1);
+
+        if counters.cached_pictures.value > 0 {
+            let rect2 = self.draw_bar(
+                &format!(
+                    "Cached pictures ({}, {} invalidated):",
+                    counters.cached_pictures.value,
+                    counters.invalidated_pictures.value,
+                ),
+                ColorU::new(0xFF, 0xFF, 0xFF, 0xFF),
+                &[
+                    (ColorU::new(0xFF, 0, 0, 0xFF), &counters.invalidated_pictures),
+                    (ColorU::new(0, 0xFF, 0, 0xFF), &counters.cached_pictures),
+                ],
+                debug_renderer,
+            );
+            total_rect = total_rect.union(&rect2);
+        }
+
+        let total_rect = total_rect.inflate(10.0, 10.0);
         debug_renderer.add_quad(
             total_rect.origin.x,
             total_rect.origin.y,
@@ -1073,6 +1127,7 @@ impl Profiler {
                 &backend_profile.resources.texture_cache.pages_a8_linear,
                 &backend_profile.resources.texture_cache.pages_rgba8_linear,
                 &backend_profile.resources.texture_cache.pages_rgba8_nearest,
+                &backend_profile.resources.texture_cache.entries,
                 &backend_profile.ipc.display_lists,
             ],
             debug_renderer,
@@ -1080,6 +1135,18 @@ impl Profiler {
             &mut self.draw_state
         );
 
+        Profiler::draw_counters(
+            &[
+                &backend_profile.resources.glyph_cache.hits,
+                &backend_profile.resources.glyph_cache.misses,
+                &backend_profile.resources.glyph_cache.fonts,
+                &backend_profile.resources.glyph_cache.evicted_fonts,
+            ],
+            debug_renderer,
+            true,
+            &mut self.draw_state
+        );
+
         Profiler::draw_counters(
             &[
                 &backend_profile.ipc.build_time,
@@ -82,7 +82,8 @@ pub fn should_record_msg(msg: &ApiMsg) -> bool {
             for msg in &msgs.frame_ops {
                 match *msg {
                     FrameMsg::GetScrollNodeState(..) |
-                    FrameMsg::HitTest(..) => {}
+                    FrameMsg::HitTest(..) |
+                    FrameMsg::HitTestArea(..) => {}
                     _ => return true,
                 }
             }
@@ -6,10 +6,11 @@ use api::{ApiMsg, BuiltDisplayList, ClearCache, DebugCommand};
 #[cfg(feature = "debugger")]
 use api::{BuiltDisplayListIter, SpecificDisplayItem};
 use api::{DeviceIntPoint, DevicePixelScale, DeviceUintPoint, DeviceUintRect, DeviceUintSize};
-use api::{DocumentId, DocumentLayer, ExternalScrollId, FrameMsg, HitTestFlags, HitTestResult};
+use api::{DocumentId, DocumentLayer, DocumentPriority, ExternalScrollId, FrameMsg};
+use api::{HitTestFlags, HitTestResult};
 use api::{IdNamespace, LayoutPoint, PipelineId, RenderNotifier, SceneMsg, ScrollClamping};
 use api::{ScrollLocation, ScrollNodeState, TransactionMsg, ResourceUpdate, ImageKey};
-use api::channel::{MsgReceiver, Payload};
+use api::channel::{MsgReceiver, MsgReceiverHelperMethods, Payload};
 #[cfg(feature = "capture")]
 use api::CaptureBits;
 #[cfg(feature = "replay")]
@@ -42,6 +43,7 @@ use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
 use std::mem::replace;
 use std::sync::mpsc::{channel, Sender, Receiver};
 use std::u32;
+use std::u64;
 #[cfg(feature = "replay")]
 use tiling::Frame;
 use time::precise_time_ns;
@@ -79,6 +81,46 @@ struct SceneData {
 #[cfg_attr(feature = "replay", derive(Deserialize))]
 pub struct FrameId(pub u32);
 
+/// How long the frame of a visible low priority document can be deferred
+/// while other messages keep arriving, before it is built anyway.
+const MAX_FRAME_DEFER_NS: u64 = 100 * 1000 * 1000;
+
+/// A frame that was requested but not built yet, because its
+/// document is hidden or has a low priority.
+#[derive(Copy, Clone, Debug)]
+struct PendingFrame {
+    is_new_scene: bool,
+    scroll: bool,
+    composite: bool,
+    // Whether the `RenderNotifier` is waiting for this frame.
+    notify: bool,
+    // When the oldest of the requests combined in this frame was deferred.
+    deferred_at: u64,
+}
+
+impl PendingFrame {
+    fn combine(&mut self, other: Self) {
+        self.is_new_scene = self.is_new_scene || other.is_new_scene;
+        self.scroll = self.scroll || other.scroll;
+        self.composite = self.composite || other.composite;
+        self.notify = self.notify || other.notify;
+        self.deferred_at = self.deferred_at.min(other.deferred_at);
+    }
+
+    fn is_overdue(&self, now: u64) -> bool {
+        now.saturating_sub(self.deferred_at) >= MAX_FRAME_DEFER_NS
+    }
+
+    /// Hidden documents aren't drawn, so there is no point in having the
+    /// notifier wait for their frames until they are shown again.
+    fn notify_hidden(&mut self, document_id: DocumentId, notifier: &RenderNotifier) {
+        if self.notify {
+            notifier.new_frame_ready(document_id, self.scroll, false, None);
+            self.notify = false;
+        }
+    }
+}
+
 struct Document {
     // The latest built scene, usable to build frames.
     // received from the scene builder thread.
@@ -119,6 +161,14 @@ struct Document {
     /// Properties that are resolved during frame building and can be changed at any time
     /// without requiring the scene to be re-built.
     dynamic_properties: SceneProperties,
+
+    /// No frame is built for a hidden document.
+    visible: bool,
+    /// How urgently the frames of this document are built.
+    priority: DocumentPriority,
+    /// A frame that will be built once the document is visible, and
+    /// once there is nothing more urgent to do if it has a low priority.
+    pending_frame: Option<PendingFrame>,
 }
 
 impl Document {
@@ -160,6 +210,9 @@ impl Document {
             render_on_scroll,
             hit_tester: None,
             dynamic_properties: SceneProperties::new(),
+            visible: true,
+            priority: DocumentPriority::Normal,
+            pending_frame: None,
         }
     }
 
@@ -203,6 +256,7 @@ impl Document {
             &self.pending.scene,
             &mut self.clip_scroll_tree,
             resource_cache.get_font_instances(),
+            resource_cache.get_font_contexts(),
             &self.view,
             &self.output_pipelines,
             &self.frame_builder_config,
@@ -246,6 +300,7 @@ impl Document {
                 removed_pipelines: replace(&mut self.pending.removed_pipelines, Vec::new()),
                 view: self.view.clone(),
                 font_instances: resource_cache.get_font_instances(),
+                font_contexts: resource_cache.get_font_contexts(),
                 output_pipelines: self.output_pipelines.clone(),
                 scene_id,
             })
@@ -270,6 +325,7 @@ impl Document {
 
     fn render(
         &mut self,
+        document_id: DocumentId,
         resource_cache: &mut ResourceCache,
         gpu_cache: &mut GpuCache,
         resource_profile: &mut ResourceProfileCounters,
@@ -284,12 +340,14 @@ impl Document {
                 resource_cache,
                 gpu_cache,
                 self.frame_id,
+                document_id,
                 &mut self.clip_scroll_tree,
                 &self.current.scene.pipelines,
                 accumulated_scale_factor,
                 self.view.layer,
                 pan,
                 &mut resource_profile.texture_cache,
+                &mut resource_profile.glyph_cache,
                 &mut resource_profile.gpu_cache,
                 &self.dynamic_properties,
             );
@@ -300,6 +358,7 @@ impl Document {
         RenderedDocument {
             frame,
             is_new_scene,
+            priority: self.priority,
         }
     }
 
@@ -662,6 +721,17 @@ impl RenderBackend {
                 tx.send(result).unwrap();
                 DocumentOps::nop()
             }
+            FrameMsg::HitTestArea(pipeline_id, area, flags, tx) => {
+                let result = match doc.hit_tester {
+                    Some(ref hit_tester) => {
+                        hit_tester.hit_test_area(pipeline_id, area, flags)
+                    }
+                    None => HitTestResult { items: Vec::new() },
+                };
+
+                tx.send(result).unwrap();
+                DocumentOps::nop()
+            }
             FrameMsg::SetPan(pan) => {
                 doc.view.pan = pan;
                 DocumentOps::nop()
@@ -692,6 +762,39 @@ impl RenderBackend {
                 doc.dynamic_properties.add_properties(property_bindings);
                 DocumentOps::render()
             }
+            FrameMsg::SetDocumentVisibility(visible) => {
+                if doc.visible == visible {
+                    return DocumentOps::nop();
+                }
+                doc.visible = visible;
+
+                if visible {
+                    // The renderer dropped the last frame when the document
+                    // was hidden, so a new one is needed right away.
+                    DocumentOps {
+                        composite: true,
+                        ..DocumentOps::render()
+                    }
+                } else {
+                    if let Some(ref mut frame) = doc.pending_frame {
+                        frame.notify_hidden(document_id, &*self.notifier);
+                    }
+                    // The renderer drops the frame of the document, so it
+                    // doesn't hold back the compaction of the GPU cache.
+                    self.gpu_cache.remove_document(document_id);
+                    self.result_tx.send(ResultMsg::HideDocument(document_id)).unwrap();
+                    DocumentOps::nop()
+                }
+            }
+            FrameMsg::SetDocumentPriority(priority) => {
+                doc.priority = priority;
+                // Build the frame that was deferred because of the previous priority.
+                if priority == DocumentPriority::Normal && doc.pending_frame.is_some() {
+                    DocumentOps::render()
+                } else {
+                    DocumentOps::nop()
+                }
+            }
         }
     }
 
@@ -790,7 +893,20 @@ impl RenderBackend {
                 }
             }
 
-            keep_going = match self.api_rx.recv() {
+            // Don't let a steady stream of messages starve the low priority documents.
+            self.build_pending_frames(precise_time_ns(), &mut frame_counter, &mut profile_counters);
+
+            let msg = match self.api_rx.try_recv_msg() {
+                Some(msg) => Ok(msg),
+                None => {
+                    // There is nothing more urgent to do than building
+                    // the frames of the low priority documents.
+                    self.build_pending_frames(u64::MAX, &mut frame_counter, &mut profile_counters);
+                    self.api_rx.recv()
+                }
+            };
+
+            keep_going = match msg {
                 Ok(msg) => {
                     if let Some(ref mut r) = self.recorder {
                         r.write_msg(frame_counter, &msg);
@@ -860,6 +976,13 @@ impl RenderBackend {
                 }
                 tx.send(glyph_dimensions).unwrap();
             }
+            ApiMsg::GetGlyphOutlines(instance_key, glyph_indices, tx) => {
+                let glyph_outlines = match self.resource_cache.get_font_instance(instance_key) {
+                    Some(font) => self.resource_cache.get_glyph_outlines(&font, &glyph_indices),
+                    None => Vec::new(),
+                };
+                tx.send(glyph_outlines).unwrap();
+            }
             ApiMsg::GetGlyphIndices(font_key, text, tx) => {
                 let mut glyph_indices = Vec::new();
                 for ch in text.chars() {
@@ -883,6 +1006,8 @@ impl RenderBackend {
             }
             ApiMsg::DeleteDocument(document_id) => {
                 self.documents.remove(&document_id);
+                self.resource_cache.delete_document(document_id);
+                self.gpu_cache.remove_document(document_id);
             }
             ApiMsg::ExternalEvent(evt) => {
                 self.notifier.external_event(evt);
@@ -896,6 +1021,8 @@ impl RenderBackend {
                     .collect::<Vec<_>>();
                 for document in document_ids {
                     self.documents.remove(&document);
+                    self.resource_cache.delete_document(document);
+                    self.gpu_cache.remove_document(document);
                 }
             }
             ApiMsg::MemoryPressure => {
@@ -933,6 +1060,10 @@ impl RenderBackend {
                         // We don't want to forward this message to the renderer.
                         return true;
                     }
+                    DebugCommand::EnableCompressedTextures(enable) => {
+                        self.resource_cache.set_compressed_textures_enabled(enable);
+                        return true;
+                    }
                     DebugCommand::FetchDocuments => {
                         let json = self.get_docs_for_debugger();
                         ResultMsg::DebugOutput(DebugOutput::FetchDocuments(json))
@@ -1075,31 +1206,99 @@ impl RenderBackend {
             op.combine(self.process_frame_msg(document_id, frame_msg));
         }
 
-        let doc = self.documents.get_mut(&document_id).unwrap();
+        let frame = {
+            let doc = self.documents.get_mut(&document_id).unwrap();
+
+            if transaction_msg.generate_frame {
+                if let Some(ref mut ros) = doc.render_on_scroll {
+                    *ros = true;
+                }
 
-        if transaction_msg.generate_frame {
-            if let Some(ref mut ros) = doc.render_on_scroll {
-                *ros = true;
+                if doc.current.scene.root_pipeline_id.is_some() {
+                    op.render = true;
+                    op.composite = true;
+                }
             }
 
-            if doc.current.scene.root_pipeline_id.is_some() {
-                op.render = true;
-                op.composite = true;
+            if !doc.can_render() {
+                // TODO: this happens if we are building the first scene asynchronously and
+                // scroll at the same time. we should keep track of the fact that we skipped
+                // composition here and do it as soon as we receive the scene.
+                op.render = false;
+                op.composite = false;
+            }
+
+            debug_assert!(op.render || !op.composite);
+
+            if !op.render {
+                if transaction_msg.generate_frame {
+                    self.notifier.new_frame_ready(document_id, op.scroll, op.composite, None);
+                }
+                return;
+            }
+
+            let mut frame = PendingFrame {
+                is_new_scene: op.build || has_built_scene,
+                scroll: op.scroll,
+                composite: op.composite,
+                notify: transaction_msg.generate_frame,
+                deferred_at: precise_time_ns(),
+            };
+            if let Some(pending_frame) = doc.pending_frame.take() {
+                frame.combine(pending_frame);
             }
-        }
 
-        if !doc.can_render() {
-            // TODO: this happens if we are building the first scene asynchronously and
-            // scroll at the same time. we should keep track of the fact that we skipped
-            // composition here and do it as soon as we receive the scene.
-            op.render = false;
-            op.composite = false;
+            if !doc.visible {
+                frame.notify_hidden(document_id, &*self.notifier);
+            }
+            if !doc.visible || doc.priority == DocumentPriority::Low {
+                doc.pending_frame = Some(frame);
+                return;
+            }
+
+            frame
+        };
+
+        self.build_frame(document_id, frame, frame_counter, profile_counters);
+    }
+
+    /// Build the pending frames of the visible documents that are overdue
+    /// at `now`, or all of them if `now` is `u64::MAX`.
+    fn build_pending_frames(
+        &mut self,
+        now: u64,
+        frame_counter: &mut u32,
+        profile_counters: &mut BackendProfileCounters,
+    ) {
+        let mut pending_documents = self.documents
+            .iter()
+            .filter(|&(_, doc)| {
+                doc.visible && doc.pending_frame.map_or(false, |frame| frame.is_overdue(now))
+            })
+            .map(|(&document_id, doc)| (doc.view.layer, document_id))
+            .collect::<Vec<_>>();
+        pending_documents.sort_by_key(|&(layer, _)| layer);
+
+        for (_, document_id) in pending_documents {
+            let frame = self.documents
+                .get_mut(&document_id)
+                .and_then(|doc| doc.pending_frame.take())
+                .unwrap();
+            self.build_frame(document_id, frame, frame_counter, profile_counters);
         }
+    }
 
-        debug_assert!(op.render || !op.composite);
+    fn build_frame(
+        &mut self,
+        document_id: DocumentId,
+        frame: PendingFrame,
+        frame_counter: &mut u32,
+        profile_counters: &mut BackendProfileCounters,
+    ) {
+        let doc = self.documents.get_mut(&document_id).unwrap();
 
         let mut render_time = None;
-        if op.render && doc.has_pixels() {
+        if doc.has_pixels() {
             profile_scope!("generate frame");
 
             *frame_counter += 1;
@@ -1110,10 +1309,11 @@ impl RenderBackend {
                 let render_start_time = precise_time_ns();
 
                 let rendered_document = doc.render(
+                    document_id,
                     &mut self.resource_cache,
                     &mut self.gpu_cache,
                     &mut profile_counters.resources,
-                    op.build || has_built_scene,
+                    frame.is_new_scene,
                 );
 
                 debug!("generated frame for document {:?} with {} passes",
@@ -1140,7 +1340,11 @@ impl RenderBackend {
             );
             self.result_tx.send(msg).unwrap();
             profile_counters.reset();
-        } else if op.render {
+
+            if let Some((used_bytes, budget_bytes)) = self.resource_cache.texture_cache_budget_overrun() {
+                self.notifier.texture_cache_budget_exceeded(used_bytes, budget_bytes);
+            }
+        } else {
             // WR-internal optimization to avoid doing a bunch of render work if
             // there's no pixels. We still want to pretend to render and request
             // a composite to make sure that the callbacks (particularly the
@@ -1149,8 +1353,8 @@ impl RenderBackend {
             self.result_tx.send(msg).unwrap();
         }
 
-        if transaction_msg.generate_frame {
-            self.notifier.new_frame_ready(document_id, op.scroll, op.composite, render_time);
+        if frame.notify {
+            self.notifier.new_frame_ready(document_id, frame.scroll, frame.composite, render_time);
         }
     }
 
@@ -1279,6 +1483,7 @@ impl ToDebugString for SpecificDisplayItem {
             SpecificDisplayItem::ClearRectangle => String::from("clear_rectangle"),
             SpecificDisplayItem::Clip(..) => String::from("clip"),
             SpecificDisplayItem::ClipChain(..) => String::from("clip_chain"),
+            SpecificDisplayItem::ConicGradient(..) => String::from("conic_gradient"),
             SpecificDisplayItem::Gradient(..) => String::from("gradient"),
             SpecificDisplayItem::Iframe(..) => String::from("iframe"),
             SpecificDisplayItem::Image(..) => String::from("image"),
@@ -1293,6 +1498,7 @@ impl ToDebugString for SpecificDisplayItem {
             SpecificDisplayItem::Rectangle(..) => String::from("rectangle"),
             SpecificDisplayItem::ScrollFrame(..) => String::from("scroll_frame"),
             SpecificDisplayItem::SetGradientStops => String::from("set_gradient_stops"),
+            SpecificDisplayItem::SetFilterPrimitives => String::from("set_filter_primitives"),
             SpecificDisplayItem::StickyFrame(..) => String::from("sticky_frame"),
             SpecificDisplayItem::Text(..) => String::from("text"),
             SpecificDisplayItem::YuvImage(..) => String::from("yuv_image"),
@@ -1328,6 +1534,7 @@ impl RenderBackend {
             }
             if config.bits.contains(CaptureBits::FRAME) {
                 let rendered_document = doc.render(
+                    id,
                     &mut self.resource_cache,
                     &mut self.gpu_cache,
                     &mut profile_counters.resources,
@@ -1439,18 +1646,26 @@ impl RenderBackend {
                 render_on_scroll: None,
                 dynamic_properties: SceneProperties::new(),
                 hit_tester: None,
+                visible: true,
+                priority: DocumentPriority::Normal,
+                pending_frame: None,
             };
 
             let frame_name = format!("frame-{}-{}", (id.0).0, id.1);
             let render_doc = match CaptureConfig::deserialize::<Frame, _>(root, frame_name) {
                 Some(frame) => {
                     info!("\tloaded a built frame with {} passes", frame.passes.len());
-                    RenderedDocument { frame, is_new_scene: true }
+                    RenderedDocument {
+                        frame,
+                        is_new_scene: true,
+                        priority: DocumentPriority::Normal,
+                    }
                 }
                 None => {
                     last_scene_id += 1;
                     doc.build_scene(&mut self.resource_cache, last_scene_id);
                     doc.render(
+                        id,
                         &mut self.resource_cache,
                         &mut self.gpu_cache,
                         &mut profile_counters.resources,
@@ -1477,3 +1692,77 @@ impl RenderBackend {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use api::{DocumentId, IdNamespace, RenderNotifier};
+    use render_backend::{MAX_FRAME_DEFER_NS, PendingFrame};
+    use std::sync::{Arc, Mutex};
+
+    struct TestNotifier {
+        frames: Arc<Mutex<Vec<(DocumentId, bool)>>>,
+    }
+
+    impl RenderNotifier for TestNotifier {
+        fn clone(&self) -> Box<RenderNotifier> {
+            Box::new(TestNotifier { frames: self.frames.clone() })
+        }
+
+        fn wake_up(&self) {}
+
+        fn new_frame_ready(
+            &self,
+            document_id: DocumentId,
+            _scrolled: bool,
+            composite_needed: bool,
+            _render_time_ns: Option<u64>,
+        ) {
+            self.frames.lock().unwrap().push((document_id, composite_needed));
+        }
+    }
+
+    fn pending_frame(deferred_at: u64) -> PendingFrame {
+        PendingFrame {
+            is_new_scene: false,
+            scroll: false,
+            composite: true,
+            notify: true,
+            deferred_at,
+        }
+    }
+
+    #[test]
+    fn hidden_frames_are_notified_once() {
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let notifier = TestNotifier { frames: frames.clone() };
+        let document_id = DocumentId(IdNamespace(0), 0);
+
+        let mut frame = pending_frame(0);
+        frame.notify_hidden(document_id, &notifier);
+        assert_eq!(*frames.lock().unwrap(), vec![(document_id, false)]);
+        assert!(!frame.notify);
+
+        // A request that arrives while the document is hidden is combined
+        // with the pending frame, and gets its own notification.
+        frame.notify_hidden(document_id, &notifier);
+        frame.combine(pending_frame(1));
+        frame.notify_hidden(document_id, &notifier);
+        assert_eq!(frames.lock().unwrap().len(), 2);
+        assert!(frame.composite);
+    }
+
+    #[test]
+    fn deferred_frames_are_eventually_overdue() {
+        let mut frame = pending_frame(1000);
+        assert!(!frame.is_overdue(1000));
+        assert!(!frame.is_overdue(1000 + MAX_FRAME_DEFER_NS - 1));
+
+        // Newer requests don't push the deadline of the pending frame back,
+        // so a stream of them can't starve the document.
+        frame.combine(pending_frame(1000 + MAX_FRAME_DEFER_NS / 2));
+        assert!(frame.is_overdue(1000 + MAX_FRAME_DEFER_NS));
+
+        // A clock reading from before the deferral doesn't underflow.
+        assert!(!frame.is_overdue(0));
+    }
+}
@@ -3,6 +3,8 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use api::{DeviceIntPoint, DeviceIntRect, DeviceIntSize, DeviceSize, DeviceIntSideOffsets, ImageDescriptor, ImageFormat};
+use api::{CompositeOperator, DevicePixelScale, DeviceVector2D, FilterPrimitive, FilterPrimitiveInput};
+use api::{PremultipliedColorF, TransferFunction};
 #[cfg(feature = "pathfinder")]
 use api::FontRenderMode;
 use border::BorderCacheKey;
@@ -218,6 +220,103 @@ impl BlurTask {
     }
 }
 
+// An image read by an SVG filter task.
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "capture", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub struct SvgFilterTaskInput {
+    pub task_id: RenderTaskId,
+    // Only the alpha channel of the task is read (SourceAlpha).
+    pub alpha_only: bool,
+}
+
+// The operation of a single SVG filter primitive, resolved to
+// device space.
+#[derive(Debug)]
+#[cfg_attr(feature = "capture", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub enum SvgFilterInfo {
+    Flood(PremultipliedColorF),
+    Offset(DeviceVector2D),
+    ComponentTransfer([TransferFunction; 4]),
+    Composite(CompositeOperator),
+    Merge,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "capture", derive(Serialize))]
+#[cfg_attr(feature = "replay", derive(Deserialize))]
+pub struct SvgFilterTask {
+    pub info: SvgFilterInfo,
+    pub inputs: Vec<SvgFilterTaskInput>,
+    pub extra_data_handle: GpuCacheHandle,
+    pub uv_rect_handle: GpuCacheHandle,
+    uv_rect_kind: UvRectKind,
+}
+
+impl SvgFilterTask {
+    // Write the parameters of the primitive, which are read by
+    // the cs_svg_filter shader.
+    fn write_gpu_blocks(&mut self, gpu_cache: &mut GpuCache) {
+        let mut request = match gpu_cache.request(&mut self.extra_data_handle) {
+            Some(request) => request,
+            None => return,
+        };
+
+        match self.info {
+            SvgFilterInfo::Flood(color) => {
+                request.push(color);
+            }
+            SvgFilterInfo::Offset(offset) => {
+                request.push([offset.x, offset.y, 0.0, 0.0]);
+            }
+            SvgFilterInfo::Composite(CompositeOperator::Arithmetic(k)) => {
+                request.push(k);
+            }
+            SvgFilterInfo::Composite(..) |
+            SvgFilterInfo::Merge => {
+                request.push([0.0; 4]);
+            }
+            SvgFilterInfo::ComponentTransfer(ref functions) => {
+                // One header block per channel, followed by the
+                // values of any table functions, four per block.
+                // Note: has to match `TRANSFER_*` in cs_svg_filter.glsl.
+                let mut values: Vec<f32> = Vec::new();
+                {
+                    let mut push_table = |table: &[f32]| {
+                        let first_block = functions.len() + values.len() / 4;
+                        values.extend_from_slice(table);
+                        while values.len() % 4 != 0 {
+                            values.push(0.0);
+                        }
+                        first_block as f32
+                    };
+                    for function in functions {
+                        request.push(match *function {
+                            TransferFunction::Identity => [0.0; 4],
+                            TransferFunction::Table(ref table) => {
+                                [1.0, table.len() as f32, push_table(table), 0.0]
+                            }
+                            TransferFunction::Discrete(ref table) => {
+                                [2.0, table.len() as f32, push_table(table), 0.0]
+                            }
+                            TransferFunction::Linear { slope, intercept } => {
+                                [3.0, slope, intercept, 0.0]
+                            }
+                            TransferFunction::Gamma { amplitude, exponent, offset } => {
+                                [4.0, amplitude, exponent, offset]
+                            }
+                        });
+                    }
+                }
+                for block in values.chunks(4) {
+                    request.push([block[0], block[1], block[2], block[3]]);
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 #[cfg(feature = "pathfinder")]
 #[cfg_attr(feature = "capture", derive(Serialize))]
@@ -287,6 +386,7 @@ pub enum RenderTaskKind {
     Scaling(RenderTargetKind),
     Blit(BlitTask),
     Border(BorderTask),
+    SvgFilter(SvgFilterTask),
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
@@ -453,7 +553,8 @@ impl RenderTask {
                     ClipSource::Rectangle(..) |
                     ClipSource::RoundedRectangle(..) |
                     ClipSource::Image(..) |
-                    ClipSource::LineDecoration(..) => {}
+                    ClipSource::LineDecoration(..) |
+                    ClipSource::Path(..) => {}
                 }
             }
         }
@@ -565,6 +666,100 @@ impl RenderTask {
         }
     }
 
+    // Construct the render tasks of an SVG filter graph, and return the
+    // task holding the result of the last primitive.
+    //
+    // Each primitive gets its own task, which has the task of the previous
+    // primitive as its only child. This places the primitives in consecutive
+    // passes, in order, with the source picture in the pass before the first
+    // one. A task can read the output of the pass right before it directly;
+    // any older input is saved for the rest of the frame instead.
+    //
+    //    SourceGraphic: The picture of the stacking context contents.
+    //           ^
+    //           |
+    //    SvgFilter(s): One per primitive, all with the size of the picture.
+    //           |
+    //           +---- The last one is the input to the primitive shader.
+    //
+    pub fn new_svg_filter_graph(
+        primitives: &[FilterPrimitive],
+        src_task_id: RenderTaskId,
+        render_tasks: &mut RenderTaskTree,
+        device_pixel_scale: DevicePixelScale,
+    ) -> RenderTaskId {
+        let (target_size, uv_rect_kind) = {
+            let src_task = &render_tasks[src_task_id];
+            (src_task.get_dynamic_size(), src_task.uv_rect_kind())
+        };
+
+        let mut results: Vec<RenderTaskId> = Vec::with_capacity(primitives.len());
+        let mut prev_task_id = src_task_id;
+
+        for primitive in primitives {
+            let mut inputs = Vec::new();
+            for input in primitive.inputs() {
+                let (task_id, alpha_only) = match input {
+                    FilterPrimitiveInput::SourceGraphic => (src_task_id, false),
+                    FilterPrimitiveInput::SourceAlpha => (src_task_id, true),
+                    FilterPrimitiveInput::Result(index) => (results[index], false),
+                };
+                if task_id != prev_task_id {
+                    render_tasks[task_id].mark_for_saving();
+                }
+                inputs.push(SvgFilterTaskInput {
+                    task_id,
+                    alpha_only,
+                });
+            }
+
+            let info = match *primitive {
+                FilterPrimitive::Flood { color } => {
+                    SvgFilterInfo::Flood(color.premultiplied())
+                }
+                FilterPrimitive::Offset { offset, .. } => {
+                    SvgFilterInfo::Offset(DeviceVector2D::new(
+                        offset.x * device_pixel_scale.0,
+                        offset.y * device_pixel_scale.0,
+                    ))
+                }
+                FilterPrimitive::ComponentTransfer { ref red, ref green, ref blue, ref alpha, .. } => {
+                    SvgFilterInfo::ComponentTransfer([
+                        red.clone(),
+                        green.clone(),
+                        blue.clone(),
+                        alpha.clone(),
+                    ])
+                }
+                FilterPrimitive::Composite { operator, .. } => {
+                    SvgFilterInfo::Composite(operator)
+                }
+                FilterPrimitive::Merge { .. } => {
+                    SvgFilterInfo::Merge
+                }
+            };
+
+            let task = RenderTask {
+                children: vec![prev_task_id],
+                location: RenderTaskLocation::Dynamic(None, Some(target_size)),
+                kind: RenderTaskKind::SvgFilter(SvgFilterTask {
+                    info,
+                    inputs,
+                    extra_data_handle: GpuCacheHandle::new(),
+                    uv_rect_handle: GpuCacheHandle::new(),
+                    uv_rect_kind,
+                }),
+                clear_mode: ClearMode::Transparent,
+                saved_index: None,
+            };
+
+            prev_task_id = render_tasks.add(task);
+            results.push(prev_task_id);
+        }
+
+        prev_task_id
+    }
+
     pub fn new_border(
         size: DeviceIntSize,
         instances: Vec<BorderInstance>,
@@ -637,6 +832,10 @@ impl RenderTask {
                 task.uv_rect_kind
             }
 
+            RenderTaskKind::SvgFilter(ref task) => {
+                task.uv_rect_kind
+            }
+
             RenderTaskKind::ClipRegion(..) |
             RenderTaskKind::Glyph(_) |
             RenderTaskKind::Border(..) |
@@ -695,6 +894,7 @@ impl RenderTask {
             RenderTaskKind::Readback(..) |
             RenderTaskKind::Scaling(..) |
             RenderTaskKind::Border(..) |
+            RenderTaskKind::SvgFilter(..) |
             RenderTaskKind::Blit(..) => {
                 [0.0; 3]
             }
@@ -731,6 +931,9 @@ impl RenderTask {
             RenderTaskKind::HorizontalBlur(ref info) => {
                 gpu_cache.get_address(&info.uv_rect_handle)
             }
+            RenderTaskKind::SvgFilter(ref info) => {
+                gpu_cache.get_address(&info.uv_rect_handle)
+            }
             RenderTaskKind::ClipRegion(..) |
             RenderTaskKind::Readback(..) |
             RenderTaskKind::Scaling(..) |
@@ -809,6 +1012,7 @@ impl RenderTask {
             }
 
             RenderTaskKind::Border(..) |
+            RenderTaskKind::SvgFilter(..) |
             RenderTaskKind::Picture(..) => {
                 RenderTargetKind::Color
             }
@@ -835,6 +1039,7 @@ impl RenderTask {
             RenderTaskKind::ClipRegion(..) |
             RenderTaskKind::Blit(..) |
             RenderTaskKind::Border(..) |
+            RenderTaskKind::SvgFilter(..) |
             RenderTaskKind::Glyph(..) => false,
 
             // TODO(gw): For now, we've disabled the shared clip mask
@@ -867,6 +1072,10 @@ impl RenderTask {
             RenderTaskKind::Picture(ref mut info) => {
                 (&mut info.uv_rect_handle, info.uv_rect_kind)
             }
+            RenderTaskKind::SvgFilter(ref mut info) => {
+                info.write_gpu_blocks(gpu_cache);
+                (&mut info.uv_rect_handle, info.uv_rect_kind)
+            }
             RenderTaskKind::Readback(..) |
             RenderTaskKind::Scaling(..) |
             RenderTaskKind::Blit(..) |
@@ -932,6 +1141,10 @@ impl RenderTask {
             RenderTaskKind::Glyph(..) => {
                 pt.new_level("Glyph".to_owned());
             }
+            RenderTaskKind::SvgFilter(ref task) => {
+                pt.new_level("SvgFilter".to_owned());
+                pt.add_item(format!("info: {:?}", task.info));
+            }
         }
 
         pt.add_item(format!("clear to: {:?}", self.clear_mode));
@@ -1185,6 +1398,14 @@ impl RenderTaskCache {
         texture_cache.get(&cache_entry.handle)
     }
 
+    #[allow(dead_code)]
+    pub fn get_texture_cache_handle(&self, key: &RenderTaskCacheKey)
+                                    -> Option<&TextureCacheHandle> {
+        self.map
+            .get(key)
+            .map(|handle| &self.cache_entries.get(handle).handle)
+    }
+
     #[allow(dead_code)]
     pub fn cache_item_is_allocated_for_render_task(&self,
                                                    texture_cache: &TextureCache,
@@ -28,7 +28,7 @@ use device::{ProgramCache, ReadPixelsFormat};
 use euclid::{rect, Transform3D};
 use frame_builder::{ChasePrimitive, FrameBuilderConfig};
 use gleam::gl;
-use glyph_rasterizer::{GlyphFormat, GlyphRasterizer};
+use glyph_rasterizer::{GlyphDiskCacheOptions, GlyphFormat, GlyphRasterizer};
 use gpu_cache::{GpuBlockData, GpuCacheUpdate, GpuCacheUpdateList};
 #[cfg(feature = "pathfinder")]
 use gpu_glyph_renderer::GpuGlyphRenderer;
@@ -104,6 +104,10 @@ const GPU_TAG_BRUSH_RADIAL_GRADIENT: GpuProfileTag = GpuProfileTag {
     label: "B_RadialGradient",
     color: debug_colors::LIGHTPINK,
 };
+const GPU_TAG_BRUSH_CONIC_GRADIENT: GpuProfileTag = GpuProfileTag {
+    label: "B_ConicGradient",
+    color: debug_colors::PLUM,
+};
 const GPU_TAG_BRUSH_YUV_IMAGE: GpuProfileTag = GpuProfileTag {
     label: "B_YuvImage",
     color: debug_colors::DARKGREEN,
@@ -152,6 +156,10 @@ const GPU_TAG_BLUR: GpuProfileTag = GpuProfileTag {
     label: "Blur",
     color: debug_colors::VIOLET,
 };
+const GPU_TAG_SVG_FILTER: GpuProfileTag = GpuProfileTag {
+    label: "SvgFilter",
+    color: debug_colors::LEMONCHIFFON,
+};
 const GPU_TAG_BLIT: GpuProfileTag = GpuProfileTag {
     label: "Blit",
     color: debug_colors::LIME,
@@ -183,6 +191,7 @@ impl BatchKind {
                     BrushBatchKind::MixBlend { .. } => "Brush (Composite)",
                     BrushBatchKind::YuvImage(..) => "Brush (YuvImage)",
                     BrushBatchKind::RadialGradient => "Brush (RadialGradient)",
+                    BrushBatchKind::ConicGradient => "Brush (ConicGradient)",
                     BrushBatchKind::LinearGradient => "Brush (LinearGradient)",
                 }
             }
@@ -201,6 +210,7 @@ impl BatchKind {
                     BrushBatchKind::MixBlend { .. } => GPU_TAG_BRUSH_MIXBLEND,
                     BrushBatchKind::YuvImage(..) => GPU_TAG_BRUSH_YUV_IMAGE,
                     BrushBatchKind::RadialGradient => GPU_TAG_BRUSH_RADIAL_GRADIENT,
+                    BrushBatchKind::ConicGradient => GPU_TAG_BRUSH_CONIC_GRADIENT,
                     BrushBatchKind::LinearGradient => GPU_TAG_BRUSH_LINEAR_GRADIENT,
                 }
             }
@@ -234,6 +244,16 @@ fn flag_changed(before: DebugFlags, after: DebugFlags, select: DebugFlags) -> Op
     }
 }
 
+// Compressed texture cache entries live in 2D textures, since
+// there is no way to upload compressed data to texture arrays.
+fn cache_texture_target(format: ImageFormat) -> TextureTarget {
+    if format.is_compressed() {
+        TextureTarget::Default
+    } else {
+        TextureTarget::Array
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub enum ShaderColorMode {
@@ -366,6 +386,58 @@ pub(crate) mod desc {
         ],
     };
 
+    pub const SVG_FILTER: VertexDescriptor = VertexDescriptor {
+        vertex_attributes: &[
+            VertexAttribute {
+                name: "aPosition",
+                count: 2,
+                kind: VertexAttributeKind::F32,
+            },
+        ],
+        instance_attributes: &[
+            VertexAttribute {
+                name: "aFilterRenderTaskAddress",
+                count: 1,
+                kind: VertexAttributeKind::I32,
+            },
+            VertexAttribute {
+                name: "aFilterInput1TaskAddress",
+                count: 1,
+                kind: VertexAttributeKind::I32,
+            },
+            VertexAttribute {
+                name: "aFilterInput2TaskAddress",
+                count: 1,
+                kind: VertexAttributeKind::I32,
+            },
+            VertexAttribute {
+                name: "aFilterKind",
+                count: 1,
+                kind: VertexAttributeKind::U16,
+            },
+            VertexAttribute {
+                name: "aFilterInputFlags",
+                count: 1,
+                kind: VertexAttributeKind::U16,
+            },
+            VertexAttribute {
+                name: "aFilterGenericInt",
+                count: 1,
+                kind: VertexAttributeKind::U16,
+            },
+            VertexAttribute {
+                name: "aPad",
+                count: 1,
+                kind: VertexAttributeKind::U16,
+            },
+            VertexAttribute {
+                name: "aFilterExtraDataAddress",
+                count: 2,
+                kind: VertexAttributeKind::U16,
+            },
+        ],
+    };
+
     pub const BORDER: VertexDescriptor = VertexDescriptor {
         vertex_attributes: &[
             VertexAttribute {
@@ -606,6 +678,7 @@ pub(crate) enum VertexArrayKind {
     VectorStencil,
     VectorCover,
     Border,
+    SvgFilter,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -859,7 +932,8 @@ impl SourceTextureResolver {
                     .expect(&format!("BUG: External image should be resolved by now"));
                 device.bind_external_texture(sampler, texture);
             }
-            SourceTexture::TextureCache(index) => {
+            SourceTexture::TextureCache(index) |
+            SourceTexture::CompressedTextureCache(index) => {
                 let texture = &self.cache_texture_map[index.0];
                 device.bind_texture(sampler, texture);
             }
@@ -891,7 +965,8 @@ impl SourceTextureResolver {
             SourceTexture::External(..) => {
                 panic!("BUG: External textures cannot be resolved, they can only be bound.");
             }
-            SourceTexture::TextureCache(index) => {
+            SourceTexture::TextureCache(index) |
+            SourceTexture::CompressedTextureCache(index) => {
                 Some(&self.cache_texture_map[index.0])
             }
             SourceTexture::RenderTaskCache(saved_index) => {
@@ -1083,6 +1158,10 @@ impl CacheTexture {
     }
 
     fn update(&mut self, device: &mut Device, updates: &GpuCacheUpdateList) {
+        if let CacheBus::Scatter { .. } = self.bus {
+            self.blit_moved_blocks(device, updates);
+        }
+
         match self.bus {
             CacheBus::PixelBuffer { ref mut rows, ref mut cpu_blocks, .. } => {
                 for update in &updates.updates {
@@ -1114,6 +1193,27 @@ impl CacheTexture {
                                 data[i] = updates.blocks[block_index + i];
                             }
                         }
+                        GpuCacheUpdate::Move {
+                            src,
+                            dst,
+                            block_count,
+                        } => {
+                            // Blocks are always moved to a lower row.
+                            debug_assert!(dst.v < src.v);
+                            while rows.len() <= src.v as usize {
+                                rows.push(CacheRow::new());
+                                cpu_blocks
+                                    .extend_from_slice(&[GpuBlockData::EMPTY; MAX_VERTEX_TEXTURE_WIDTH]);
+                            }
+
+                            rows[dst.v as usize].is_dirty = true;
+
+                            let src_offset = src.v as usize * MAX_VERTEX_TEXTURE_WIDTH + src.u as usize;
+                            let dst_offset = dst.v as usize * MAX_VERTEX_TEXTURE_WIDTH + dst.u as usize;
+                            for i in 0 .. block_count {
+                                cpu_blocks[dst_offset + i] = cpu_blocks[src_offset + i];
+                            }
+                        }
                     }
                 }
             }
@@ -1143,6 +1243,8 @@ impl CacheTexture {
                                 position_data[block_index + i] = [x as _, y as _];
                             }
                         }
+                        // Already applied by `blit_moved_blocks`.
+                        GpuCacheUpdate::Move { .. } => {}
                     }
                 }
 
@@ -1190,7 +1292,7 @@ impl CacheTexture {
 
                 rows_dirty
             }
-            CacheBus::Scatter { ref program, ref vao, count, .. } => {
+            CacheBus::Scatter { ref program, ref vao, ref mut count, .. } => {
                 device.disable_depth();
                 device.set_blend(false);
                 device.bind_program(program);
@@ -1199,11 +1301,74 @@ impl CacheTexture {
                     Some((&self.texture, 0)),
                     Some(self.texture.get_dimensions()),
                 );
-                device.draw_nonindexed_points(0, count as _);
+                device.draw_nonindexed_points(0, *count as _);
+                *count = 0;
                 0
             }
         }
     }
+
+    // With the scatter bus, the moved blocks are copied within the texture.
+    // This has to happen after the scattered updates of the previous lists,
+    // which may have written to the source blocks, and before the ones of
+    // this list, which may reuse them.
+    fn blit_moved_blocks(&mut self, device: &mut Device, updates: &GpuCacheUpdateList) {
+        let mut moves = updates.updates
+            .iter()
+            .filter_map(|update| match *update {
+                GpuCacheUpdate::Move { src, dst, block_count } => Some((src, dst, block_count)),
+                GpuCacheUpdate::Copy { .. } => None,
+            })
+            .peekable();
+        if moves.peek().is_none() {
+            return;
+        }
+
+        self.flush(device);
+
+        device.bind_read_target(Some((&self.texture, 0)));
+        device.bind_draw_target(
+            Some((&self.texture, 0)),
+            Some(self.texture.get_dimensions()),
+        );
+        for (src, dst, block_count) in moves {
+            let size = DeviceIntSize::new(block_count as i32, 1);
+            device.blit_render_target(
+                DeviceIntRect::new(DeviceIntPoint::new(src.u as i32, src.v as i32), size),
+                DeviceIntRect::new(DeviceIntPoint::new(dst.u as i32, dst.v as i32), size),
+            );
+        }
+        device.bind_read_target(None);
+    }
+
+    // Reduce the height of the texture after a compaction of the cache.
+    fn shrink(&mut self, device: &mut Device, height: u32) {
+        let new_size = DeviceUintSize::new(MAX_VERTEX_TEXTURE_WIDTH as _, height);
+
+        match self.bus {
+            CacheBus::PixelBuffer { ref mut rows, ref mut cpu_blocks, .. } => {
+                device.init_texture::<u8>(
+                    &mut self.texture,
+                    new_size.width,
+                    new_size.height,
+                    TextureFilter::Nearest,
+                    None,
+                    1,
+                    None,
+                );
+
+                // Upload the remaining rows again during the next flush.
+                rows.truncate(height as usize);
+                cpu_blocks.truncate(height as usize * MAX_VERTEX_TEXTURE_WIDTH);
+                for row in rows.iter_mut() {
+                    row.is_dirty = true;
+                }
+            }
+            CacheBus::Scatter { .. } => {
+                device.resize_renderable_texture(&mut self.texture, new_size);
+            }
+        }
+    }
 }
 
 struct VertexDataTexture {
@@ -1333,6 +1498,7 @@ pub struct RendererVAOs {
     clip_vao: VAO,
     dash_and_dot_vao: VAO,
     border_vao: VAO,
+    svg_filter_vao: VAO,
 }
 
 /// The renderer is responsible for submitting to the GPU the work prepared by the
@@ -1351,6 +1517,7 @@ pub struct Renderer {
     pub gpu_glyph_renderer: GpuGlyphRenderer,
 
     max_texture_size: u32,
+    supports_dual_source_blending: bool,
     max_recorded_profiles: usize,
 
     clear_color: Option<ColorF>,
@@ -1485,8 +1652,9 @@ impl Renderer {
             options.cached_programs.take(),
         );
 
+        let supports_dual_source_blending = device.supports_extension("GL_ARB_blend_func_extended");
         let ext_dual_source_blending = !options.disable_dual_source_blending &&
-            device.supports_extension("GL_ARB_blend_func_extended");
+            supports_dual_source_blending;
 
         let device_max_size = device.max_texture_size();
         // 512 is the minimum that the texture cache can work with.
@@ -1507,6 +1675,7 @@ impl Renderer {
             ),
             min_texture_size,
         );
+        let compressed_formats = device.supported_compressed_formats();
 
         register_thread_with_profiler("Compositor".to_owned());
 
@@ -1625,6 +1794,7 @@ impl Renderer {
 
         let blur_vao = device.create_vao_with_new_instances(&desc::BLUR, &prim_vao);
         let clip_vao = device.create_vao_with_new_instances(&desc::CLIP, &prim_vao);
+        let svg_filter_vao = device.create_vao_with_new_instances(&desc::SVG_FILTER, &prim_vao);
         let border_vao =
             device.create_vao_with_new_instances(&desc::BORDER, &prim_vao);
         let dash_and_dot_vao =
@@ -1692,6 +1862,7 @@ impl Renderer {
             });
         let sampler = options.sampler;
         let enable_render_on_scroll = options.enable_render_on_scroll;
+        let texture_cache_budget = options.texture_cache_budget;
 
         let blob_image_handler = options.blob_image_handler.take();
         let thread_listener_for_render_backend = thread_listener.clone();
@@ -1699,7 +1870,10 @@ impl Renderer {
         let scene_builder_hooks = options.scene_builder_hooks;
         let rb_thread_name = format!("WRRenderBackend#{}", options.renderer_id.unwrap_or(0));
         let scene_thread_name = format!("WRSceneBuilder#{}", options.renderer_id.unwrap_or(0));
-        let glyph_rasterizer = GlyphRasterizer::new(workers)?;
+        let mut glyph_rasterizer = GlyphRasterizer::new(workers)?;
+        if let Some(glyph_disk_cache) = options.glyph_disk_cache.take() {
+            glyph_rasterizer.enable_disk_cache(glyph_disk_cache);
+        }
 
         let (scene_builder, scene_tx, scene_rx) = SceneBuilder::new(
             config,
@@ -1725,7 +1899,11 @@ impl Renderer {
                 thread_listener.thread_started(&rb_thread_name);
             }
 
-            let texture_cache = TextureCache::new(max_device_size);
+            let texture_cache = TextureCache::new(
+                max_device_size,
+                texture_cache_budget,
+                compressed_formats,
+            );
             let resource_cache = ResourceCache::new(
                 texture_cache,
                 glyph_rasterizer,
@@ -1777,6 +1955,7 @@ impl Renderer {
             #[cfg(feature = "debug_renderer")]
             new_scene_indicator: ChangeIndicator::new(),
             max_texture_size: max_device_size,
+            supports_dual_source_blending,
             max_recorded_profiles: options.max_recorded_profiles,
             clear_color: options.clear_color,
             enable_clear_scissor: options.enable_clear_scissor,
@@ -1789,6 +1968,7 @@ impl Renderer {
                 clip_vao,
                 dash_and_dot_vao,
                 border_vao,
+                svg_filter_vao,
             },
             transforms_texture,
             prim_header_i_texture,
@@ -1823,6 +2003,12 @@ impl Renderer {
         self.max_texture_size
     }
 
+    /// Returns true if the device can draw subpixel text with dual-source
+    /// blending, whether or not it has been disabled in the options.
+    pub fn supports_dual_source_blending(&self) -> bool {
+        self.supports_dual_source_blending
+    }
+
     pub fn get_graphics_api_info(&self) -> GraphicsApiInfo {
         GraphicsApiInfo {
             kind: GraphicsApi::OpenGL,
@@ -1900,6 +2086,17 @@ impl Renderer {
                     self.pending_texture_updates.push(texture_update_list);
                     self.backend_profile_counters = profile_counters;
                 }
+                ResultMsg::HideDocument(document_id) => {
+                    // Hidden documents are not rendered until they are shown
+                    // again, with a new frame.
+                    if let Some(pos) = self.active_documents.iter().position(|&(id, _)| id == document_id) {
+                        // As above, the frame may have to be drawn to update the texture cache.
+                        if self.active_documents[pos].1.frame.must_be_drawn() {
+                            self.render_impl(None).ok();
+                        }
+                        self.active_documents.remove(pos);
+                    }
+                }
                 ResultMsg::UpdateGpuCache(list) => {
                     self.pending_gpu_cache_updates.push(list);
                 }
@@ -1994,6 +2191,11 @@ impl Renderer {
             "LineDecorations",
             target.clip_batcher.line_decorations.len(),
         );
+        debug_target.add(
+            debug_server::BatchKind::Clip,
+            "Paths",
+            target.clip_batcher.paths.len(),
+        );
         debug_target.add(
             debug_server::BatchKind::Cache,
             "Vertical Blur",
@@ -2040,6 +2242,13 @@ impl Renderer {
             "Horizontal Blur",
             target.horizontal_blurs.len(),
         );
+        for &(_, ref instances) in &target.svg_filters {
+            debug_target.add(
+                debug_server::BatchKind::Cache,
+                "SVG Filter",
+                instances.len(),
+            );
+        }
 
         for alpha_batch_container in &target.alpha_batch_containers {
             for batch in alpha_batch_container
@@ -2152,7 +2361,8 @@ impl Renderer {
             DebugCommand::EnableNewSceneIndicator(enable) => {
                 self.set_debug_flag(DebugFlags::NEW_SCENE_INDICATOR, enable);
             }
-            DebugCommand::EnableDualSourceBlending(_) => {
+            DebugCommand::EnableDualSourceBlending(_) |
+            DebugCommand::EnableCompressedTextures(_) => {
                 panic!("Should be handled by render backend");
             }
             DebugCommand::FetchDocuments |
@@ -2305,8 +2515,11 @@ impl Renderer {
 
             //Note: another borrowck dance
             let mut active_documents = mem::replace(&mut self.active_documents, Vec::default());
-            // sort by the document layer id
-            active_documents.sort_by_key(|&(_, ref render_doc)| render_doc.frame.layer);
+            // sort by the document layer id, and submit the work of the low
+            // priority documents after the other documents of their layer
+            active_documents.sort_by_key(|&(_, ref render_doc)| {
+                (render_doc.frame.layer, render_doc.priority)
+            });
 
             // don't clear the framebuffer if one of the rendered documents will overwrite it
             if let Some(framebuffer_size) = framebuffer_size {
@@ -2472,6 +2685,15 @@ impl Renderer {
             self.renderer_errors.push(RendererError::MaxTextureSize);
         }
 
+        // The lists coming from the backend carry the height of the cache, which
+        // is reduced when it gets compacted. The other lists have a null frame id.
+        let backend_height = self
+            .pending_gpu_cache_updates
+            .iter()
+            .rev()
+            .find(|list| list.frame_id != FrameId::new(0))
+            .map(|list| list.height);
+
         // Note: if we decide to switch to scatter-style GPU cache update
         // permanently, we can have this code nicer with `BufferUploader` kind
         // of helper, similarly to how `TextureUploader` API is used.
@@ -2490,7 +2712,15 @@ impl Renderer {
                 .update(&mut self.device, &update_list);
         }
 
-        let updated_rows = self.gpu_cache_texture.flush(&mut self.device);
+        let mut updated_rows = self.gpu_cache_texture.flush(&mut self.device);
+
+        // Only shrink the texture once the moved blocks have been copied.
+        if let Some(height) = backend_height {
+            if height < self.gpu_cache_texture.get_height() {
+                self.gpu_cache_texture.shrink(&mut self.device, height);
+                updated_rows += self.gpu_cache_texture.flush(&mut self.device);
+            }
+        }
 
         let counters = &mut self.backend_profile_counters.resources.gpu_cache;
         counters.updated_rows.set(updated_rows);
@@ -2529,7 +2759,7 @@ impl Renderer {
                         let CacheTextureId(cache_texture_index) = update.id;
                         if self.texture_resolver.cache_texture_map.len() == cache_texture_index {
                             // Create a new native texture, as requested by the texture cache.
-                            let texture = self.device.create_texture(TextureTarget::Array, format);
+                            let texture = self.device.create_texture(cache_texture_target(format), format);
                             self.texture_resolver.cache_texture_map.push(texture);
                         }
                         let texture =
@@ -2582,9 +2812,9 @@ impl Renderer {
                                     }
                                     ExternalImageSource::Invalid => {
                                         // Create a local buffer to fill the pbo.
-                                        let bpp = texture.get_format().bytes_per_pixel();
-                                        let width = stride.unwrap_or(rect.size.width * bpp);
-                                        let total_size = width * rect.size.height;
+                                        let format = texture.get_format();
+                                        let width = stride.unwrap_or(format.row_size(rect.size.width));
+                                        let total_size = width * format.row_count(rect.size.height);
                                         // WR haven't support RGBAF32 format in texture_cache, so
                                         // we use u8 type here.
                                         let dummy_data: Vec<u8> = vec![255; total_size as usize];
@@ -2900,6 +3130,29 @@ impl Renderer {
             }
         }
 
+        // Draw any SVG filter primitives for this target. The target is
+        // cleared to transparent, and the inputs of a merge are blended
+        // on top of each other.
+        if !target.svg_filters.is_empty() {
+            let _timer = self.gpu_profile.start_timer(GPU_TAG_SVG_FILTER);
+
+            self.device.set_blend(true);
+            self.device.set_blend_mode_premultiplied_alpha();
+            self.shaders.cs_svg_filter
+                .bind(&mut self.device, projection, &mut self.renderer_errors);
+
+            for &(ref textures, ref instances) in &target.svg_filters {
+                self.draw_instanced_batch(
+                    instances,
+                    VertexArrayKind::SvgFilter,
+                    textures,
+                    stats,
+                );
+            }
+
+            self.device.set_blend(false);
+        }
+
         self.handle_scaling(render_tasks, &target.scalings, SourceTexture::CacheRGBA8);
 
         //TODO: record the pixel count for cached primitives
@@ -3249,6 +3502,22 @@ impl Renderer {
                 );
             }
 
+            // draw path clips
+            if !target.clip_batcher.paths.is_empty() {
+                let _gm2 = self.gpu_profile.start_marker("clip paths");
+                self.shaders.cs_clip_path.bind(
+                    &mut self.device,
+                    projection,
+                    &mut self.renderer_errors,
+                );
+                self.draw_instanced_batch(
+                    &target.clip_batcher.paths,
+                    VertexArrayKind::Clip,
+                    &BatchTextures::no_texture(),
+                    stats,
+                );
+            }
+
             // draw image masks
             for (mask_texture_id, items) in target.clip_batcher.images.iter() {
                 let _gm2 = self.gpu_profile.start_marker("clip images");
@@ -3259,8 +3528,13 @@ impl Renderer {
                         SourceTexture::Invalid,
                     ],
                 };
-                self.shaders.cs_clip_image
-                    .bind(&mut self.device, projection, &mut self.renderer_errors);
+                let shader = match *mask_texture_id {
+                    SourceTexture::CompressedTextureCache(..) => {
+                        &mut self.shaders.cs_clip_image_texture_2d
+                    }
+                    _ => &mut self.shaders.cs_clip_image,
+                };
+                shader.bind(&mut self.device, projection, &mut self.renderer_errors);
                 self.draw_instanced_batch(
                     items,
                     VertexArrayKind::Clip,
@@ -3844,9 +4118,11 @@ impl Renderer {
         let mut spacing = 16;
         let mut size = 512;
         let fb_width = framebuffer_size.width as i32;
+        // Compressed textures can't be read from, so they are not displayed.
         let num_layers: i32 = self.texture_resolver
             .cache_texture_map
             .iter()
+            .filter(|texture| !texture.get_format().is_compressed())
             .map(|texture| texture.get_layer_count())
             .sum();
 
@@ -3858,6 +4134,9 @@ impl Renderer {
 
         let mut i = 0;
         for texture in &self.texture_resolver.cache_texture_map {
+            if texture.get_format().is_compressed() {
+                continue;
+            }
             let y = spacing + if self.debug_flags.contains(DebugFlags::RENDER_TARGET_DBG) {
                 528
             } else {
@@ -3966,6 +4245,7 @@ impl Renderer {
         self.device.delete_vao(self.vaos.blur_vao);
         self.device.delete_vao(self.vaos.dash_and_dot_vao);
         self.device.delete_vao(self.vaos.border_vao);
+        self.device.delete_vao(self.vaos.svg_filter_vao);
 
         #[cfg(feature = "debug_renderer")]
         {
@@ -4092,6 +4372,15 @@ pub struct RendererOptions {
     pub clear_color: Option<ColorF>,
     pub enable_clear_scissor: bool,
     pub max_texture_size: Option<u32>,
+    /// Maximum number of bytes of texture memory allocated by the texture
+    /// cache, for both its shared texture arrays and standalone textures.
+    /// Least recently used items are evicted to stay within the budget, and
+    /// `RenderNotifier::texture_cache_budget_exceeded` is called when the
+    /// items needed by a frame don't fit.
+    pub texture_cache_budget: Option<usize>,
+    /// Where to keep rasterized glyphs between sessions, so that they are
+    /// loaded from disk rather than rasterized again.
+    pub glyph_disk_cache: Option<GlyphDiskCacheOptions>,
     pub scatter_gpu_cache_updates: bool,
     pub upload_method: UploadMethod,
     pub workers: Option<Arc<ThreadPool>>,
@@ -4124,6 +4413,8 @@ impl Default for RendererOptions {
             clear_color: Some(ColorF::new(1.0, 1.0, 1.0, 1.0)),
             enable_clear_scissor: true,
             max_texture_size: None,
+            texture_cache_budget: None,
+            glyph_disk_cache: None,
             // Scattered GPU cache updates haven't met a test that would show their superiority yet.
             scatter_gpu_cache_updates: false,
             // This is best as `Immediate` on Angle, or `Pixelbuffer(Dynamic)` on GL,
@@ -4252,8 +4543,6 @@ impl Renderer {
 
         let short_path = format!("textures/{}.raw", name);
 
-        let bytes_per_pixel = texture.get_format().bytes_per_pixel();
-        let read_format = ReadPixelsFormat::Standard(texture.get_format());
         let rect = DeviceUintRect::new(
             DeviceUintPoint::zero(),
             texture.get_dimensions(),
@@ -4261,6 +4550,28 @@ impl Renderer {
 
         let mut file = fs::File::create(root.join(&short_path))
             .expect(&format!("Unable to create {}", short_path));
+
+        let format = texture.get_format();
+        let bytes_per_pixel = match format.bytes_per_pixel_checked() {
+            Some(bytes_per_pixel) => bytes_per_pixel,
+            None => {
+                // Compressed textures can't be read back, so only their storage is saved.
+                warn!("Unable to capture the contents of compressed texture {}", name);
+                let bytes_per_layer = format.row_size(rect.size.width) * format.row_count(rect.size.height);
+                let data = vec![0; bytes_per_layer as usize * texture.get_layer_count() as usize];
+                file.write_all(&data)
+                    .unwrap();
+
+                return PlainTexture {
+                    data: short_path,
+                    size: (rect.size.width, rect.size.height, texture.get_layer_count()),
+                    format,
+                    filter: texture.get_filter(),
+                    render_target: texture.get_render_target(),
+                };
+            }
+        };
+        let read_format = ReadPixelsFormat::Standard(format);
         let bytes_per_layer = (rect.size.width * rect.size.height * bytes_per_pixel) as usize;
         let mut data = vec![0; bytes_per_layer];
 
@@ -4480,7 +4791,7 @@ impl Renderer {
             }
             for texture in renderer.textures {
                 info!("\t{}", texture.data);
-                let mut t = self.device.create_texture(TextureTarget::Array, texture.format);
+                let mut t = self.device.create_texture(cache_texture_target(texture.format), texture.format);
                 Self::load_texture(&mut t, &texture, &root, &mut self.device);
                 self.texture_resolver.cache_texture_map.push(t);
             }
@@ -4567,6 +4878,7 @@ fn get_vao<'a>(vertex_array_kind: VertexArrayKind,
         VertexArrayKind::VectorStencil => &gpu_glyph_renderer.vector_stencil_vao,
         VertexArrayKind::VectorCover => &gpu_glyph_renderer.vector_cover_vao,
         VertexArrayKind::Border => &vaos.border_vao,
+        VertexArrayKind::SvgFilter => &vaos.svg_filter_vao,
     }
 }
 
@@ -4581,5 +4893,6 @@ fn get_vao<'a>(vertex_array_kind: VertexArrayKind,
         VertexArrayKind::Blur => &vaos.blur_vao,
         VertexArrayKind::VectorStencil | VertexArrayKind::VectorCover => unreachable!(),
         VertexArrayKind::Border => &vaos.border_vao,
+        VertexArrayKind::SvgFilter => &vaos.svg_filter_vao,
     }
 }
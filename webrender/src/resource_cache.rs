@@ -7,8 +7,8 @@ use api::{BlobImageDescriptor, BlobImageHandler, BlobImageRequest};
 use api::{ClearCache, ColorF, DevicePoint, DeviceUintPoint, DeviceUintRect, DeviceUintSize};
 use api::{FontInstanceKey, FontKey, FontTemplate, GlyphIndex};
 use api::{ExternalImageData, ExternalImageType, BlobImageResult, BlobImageParams};
-use api::{FontInstanceOptions, FontInstancePlatformOptions, FontVariation};
-use api::{GlyphDimensions, IdNamespace};
+use api::{FontInstanceOptions, FontInstancePlatformOptions, FontVariation, FontVariationAnimation};
+use api::{DocumentId, GlyphDimensions, GlyphOutline, IdNamespace};
 use api::{ImageData, ImageDescriptor, ImageKey, ImageRendering};
 use api::{TileOffset, TileSize, TileRange, NormalizedRect, BlobImageData};
 use app_units::Au;
@@ -18,22 +18,23 @@ use capture::ExternalCaptureImage;
 use capture::PlainExternalImage;
 #[cfg(any(feature = "replay", feature = "png"))]
 use capture::CaptureConfig;
+use compressed_texture::decompress_image;
 use device::TextureFilter;
 use euclid::{point2, size2};
 use glyph_cache::GlyphCache;
 #[cfg(not(feature = "pathfinder"))]
 use glyph_cache::GlyphCacheEntry;
-use glyph_rasterizer::{FontInstance, GlyphFormat, GlyphKey, GlyphRasterizer};
+use glyph_rasterizer::{FontContexts, FontInstance, GlyphFormat, GlyphKey, GlyphRasterizer};
 use gpu_cache::{GpuCache, GpuCacheAddress, GpuCacheHandle};
 use gpu_types::UvRectKind;
 use image::{compute_tile_range, for_each_tile_in_range};
 use internal_types::{FastHashMap, FastHashSet, SourceTexture, TextureUpdateList};
-use profiler::{ResourceProfileCounters, TextureCacheProfileCounters};
+use profiler::{GlyphCacheProfileCounters, ResourceProfileCounters, TextureCacheProfileCounters};
 use render_backend::FrameId;
 use render_task::{RenderTaskCache, RenderTaskCacheKey, RenderTaskId};
 use render_task::{RenderTaskCacheEntry, RenderTaskCacheEntryHandle, RenderTaskTree};
 use std::collections::hash_map::Entry::{self, Occupied, Vacant};
-use std::collections::hash_map::ValuesMut;
+use std::collections::hash_map::{Values, ValuesMut};
 use std::{cmp, mem};
 use std::fmt::Debug;
 use std::hash::Hash;
@@ -115,6 +116,8 @@ struct ImageResource {
     descriptor: ImageDescriptor,
     tiling: Option<TileSize>,
     viewport_tiles: Option<TileRange>,
+    // Changes whenever the image is added or updated.
+    generation: u64,
 }
 
 #[derive(Clone, Debug)]
@@ -238,6 +241,10 @@ where
         self.resources.entry(key)
     }
 
+    pub fn values(&self) -> Values<K, V> {
+        self.resources.values()
+    }
+
     pub fn values_mut(&mut self) -> ValuesMut<K, V> {
         self.resources.values_mut()
     }
@@ -370,14 +377,29 @@ pub struct ResourceCache {
     cached_glyph_dimensions: GlyphDimensionsCache,
     glyph_rasterizer: GlyphRasterizer,
 
+    // The animated variation axes of font instances, which are snapped
+    // to the steps of their animation when the instances are added.
+    font_variation_animations: FastHashMap<FontInstanceKey, Vec<FontVariationAnimation>>,
+    // The variations of each font that were snapped to an intermediate
+    // step of an animation. The glyphs of these are evicted as soon as
+    // no document uses them.
+    intermediate_variations: FastHashMap<FontKey, Vec<Vec<FontVariation>>>,
+
     // The set of images that aren't present or valid in the texture cache,
     // and need to be rasterized and/or uploaded this frame. This includes
     // both blobs and regular images.
     pending_image_requests: FastHashSet<ImageRequest>,
 
+    // The compressed images that are sampled by shaders which can't read
+    // compressed textures, such as clip masks and blit sources. These are
+    // decoded when they are uploaded to the texture cache.
+    decoded_images: FastHashSet<ImageKey>,
+
     blob_image_handler: Option<Box<BlobImageHandler>>,
     rasterized_blob_images: FastHashMap<ImageKey, RasterizedBlobImage>,
     blob_image_templates: FastHashMap<ImageKey, BlobImageTemplate>,
+    // The generation of the next image template that is added or updated.
+    next_image_generation: u64,
 
     // If while building a frame we encounter blobs that we didn't already
     // rasterize, add them to this list and rasterize them synchronously.
@@ -402,10 +424,14 @@ impl ResourceCache {
             state: State::Idle,
             current_frame_id: FrameId(0),
             pending_image_requests: FastHashSet::default(),
+            decoded_images: FastHashSet::default(),
             glyph_rasterizer,
+            font_variation_animations: FastHashMap::default(),
+            intermediate_variations: FastHashMap::default(),
             blob_image_handler,
             rasterized_blob_images: FastHashMap::default(),
             blob_image_templates: FastHashMap::default(),
+            next_image_generation: 0,
             missing_blob_images: Vec::new(),
             blob_image_rasterizer: None,
         }
@@ -415,6 +441,10 @@ impl ResourceCache {
         self.texture_cache.max_texture_size()
     }
 
+    pub fn texture_cache_budget_overrun(&self) -> Option<(usize, usize)> {
+        self.texture_cache.budget_overrun()
+    }
+
     fn should_tile(limit: u32, descriptor: &ImageDescriptor, data: &ImageData) -> bool {
         let size_check = descriptor.size.width > limit || descriptor.size.height > limit;
         match *data {
@@ -485,7 +515,8 @@ impl ResourceCache {
                     self.discard_tiles_outside_visible_area(key, &area);
                 }
                 ResourceUpdate::AddFont(_) |
-                ResourceUpdate::AddFontInstance(_) => {
+                ResourceUpdate::AddFontInstance(_) |
+                ResourceUpdate::SetFontVariationAnimations(..) => {
                     // Handled in update_resources_pre_scene_building
                 }
             }
@@ -556,6 +587,9 @@ impl ResourceCache {
                         instance.variations,
                     );
                 }
+                ResourceUpdate::SetFontVariationAnimations(key, animations) => {
+                    self.set_font_variation_animations(key, animations);
+                }
                 other => {
                     new_updates.push(other);
                 }
@@ -588,6 +622,7 @@ impl ResourceCache {
     pub fn delete_font_template(&mut self, font_key: FontKey) {
         self.glyph_rasterizer.delete_font(font_key);
         self.resources.font_templates.remove(&font_key);
+        self.intermediate_variations.remove(&font_key);
         self.cached_glyphs
             .clear_fonts(|font| font.font_key == font_key);
         if let Some(ref mut r) = self.blob_image_handler {
@@ -595,6 +630,27 @@ impl ResourceCache {
         }
     }
 
+    pub fn set_font_variation_animations(
+        &mut self,
+        instance_key: FontInstanceKey,
+        animations: Vec<FontVariationAnimation>,
+    ) {
+        if animations.is_empty() {
+            self.font_variation_animations.remove(&instance_key);
+            return;
+        }
+
+        // Snap the instance if it was added before the animations.
+        if let Some(instance) = self.resources.font_instances
+            .write()
+            .unwrap()
+            .get_mut(&instance_key)
+        {
+            snap_variations(instance, &animations, &mut self.intermediate_variations);
+        }
+        self.font_variation_animations.insert(instance_key, animations);
+    }
+
     pub fn add_font_instance(
         &mut self,
         instance_key: FontInstanceKey,
@@ -609,9 +665,9 @@ impl ResourceCache {
             flags,
             bg_color,
             synthetic_italics,
-            ..
+            color_palette,
         } = options.unwrap_or_default();
-        let instance = FontInstance::new(
+        let mut instance = FontInstance::new(
             font_key,
             glyph_size,
             ColorF::new(0.0, 0.0, 0.0, 1.0),
@@ -619,9 +675,13 @@ impl ResourceCache {
             render_mode,
             flags,
             synthetic_italics,
+            color_palette,
             platform_options,
             variations,
         );
+        if let Some(animations) = self.font_variation_animations.get(&instance_key) {
+            snap_variations(&mut instance, animations, &mut self.intermediate_variations);
+        }
         self.resources.font_instances
             .write()
             .unwrap()
@@ -633,6 +693,10 @@ impl ResourceCache {
             .write()
             .unwrap()
             .remove(&instance_key);
+        self.font_variation_animations.remove(&instance_key);
+        self.glyph_rasterizer
+            .get_font_contexts()
+            .delete_font_instance(instance_key);
         if let Some(ref mut r) = self.blob_image_handler {
             r.delete_font_instance(instance_key);
         }
@@ -642,6 +706,10 @@ impl ResourceCache {
         self.resources.font_instances.clone()
     }
 
+    pub fn get_font_contexts(&self) -> Arc<FontContexts> {
+        self.glyph_rasterizer.get_font_contexts()
+    }
+
     pub fn get_font_instance(&self, instance_key: FontInstanceKey) -> Option<FontInstance> {
         let instance_map = self.resources.font_instances.read().unwrap();
         instance_map.get(&instance_key).cloned()
@@ -665,7 +733,9 @@ impl ResourceCache {
             data,
             tiling,
             viewport_tiles: None,
+            generation: self.next_image_generation,
         };
+        self.next_image_generation += 1;
 
         self.resources.image_templates.insert(image_key, resource);
     }
@@ -707,7 +777,9 @@ impl ResourceCache {
             data,
             tiling,
             viewport_tiles: image.viewport_tiles,
+            generation: self.next_image_generation,
         };
+        self.next_image_generation += 1;
     }
 
     // Happens before scene building.
@@ -773,6 +845,7 @@ impl ResourceCache {
         let value = self.resources.image_templates.remove(image_key);
 
         self.cached_images.remove(&image_key);
+        self.decoded_images.remove(&image_key);
 
         match value {
             Some(image) => if image.data.is_blob() {
@@ -787,6 +860,39 @@ impl ResourceCache {
         }
     }
 
+    pub fn set_compressed_textures_enabled(&mut self, enabled: bool) {
+        self.texture_cache.set_compressed_textures_enabled(enabled);
+    }
+
+    /// Requests an image that is sampled by shaders which can't read
+    /// compressed textures. Compressed images are decoded, and their
+    /// cached copy is stored as BGRA8 from then on.
+    pub fn request_decoded_image(
+        &mut self,
+        request: ImageRequest,
+        gpu_cache: &mut GpuCache,
+    ) {
+        if let Some(template) = self.resources.image_templates.get(request.key) {
+            if template.descriptor.format.is_compressed() {
+                match template.data {
+                    ImageData::Raw(..) => {
+                        // Drop any copy that was uploaded compressed, so the
+                        // image is uploaded again and decoded.
+                        if self.decoded_images.insert(request.key) {
+                            self.cached_images.remove(&request.key);
+                        }
+                    }
+                    ImageData::External(..) | ImageData::Blob(..) => {
+                        // The data of external images is only available
+                        // to the renderer, so it can't be decoded here.
+                        warn!("Unable to decode compressed image {:?}", request.key);
+                    }
+                }
+            }
+        }
+        self.request_image(request, gpu_cache);
+    }
+
     pub fn request_image(
         &mut self,
         request: ImageRequest,
@@ -1224,6 +1330,14 @@ impl ResourceCache {
         }
     }
 
+    pub fn get_glyph_outlines(
+        &mut self,
+        font: &FontInstance,
+        glyph_indices: &[GlyphIndex],
+    ) -> Vec<Option<GlyphOutline>> {
+        self.glyph_rasterizer.get_glyph_outlines(font, glyph_indices)
+    }
+
     pub fn get_glyph_index(&mut self, font_key: FontKey, ch: char) -> Option<u32> {
         self.glyph_rasterizer.get_glyph_index(font_key, ch)
     }
@@ -1284,11 +1398,27 @@ impl ResourceCache {
         })
     }
 
-    pub fn begin_frame(&mut self, frame_id: FrameId) {
+    /// Returns a value that changes whenever the image is added or updated,
+    /// so that content drawn with the image can be cached until then.
+    pub fn get_image_generation(&self, image_key: ImageKey) -> Option<u64> {
+        self.resources.image_templates.get(image_key).map(|image| image.generation)
+    }
+
+    pub fn begin_frame(&mut self, frame_id: FrameId, document_id: DocumentId) {
         debug_assert_eq!(self.state, State::Idle);
         self.state = State::AddResources;
         self.texture_cache.begin_frame(frame_id);
-        self.cached_glyphs.begin_frame(&self.texture_cache, &self.cached_render_tasks);
+        let intermediate_variations = &self.intermediate_variations;
+        self.cached_glyphs.begin_frame(
+            document_id,
+            &mut self.texture_cache,
+            &self.cached_render_tasks,
+            |font| {
+                intermediate_variations
+                    .get(&font.font_key)
+                    .map_or(false, |variations| variations.contains(&font.variations))
+            },
+        );
         self.cached_render_tasks.begin_frame(&mut self.texture_cache);
         self.current_frame_id = frame_id;
     }
@@ -1298,6 +1428,7 @@ impl ResourceCache {
         gpu_cache: &mut GpuCache,
         render_tasks: &mut RenderTaskTree,
         texture_cache_profile: &mut TextureCacheProfileCounters,
+        glyph_cache_profile: &mut GlyphCacheProfileCounters,
     ) {
         profile_scope!("block_until_all_resources_added");
 
@@ -1312,6 +1443,7 @@ impl ResourceCache {
             render_tasks,
             texture_cache_profile,
         );
+        self.cached_glyphs.end_frame(glyph_cache_profile);
 
         self.rasterize_missing_blob_images();
 
@@ -1351,7 +1483,7 @@ impl ResourceCache {
             let image_template = self.resources.image_templates.get_mut(request.key).unwrap();
             debug_assert!(image_template.data.uses_texture_cache());
 
-            let image_data = match image_template.data {
+            let mut image_data = match image_template.data {
                 ImageData::Raw(..) | ImageData::External(..) => {
                     // Safe to clone here since the Raw image data is an
                     // Arc, and the external image data is small.
@@ -1405,12 +1537,12 @@ impl ResourceCache {
                 // and offset.
                 let tiled_on_cpu = image_template.data.is_blob();
                 if !tiled_on_cpu {
-                    let bpp = descriptor.format.bytes_per_pixel();
+                    let format = descriptor.format;
                     let stride = descriptor.compute_stride();
                     descriptor.stride = Some(stride);
                     descriptor.offset +=
-                        tile.y as u32 * tile_size as u32 * stride +
-                        tile.x as u32 * tile_size as u32 * bpp;
+                        format.row_count(tile.y as u32 * tile_size as u32) * stride +
+                        format.row_size(tile.x as u32 * tile_size as u32);
                 }
 
                 descriptor.size = clipped_tile_size;
@@ -1418,6 +1550,20 @@ impl ResourceCache {
                 local_dirty_rect = entry.dirty_rect.take();
             }
 
+            // Only raw data can be decoded. The data of an image that was
+            // decoded may since have been replaced by external data, which
+            // is uploaded as it is.
+            if descriptor.format.is_compressed() && self.decoded_images.contains(&request.key) {
+                if let ImageData::Raw(..) = image_data {
+                    let (decoded_descriptor, decoded_data) =
+                        decompress_image(&descriptor, Some(image_data.clone()));
+                    if let Some(decoded_data) = decoded_data {
+                        descriptor = decoded_descriptor;
+                        image_data = decoded_data;
+                    }
+                }
+            }
+
             let filter = match request.rendering {
                 ImageRendering::Pixelated => {
                     TextureFilter::Nearest
@@ -1442,6 +1588,11 @@ impl ResourceCache {
                         TextureFilter::Linear
                     }
                 }
+                ImageRendering::HighQuality => {
+                    // Always sample minified images from a mip chain. The
+                    // texture cache puts these images in their own textures.
+                    TextureFilter::Trilinear
+                }
             };
 
             //Note: at this point, the dirty rectangle is local to the descriptor space
@@ -1489,11 +1640,17 @@ impl ResourceCache {
             .retain(|key, _| key.0 != namespace);
         self.cached_images
             .clear_keys(|key| key.0 == namespace);
+        self.decoded_images
+            .retain(|key| key.0 != namespace);
 
         self.resources.font_instances
             .write()
             .unwrap()
             .retain(|key, _| key.0 != namespace);
+        self.font_variation_animations
+            .retain(|key, _| key.0 != namespace);
+        self.intermediate_variations
+            .retain(|key, _| key.0 != namespace);
         for &key in self.resources.font_templates.keys().filter(|key| key.0 == namespace) {
             self.glyph_rasterizer.delete_font(key);
         }
@@ -1507,6 +1664,27 @@ impl ResourceCache {
             r.clear_namespace(namespace);
         }
     }
+
+    pub fn delete_document(&mut self, document_id: DocumentId) {
+        self.cached_glyphs.remove_document(document_id);
+    }
+}
+
+// Snap the animated variation axes of a font instance, and remember the
+// variations at intermediate steps, whose glyphs aren't kept once unused.
+fn snap_variations(
+    instance: &mut FontInstance,
+    animations: &[FontVariationAnimation],
+    intermediate_variations: &mut FastHashMap<FontKey, Vec<Vec<FontVariation>>>,
+) {
+    if instance.snap_variations(animations) {
+        let variations = intermediate_variations
+            .entry(instance.font_key)
+            .or_insert_with(Vec::new);
+        if !variations.contains(&instance.variations) {
+            variations.push(instance.variations.clone());
+        }
+    }
 }
 
 pub fn get_blob_tiling(
@@ -1820,11 +1998,21 @@ impl ResourceCache {
                 self.cached_images.clear();
                 self.cached_render_tasks.clear();
                 let max_texture_size = self.texture_cache.max_texture_size();
-                self.texture_cache = TextureCache::new(max_texture_size);
+                let memory_budget = self.texture_cache.memory_budget();
+                let compressed_formats = self.texture_cache.compressed_formats().to_vec();
+                let compressed_textures_enabled = self.texture_cache.compressed_textures_enabled();
+                self.texture_cache = TextureCache::new(
+                    max_texture_size,
+                    memory_budget,
+                    compressed_formats,
+                );
+                self.texture_cache.set_compressed_textures_enabled(compressed_textures_enabled);
             }
         }
 
         self.glyph_rasterizer.reset();
+        self.font_variation_animations.clear();
+        self.intermediate_variations.clear();
         let res = &mut self.resources;
         res.font_templates.clear();
         *res.font_instances.write().unwrap() = resources.font_instances;
@@ -1893,7 +2081,9 @@ impl ResourceCache {
                 descriptor: template.descriptor,
                 tiling: template.tiling,
                 viewport_tiles: None,
+                generation: self.next_image_generation,
             });
+            self.next_image_generation += 1;
         }
 
         external_images
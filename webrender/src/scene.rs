@@ -3,8 +3,8 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
 use api::{BuiltDisplayList, ColorF, DynamicProperties, Epoch, LayoutSize};
-use api::{FilterOp, LayoutTransform, PipelineId, PropertyBinding, PropertyBindingId};
-use api::{ItemRange, MixBlendMode, StackingContext};
+use api::{FilterOp, FilterPrimitive, FilterPrimitiveInput, LayoutTransform, PipelineId};
+use api::{ItemRange, MixBlendMode, PropertyBinding, PropertyBindingId, StackingContext};
 use internal_types::FastHashMap;
 use std::sync::Arc;
 
@@ -215,6 +215,11 @@ pub trait StackingContextHelpers {
         display_list: &BuiltDisplayList,
         input_filters: ItemRange<FilterOp>,
     ) -> Vec<FilterOp>;
+    fn filter_primitives_for_compositing(
+        &self,
+        display_list: &BuiltDisplayList,
+        input_primitives: ItemRange<FilterPrimitive>,
+    ) -> Vec<FilterPrimitive>;
 }
 
 impl StackingContextHelpers for StackingContext {
@@ -239,4 +244,27 @@ impl StackingContextHelpers for StackingContext {
         }
         filters
     }
+
+    fn filter_primitives_for_compositing(
+        &self,
+        display_list: &BuiltDisplayList,
+        input_primitives: ItemRange<FilterPrimitive>,
+    ) -> Vec<FilterPrimitive> {
+        let primitives: Vec<FilterPrimitive> = display_list.get(input_primitives).collect();
+
+        // A primitive can only read the results of the primitives before it,
+        // which keeps the graph acyclic. Drop the whole graph otherwise.
+        for (index, primitive) in primitives.iter().enumerate() {
+            for input in primitive.inputs() {
+                if let FilterPrimitiveInput::Result(result) = input {
+                    if result >= index {
+                        warn!("Filter primitive {} reads an invalid result {}", index, result);
+                        return Vec::new();
+                    }
+                }
+            }
+        }
+
+        primitives
+    }
 }
@@ -8,11 +8,13 @@ use api::channel::MsgSender;
 use display_list_flattener::build_scene;
 use frame_builder::{FrameBuilderConfig, FrameBuilder};
 use clip_scroll_tree::ClipScrollTree;
+use glyph_rasterizer::FontContexts;
 use internal_types::FastHashSet;
 use resource_cache::FontInstanceMap;
 use render_backend::DocumentView;
 use renderer::{PipelineInfo, SceneBuilderHooks};
 use scene::Scene;
+use std::sync::Arc;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use time::precise_time_ns;
 
@@ -61,6 +63,7 @@ pub struct SceneRequest {
     pub scene: Scene,
     pub view: DocumentView,
     pub font_instances: FontInstanceMap,
+    pub font_contexts: Arc<FontContexts>,
     pub output_pipelines: FastHashSet<PipelineId>,
     pub removed_pipelines: Vec<PipelineId>,
     pub scene_id: u64,
@@ -337,6 +337,7 @@ fn create_prim_shader(
         VertexArrayKind::VectorStencil => desc::VECTOR_STENCIL,
         VertexArrayKind::VectorCover => desc::VECTOR_COVER,
         VertexArrayKind::Border => desc::BORDER,
+        VertexArrayKind::SvgFilter => desc::SVG_FILTER,
     };
 
     let program = device.create_program(name, &prefix, &vertex_descriptor);
@@ -401,6 +402,7 @@ pub struct Shaders {
     pub cs_blur_a8: LazilyCompiledShader,
     pub cs_blur_rgba8: LazilyCompiledShader,
     pub cs_border_segment: LazilyCompiledShader,
+    pub cs_svg_filter: LazilyCompiledShader,
 
     // Brush shaders
     brush_solid: BrushShader,
@@ -409,6 +411,7 @@ pub struct Shaders {
     brush_mix_blend: BrushShader,
     brush_yuv_image: Vec<Option<BrushShader>>,
     brush_radial_gradient: BrushShader,
+    brush_conic_gradient: BrushShader,
     brush_linear_gradient: BrushShader,
 
     /// These are "cache clip shaders". These shaders are used to
@@ -417,7 +420,11 @@ pub struct Shaders {
     pub cs_clip_rectangle: LazilyCompiledShader,
     pub cs_clip_box_shadow: LazilyCompiledShader,
     pub cs_clip_image: LazilyCompiledShader,
+    // Image masks in standalone compressed textures, which
+    // aren't texture arrays.
+    pub cs_clip_image_texture_2d: LazilyCompiledShader,
     pub cs_clip_line: LazilyCompiledShader,
+    pub cs_clip_path: LazilyCompiledShader,
 
     // The are "primitive shaders". These shaders draw and blend
     // final results on screen. They are aware of tile boundaries.
@@ -483,6 +490,18 @@ impl Shaders {
             false,
         )?;
 
+        let brush_conic_gradient = BrushShader::new(
+            "brush_conic_gradient",
+            device,
+            if options.enable_dithering {
+               &[DITHERING_FEATURE]
+            } else {
+               &[]
+            },
+            options.precache_shaders,
+            false,
+        )?;
+
         let brush_linear_gradient = BrushShader::new(
             "brush_linear_gradient",
             device,
@@ -511,6 +530,14 @@ impl Shaders {
             options.precache_shaders,
         )?;
 
+        let cs_svg_filter = LazilyCompiledShader::new(
+            ShaderKind::Cache(VertexArrayKind::SvgFilter),
+            "cs_svg_filter",
+            &[],
+            device,
+            options.precache_shaders,
+        )?;
+
         let cs_clip_rectangle = LazilyCompiledShader::new(
             ShaderKind::ClipCache,
             "cs_clip_rectangle",
@@ -535,6 +562,14 @@ impl Shaders {
             options.precache_shaders,
         )?;
 
+        let cs_clip_path = LazilyCompiledShader::new(
+            ShaderKind::ClipCache,
+            "cs_clip_path",
+            &[],
+            device,
+            options.precache_shaders,
+        )?;
+
         let cs_clip_image = LazilyCompiledShader::new(
             ShaderKind::ClipCache,
             "cs_clip_image",
@@ -543,6 +578,14 @@ impl Shaders {
             options.precache_shaders,
         )?;
 
+        let cs_clip_image_texture_2d = LazilyCompiledShader::new(
+            ShaderKind::ClipCache,
+            "cs_clip_image",
+            &[ImageBufferKind::Texture2D.get_feature_string()],
+            device,
+            options.precache_shaders,
+        )?;
+
         let ps_text_run = TextShader::new("ps_text_run",
             device,
             &[],
@@ -647,17 +690,21 @@ impl Shaders {
             cs_blur_a8,
             cs_blur_rgba8,
             cs_border_segment,
+            cs_svg_filter,
             brush_solid,
             brush_image,
             brush_blend,
             brush_mix_blend,
             brush_yuv_image,
             brush_radial_gradient,
+            brush_conic_gradient,
             brush_linear_gradient,
             cs_clip_rectangle,
             cs_clip_box_shadow,
             cs_clip_image,
+            cs_clip_image_texture_2d,
             cs_clip_line,
+            cs_clip_path,
             ps_text_run,
             ps_text_run_dual_source,
             ps_split_composite,
@@ -697,6 +744,9 @@ impl Shaders {
                     BrushBatchKind::RadialGradient => {
                         &mut self.brush_radial_gradient
                     }
+                    BrushBatchKind::ConicGradient => {
+                        &mut self.brush_conic_gradient
+                    }
                     BrushBatchKind::LinearGradient => {
                         &mut self.brush_linear_gradient
                     }
@@ -727,11 +777,14 @@ impl Shaders {
         self.brush_blend.deinit(device);
         self.brush_mix_blend.deinit(device);
         self.brush_radial_gradient.deinit(device);
+        self.brush_conic_gradient.deinit(device);
         self.brush_linear_gradient.deinit(device);
         self.cs_clip_rectangle.deinit(device);
         self.cs_clip_box_shadow.deinit(device);
         self.cs_clip_image.deinit(device);
+        self.cs_clip_image_texture_2d.deinit(device);
         self.cs_clip_line.deinit(device);
+        self.cs_clip_path.deinit(device);
         self.ps_text_run.deinit(device);
         self.ps_text_run_dual_source.deinit(device);
         for shader in self.brush_image {
@@ -745,6 +798,7 @@ impl Shaders {
             }
         }
         self.cs_border_segment.deinit(device);
+        self.cs_svg_filter.deinit(device);
         self.ps_split_composite.deinit(device);
     }
 }
@@ -2,14 +2,16 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use api::{DeviceUintPoint, DeviceUintRect, DeviceUintSize};
+use api::{COMPRESSED_BLOCK_DIMENSION, DeviceUintPoint, DeviceUintRect, DeviceUintSize};
 use api::{ExternalImageType, ImageData, ImageFormat};
 use api::ImageDescriptor;
+use compressed_texture::decompress_image;
 use device::TextureFilter;
 use freelist::{FreeList, FreeListHandle, UpsertResult, WeakFreeListHandle};
 use gpu_cache::{GpuCache, GpuCacheHandle};
 use gpu_types::{ImageSource, UvRectKind};
-use internal_types::{CacheTextureId, FastHashMap, TextureUpdateList, TextureUpdateSource};
+use internal_types::{CacheTextureId, FastHashMap, FastHashSet, TextureUpdateList};
+use internal_types::TextureUpdateSource;
 use internal_types::{RenderTargetInfo, SourceTexture, TextureUpdate, TextureUpdateOp};
 use profiler::{ResourceProfileCounter, TextureCacheProfileCounters};
 use render_backend::FrameId;
@@ -30,6 +32,13 @@ const TEXTURE_LAYER_DIMENSIONS: u32 = 2048;
 // The size of each region (page) in a texture layer.
 const TEXTURE_REGION_DIMENSIONS: u32 = 512;
 
+// The format and filter of each shared texture array.
+const SHARED_TEXTURE_ARRAYS: [(ImageFormat, TextureFilter); 3] = [
+    (ImageFormat::R8, TextureFilter::Linear),
+    (ImageFormat::BGRA8, TextureFilter::Linear),
+    (ImageFormat::BGRA8, TextureFilter::Nearest),
+];
+
 // Maintains a simple freelist of texture IDs that are mapped
 // to real API-specific texture IDs in the renderer.
 #[cfg_attr(feature = "capture", derive(Serialize))]
@@ -83,6 +92,13 @@ enum EntryKind {
     },
 }
 
+// The items that can be evicted to bring the texture cache within its
+// memory budget. The items of a shared texture array are evicted together.
+enum EvictionCandidate {
+    Standalone(FreeListHandle<CacheEntryMarker>),
+    TextureArray(ImageFormat, TextureFilter),
+}
+
 #[derive(Debug)]
 pub enum CacheEntryMarker {}
 
@@ -164,6 +180,25 @@ impl CacheEntry {
         }
     }
 
+    // The number of bytes of the standalone texture allocated for this entry.
+    fn size_in_bytes(&self) -> usize {
+        let size = self.format.row_size(self.size.width) as usize *
+            self.format.row_count(self.size.height) as usize;
+        // The mip-map chain adds up to a third of the base level.
+        match self.filter {
+            TextureFilter::Trilinear => size + size / 3,
+            TextureFilter::Linear | TextureFilter::Nearest => size,
+        }
+    }
+
+    fn texture_source(&self) -> SourceTexture {
+        if self.format.is_compressed() {
+            SourceTexture::CompressedTextureCache(self.texture_id)
+        } else {
+            SourceTexture::TextureCache(self.texture_id)
+        }
+    }
+
     fn evict(&self) {
         if let Some(eviction_notice) = self.eviction_notice.as_ref() {
             eviction_notice.notify();
@@ -259,10 +294,29 @@ pub struct TextureCache {
     // allocated in the shared texture cache. Used
     // for evicting old cache items.
     shared_entry_handles: Vec<FreeListHandle<CacheEntryMarker>>,
+
+    // Optional upper bound, in bytes, for the texture memory
+    // allocated by the cache (both shared and standalone).
+    memory_budget: Option<usize>,
+
+    // The number of bytes of the allocated standalone textures.
+    standalone_bytes: usize,
+
+    // The compressed formats that the device can sample from. Images
+    // in other compressed formats are decoded when they are uploaded.
+    compressed_formats: Vec<ImageFormat>,
+
+    // When disabled, all the compressed images are decoded, as if the
+    // device didn't support any compressed format.
+    compressed_textures_enabled: bool,
 }
 
 impl TextureCache {
-    pub fn new(max_texture_size: u32) -> Self {
+    pub fn new(
+        max_texture_size: u32,
+        memory_budget: Option<usize>,
+        compressed_formats: Vec<ImageFormat>,
+    ) -> Self {
         TextureCache {
             max_texture_size,
             array_a8_linear: TextureArray::new(
@@ -286,6 +340,10 @@ impl TextureCache {
             entries: FreeList::new(),
             standalone_entry_handles: Vec::new(),
             shared_entry_handles: Vec::new(),
+            memory_budget,
+            standalone_bytes: 0,
+            compressed_formats,
+            compressed_textures_enabled: true,
         }
     }
 
@@ -314,28 +372,8 @@ impl TextureCache {
 
         assert!(self.entries.len() == 0);
 
-        if let Some(texture_id) = self.array_a8_linear.clear() {
-            self.pending_updates.push(TextureUpdate {
-                id: texture_id,
-                op: TextureUpdateOp::Free,
-            });
-            self.cache_textures.free(texture_id, self.array_a8_linear.format);
-        }
-
-        if let Some(texture_id) = self.array_rgba8_linear.clear() {
-            self.pending_updates.push(TextureUpdate {
-                id: texture_id,
-                op: TextureUpdateOp::Free,
-            });
-            self.cache_textures.free(texture_id, self.array_rgba8_linear.format);
-        }
-
-        if let Some(texture_id) = self.array_rgba8_nearest.clear() {
-            self.pending_updates.push(TextureUpdate {
-                id: texture_id,
-                op: TextureUpdateOp::Free,
-            });
-            self.cache_textures.free(texture_id, self.array_rgba8_nearest.format);
+        for &(format, filter) in &SHARED_TEXTURE_ARRAYS {
+            self.free_texture_array(format, filter);
         }
     }
 
@@ -345,7 +383,9 @@ impl TextureCache {
 
     pub fn end_frame(&mut self, texture_cache_profile: &mut TextureCacheProfileCounters) {
         self.expire_old_standalone_entries();
+        self.expire_entries_over_budget();
 
+        texture_cache_profile.entries.set(self.entries.len(), self.allocated_bytes());
         self.array_a8_linear
             .update_profile(&mut texture_cache_profile.pages_a8_linear);
         self.array_rgba8_linear
@@ -394,6 +434,45 @@ impl TextureCache {
         self.max_texture_size
     }
 
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.memory_budget
+    }
+
+    pub fn compressed_formats(&self) -> &[ImageFormat] {
+        &self.compressed_formats
+    }
+
+    pub fn compressed_textures_enabled(&self) -> bool {
+        self.compressed_textures_enabled
+    }
+
+    // Only affects the images that are uploaded afterwards.
+    pub fn set_compressed_textures_enabled(&mut self, enabled: bool) {
+        self.compressed_textures_enabled = enabled;
+    }
+
+    // Returns the number of allocated bytes and the memory budget,
+    // if the cache is still above its budget after evicting all the
+    // items that were not used during the current frame.
+    pub fn budget_overrun(&self) -> Option<(usize, usize)> {
+        let allocated_bytes = self.allocated_bytes();
+        match self.memory_budget {
+            Some(budget) if allocated_bytes > budget => {
+                Some((allocated_bytes, budget))
+            }
+            _ => None,
+        }
+    }
+
+    // The number of bytes of texture memory allocated by the cache. The
+    // shared texture arrays are counted as a whole, however full they are.
+    fn allocated_bytes(&self) -> usize {
+        self.standalone_bytes +
+            self.array_a8_linear.size_in_bytes() +
+            self.array_rgba8_linear.size_in_bytes() +
+            self.array_rgba8_nearest.size_in_bytes()
+    }
+
     pub fn pending_updates(&mut self) -> TextureUpdateList {
         mem::replace(&mut self.pending_updates, TextureUpdateList::new())
     }
@@ -402,15 +481,29 @@ impl TextureCache {
     pub fn update(
         &mut self,
         handle: &mut TextureCacheHandle,
-        descriptor: ImageDescriptor,
-        filter: TextureFilter,
-        data: Option<ImageData>,
+        mut descriptor: ImageDescriptor,
+        mut filter: TextureFilter,
+        mut data: Option<ImageData>,
         user_data: [f32; 3],
         mut dirty_rect: Option<DeviceUintRect>,
         gpu_cache: &mut GpuCache,
         eviction_notice: Option<&EvictionNotice>,
         uv_rect_kind: UvRectKind,
     ) {
+        if descriptor.format.is_compressed() {
+            if !self.compressed_textures_enabled ||
+                !self.compressed_formats.contains(&descriptor.format) {
+                // The device can't sample from this format, so decode the
+                // image and store it as a regular BGRA8 one.
+                let (decoded_descriptor, decoded_data) = decompress_image(&descriptor, data);
+                descriptor = decoded_descriptor;
+                data = decoded_data;
+            } else if filter == TextureFilter::Trilinear {
+                // Mipmaps can't be generated for compressed textures.
+                filter = TextureFilter::Linear;
+            }
+        }
+
         // Determine if we need to allocate texture cache memory
         // for this item. We need to reallocate if any of the following
         // is true:
@@ -489,27 +582,52 @@ impl TextureCache {
         }
     }
 
-    // Get a specific region by index from a shared texture array.
-    fn get_region_mut(&mut self,
+    // Get the shared texture array for a given format and filter.
+    fn get_texture_array_mut(
+        &mut self,
         format: ImageFormat,
         filter: TextureFilter,
-        region_index: u16
-    ) -> &mut TextureRegion {
-        let texture_array = match (format, filter) {
+    ) -> &mut TextureArray {
+        match (format, filter) {
             (ImageFormat::R8, TextureFilter::Linear) => &mut self.array_a8_linear,
             (ImageFormat::BGRA8, TextureFilter::Linear) => &mut self.array_rgba8_linear,
             (ImageFormat::BGRA8, TextureFilter::Nearest) => &mut self.array_rgba8_nearest,
             (ImageFormat::RGBAF32, _) |
             (ImageFormat::RG8, _) |
             (ImageFormat::RGBAI32, _) |
+            (ImageFormat::BC1, _) |
+            (ImageFormat::BC3, _) |
+            (ImageFormat::ETC2, _) |
+            (ImageFormat::ETC2A8, _) |
+            (ImageFormat::ASTC4x4, _) |
             (ImageFormat::R8, TextureFilter::Nearest) |
             (ImageFormat::R8, TextureFilter::Trilinear) |
             (ImageFormat::BGRA8, TextureFilter::Trilinear) => unreachable!(),
-        };
+        }
+    }
 
+    // Get a specific region by index from a shared texture array.
+    fn get_region_mut(&mut self,
+        format: ImageFormat,
+        filter: TextureFilter,
+        region_index: u16
+    ) -> &mut TextureRegion {
+        let texture_array = self.get_texture_array_mut(format, filter);
         &mut texture_array.regions[region_index as usize]
     }
 
+    // Free the texture of a shared texture array. It is
+    // allocated again when an item is next added to the array.
+    fn free_texture_array(&mut self, format: ImageFormat, filter: TextureFilter) {
+        if let Some(texture_id) = self.get_texture_array_mut(format, filter).clear() {
+            self.pending_updates.push(TextureUpdate {
+                id: texture_id,
+                op: TextureUpdateOp::Free,
+            });
+            self.cache_textures.free(texture_id, format);
+        }
+    }
+
     // Check if a given texture handle has a valid allocation
     // in the texture cache.
     pub fn is_allocated(&self, handle: &TextureCacheHandle) -> bool {
@@ -518,6 +636,57 @@ impl TextureCache {
         })
     }
 
+    // Free the allocations of handles that won't be requested again,
+    // instead of waiting for them to expire. Items that were used during
+    // the current frame are kept, since the frame may still refer to them.
+    pub fn free_handles<'a, I>(&mut self, handles: I)
+    where
+        I: IntoIterator<Item = &'a TextureCacheHandle>,
+    {
+        let weak_handles = handles
+            .into_iter()
+            .filter_map(|handle| handle.entry.clone())
+            .collect::<FastHashSet<_>>();
+        if weak_handles.is_empty() {
+            return;
+        }
+
+        let standalone_entry_handles = mem::replace(
+            &mut self.standalone_entry_handles,
+            Vec::new(),
+        );
+        let shared_entry_handles = mem::replace(
+            &mut self.shared_entry_handles,
+            Vec::new(),
+        );
+
+        let mut handles_to_free = Vec::new();
+        {
+            let entries = &self.entries;
+            let frame_id = self.frame_id;
+            let mut select = |handles: Vec<FreeListHandle<CacheEntryMarker>>| {
+                let mut retained_entries = Vec::new();
+                for handle in handles {
+                    if weak_handles.contains(&handle.weak()) &&
+                       entries.get(&handle).last_access != frame_id {
+                        handles_to_free.push(handle);
+                    } else {
+                        retained_entries.push(handle);
+                    }
+                }
+                retained_entries
+            };
+            self.standalone_entry_handles = select(standalone_entry_handles);
+            self.shared_entry_handles = select(shared_entry_handles);
+        }
+
+        for handle in handles_to_free {
+            let entry = self.entries.free(handle);
+            entry.evict();
+            self.free(entry);
+        }
+    }
+
     // Retrieve the details of an item in the cache. This is used
     // during batch creation to provide the resource rect address
     // to the shaders and texture ID to the batching logic.
@@ -542,7 +711,7 @@ impl TextureCache {
                 };
                 CacheItem {
                     uv_rect_handle: entry.uv_rect_handle,
-                    texture_id: SourceTexture::TextureCache(entry.texture_id),
+                    texture_id: entry.texture_source(),
                     uv_rect: DeviceUintRect::new(origin, entry.size),
                     texture_layer: layer_index as i32,
                 }
@@ -576,7 +745,7 @@ impl TextureCache {
                 ..
             } => (layer_index, origin),
         };
-        (SourceTexture::TextureCache(entry.texture_id),
+        (entry.texture_source(),
          layer_index as i32,
          DeviceUintRect::new(origin, entry.size))
     }
@@ -623,6 +792,106 @@ impl TextureCache {
         self.standalone_entry_handles = retained_entries;
     }
 
+    // Evict the least recently used items until the texture memory allocated
+    // by the cache fits in the budget. A shared texture array only releases
+    // its memory once it is empty, so its items are evicted together, as if
+    // they had all been last used with the most recent of them. Items that
+    // were used during the current frame are never evicted, so the cache can
+    // remain above the budget (see budget_overrun).
+    fn expire_entries_over_budget(&mut self) {
+        let budget = match self.memory_budget {
+            Some(budget) => budget,
+            None => return,
+        };
+        if self.allocated_bytes() <= budget {
+            return;
+        }
+
+        let mut eviction_candidates = Vec::new();
+        let mut retained_entries = Vec::new();
+
+        // Build a list of eviction candidates (which are
+        // anything not used this frame).
+        for handle in self.standalone_entry_handles.drain(..) {
+            let entry = self.entries.get(&handle);
+            if entry.last_access == self.frame_id {
+                retained_entries.push(handle);
+            } else {
+                eviction_candidates.push((
+                    Some(entry.last_access),
+                    EvictionCandidate::Standalone(handle),
+                ));
+            }
+        }
+
+        // Allocated texture arrays without any item are the first to go.
+        for &(format, filter) in &SHARED_TEXTURE_ARRAYS {
+            if self.get_texture_array_mut(format, filter).texture_id.is_none() {
+                continue;
+            }
+            let last_access = self.shared_entry_handles
+                .iter()
+                .map(|handle| self.entries.get(handle))
+                .filter(|entry| entry.format == format && entry.filter == filter)
+                .map(|entry| entry.last_access)
+                .max();
+            if last_access != Some(self.frame_id) {
+                eviction_candidates.push((
+                    last_access,
+                    EvictionCandidate::TextureArray(format, filter),
+                ));
+            }
+        }
+
+        // Sort by access time so we remove the oldest ones first.
+        eviction_candidates.sort_by_key(|&(last_access, _)| last_access);
+
+        for (_, candidate) in eviction_candidates {
+            match candidate {
+                EvictionCandidate::Standalone(handle) => {
+                    if self.allocated_bytes() > budget {
+                        let entry = self.entries.free(handle);
+                        entry.evict();
+                        self.free(entry);
+                    } else {
+                        retained_entries.push(handle);
+                    }
+                }
+                EvictionCandidate::TextureArray(format, filter) => {
+                    if self.allocated_bytes() > budget {
+                        self.expire_texture_array(format, filter);
+                    }
+                }
+            }
+        }
+
+        // Keep a record of the remaining handles for next frame.
+        self.standalone_entry_handles = retained_entries;
+    }
+
+    // Evict all the items of a shared texture array, and free its texture.
+    fn expire_texture_array(&mut self, format: ImageFormat, filter: TextureFilter) {
+        let mut evicted_entries = Vec::new();
+        let mut retained_entries = Vec::new();
+        for handle in self.shared_entry_handles.drain(..) {
+            let entry = self.entries.get(&handle);
+            if entry.format == format && entry.filter == filter {
+                evicted_entries.push(handle);
+            } else {
+                retained_entries.push(handle);
+            }
+        }
+        self.shared_entry_handles = retained_entries;
+
+        for handle in evicted_entries {
+            let entry = self.entries.free(handle);
+            entry.evict();
+            self.free(entry);
+        }
+
+        self.free_texture_array(format, filter);
+    }
+
     // Expire old shared items. Pass in the allocation size
     // that is being requested, so we know when we've evicted
     // enough items to guarantee we can fit this allocation in
@@ -686,6 +955,7 @@ impl TextureCache {
     fn free(&mut self, entry: CacheEntry) -> Option<&TextureRegion> {
         match entry.kind {
             EntryKind::Standalone { .. } => {
+                self.standalone_bytes -= entry.size_in_bytes();
                 // This is a standalone texture allocation. Just push it back onto the free
                 // list.
                 self.pending_updates.push(TextureUpdate {
@@ -727,6 +997,11 @@ impl TextureCache {
             (ImageFormat::BGRA8, TextureFilter::Nearest) => &mut self.array_rgba8_nearest,
             (ImageFormat::RGBAF32, _) |
             (ImageFormat::RGBAI32, _) |
+            (ImageFormat::BC1, _) |
+            (ImageFormat::BC3, _) |
+            (ImageFormat::ETC2, _) |
+            (ImageFormat::ETC2A8, _) |
+            (ImageFormat::ASTC4x4, _) |
             (ImageFormat::R8, TextureFilter::Nearest) |
             (ImageFormat::R8, TextureFilter::Trilinear) |
             (ImageFormat::BGRA8, TextureFilter::Trilinear) |
@@ -796,6 +1071,17 @@ impl TextureCache {
             allowed_in_shared_cache = false;
         }
 
+        // Compressed images are uploaded as is, so they need a texture of their own.
+        if descriptor.format.is_compressed() {
+            allowed_in_shared_cache = false;
+        }
+
+        // Mip-maps are generated for a whole texture, so the levels of items
+        // in a shared texture would bleed into each other.
+        if filter == TextureFilter::Trilinear {
+            allowed_in_shared_cache = false;
+        }
+
         allowed_in_shared_cache
     }
 
@@ -851,7 +1137,13 @@ impl TextureCache {
             let texture_id = self.cache_textures.allocate(descriptor.format);
 
             // Create an update operation to allocate device storage
-            // of the right size / format.
+            // of the right size / format. Compressed formats can't be
+            // rendered to.
+            let render_target = if descriptor.format.is_compressed() {
+                None
+            } else {
+                Some(RenderTargetInfo { has_depth: false })
+            };
             let update_op = TextureUpdate {
                 id: texture_id,
                 op: TextureUpdateOp::Create {
@@ -859,13 +1151,13 @@ impl TextureCache {
                     height: descriptor.size.height,
                     format: descriptor.format,
                     filter,
-                    render_target: Some(RenderTargetInfo { has_depth: false }),
+                    render_target,
                     layer_count: 1,
                 },
             };
             self.pending_updates.push(update_op);
 
-            new_cache_entry = Some(CacheEntry::new_standalone(
+            let entry = CacheEntry::new_standalone(
                 texture_id,
                 descriptor.size,
                 descriptor.format,
@@ -873,7 +1165,9 @@ impl TextureCache {
                 user_data,
                 frame_id,
                 uv_rect_kind,
-            ));
+            );
+            self.standalone_bytes += entry.size_in_bytes();
+            new_cache_entry = Some(entry);
 
             allocated_in_shared_cache = false;
         }
@@ -1103,11 +1397,21 @@ impl TextureArray {
         self.texture_id.take()
     }
 
+    // The number of bytes of the texture of this array, if allocated.
+    fn size_in_bytes(&self) -> usize {
+        match self.texture_id {
+            Some(..) => {
+                self.layer_count *
+                    self.format.row_size(TEXTURE_LAYER_DIMENSIONS) as usize *
+                    self.format.row_count(TEXTURE_LAYER_DIMENSIONS) as usize
+            }
+            None => 0,
+        }
+    }
+
     fn update_profile(&self, counter: &mut ResourceProfileCounter) {
         if self.is_allocated {
-            let size = self.layer_count as u32 * TEXTURE_LAYER_DIMENSIONS *
-                TEXTURE_LAYER_DIMENSIONS * self.format.bytes_per_pixel();
-            counter.set(self.layer_count as usize, size as usize);
+            counter.set(self.layer_count as usize, self.size_in_bytes());
         } else {
             counter.set(0, 0);
         }
@@ -1238,8 +1542,8 @@ impl TextureUpdate {
             },
             ImageData::Raw(bytes) => {
                 let finish = descriptor.offset +
-                    descriptor.size.width * descriptor.format.bytes_per_pixel() +
-                    (descriptor.size.height - 1) * descriptor.compute_stride();
+                    descriptor.format.row_size(descriptor.size.width) +
+                    (descriptor.format.row_count(descriptor.size.height) - 1) * descriptor.compute_stride();
                 assert!(bytes.len() >= finish as usize);
 
                 TextureUpdateSource::Bytes { data: bytes }
@@ -1248,9 +1552,17 @@ impl TextureUpdate {
 
         let update_op = match dirty_rect {
             Some(dirty) => {
+                // Compressed images can only be updated by whole blocks.
+                let dirty = if descriptor.format.is_compressed() {
+                    align_to_compressed_blocks(dirty, size)
+                } else {
+                    dirty
+                };
                 // the dirty rectangle doesn't have to be within the area but has to intersect it, at least
                 let stride = descriptor.compute_stride();
-                let offset = descriptor.offset + dirty.origin.y * stride + dirty.origin.x * descriptor.format.bytes_per_pixel();
+                let offset = descriptor.offset +
+                    descriptor.format.row_count(dirty.origin.y) * stride +
+                    descriptor.format.row_size(dirty.origin.x);
 
                 TextureUpdateOp::Update {
                     rect: DeviceUintRect::new(
@@ -1284,6 +1596,20 @@ impl TextureUpdate {
     }
 }
 
+// Expands a rectangle to the boundaries of the compressed blocks it
+// touches, without going past the size of the image.
+fn align_to_compressed_blocks(rect: DeviceUintRect, size: DeviceUintSize) -> DeviceUintRect {
+    let align_down = |value: u32| value / COMPRESSED_BLOCK_DIMENSION * COMPRESSED_BLOCK_DIMENSION;
+    let align_up = |value: u32| align_down(value + COMPRESSED_BLOCK_DIMENSION - 1);
+    let origin = DeviceUintPoint::new(align_down(rect.origin.x), align_down(rect.origin.y));
+    let max_x = cmp::min(align_up(rect.max_x()), size.width);
+    let max_y = cmp::min(align_up(rect.max_y()), size.height);
+    DeviceUintRect::new(
+        origin,
+        DeviceUintSize::new(max_x.saturating_sub(origin.x), max_y.saturating_sub(origin.y)),
+    )
+}
+
 fn quantize_dimension(size: u32) -> u32 {
     match size {
         0 => unreachable!(),
@@ -1296,3 +1622,205 @@ fn quantize_dimension(size: u32) -> u32 {
         _ => panic!("Invalid dimensions for cache!"),
     }
 }
+
+#[cfg(test)]
+mod test_texture_cache {
+    use api::{ImageDescriptor, ImageFormat};
+    use device::TextureFilter;
+    use gpu_cache::GpuCache;
+    use gpu_types::UvRectKind;
+    use internal_types::TextureUpdateOp;
+    use profiler::TextureCacheProfileCounters;
+    use render_backend::FrameId;
+    use texture_cache::{EvictionNotice, TextureCache, TextureCacheHandle};
+
+    // The size of a 600x600 BGRA8 image, which is too large for the shared cache.
+    const STANDALONE_BYTES: usize = 600 * 600 * 4;
+    // The size of the shared A8 texture array.
+    const ARRAY_A8_BYTES: usize = 4 * 2048 * 2048;
+
+    fn allocate(
+        texture_cache: &mut TextureCache,
+        gpu_cache: &mut GpuCache,
+        handle: &mut TextureCacheHandle,
+        descriptor: ImageDescriptor,
+        eviction_notice: &EvictionNotice,
+    ) {
+        texture_cache.update(
+            handle,
+            descriptor,
+            TextureFilter::Linear,
+            None,
+            [0.0; 3],
+            None,
+            gpu_cache,
+            Some(eviction_notice),
+            UvRectKind::Rect,
+        );
+    }
+
+    fn standalone_descriptor() -> ImageDescriptor {
+        ImageDescriptor::new(600, 600, ImageFormat::BGRA8, false, false)
+    }
+
+    fn shared_descriptor() -> ImageDescriptor {
+        ImageDescriptor::new(16, 16, ImageFormat::R8, false, false)
+    }
+
+    #[test]
+    fn evict_least_recently_used_items_over_budget() {
+        let mut texture_cache = TextureCache::new(2048, Some(2 * STANDALONE_BYTES), Vec::new());
+        let mut gpu_cache = GpuCache::new();
+        let mut profile = TextureCacheProfileCounters::new();
+        let mut handles = vec![
+            TextureCacheHandle::new(),
+            TextureCacheHandle::new(),
+            TextureCacheHandle::new(),
+            TextureCacheHandle::new(),
+        ];
+        let notices = vec![
+            EvictionNotice::default(),
+            EvictionNotice::default(),
+            EvictionNotice::default(),
+            EvictionNotice::default(),
+        ];
+
+        // Each of the first three frames uses a new item.
+        for i in 0 .. 3 {
+            texture_cache.begin_frame(FrameId(i as u32 + 1));
+            allocate(
+                &mut texture_cache,
+                &mut gpu_cache,
+                &mut handles[i],
+                standalone_descriptor(),
+                &notices[i],
+            );
+            texture_cache.end_frame(&mut profile);
+        }
+
+        // Only the least recently used item was evicted.
+        assert!(notices[0].check());
+        assert!(!texture_cache.is_allocated(&handles[0]));
+        assert!(texture_cache.is_allocated(&handles[1]));
+        assert!(texture_cache.is_allocated(&handles[2]));
+        assert_eq!(texture_cache.budget_overrun(), None);
+
+        // Using the second item makes the third one the least recently used.
+        texture_cache.begin_frame(FrameId(4));
+        texture_cache.request(&handles[1], &mut gpu_cache);
+        allocate(
+            &mut texture_cache,
+            &mut gpu_cache,
+            &mut handles[3],
+            standalone_descriptor(),
+            &notices[3],
+        );
+        texture_cache.end_frame(&mut profile);
+
+        assert!(notices[2].check());
+        assert!(!notices[1].check());
+        assert!(texture_cache.is_allocated(&handles[1]));
+        assert!(!texture_cache.is_allocated(&handles[2]));
+        assert!(texture_cache.is_allocated(&handles[3]));
+        assert_eq!(texture_cache.budget_overrun(), None);
+    }
+
+    #[test]
+    fn report_budget_overrun_of_items_used_by_the_frame() {
+        let budget = 1024 * 1024;
+        let mut texture_cache = TextureCache::new(2048, Some(budget), Vec::new());
+        let mut gpu_cache = GpuCache::new();
+        let mut profile = TextureCacheProfileCounters::new();
+        let mut handle = TextureCacheHandle::new();
+        let notice = EvictionNotice::default();
+
+        // A small item allocates the whole shared texture array, which
+        // can't be evicted while the frame uses it.
+        texture_cache.begin_frame(FrameId(1));
+        allocate(&mut texture_cache, &mut gpu_cache, &mut handle, shared_descriptor(), &notice);
+        texture_cache.end_frame(&mut profile);
+
+        assert!(!notice.check());
+        assert_eq!(texture_cache.budget_overrun(), Some((ARRAY_A8_BYTES, budget)));
+
+        // Once the item is unused, the array is freed.
+        texture_cache.pending_updates();
+        texture_cache.begin_frame(FrameId(2));
+        texture_cache.end_frame(&mut profile);
+
+        assert!(notice.check());
+        assert_eq!(texture_cache.budget_overrun(), None);
+        let updates = texture_cache.pending_updates().updates;
+        assert_eq!(updates.len(), 1);
+        match updates[0].op {
+            TextureUpdateOp::Free => {}
+            _ => panic!("The texture array should have been freed"),
+        }
+    }
+
+    #[test]
+    fn evict_shared_texture_arrays_with_their_most_recent_item() {
+        let mut texture_cache = TextureCache::new(2048, Some(ARRAY_A8_BYTES), Vec::new());
+        let mut gpu_cache = GpuCache::new();
+        let mut profile = TextureCacheProfileCounters::new();
+        let mut shared_handles = vec![TextureCacheHandle::new(), TextureCacheHandle::new()];
+        let shared_notices = vec![EvictionNotice::default(), EvictionNotice::default()];
+        let mut standalone_handle = TextureCacheHandle::new();
+        let standalone_notice = EvictionNotice::default();
+
+        texture_cache.begin_frame(FrameId(1));
+        allocate(
+            &mut texture_cache,
+            &mut gpu_cache,
+            &mut shared_handles[0],
+            shared_descriptor(),
+            &shared_notices[0],
+        );
+        texture_cache.end_frame(&mut profile);
+
+        texture_cache.begin_frame(FrameId(2));
+        allocate(
+            &mut texture_cache,
+            &mut gpu_cache,
+            &mut standalone_handle,
+            standalone_descriptor(),
+            &standalone_notice,
+        );
+        texture_cache.end_frame(&mut profile);
+
+        texture_cache.begin_frame(FrameId(3));
+        allocate(
+            &mut texture_cache,
+            &mut gpu_cache,
+            &mut shared_handles[1],
+            shared_descriptor(),
+            &shared_notices[1],
+        );
+        texture_cache.end_frame(&mut profile);
+
+        // The array was used more recently than the standalone item, so
+        // the standalone item is evicted first, while the array stays.
+        assert!(standalone_notice.check());
+        assert!(!shared_notices[0].check());
+        assert!(!shared_notices[1].check());
+        assert_eq!(texture_cache.budget_overrun(), None);
+
+        // A newer standalone item evicts the whole array.
+        texture_cache.begin_frame(FrameId(4));
+        let new_standalone_notice = EvictionNotice::default();
+        allocate(
+            &mut texture_cache,
+            &mut gpu_cache,
+            &mut standalone_handle,
+            standalone_descriptor(),
+            &new_standalone_notice,
+        );
+        texture_cache.end_frame(&mut profile);
+
+        assert!(shared_notices[0].check());
+        assert!(shared_notices[1].check());
+        assert!(!new_standalone_notice.check());
+        assert!(texture_cache.is_allocated(&standalone_handle));
+        assert_eq!(texture_cache.budget_overrun(), None);
+    }
+}
@@ -2,17 +2,18 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
 
-use api::{ColorF, DeviceIntPoint, DeviceIntRect, DeviceIntSize, DevicePixelScale, DeviceUintPoint};
-use api::{DeviceUintRect, DeviceUintSize, DocumentLayer, FilterOp, ImageFormat, LayoutRect};
-use api::{MixBlendMode, PipelineId};
-use batch::{AlphaBatchBuilder, AlphaBatchContainer, ClipBatcher, resolve_image};
+use api::{ColorF, CompositeOperator, DeviceIntPoint, DeviceIntRect, DeviceIntSize, DevicePixelScale};
+use api::{DeviceUintPoint, DeviceUintRect, DeviceUintSize, DocumentLayer, FilterOp, FilterPrimitive};
+use api::{ImageFormat, LayoutRect, MixBlendMode, PipelineId, StackingContextMask};
+use batch::{AlphaBatchBuilder, AlphaBatchContainer, BatchTextures, ClipBatcher, resolve_image};
 use clip::{ClipStore};
 use clip_scroll_tree::SpatialNodeIndex;
 use device::{FrameId, Texture};
 #[cfg(feature = "pathfinder")]
 use euclid::{TypedPoint2D, TypedVector2D};
 use gpu_cache::{GpuCache};
-use gpu_types::{BorderInstance, BlurDirection, BlurInstance, PrimitiveHeaders, TransformData, TransformPalette};
+use gpu_types::{BorderInstance, BlurDirection, BlurInstance, PrimitiveHeaders, SvgFilterInstance};
+use gpu_types::{SvgFilterKind, TransformData, TransformPalette};
 use internal_types::{FastHashMap, SavedTargetIndex, SourceTexture};
 #[cfg(feature = "pathfinder")]
 use pathfinder_partitioner::mesh::Mesh;
@@ -21,6 +22,7 @@ use prim_store::{BrushKind, DeferredResolve};
 use profiler::FrameProfileCounters;
 use render_task::{BlitSource, RenderTaskAddress, RenderTaskId, RenderTaskKind};
 use render_task::{BlurTask, ClearMode, GlyphTask, RenderTaskLocation, RenderTaskTree};
+use render_task::{SvgFilterInfo, SvgFilterTask};
 use resource_cache::ResourceCache;
 use std::{cmp, usize, f32, i32, mem};
 use texture_allocator::GuillotineAllocator;
@@ -297,6 +299,9 @@ pub struct ColorRenderTarget {
     // List of blur operations to apply for this render target.
     pub vertical_blurs: Vec<BlurInstance>,
     pub horizontal_blurs: Vec<BlurInstance>,
+    // SVG filter primitives to draw, in order, with the
+    // textures of their inputs.
+    pub svg_filters: Vec<(BatchTextures, Vec<SvgFilterInstance>)>,
     pub readbacks: Vec<DeviceIntRect>,
     pub scalings: Vec<ScalingInfo>,
     pub blits: Vec<BlitJob>,
@@ -323,6 +328,7 @@ impl RenderTarget for ColorRenderTarget {
             alpha_batch_containers: Vec::new(),
             vertical_blurs: Vec::new(),
             horizontal_blurs: Vec::new(),
+            svg_filters: Vec::new(),
             readbacks: Vec::new(),
             scalings: Vec::new(),
             blits: Vec::new(),
@@ -413,6 +419,15 @@ impl RenderTarget for ColorRenderTarget {
                     render_tasks.get_task_address(task.children[0]),
                 );
             }
+            RenderTaskKind::SvgFilter(ref task_info) => {
+                task_info.add_instances(
+                    &mut self.svg_filters,
+                    task_id,
+                    task.children[0],
+                    render_tasks,
+                    gpu_cache,
+                );
+            }
             RenderTaskKind::Picture(ref task_info) => {
                 let prim_metadata = ctx.prim_store.get_metadata(task_info.prim_index);
                 match prim_metadata.prim_kind {
@@ -466,6 +481,13 @@ impl RenderTarget for ColorRenderTarget {
                             deferred_resolves,
                         );
 
+                        // Compressed textures can't be attached to a framebuffer to be read from.
+                        // Compressed images are decoded for blits, except external ones.
+                        if let SourceTexture::CompressedTextureCache(..) = cache_item.texture_id {
+                            warn!("Unable to blit from compressed image {:?}", key.request);
+                            return;
+                        }
+
                         // Work out a source rect to copy from the texture, depending on whether
                         // a sub-rect is present or not.
                         // TODO(gw): We have much type confusion below - f32, i32 and u32 for
@@ -572,6 +594,7 @@ impl RenderTarget for AlphaRenderTarget {
             RenderTaskKind::Picture(..) |
             RenderTaskKind::Blit(..) |
             RenderTaskKind::Border(..) |
+            RenderTaskKind::SvgFilter(..) |
             RenderTaskKind::Glyph(..) => {
                 panic!("BUG: should not be added to alpha target!");
             }
@@ -712,6 +735,7 @@ impl TextureCacheRenderTarget {
             RenderTaskKind::ClipRegion(..) |
             RenderTaskKind::CacheMask(..) |
             RenderTaskKind::Readback(..) |
+            RenderTaskKind::SvgFilter(..) |
             RenderTaskKind::Scaling(..) => {
                 panic!("BUG: unexpected task kind for texture cache target");
             }
@@ -954,18 +978,34 @@ pub struct CompositeOps {
 
     // Requires two source textures (e.g. mix-blend-mode)
     pub mix_blend_mode: Option<MixBlendMode>,
+
+    // Applied as an image clip when compositing (e.g. mask-image)
+    pub mask: Option<StackingContextMask>,
+
+    // An SVG-style filter graph, applied after the filters above
+    pub filter_primitives: Vec<FilterPrimitive>,
 }
 
 impl CompositeOps {
-    pub fn new(filters: Vec<FilterOp>, mix_blend_mode: Option<MixBlendMode>) -> Self {
+    pub fn new(
+        filters: Vec<FilterOp>,
+        mix_blend_mode: Option<MixBlendMode>,
+        mask: Option<StackingContextMask>,
+        filter_primitives: Vec<FilterPrimitive>,
+    ) -> Self {
         CompositeOps {
             filters,
             mix_blend_mode,
+            mask,
+            filter_primitives,
         }
     }
 
     pub fn count(&self) -> usize {
-        self.filters.len() + if self.mix_blend_mode.is_some() { 1 } else { 0 }
+        self.filters.len() +
+            if self.mix_blend_mode.is_some() { 1 } else { 0 } +
+            if self.mask.is_some() { 1 } else { 0 } +
+            if self.filter_primitives.is_empty() { 0 } else { 1 }
     }
 }
 
@@ -1032,6 +1072,105 @@ impl BlurTask {
     }
 }
 
+impl SvgFilterTask {
+    fn add_instances(
+        &self,
+        batches: &mut Vec<(BatchTextures, Vec<SvgFilterInstance>)>,
+        task_id: RenderTaskId,
+        prev_task_id: RenderTaskId,
+        render_tasks: &RenderTaskTree,
+        gpu_cache: &GpuCache,
+    ) {
+        let task_address = render_tasks.get_task_address(task_id);
+        let extra_data_address = gpu_cache.get_address(&self.extra_data_handle);
+
+        // The output of the previous pass is bound as the render target
+        // cache. Older inputs have been saved for the rest of the frame.
+        let resolve = |input: Option<usize>| {
+            match input.map(|index| self.inputs[index]) {
+                Some(input) if input.task_id == prev_task_id => (
+                    render_tasks.get_task_address(input.task_id),
+                    SourceTexture::CacheRGBA8,
+                    input.alpha_only,
+                ),
+                Some(input) => {
+                    let saved_index = render_tasks[input.task_id]
+                        .saved_index
+                        .expect("bug: filter input was not saved");
+                    debug_assert_ne!(saved_index, SavedTargetIndex::PENDING);
+                    (
+                        render_tasks.get_task_address(input.task_id),
+                        SourceTexture::RenderTaskCache(saved_index),
+                        input.alpha_only,
+                    )
+                }
+                None => (task_address, SourceTexture::Invalid, false),
+            }
+        };
+
+        let mut push_instance = |
+            kind: SvgFilterKind,
+            input_1: Option<usize>,
+            input_2: Option<usize>,
+            generic_int: u16,
+        | {
+            let (input_1_task_address, input_1_texture, input_1_alpha_only) = resolve(input_1);
+            let (input_2_task_address, input_2_texture, input_2_alpha_only) = resolve(input_2);
+            let textures = BatchTextures {
+                colors: [input_1_texture, input_2_texture, SourceTexture::Invalid],
+            };
+            let instance = SvgFilterInstance {
+                task_address,
+                input_1_task_address,
+                input_2_task_address,
+                kind,
+                input_flags: input_1_alpha_only as u16 | (input_2_alpha_only as u16) << 1,
+                generic_int,
+                padding: 0,
+                extra_data_address,
+            };
+
+            if let Some(&mut (ref batch_textures, ref mut instances)) = batches.last_mut() {
+                if batch_textures.colors == textures.colors {
+                    instances.push(instance);
+                    return;
+                }
+            }
+            batches.push((textures, vec![instance]));
+        };
+
+        match self.info {
+            SvgFilterInfo::Flood(..) => {
+                push_instance(SvgFilterKind::Flood, None, None, 0);
+            }
+            SvgFilterInfo::Offset(..) => {
+                push_instance(SvgFilterKind::Offset, Some(0), None, 0);
+            }
+            SvgFilterInfo::ComponentTransfer(..) => {
+                push_instance(SvgFilterKind::ComponentTransfer, Some(0), None, 0);
+            }
+            SvgFilterInfo::Composite(operator) => {
+                // Note: has to match `COMPOSITE_*` in cs_svg_filter.glsl.
+                let operator = match operator {
+                    CompositeOperator::Over => 0,
+                    CompositeOperator::In => 1,
+                    CompositeOperator::Out => 2,
+                    CompositeOperator::Atop => 3,
+                    CompositeOperator::Xor => 4,
+                    CompositeOperator::Arithmetic(..) => 5,
+                };
+                push_instance(SvgFilterKind::Composite, Some(0), Some(1), operator);
+            }
+            SvgFilterInfo::Merge => {
+                // Each input is drawn on top of the previous ones.
+                for index in 0 .. self.inputs.len() {
+                    push_instance(SvgFilterKind::Identity, Some(index), None, 0);
+                }
+            }
+        }
+    }
+}
+
 pub struct SpecialRenderPasses {
     pub alpha_glyph_pass: RenderPass,
     pub color_glyph_pass: RenderPass,
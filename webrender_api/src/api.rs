@@ -13,14 +13,26 @@ use std::path::PathBuf;
 use std::u32;
 use {BuiltDisplayList, BuiltDisplayListDescriptor, ColorF, DeviceIntPoint, DeviceUintRect};
 use {DeviceUintSize, ExternalScrollId, FontInstanceKey, FontInstanceOptions};
-use {FontInstancePlatformOptions, FontKey, FontVariation, GlyphDimensions, GlyphIndex, ImageData};
+use {FontInstancePlatformOptions, FontKey, FontVariation, FontVariationAnimation};
+use {GlyphDimensions, GlyphIndex, GlyphOutline, ImageData};
 use {ImageDescriptor, ImageKey, ItemTag, LayoutPoint, LayoutSize, LayoutTransform, LayoutVector2D};
-use {NativeFontHandle, WorldPoint, NormalizedRect};
+use {NativeFontHandle, WorldPoint, WorldRect, WorldSize, NormalizedRect};
 
 pub type TileSize = u16;
 /// Documents are rendered in the ascending order of their associated layer values.
 pub type DocumentLayer = i8;
 
+/// How urgently the frames of a document are built and drawn.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum DocumentPriority {
+    /// Frames are built as soon as they are requested.
+    Normal,
+    /// Frames are only built once the render backend has no other message
+    /// to process, so that other documents are not held up by this one,
+    /// and are drawn after the other documents of the same layer.
+    Low,
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 pub enum ResourceUpdate {
     AddImage(AddImage),
@@ -29,6 +41,7 @@ pub enum ResourceUpdate {
     SetImageVisibleArea(ImageKey, NormalizedRect),
     AddFont(AddFont),
     DeleteFont(FontKey),
+    SetFontVariationAnimations(FontInstanceKey, Vec<FontVariationAnimation>),
     AddFontInstance(AddFontInstance),
     DeleteFontInstance(FontInstanceKey),
 }
@@ -243,6 +256,18 @@ impl Transaction {
         self.frame_ops.push(FrameMsg::EnableFrameOutput(pipeline_id, enable));
     }
 
+    /// Show or hide the document. No frame is built or rendered for a hidden
+    /// document; its scene is still kept up to date, and a new frame is built
+    /// when the document is shown again.
+    pub fn set_document_visibility(&mut self, visible: bool) {
+        self.frame_ops.push(FrameMsg::SetDocumentVisibility(visible));
+    }
+
+    /// Set how urgently the frames of the document are built.
+    pub fn set_document_priority(&mut self, priority: DocumentPriority) {
+        self.frame_ops.push(FrameMsg::SetDocumentPriority(priority));
+    }
+
     /// Consumes this object and just returns the frame ops.
     pub fn get_frame_ops(self) -> Vec<FrameMsg> {
         self.frame_ops
@@ -313,6 +338,19 @@ impl Transaction {
         self.resource_updates.push(ResourceUpdate::DeleteFont(key));
     }
 
+    /// Declares which variation axes of a font instance are animated. The
+    /// instance snaps these axes to the steps of the animation, including
+    /// when it's added again under the same key with new variations. An
+    /// empty list stops snapping the axes of the instance.
+    pub fn set_font_variation_animations(
+        &mut self,
+        key: FontInstanceKey,
+        animations: Vec<FontVariationAnimation>,
+    ) {
+        self.resource_updates
+            .push(ResourceUpdate::SetFontVariationAnimations(key, animations));
+    }
+
     pub fn add_font_instance(
         &mut self,
         key: FontInstanceKey,
@@ -428,6 +466,14 @@ pub struct HitTestItem {
     /// The coordinates of the original hit test point relative to the origin of this item.
     /// This is useful for calculating things like text offsets in the client.
     pub point_relative_to_item: LayoutPoint,
+
+    /// The external id of the innermost scroll frame enclosing the hit display item, if
+    /// any. This allows routing scroll events for the hit without another round-trip.
+    pub scroll_node: Option<ExternalScrollId>,
+
+    /// The fraction of the hit test area that is covered by this item. This is always
+    /// 1.0 for hit tests against a single point.
+    pub coverage: f32,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -435,11 +481,49 @@ pub struct HitTestResult {
     pub items: Vec<HitTestItem>,
 }
 
+/// An area to hit test against, used by `RenderApi::hit_test_area`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum HitTestArea {
+    Rect(WorldRect),
+    Circle(WorldPoint, f32),
+}
+
+impl HitTestArea {
+    pub fn bounding_rect(&self) -> WorldRect {
+        match *self {
+            HitTestArea::Rect(rect) => rect,
+            HitTestArea::Circle(center, radius) => WorldRect::new(
+                WorldPoint::new(center.x - radius, center.y - radius),
+                WorldSize::new(2.0 * radius, 2.0 * radius),
+            ),
+        }
+    }
+
+    pub fn contains(&self, point: &WorldPoint) -> bool {
+        match *self {
+            HitTestArea::Rect(ref rect) => rect.contains(point),
+            HitTestArea::Circle(center, radius) => {
+                (*point - center).square_length() <= radius * radius
+            }
+        }
+    }
+
+    pub fn area(&self) -> f32 {
+        match *self {
+            HitTestArea::Rect(ref rect) => rect.size.width * rect.size.height,
+            HitTestArea::Circle(_, radius) => ::std::f32::consts::PI * radius * radius,
+        }
+    }
+}
+
 bitflags! {
     #[derive(Deserialize, Serialize)]
     pub struct HitTestFlags: u8 {
         const FIND_ALL = 0b00000001;
         const POINT_RELATIVE_TO_PIPELINE_VIEWPORT = 0b00000010;
+        /// Order hits that belong to the same preserve-3d rendering context by
+        /// the depth of the hit point, front to back, instead of by paint order.
+        const SORT_BY_DEPTH = 0b00000100;
     }
 }
 
@@ -482,6 +566,7 @@ pub enum SceneMsg {
 pub enum FrameMsg {
     UpdateEpoch(PipelineId, Epoch),
     HitTest(Option<PipelineId>, WorldPoint, HitTestFlags, MsgSender<HitTestResult>),
+    HitTestArea(Option<PipelineId>, HitTestArea, HitTestFlags, MsgSender<HitTestResult>),
     SetPan(DeviceIntPoint),
     EnableFrameOutput(PipelineId, bool),
     Scroll(ScrollLocation, WorldPoint),
@@ -489,6 +574,8 @@ pub enum FrameMsg {
     GetScrollNodeState(MsgSender<Vec<ScrollNodeState>>),
     UpdateDynamicProperties(DynamicProperties),
     AppendDynamicProperties(DynamicProperties),
+    SetDocumentVisibility(bool),
+    SetDocumentPriority(DocumentPriority),
 }
 
 impl fmt::Debug for SceneMsg {
@@ -510,6 +597,7 @@ impl fmt::Debug for FrameMsg {
         f.write_str(match *self {
             FrameMsg::UpdateEpoch(..) => "FrameMsg::UpdateEpoch",
             FrameMsg::HitTest(..) => "FrameMsg::HitTest",
+            FrameMsg::HitTestArea(..) => "FrameMsg::HitTestArea",
             FrameMsg::SetPan(..) => "FrameMsg::SetPan",
             FrameMsg::Scroll(..) => "FrameMsg::Scroll",
             FrameMsg::ScrollNodeWithId(..) => "FrameMsg::ScrollNodeWithId",
@@ -517,6 +605,8 @@ impl fmt::Debug for FrameMsg {
             FrameMsg::EnableFrameOutput(..) => "FrameMsg::EnableFrameOutput",
             FrameMsg::UpdateDynamicProperties(..) => "FrameMsg::UpdateDynamicProperties",
             FrameMsg::AppendDynamicProperties(..) => "FrameMsg::AppendDynamicProperties",
+            FrameMsg::SetDocumentVisibility(..) => "FrameMsg::SetDocumentVisibility",
+            FrameMsg::SetDocumentPriority(..) => "FrameMsg::SetDocumentPriority",
         })
     }
 }
@@ -566,6 +656,10 @@ pub enum DebugCommand {
     EnableGpuSampleQueries(bool),
     /// Configure if dual-source blending is used, if available.
     EnableDualSourceBlending(bool),
+    /// Configure if compressed images are uploaded as compressed textures,
+    /// if their format is supported, or decoded. Only affects the images
+    /// that are uploaded afterwards.
+    EnableCompressedTextures(bool),
     /// Show an indicator that moves every time a frame is rendered.
     EnableNewFrameIndicator(bool),
     /// Show an indicator that moves every time a scene is built.
@@ -600,6 +694,12 @@ pub enum ApiMsg {
         Vec<GlyphIndex>,
         MsgSender<Vec<Option<GlyphDimensions>>>,
     ),
+    /// Gets the glyph outlines and the kerning between them
+    GetGlyphOutlines(
+        FontInstanceKey,
+        Vec<GlyphIndex>,
+        MsgSender<Vec<Option<GlyphOutline>>>,
+    ),
     /// Gets the glyph indices from a string
     GetGlyphIndices(FontKey, String, MsgSender<Vec<Option<u32>>>),
     /// Adds a new document namespace.
@@ -633,6 +733,7 @@ impl fmt::Debug for ApiMsg {
         f.write_str(match *self {
             ApiMsg::UpdateResources(..) => "ApiMsg::UpdateResources",
             ApiMsg::GetGlyphDimensions(..) => "ApiMsg::GetGlyphDimensions",
+            ApiMsg::GetGlyphOutlines(..) => "ApiMsg::GetGlyphOutlines",
             ApiMsg::GetGlyphIndices(..) => "ApiMsg::GetGlyphIndices",
             ApiMsg::CloneApi(..) => "ApiMsg::CloneApi",
             ApiMsg::AddDocument(..) => "ApiMsg::AddDocument",
@@ -814,6 +915,20 @@ impl RenderApi {
         rx.recv().unwrap()
     }
 
+    /// Gets the outlines of the supplied glyphs, along with their advances
+    /// and the kerning between each glyph and the next one, as for drawing
+    /// text along a path.
+    pub fn get_glyph_outlines(
+        &self,
+        font: FontInstanceKey,
+        glyph_indices: Vec<GlyphIndex>,
+    ) -> Vec<Option<GlyphOutline>> {
+        let (tx, rx) = channel::msg_channel().unwrap();
+        let msg = ApiMsg::GetGlyphOutlines(font, glyph_indices, tx);
+        self.api_sender.send(msg).unwrap();
+        rx.recv().unwrap()
+    }
+
     /// Gets the glyph indices for the supplied string. These
     /// can be used to construct GlyphKeys.
     pub fn get_glyph_indices(&self, font_key: FontKey, text: &str) -> Vec<Option<u32>> {
@@ -919,7 +1034,8 @@ impl RenderApi {
     /// hit results so that only items inside that pipeline are matched. If the
     /// HitTestFlags argument contains the FIND_ALL flag, then the vector of hit
     /// results will contain all display items that match, ordered from front
-    /// to back.
+    /// to back. With SORT_BY_DEPTH, items in a preserve-3d rendering context are
+    /// ordered by their depth at the hit point rather than by paint order.
     pub fn hit_test(&self,
                     document_id: DocumentId,
                     pipeline_id: Option<PipelineId>,
@@ -935,6 +1051,27 @@ impl RenderApi {
         rx.recv().unwrap()
     }
 
+    /// Does a hit test on display items in the specified document against an area,
+    /// such as the contact area of a touch or a selection rectangle. Items are hit
+    /// using the same clipping and backface rules as `hit_test`, and each result
+    /// reports the fraction of the area it covers. Results are ordered by decreasing
+    /// coverage, and only the best match is returned unless the HitTestFlags argument
+    /// contains the FIND_ALL flag.
+    pub fn hit_test_area(&self,
+                         document_id: DocumentId,
+                         pipeline_id: Option<PipelineId>,
+                         area: HitTestArea,
+                         flags: HitTestFlags)
+                         -> HitTestResult {
+        let (tx, rx) = channel::msg_channel().unwrap();
+
+        self.send_frame_msg(
+            document_id,
+            FrameMsg::HitTestArea(pipeline_id, area, flags, tx)
+        );
+        rx.recv().unwrap()
+    }
+
     pub fn set_window_parameters(
         &self,
         document_id: DocumentId,
@@ -1113,4 +1250,8 @@ pub trait RenderNotifier: Send {
         unimplemented!()
     }
     fn shut_down(&self) {}
+    /// Called after a frame is built if the texture cache is still above
+    /// the `texture_cache_budget` after evicting everything it could, so
+    /// that the embedder can release some of its image keys.
+    fn texture_cache_budget_exceeded(&self, _used_bytes: usize, _budget_bytes: usize) {}
 }
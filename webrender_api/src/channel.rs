@@ -81,6 +81,13 @@ pub trait PayloadReceiverHelperMethods {
     fn to_mpsc_receiver(self) -> Receiver<Payload>;
 }
 
+/// A helper to handle the interface difference between `IpcReceiver`
+/// and `Receiver` when polling for a message.
+pub trait MsgReceiverHelperMethods<T> {
+    /// Returns the next message if one is available, without blocking.
+    fn try_recv_msg(&self) -> Option<T>;
+}
+
 #[cfg(not(feature = "ipc"))]
 include!("channel_mpsc.rs");
 
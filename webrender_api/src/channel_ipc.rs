@@ -48,6 +48,15 @@ impl PayloadReceiverHelperMethods for PayloadReceiver {
     }
 }
 
+impl<T> MsgReceiverHelperMethods<T> for MsgReceiver<T>
+where
+    T: Serialize + for<'de> Deserialize<'de>,
+{
+    fn try_recv_msg(&self) -> Option<T> {
+        self.try_recv().ok()
+    }
+}
+
 pub fn msg_channel<T: Serialize + for<'de> Deserialize<'de>>() -> Result<(MsgSender<T>, MsgReceiver<T>), Error> {
     ipc::channel()
 }
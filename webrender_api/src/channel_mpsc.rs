@@ -42,6 +42,12 @@ impl<T> MsgReceiver<T> {
     }
 }
 
+impl<T> MsgReceiverHelperMethods<T> for MsgReceiver<T> {
+    fn try_recv_msg(&self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
 #[derive(Clone)]
 pub struct MsgSender<T> {
     tx: mpsc::Sender<T>,
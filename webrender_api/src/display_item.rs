@@ -6,7 +6,7 @@
 use GlyphInstance;
 use euclid::{SideOffsets2D, TypedRect};
 use std::ops::Not;
-use {ColorF, FontInstanceKey, GlyphOptions, ImageKey, LayoutPixel, LayoutPoint};
+use {ColorF, FontInstanceKey, GlyphIndex, GlyphOptions, ImageKey, LayoutPixel, LayoutPoint};
 use {LayoutRect, LayoutSize, LayoutTransform, LayoutVector2D, PipelineId, PropertyBinding};
 
 
@@ -105,6 +105,7 @@ pub enum SpecificDisplayItem {
     BoxShadow(BoxShadowDisplayItem),
     Gradient(GradientDisplayItem),
     RadialGradient(RadialGradientDisplayItem),
+    ConicGradient(ConicGradientDisplayItem),
     ClipChain(ClipChainItem),
     Iframe(IframeDisplayItem),
     PushStackingContext(PushStackingContextDisplayItem),
@@ -112,6 +113,7 @@ pub enum SpecificDisplayItem {
     PushReferenceFrame(PushReferenceFrameDisplayListItem),
     PopReferenceFrame,
     SetGradientStops,
+    SetFilterPrimitives,
     PushShadow(Shadow),
     PopAllShadows,
 }
@@ -123,7 +125,7 @@ pub enum SpecificDisplayItem {
 #[cfg_attr(feature = "serialize", derive(Serialize))]
 #[cfg_attr(feature = "deserialize", derive(Deserialize))]
 pub enum CompletelySpecificDisplayItem {
-    Clip(ClipDisplayItem, Vec<ComplexClipRegion>),
+    Clip(ClipDisplayItem, Vec<ComplexClipRegion>, Vec<PathOp>),
     ClipChain(ClipChainItem, Vec<ClipId>),
     ScrollFrame(ScrollFrameDisplayItem, Vec<ComplexClipRegion>),
     StickyFrame(StickyFrameDisplayItem),
@@ -137,12 +139,14 @@ pub enum CompletelySpecificDisplayItem {
     BoxShadow(BoxShadowDisplayItem),
     Gradient(GradientDisplayItem),
     RadialGradient(RadialGradientDisplayItem),
+    ConicGradient(ConicGradientDisplayItem),
     Iframe(IframeDisplayItem),
     PushStackingContext(PushStackingContextDisplayItem, Vec<FilterOp>),
     PopStackingContext,
     PushReferenceFrame(PushReferenceFrameDisplayListItem),
     PopReferenceFrame,
     SetGradientStops(Vec<GradientStop>),
+    SetFilterPrimitives(Vec<FilterPrimitive>),
     PushShadow(Shadow),
     PopAllShadows,
 }
@@ -151,6 +155,9 @@ pub enum CompletelySpecificDisplayItem {
 pub struct ClipDisplayItem {
     pub id: ClipId,
     pub image_mask: Option<ImageMask>,
+    /// How the inside of the clip path is determined, if the
+    /// clip has a path (see `DisplayListBuilder::define_path_clip`).
+    pub fill_rule: FillRule,
 }
 
 /// The minimum and maximum allowable offset for a sticky frame in a single dimension.
@@ -252,8 +259,60 @@ pub struct TextDisplayItem {
     pub font_key: FontInstanceKey,
     pub color: ColorF,
     pub glyph_options: Option<GlyphOptions>,
+    pub decorations: TextDecorations,
+    /// The number of text items that follow this one and continue its run,
+    /// which long runs are split into. The decorations span all of them.
+    pub continuation_count: u32,
 } // IMPLICIT: glyphs: Vec<GlyphInstance>
 
+/// The lines drawn along a text run, and the marks drawn over its glyphs.
+/// They span the advances of its glyphs, and follow the baseline of its
+/// first glyph.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct TextDecorations {
+    pub underline: Option<TextDecoration>,
+    pub overline: Option<TextDecoration>,
+    pub line_through: Option<TextDecoration>,
+    pub emphasis: Option<TextEmphasis>,
+}
+
+impl TextDecorations {
+    pub fn is_empty(&self) -> bool {
+        self.underline.is_none() &&
+            self.overline.is_none() &&
+            self.line_through.is_none() &&
+            self.emphasis.is_none()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TextDecoration {
+    /// The offset of the top of the line from the baseline, positive downwards.
+    pub offset: f32,
+    /// The thickness of the line. Wavy lines oscillate in a band three
+    /// times as tall.
+    pub thickness: f32,
+    pub color: ColorF,
+    pub style: LineStyle,
+    /// Whether the line is interrupted where glyphs cross it, like
+    /// `text-decoration-skip-ink: auto`. Line-throughs never skip ink.
+    pub skip_ink: bool,
+}
+
+/// A mark centered on the advance of each glyph of a text run that has
+/// ink, like `text-emphasis`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TextEmphasis {
+    /// The font instance of the mark, which is usually smaller than
+    /// the one of the run.
+    pub font_key: FontInstanceKey,
+    pub mark: GlyphIndex,
+    /// The offset of the baseline of the marks from the baseline of
+    /// the run, positive downwards.
+    pub offset: f32,
+    pub color: ColorF,
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub struct NormalBorder {
     pub left: BorderSide,
@@ -277,6 +336,7 @@ pub enum NinePatchBorderSource {
     Image(ImageKey),
     Gradient(Gradient),
     RadialGradient(RadialGradient),
+    ConicGradient(ConicGradient),
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
@@ -458,6 +518,26 @@ pub struct RadialGradientDisplayItem {
     pub tile_spacing: LayoutSize,
 }
 
+/// A gradient whose color varies with the angle around a center point,
+/// as described by CSS `conic-gradient()`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConicGradient {
+    pub center: LayoutPoint,
+    /// The angle of the zero offset, in radians, measured clockwise
+    /// from the upwards direction.
+    pub angle: f32,
+    pub start_offset: f32,
+    pub end_offset: f32,
+    pub extend_mode: ExtendMode,
+} // IMPLICIT stops: Vec<GradientStop>
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConicGradientDisplayItem {
+    pub gradient: ConicGradient,
+    pub tile_size: LayoutSize,
+    pub tile_spacing: LayoutSize,
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub struct PushReferenceFrameDisplayListItem {
     pub reference_frame: ReferenceFrame,
@@ -481,8 +561,30 @@ pub struct StackingContext {
     pub mix_blend_mode: MixBlendMode,
     pub clip_node_id: Option<ClipId>,
     pub glyph_raster_space: GlyphRasterSpace,
+    pub mask: Option<StackingContextMask>,
 } // IMPLICIT: filters: Vec<FilterOp>
 
+/// An image used as a mask for the whole contents of a stacking context,
+/// as with CSS `mask-image`. The mask is applied after any filters.
+/// Generated masks such as gradients can be supplied as blob images.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct StackingContextMask {
+    pub image: ImageKey,
+    /// The area the mask image is stretched over, in the coordinate space
+    /// of the stacking context. Content outside of it is masked out.
+    pub rect: LayoutRect,
+    pub mode: MaskMode,
+}
+
+/// How the mask image is converted to coverage, as with CSS `mask-mode`.
+/// Single channel (`R8`) mask images always use their only channel.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum MaskMode {
+    Alpha = 0,
+    Luminance = 1,
+}
+
 
 #[repr(u32)]
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
@@ -544,6 +646,82 @@ pub enum FilterOp {
     ColorMatrix([f32; 20]),
 }
 
+/// The image a filter primitive reads from, as with the SVG `in` and `in2`
+/// attributes.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum FilterPrimitiveInput {
+    /// The contents of the stacking context.
+    SourceGraphic,
+    /// The alpha channel of the contents of the stacking context.
+    SourceAlpha,
+    /// The result of an earlier primitive in the same graph, by index.
+    Result(usize),
+}
+
+/// A per-channel function of an `feComponentTransfer` primitive. The
+/// functions operate on non-premultiplied color values.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TransferFunction {
+    Identity,
+    Table(Vec<f32>),
+    Discrete(Vec<f32>),
+    Linear { slope: f32, intercept: f32 },
+    Gamma { amplitude: f32, exponent: f32, offset: f32 },
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum CompositeOperator {
+    Over,
+    In,
+    Out,
+    Atop,
+    Xor,
+    /// The `k1` to `k4` coefficients of `feComposite operator="arithmetic"`.
+    Arithmetic([f32; 4]),
+}
+
+/// A node of an SVG-style filter graph. The primitives of a graph are
+/// evaluated in order and the last one is the result of the filter. Every
+/// primitive covers the same area, the bounds of the stacking context.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum FilterPrimitive {
+    Flood {
+        color: ColorF,
+    },
+    Offset {
+        input: FilterPrimitiveInput,
+        offset: LayoutVector2D,
+    },
+    ComponentTransfer {
+        input: FilterPrimitiveInput,
+        red: TransferFunction,
+        green: TransferFunction,
+        blue: TransferFunction,
+        alpha: TransferFunction,
+    },
+    Composite {
+        input1: FilterPrimitiveInput,
+        input2: FilterPrimitiveInput,
+        operator: CompositeOperator,
+    },
+    Merge {
+        inputs: Vec<FilterPrimitiveInput>,
+    },
+}
+
+impl FilterPrimitive {
+    /// Returns the inputs read by this primitive, in drawing order.
+    pub fn inputs(&self) -> Vec<FilterPrimitiveInput> {
+        match *self {
+            FilterPrimitive::Flood { .. } => Vec::new(),
+            FilterPrimitive::Offset { input, .. } |
+            FilterPrimitive::ComponentTransfer { input, .. } => vec![input],
+            FilterPrimitive::Composite { input1, input2, .. } => vec![input1, input2],
+            FilterPrimitive::Merge { ref inputs } => inputs.clone(),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub struct IframeDisplayItem {
     pub clip_id: ClipId,
@@ -566,6 +744,11 @@ pub enum ImageRendering {
     Auto = 0,
     CrispEdges = 1,
     Pixelated = 2,
+    /// Minified images are sampled from a chain of downscaled copies of
+    /// the image, so that photos drawn at a fraction of their size
+    /// don't alias. Each image using this mode gets a texture of its
+    /// own, with about a third more memory for the mip levels.
+    HighQuality = 3,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
@@ -722,6 +905,41 @@ impl Not for ClipMode {
     }
 }
 
+/// How the inside of a path is determined, as with the SVG `fill-rule`
+/// property.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum FillRule {
+    NonZero = 0,
+    EvenOdd = 1,
+}
+
+/// A segment of a clip path, in the same coordinate space as the rect
+/// of the clip. Each subpath starts with a `MoveTo`. Since the path is
+/// filled, subpaths are closed implicitly, so `Close` is only needed to
+/// start a new subpath at the same point.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum PathOp {
+    MoveTo(LayoutPoint),
+    LineTo(LayoutPoint),
+    /// A quadratic bezier curve, with a control point and an end point.
+    QuadraticTo(LayoutPoint, LayoutPoint),
+    /// A cubic bezier curve, with two control points and an end point.
+    CubicTo(LayoutPoint, LayoutPoint, LayoutPoint),
+    Close,
+}
+
+impl PathOp {
+    /// The path of a polygon, as with CSS `clip-path: polygon()`.
+    pub fn polygon(points: &[LayoutPoint]) -> Vec<PathOp> {
+        let mut ops = Vec::with_capacity(points.len());
+        for (i, point) in points.iter().enumerate() {
+            ops.push(if i == 0 { PathOp::MoveTo(*point) } else { PathOp::LineTo(*point) });
+        }
+        ops
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub struct ComplexClipRegion {
@@ -11,20 +11,22 @@ use serde::ser::{Serializer, SerializeSeq};
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
 use std::marker::PhantomData;
-use std::{io, mem, ptr, slice};
+use std::{io, iter, mem, ptr, slice};
 use time::precise_time_ns;
 use {AlphaType, BorderDetails, BorderDisplayItem, BorderRadius, BorderWidths, BoxShadowClipMode};
 use {BoxShadowDisplayItem, ClipAndScrollInfo, ClipChainId, ClipChainItem, ClipDisplayItem, ClipId};
-use {ColorF, ComplexClipRegion, DisplayItem, ExtendMode, ExternalScrollId, FilterOp};
+use {ColorF, ComplexClipRegion, ConicGradient, ConicGradientDisplayItem, DisplayItem};
+use {ExtendMode, ExternalScrollId, FillRule, FilterOp, FilterPrimitive};
 use {FontInstanceKey, GlyphInstance, GlyphOptions, GlyphRasterSpace, Gradient};
 use {GradientDisplayItem, GradientStop, IframeDisplayItem, ImageDisplayItem, ImageKey, ImageMask};
 use {ImageRendering, LayoutPoint, LayoutPrimitiveInfo, LayoutRect, LayoutSize, LayoutTransform};
-use {LayoutVector2D, LineDisplayItem, LineOrientation, LineStyle, MixBlendMode, PipelineId};
+use {LayoutVector2D, LineDisplayItem, LineOrientation, LineStyle, MixBlendMode, PathOp, PipelineId};
 use {PropertyBinding, PushReferenceFrameDisplayListItem, PushStackingContextDisplayItem};
 use {RadialGradient, RadialGradientDisplayItem, RectangleDisplayItem, ReferenceFrame};
 use {ScrollFrameDisplayItem, ScrollSensitivity, Shadow, SpecificDisplayItem, StackingContext};
-use {StickyFrameDisplayItem, StickyOffsetBounds, TextDisplayItem, TransformStyle, YuvColorSpace};
-use {YuvData, YuvImageDisplayItem};
+use {StackingContextMask, StickyFrameDisplayItem, StickyOffsetBounds, TextDecorations};
+use {TextDisplayItem, TransformStyle};
+use {YuvColorSpace, YuvData, YuvImageDisplayItem};
 
 // We don't want to push a long text-run. If a text-run is too long, split it into several parts.
 // This needs to be set to (renderer::MAX_VERTEX_TEXTURE_WIDTH - VECS_PER_TEXT_RUN) * 2
@@ -39,13 +41,23 @@ const FIRST_SPATIAL_NODE_INDEX: usize = 2;
 const FIRST_CLIP_NODE_INDEX: usize = 0;
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct ItemRange<T> {
     start: usize,
     length: usize,
     _boo: PhantomData<T>,
 }
 
+// Implemented by hand so that ranges of items that aren't `Copy`
+// themselves, like filter primitives, can still be passed around.
+impl<T> Copy for ItemRange<T> {}
+
+impl<T> Clone for ItemRange<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
 impl<T> Default for ItemRange<T> {
     fn default() -> Self {
         ItemRange {
@@ -97,8 +109,10 @@ pub struct BuiltDisplayListIter<'a> {
     cur_stops: ItemRange<GradientStop>,
     cur_glyphs: ItemRange<GlyphInstance>,
     cur_filters: ItemRange<FilterOp>,
+    cur_filter_primitives: ItemRange<FilterPrimitive>,
     cur_clip_chain_items: ItemRange<ClipId>,
     cur_complex_clip: (ItemRange<ComplexClipRegion>, usize),
+    cur_clip_path: ItemRange<PathOp>,
     peeking: Peek,
 }
 
@@ -218,8 +232,10 @@ impl<'a> BuiltDisplayListIter<'a> {
             cur_stops: ItemRange::default(),
             cur_glyphs: ItemRange::default(),
             cur_filters: ItemRange::default(),
+            cur_filter_primitives: ItemRange::default(),
             cur_clip_chain_items: ItemRange::default(),
             cur_complex_clip: (ItemRange::default(), 0),
+            cur_clip_path: ItemRange::default(),
             peeking: Peek::NotPeeking,
         }
     }
@@ -228,6 +244,12 @@ impl<'a> BuiltDisplayListIter<'a> {
         self.list
     }
 
+    /// The serialized items that haven't been read yet. Comparing this
+    /// before and after a traversal gives the bytes of the items visited.
+    pub fn unread_data(&self) -> &'a [u8] {
+        self.data
+    }
+
     pub fn next<'b>(&'b mut self) -> Option<DisplayItemRef<'a, 'b>> {
         use SpecificDisplayItem::*;
 
@@ -244,16 +266,18 @@ impl<'a> BuiltDisplayListIter<'a> {
 
         // Don't let these bleed into another item
         self.cur_stops = ItemRange::default();
+        self.cur_filter_primitives = ItemRange::default();
         self.cur_complex_clip = (ItemRange::default(), 0);
+        self.cur_clip_path = ItemRange::default();
         self.cur_clip_chain_items = ItemRange::default();
 
         loop {
             self.next_raw()?;
-            if let SetGradientStops = self.cur_item.item {
-                // SetGradientStops is a dummy item that most consumers should ignore
-                continue;
+            match self.cur_item.item {
+                // These are dummy items that most consumers should ignore
+                SetGradientStops | SetFilterPrimitives => continue,
+                _ => break,
             }
-            break;
         }
 
         Some(self.as_ref())
@@ -279,10 +303,18 @@ impl<'a> BuiltDisplayListIter<'a> {
             SetGradientStops => {
                 self.cur_stops = skip_slice::<GradientStop>(self.list, &mut self.data).0;
             }
+            SetFilterPrimitives => {
+                self.cur_filter_primitives =
+                    skip_slice::<FilterPrimitive>(self.list, &mut self.data).0;
+            }
             ClipChain(_) => {
                 self.cur_clip_chain_items = skip_slice::<ClipId>(self.list, &mut self.data).0;
             }
-            Clip(_) | ScrollFrame(_) => {
+            Clip(_) => {
+                self.cur_complex_clip = self.skip_slice::<ComplexClipRegion>();
+                self.cur_clip_path = self.skip_slice::<PathOp>().0;
+            }
+            ScrollFrame(_) => {
                 self.cur_complex_clip = self.skip_slice::<ComplexClipRegion>()
             }
             Text(_) => self.cur_glyphs = self.skip_slice::<GlyphInstance>().0,
@@ -380,6 +412,10 @@ impl<'a, 'b> DisplayItemRef<'a, 'b> {
         self.iter.cur_complex_clip
     }
 
+    pub fn clip_path(&self) -> ItemRange<PathOp> {
+        self.iter.cur_clip_path
+    }
+
     pub fn gradient_stops(&self) -> ItemRange<GradientStop> {
         self.iter.cur_stops
     }
@@ -392,6 +428,10 @@ impl<'a, 'b> DisplayItemRef<'a, 'b> {
         self.iter.cur_filters
     }
 
+    pub fn filter_primitives(&self) -> ItemRange<FilterPrimitive> {
+        self.iter.cur_filter_primitives
+    }
+
     pub fn clip_chain_items(&self) -> ItemRange<ClipId> {
         self.iter.cur_clip_chain_items
     }
@@ -463,7 +503,8 @@ impl Serialize for BuiltDisplayList {
                 item: match display_item.item {
                     SpecificDisplayItem::Clip(v) => Clip(
                         v,
-                        item.iter.list.get(item.iter.cur_complex_clip.0).collect()
+                        item.iter.list.get(item.iter.cur_complex_clip.0).collect(),
+                        item.iter.list.get(item.iter.cur_clip_path).collect(),
                     ),
                     SpecificDisplayItem::ClipChain(v) => ClipChain(
                         v,
@@ -487,6 +528,7 @@ impl Serialize for BuiltDisplayList {
                     SpecificDisplayItem::BoxShadow(v) => BoxShadow(v),
                     SpecificDisplayItem::Gradient(v) => Gradient(v),
                     SpecificDisplayItem::RadialGradient(v) => RadialGradient(v),
+                    SpecificDisplayItem::ConicGradient(v) => ConicGradient(v),
                     SpecificDisplayItem::Iframe(v) => Iframe(v),
                     SpecificDisplayItem::PushStackingContext(v) => PushStackingContext(
                         v,
@@ -498,6 +540,9 @@ impl Serialize for BuiltDisplayList {
                     SpecificDisplayItem::SetGradientStops => SetGradientStops(
                         item.iter.list.get(item.iter.cur_stops).collect()
                     ),
+                    SpecificDisplayItem::SetFilterPrimitives => SetFilterPrimitives(
+                        item.iter.list.get(item.iter.cur_filter_primitives).collect()
+                    ),
                     SpecificDisplayItem::PushShadow(v) => PushShadow(v),
                     SpecificDisplayItem::PopAllShadows => PopAllShadows,
                 },
@@ -533,9 +578,10 @@ impl<'de> Deserialize<'de> for BuiltDisplayList {
         for complete in list {
             let item = DisplayItem {
                 item: match complete.item {
-                    Clip(specific_item, complex_clips) => {
+                    Clip(specific_item, complex_clips, path) => {
                         total_clip_nodes += 1;
                         DisplayListBuilder::push_iter_impl(&mut temp, complex_clips);
+                        DisplayListBuilder::push_iter_impl(&mut temp, path);
                         SpecificDisplayItem::Clip(specific_item)
                     },
                     ClipChain(specific_item, clip_chain_ids) => {
@@ -566,6 +612,8 @@ impl<'de> Deserialize<'de> for BuiltDisplayList {
                     Gradient(specific_item) => SpecificDisplayItem::Gradient(specific_item),
                     RadialGradient(specific_item) =>
                         SpecificDisplayItem::RadialGradient(specific_item),
+                    ConicGradient(specific_item) =>
+                        SpecificDisplayItem::ConicGradient(specific_item),
                     Iframe(specific_item) => {
                         total_clip_nodes += 1;
                         SpecificDisplayItem::Iframe(specific_item)
@@ -584,6 +632,10 @@ impl<'de> Deserialize<'de> for BuiltDisplayList {
                         DisplayListBuilder::push_iter_impl(&mut temp, stops);
                         SpecificDisplayItem::SetGradientStops
                     },
+                    SetFilterPrimitives(primitives) => {
+                        DisplayListBuilder::push_iter_impl(&mut temp, primitives);
+                        SpecificDisplayItem::SetFilterPrimitives
+                    },
                     PushShadow(specific_item) => SpecificDisplayItem::PushShadow(specific_item),
                     PopAllShadows => SpecificDisplayItem::PopAllShadows,
                 },
@@ -1098,15 +1150,42 @@ impl DisplayListBuilder {
         color: ColorF,
         glyph_options: Option<GlyphOptions>,
     ) {
-        let item = SpecificDisplayItem::Text(TextDisplayItem {
+        self.push_decorated_text(
+            info,
+            glyphs,
+            font_key,
+            color,
+            glyph_options,
+            TextDecorations::default(),
+        );
+    }
+
+    /// Push a text run with underline, overline and line-through decorations,
+    /// and emphasis marks, which WebRender lays out along the glyphs of the run.
+    pub fn push_decorated_text(
+        &mut self,
+        info: &LayoutPrimitiveInfo,
+        glyphs: &[GlyphInstance],
+        font_key: FontInstanceKey,
+        color: ColorF,
+        glyph_options: Option<GlyphOptions>,
+        decorations: TextDecorations,
+    ) {
+        let run_count = (glyphs.len() + MAX_TEXT_RUN_LENGTH - 1) / MAX_TEXT_RUN_LENGTH;
+        let mut item = TextDisplayItem {
             color,
             font_key,
             glyph_options,
-        });
+            decorations,
+            continuation_count: run_count.saturating_sub(1) as u32,
+        };
 
         for split_glyphs in glyphs.chunks(MAX_TEXT_RUN_LENGTH) {
-            self.push_item(item, info);
+            self.push_item(SpecificDisplayItem::Text(item), info);
             self.push_iter(split_glyphs);
+            // Only the first item of the run has the decorations.
+            item.decorations = TextDecorations::default();
+            item.continuation_count = 0;
         }
     }
 
@@ -1235,6 +1314,29 @@ impl DisplayListBuilder {
         }
     }
 
+    // NOTE: gradients must be pushed in the order they're created
+    // because create_gradient stores the stops in anticipation
+    pub fn create_conic_gradient(
+        &mut self,
+        center: LayoutPoint,
+        angle: f32,
+        mut stops: Vec<GradientStop>,
+        extend_mode: ExtendMode,
+    ) -> ConicGradient {
+        let (start_offset, end_offset) =
+            DisplayListBuilder::normalize_stops(&mut stops, extend_mode);
+
+        self.push_stops(&stops);
+
+        ConicGradient {
+            center,
+            angle,
+            start_offset,
+            end_offset,
+            extend_mode,
+        }
+    }
+
     pub fn push_border(
         &mut self,
         info: &LayoutPrimitiveInfo,
@@ -1319,6 +1421,25 @@ impl DisplayListBuilder {
         self.push_item(item, info);
     }
 
+    /// Pushes a conic gradient to be displayed.
+    ///
+    /// See [`push_gradient`](#method.push_gradient) for explanation.
+    pub fn push_conic_gradient(
+        &mut self,
+        info: &LayoutPrimitiveInfo,
+        gradient: ConicGradient,
+        tile_size: LayoutSize,
+        tile_spacing: LayoutSize,
+    ) {
+        let item = SpecificDisplayItem::ConicGradient(ConicGradientDisplayItem {
+            gradient,
+            tile_size,
+            tile_spacing,
+        });
+
+        self.push_item(item, info);
+    }
+
     pub fn push_reference_frame(
         &mut self,
         info: &LayoutPrimitiveInfo,
@@ -1349,6 +1470,7 @@ impl DisplayListBuilder {
         mix_blend_mode: MixBlendMode,
         filters: Vec<FilterOp>,
         glyph_raster_space: GlyphRasterSpace,
+        mask: Option<StackingContextMask>,
     ) {
         let item = SpecificDisplayItem::PushStackingContext(PushStackingContextDisplayItem {
             stacking_context: StackingContext {
@@ -1356,6 +1478,7 @@ impl DisplayListBuilder {
                 mix_blend_mode,
                 clip_node_id,
                 glyph_raster_space,
+                mask,
             },
         });
 
@@ -1375,6 +1498,16 @@ impl DisplayListBuilder {
         self.push_iter(stops);
     }
 
+    /// Sets the SVG-style filter graph of the next stacking context. It is
+    /// applied after the stacking context's `FilterOp`s.
+    pub fn push_filter_primitives(&mut self, primitives: &[FilterPrimitive]) {
+        if primitives.is_empty() {
+            return;
+        }
+        self.push_new_empty_item(SpecificDisplayItem::SetFilterPrimitives);
+        self.push_iter(primitives);
+    }
+
     fn generate_clip_index(&mut self) -> ClipId {
         self.next_clip_index += 1;
         ClipId::Clip(self.next_clip_index - 1, self.pipeline_id)
@@ -1492,11 +1625,68 @@ impl DisplayListBuilder {
     where
         I: IntoIterator<Item = ComplexClipRegion>,
         I::IntoIter: ExactSizeIterator + Clone,
+    {
+        self.define_clip_impl(
+            parent,
+            clip_rect,
+            complex_clips,
+            image_mask,
+            iter::empty::<PathOp>(),
+            FillRule::NonZero,
+        )
+    }
+
+    /// Defines a clip whose inside is the area of `path` filled with
+    /// `fill_rule`, intersected with `clip_rect`. A polygon can be
+    /// built with `PathOp::polygon`. A path with too many lines to draw
+    /// is ignored, leaving only `clip_rect`.
+    pub fn define_path_clip(
+        &mut self,
+        clip_rect: LayoutRect,
+        path: &[PathOp],
+        fill_rule: FillRule,
+    ) -> ClipId {
+        let parent = self.clip_stack.last().unwrap().scroll_node_id;
+        self.define_path_clip_with_parent(parent, clip_rect, path, fill_rule)
+    }
+
+    pub fn define_path_clip_with_parent(
+        &mut self,
+        parent: ClipId,
+        clip_rect: LayoutRect,
+        path: &[PathOp],
+        fill_rule: FillRule,
+    ) -> ClipId {
+        self.define_clip_impl(
+            parent,
+            clip_rect,
+            iter::empty::<ComplexClipRegion>(),
+            None,
+            path.iter().cloned(),
+            fill_rule,
+        )
+    }
+
+    fn define_clip_impl<I, P>(
+        &mut self,
+        parent: ClipId,
+        clip_rect: LayoutRect,
+        complex_clips: I,
+        image_mask: Option<ImageMask>,
+        path: P,
+        fill_rule: FillRule,
+    ) -> ClipId
+    where
+        I: IntoIterator<Item = ComplexClipRegion>,
+        I::IntoIter: ExactSizeIterator + Clone,
+        P: IntoIterator<Item = PathOp>,
+        P::IntoIter: ExactSizeIterator + Clone,
     {
         let id = self.generate_clip_index();
         let item = SpecificDisplayItem::Clip(ClipDisplayItem {
             id,
             image_mask,
+            fill_rule,
         });
 
         let info = LayoutPrimitiveInfo::new(clip_rect);
@@ -1504,6 +1694,7 @@ impl DisplayListBuilder {
         let scrollinfo = ClipAndScrollInfo::simple(parent);
         self.push_item_with_clip_scroll_info(item, &info, scrollinfo);
         self.push_iter(complex_clips);
+        self.push_iter(path);
         id
     }
 
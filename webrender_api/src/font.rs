@@ -15,7 +15,7 @@ use serde::ser::{Serialize, Serializer};
 use std::cmp::Ordering;
 use std::hash::{Hash, Hasher};
 use std::sync::Arc;
-use {ColorU, IdNamespace, LayoutPoint};
+use {ColorU, IdNamespace, LayoutPoint, PathOp};
 
 
 #[cfg(not(any(target_os = "macos", target_os = "windows")))]
@@ -69,6 +69,17 @@ pub struct GlyphDimensions {
     pub advance: f32,
 }
 
+/// The outline of a glyph, in pixels relative to the glyph origin with y
+/// pointing down. Each contour starts with a `MoveTo` and ends with a `Close`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GlyphOutline {
+    pub commands: Vec<PathOp>,
+    pub advance: f32,
+    /// The kerning adjustment of the advance before the next glyph of the
+    /// same request, or zero for the last glyph.
+    pub kerning: f32,
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize, Ord, PartialOrd)]
 pub struct FontKey(pub IdNamespace, pub u32);
@@ -134,6 +145,46 @@ impl Hash for FontVariation {
     }
 }
 
+/// A variation axis of a font that is expected to be animated.
+///
+/// The glyphs of each value of an axis are cached separately, so a font
+/// instance snaps the value of an animated axis to one of
+/// `steps` evenly spaced values between `min` and `max`, which bounds
+/// the number of times the glyphs are rasterized during an animation.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct FontVariationAnimation {
+    pub tag: u32,
+    pub min: f32,
+    pub max: f32,
+    pub steps: u32,
+}
+
+impl FontVariationAnimation {
+    /// Returns the step nearest to the value.
+    pub fn snap(&self, value: f32) -> f32 {
+        if self.steps < 2 || self.max <= self.min {
+            return self.min;
+        }
+        let last = (self.steps - 1) as f32;
+        let step = (self.max - self.min) / last;
+        let index = ((value - self.min) / step).round().max(0.0).min(last);
+        // Return the ends exactly, so that they hash the same however
+        // the animation reaches them.
+        if index == last {
+            self.max
+        } else {
+            self.min + index * step
+        }
+    }
+
+    /// Whether the value is a step between the ends of the axis, which are
+    /// not expected to be used again once the animation moves on.
+    pub fn is_intermediate(&self, value: f32) -> bool {
+        value > self.min && value < self.max
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Deserialize, Hash, Eq, PartialEq, PartialOrd, Ord, Serialize)]
 pub struct GlyphOptions {
@@ -251,6 +302,9 @@ pub struct FontInstanceOptions {
     /// background color.
     pub bg_color: ColorU,
     pub synthetic_italics: SyntheticItalics,
+    /// The index of the palette that color glyphs are drawn with, for
+    /// fonts that have several. Out of range indices select the first.
+    pub color_palette: u16,
 }
 
 impl Default for FontInstanceOptions {
@@ -260,6 +314,7 @@ impl Default for FontInstanceOptions {
             flags: Default::default(),
             bg_color: ColorU::new(0, 0, 0, 0),
             synthetic_italics: SyntheticItalics::disabled(),
+            color_palette: 0,
         }
     }
 }
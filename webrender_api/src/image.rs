@@ -60,16 +60,81 @@ pub enum ImageFormat {
     RGBAF32 = 4,
     RG8 = 5,
     RGBAI32 = 6,
+    /// S3TC / DXT1 compressed RGB with 1-bit alpha.
+    BC1 = 7,
+    /// S3TC / DXT5 compressed RGBA.
+    BC3 = 8,
+    /// ETC2 compressed RGB.
+    ETC2 = 9,
+    /// ETC2 compressed RGB with EAC compressed alpha.
+    ETC2A8 = 10,
+    /// ASTC compressed RGBA with 4x4 blocks, decoded as LDR.
+    ASTC4x4 = 11,
 }
 
+/// The width and height, in pixels, of the blocks that the
+/// compressed image formats are made of.
+pub const COMPRESSED_BLOCK_DIMENSION: u32 = 4;
+
 impl ImageFormat {
+    /// Returns the size of a pixel. Compressed formats are addressed
+    /// by blocks instead (see `compressed_block_size`), and panic.
     pub fn bytes_per_pixel(self) -> u32 {
+        self.bytes_per_pixel_checked()
+            .unwrap_or_else(|| panic!("{:?} is a block-compressed format", self))
+    }
+
+    /// Returns the size of a pixel, or `None` for compressed formats.
+    pub fn bytes_per_pixel_checked(self) -> Option<u32> {
+        match self {
+            ImageFormat::R8 => Some(1),
+            ImageFormat::BGRA8 => Some(4),
+            ImageFormat::RGBAF32 => Some(16),
+            ImageFormat::RG8 => Some(2),
+            ImageFormat::RGBAI32 => Some(16),
+            ImageFormat::BC1 |
+            ImageFormat::BC3 |
+            ImageFormat::ETC2 |
+            ImageFormat::ETC2A8 |
+            ImageFormat::ASTC4x4 => None,
+        }
+    }
+
+    /// Returns the number of bytes of a single block for compressed formats.
+    pub fn compressed_block_size(self) -> Option<u32> {
         match self {
-            ImageFormat::R8 => 1,
-            ImageFormat::BGRA8 => 4,
-            ImageFormat::RGBAF32 => 16,
-            ImageFormat::RG8 => 2,
-            ImageFormat::RGBAI32 => 16,
+            ImageFormat::BC1 | ImageFormat::ETC2 => Some(8),
+            ImageFormat::BC3 | ImageFormat::ETC2A8 | ImageFormat::ASTC4x4 => Some(16),
+            ImageFormat::R8 |
+            ImageFormat::BGRA8 |
+            ImageFormat::RGBAF32 |
+            ImageFormat::RG8 |
+            ImageFormat::RGBAI32 => None,
+        }
+    }
+
+    pub fn is_compressed(self) -> bool {
+        self.compressed_block_size().is_some()
+    }
+
+    /// Returns the number of bytes needed to store `width` pixels of a row,
+    /// which is a row of blocks for compressed formats.
+    pub fn row_size(self, width: u32) -> u32 {
+        match (self.bytes_per_pixel_checked(), self.compressed_block_size()) {
+            (Some(bpp), _) => width * bpp,
+            (None, Some(block_size)) => {
+                (width + COMPRESSED_BLOCK_DIMENSION - 1) / COMPRESSED_BLOCK_DIMENSION * block_size
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+
+    /// Returns the number of rows needed to store `height` lines of pixels.
+    pub fn row_count(self, height: u32) -> u32 {
+        if self.is_compressed() {
+            (height + COMPRESSED_BLOCK_DIMENSION - 1) / COMPRESSED_BLOCK_DIMENSION
+        } else {
+            height
         }
     }
 }
@@ -103,11 +168,11 @@ impl ImageDescriptor {
     }
 
     pub fn compute_stride(&self) -> u32 {
-        self.stride.unwrap_or(self.size.width * self.format.bytes_per_pixel())
+        self.stride.unwrap_or(self.format.row_size(self.size.width))
     }
 
     pub fn compute_total_size(&self) -> u32 {
-        self.compute_stride() * self.size.height
+        self.compute_stride() * self.format.row_count(self.size.height)
     }
 
     pub fn full_rect(&self) -> DeviceUintRect {
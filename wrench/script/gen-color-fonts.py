@@ -0,0 +1,199 @@
+#!/usr/bin/env python
+# This Source Code Form is subject to the terms of the Mozilla Public
+# License, v. 2.0. If a copy of the MPL was not distributed with this
+# file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+# Generates the minimal color fonts of the text reftests, without any
+# dependency other than Python 3:
+#
+# - ColorLayers.ttf has COLR/CPAL color glyphs. 'A' is a square of palette
+#   entry 0 under an inner square of entry 1, 'B' is a square of the text
+#   color under the same inner square, and 'C' has no layers. The two
+#   palettes are red/blue and green/yellow.
+# - ColorBitmaps.ttf only has a 32 ppem CBDT/CBLC strike, where 'A' is
+#   green and 'B' is red over blue.
+#
+# Usage: python3 gen-color-fonts.py ../reftests/text
+
+import struct, sys
+
+def checksum(data):
+    data += b'\0' * ((4 - len(data) % 4) % 4)
+    return sum(struct.unpack('>%dI' % (len(data) // 4), data)) & 0xFFFFFFFF
+
+def sfnt(tables):
+    tags = sorted(tables)
+    n = len(tags)
+    es = 1
+    while es * 2 <= n: es *= 2
+    sr = es * 16
+    import math
+    header = struct.pack('>IHHHH', 0x00010000, n, sr, int(math.log2(es)), n * 16 - sr)
+    offset = 12 + 16 * n
+    dirs = b''; body = b''
+    for t in tags:
+        d = tables[t]
+        dirs += struct.pack('>4sIII', t.encode(), checksum(d), offset + len(body), len(d))
+        body += d + b'\0' * ((4 - len(d) % 4) % 4)
+    font = bytearray(header + dirs + body)
+    # fix head.checkSumAdjustment
+    i = tags.index('head')
+    head_off = struct.unpack('>I', bytes(font[12 + 16 * i + 8: 12 + 16 * i + 12]))[0]
+    adj = (0xB1B0AFBA - checksum(bytes(font))) & 0xFFFFFFFF
+    font[head_off + 8: head_off + 12] = struct.pack('>I', adj)
+    return bytes(font)
+
+def head(upm, bbox, loca_format=0):
+    return struct.pack('>IIIIHHqqhhhhHHhhh', 0x00010000, 0x00010000, 0, 0x5F0F3CF5, 0x000B,
+                       upm, 0, 0, bbox[0], bbox[1], bbox[2], bbox[3], 0, 8, 2, loca_format, 0)
+
+def hhea(asc, desc, adv_max, num_metrics):
+    return struct.pack('>IhhhHhhhhhhhhhhhH', 0x00010000, asc, desc, 0, adv_max, 0, 0, adv_max,
+                       1, 0, 0, 0, 0, 0, 0, 0, num_metrics)
+
+def maxp(num_glyphs, truetype=True):
+    if truetype:
+        return struct.pack('>IHHHHHHHHHHHHHH', 0x00010000, num_glyphs, 8, 2, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0)
+    return struct.pack('>IH', 0x00005000, num_glyphs)
+
+def hmtx(metrics):
+    return b''.join(struct.pack('>Hh', a, l) for a, l in metrics)
+
+def cmap(mapping):
+    # format 4 with one segment per character, plus the final 0xFFFF segment
+    chars = sorted(mapping)
+    segs = [(c, c, mapping[c]) for c in chars] + [(0xFFFF, 0xFFFF, 0)]
+    n = len(segs)
+    es = 1
+    while es * 2 <= n: es *= 2
+    ends = b''.join(struct.pack('>H', e) for s, e, g in segs)
+    starts = b''.join(struct.pack('>H', s) for s, e, g in segs)
+    deltas = b''.join(struct.pack('>h', ((g - s) + 0x8000) % 0x10000 - 0x8000 if g else 1) for s, e, g in segs)
+    ranges = b''.join(struct.pack('>H', 0) for _ in segs)
+    body = struct.pack('>HHHH', n * 2, es * 2, len(bin(es)) - 3, n * 2 - es * 2) + ends + b'\0\0' + starts + deltas + ranges
+    sub = struct.pack('>HHH', 4, 6 + len(body), 0) + body
+    return struct.pack('>HHHHI', 0, 1, 3, 1, 12) + sub
+
+def name(family):
+    recs = []
+    strings = b''
+    for nid, val in [(1, family), (2, 'Regular'), (4, family), (6, family.replace(' ', ''))]:
+        enc = val.encode('utf-16-be')
+        recs.append(struct.pack('>HHHHHH', 3, 1, 0x409, nid, len(enc), len(strings)))
+        strings += enc
+    return struct.pack('>HHH', 0, len(recs), 6 + 12 * len(recs)) + b''.join(recs) + strings
+
+def os2(asc, desc, first, last):
+    return struct.pack('>HhHHHhhhhhhhhhhh10sIIII4sHHHhhhHHIIhhHHH',
+        4, 500, 400, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, b'\0' * 10, 0, 0, 0, 0, b'NONE',
+        0x40, first, last, asc, desc, 0, asc, -desc, 1, 0, 0, 0, 0, 0, 0)
+
+def post():
+    return struct.pack('>IIhhIIIII', 0x00030000, 0, 0, 0, 0, 0, 0, 0, 0)
+
+def square_glyph(x0, y0, x1, y1):
+    # one clockwise contour
+    pts = [(x0, y0), (x0, y1), (x1, y1), (x1, y0)]
+    data = struct.pack('>hhhhh', 1, x0, y0, x1, y1)
+    data += struct.pack('>HH', 3, 0)
+    data += bytes([1] * 4)
+    px = py = 0
+    xs = ys = b''
+    for x, y in pts:
+        xs += struct.pack('>h', x - px); ys += struct.pack('>h', y - py)
+        px, py = x, y
+    return data + xs + ys
+
+def color_layers_font():
+    glyphs = [
+        b'',                                   # .notdef
+        square_glyph(0, 0, 1000, 1000),        # A, drawn in color
+        square_glyph(0, 0, 1000, 1000),        # B, drawn in color
+        square_glyph(0, 0, 1000, 1000),        # C, no layers
+        square_glyph(0, 0, 1000, 1000),        # layer: square
+        square_glyph(250, 250, 750, 750),      # layer: inner square
+    ]
+    glyf = b''; loca = b''
+    for g in glyphs:
+        g += b'\0' * (len(g) % 2)
+        loca += struct.pack('>H', len(glyf) // 2)
+        glyf += g
+    loca += struct.pack('>H', len(glyf) // 2)
+    n = len(glyphs)
+    # COLR: A = red/green-palette square under a blue/yellow inner square,
+    # B = text colored square under the same inner square.
+    bases = [(1, 0, 2), (2, 2, 2)]
+    layers = [(4, 0), (5, 1), (4, 0xFFFF), (5, 1)]
+    colr = struct.pack('>HHIIH', 0, len(bases), 14, 14 + 6 * len(bases), len(layers))
+    colr += b''.join(struct.pack('>HHH', *b) for b in bases)
+    colr += b''.join(struct.pack('>HH', *l) for l in layers)
+    # CPAL: two palettes of two BGRA entries.
+    palettes = [[(255, 0, 0), (0, 0, 255)], [(0, 128, 0), (255, 255, 0)]]
+    records = [c for p in palettes for c in p]
+    cpal = struct.pack('>HHHHI', 0, 2, len(palettes), len(records), 12 + 2 * len(palettes))
+    cpal += b''.join(struct.pack('>H', 2 * i) for i in range(len(palettes)))
+    cpal += b''.join(bytes([b, g, r, 255]) for r, g, b in records)
+    tables = {
+        'head': head(1000, (0, 0, 1000, 1000)),
+        'hhea': hhea(1000, 0, 1000, n),
+        'maxp': maxp(n),
+        'hmtx': hmtx([(1000, 0), (1000, 0), (1000, 0), (1000, 0), (1000, 0), (1000, 250)]),
+        'cmap': cmap({ord('A'): 1, ord('B'): 2, ord('C'): 3}),
+        'loca': loca,
+        'glyf': glyf,
+        'name': name('Color Layers'),
+        'OS/2': os2(1000, 0, ord('A'), ord('C')),
+        'post': post(),
+        'COLR': colr,
+        'CPAL': cpal,
+    }
+    return sfnt(tables)
+
+def color_bitmaps_font():
+    ppem = 32
+    def image(top, bottom):
+        # premultiplied BGRA rows, top half and bottom half colors
+        rows = b''
+        for y in range(ppem):
+            r, g, b = top if y < ppem // 2 else bottom
+            rows += bytes([b, g, r, 255]) * ppem
+        return rows
+    images = [
+        image((0, 128, 0), (0, 128, 0)),     # A, green
+        image((255, 0, 0), (0, 0, 255)),     # B, red over blue
+    ]
+    # CBDT with small metrics, byte aligned BGRA data (image format 1)
+    cbdt = struct.pack('>HH', 3, 0)
+    offsets = []
+    for img in images:
+        offsets.append(len(cbdt))
+        cbdt += struct.pack('>BBbbB', ppem, ppem, 0, ppem, ppem) + img
+    end = len(cbdt)
+    # CBLC with one strike and one index subtable of format 1
+    first, last = 1, len(images)
+    subtable = struct.pack('>HHI', 1, 1, 0)
+    subtable += b''.join(struct.pack('>I', o) for o in offsets + [end])
+    array = struct.pack('>HHI', first, last, 8)
+    index = array + subtable
+    line = struct.pack('>bbBbbbbbbbbb', ppem, 0, ppem, 1, 0, 0, 0, 0, ppem, 0, 0, 0)
+    size = struct.pack('>IIII', 8 + 48, len(index), 1, 0) + line + line
+    size += struct.pack('>HHBBBb', first, last, ppem, ppem, 32, 1)
+    cblc = struct.pack('>HHI', 3, 0, 1) + size + index
+    n = len(images) + 1
+    tables = {
+        'head': head(ppem, (0, 0, ppem, ppem)),
+        'hhea': hhea(ppem, 0, ppem, n),
+        'maxp': maxp(n, truetype=False),
+        'hmtx': hmtx([(ppem, 0)] * n),
+        'cmap': cmap({ord('A'): 1, ord('B'): 2}),
+        'name': name('Color Bitmaps'),
+        'OS/2': os2(ppem, 0, ord('A'), ord('B')),
+        'post': post(),
+        'CBLC': cblc,
+        'CBDT': cbdt,
+    }
+    return sfnt(tables)
+
+out = sys.argv[1]
+open(out + '/ColorLayers.ttf', 'wb').write(color_layers_font())
+open(out + '/ColorBitmaps.ttf', 'wb').write(color_bitmaps_font())
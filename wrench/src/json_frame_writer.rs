@@ -115,9 +115,7 @@ impl JsonFrameWriter {
         for update in updates {
             match *update {
                 ResourceUpdate::AddImage(ref img) => {
-                    let stride = img.descriptor.stride.unwrap_or(
-                        img.descriptor.size.width * img.descriptor.format.bytes_per_pixel(),
-                    );
+                    let stride = img.descriptor.compute_stride();
                     let bytes = match img.data {
                         ImageData::Raw(ref v) => (**v).clone(),
                         ImageData::External(_) | ImageData::Blob(_) => {
@@ -167,6 +165,7 @@ impl JsonFrameWriter {
                     }
                 },
                 ResourceUpdate::DeleteFont(_) => {}
+                ResourceUpdate::SetFontVariationAnimations(..) => {}
                 ResourceUpdate::AddFontInstance(ref instance) => {
                     self.font_instances.insert(
                         instance.key,
@@ -66,7 +66,7 @@ use glutin::GlContext;
 use perf::PerfHarness;
 use png::save_flipped;
 use rawtest::RawtestHarness;
-use reftest::{ReftestHarness, ReftestOptions};
+use reftest::{ReftestHarness, ReftestOptions, ReftestReportFormat};
 #[cfg(feature = "headless")]
 use std::ffi::CString;
 #[cfg(feature = "headless")]
@@ -377,12 +377,17 @@ fn rawtest(mut wrench: Wrench, window: &mut WindowWrapper, rx: Receiver<Notifier
     wrench.shut_down(rx);
 }
 
-fn reftest<'a>(
+fn reftest<'a, F>(
     mut wrench: Wrench,
     window: &mut WindowWrapper,
+    args: &clap::ArgMatches<'a>,
     subargs: &clap::ArgMatches<'a>,
-    rx: Receiver<NotifierEvent>
-) -> usize {
+    rx: Receiver<NotifierEvent>,
+    make_headless_instance: F,
+) -> usize
+where
+    F: Fn() -> (Wrench, WindowWrapper, Receiver<NotifierEvent>) + Sync,
+{
     let dim = window.get_inner_size();
     let base_manifest = Path::new("reftests/reftest.list");
     let specific_reftest = subargs.value_of("REFTEST").map(|x| Path::new(x));
@@ -391,8 +396,31 @@ fn reftest<'a>(
         reftest_options.allow_max_difference = allow_max_diff.parse().unwrap_or(1);
         reftest_options.allow_num_differences = dim.width as usize * dim.height as usize;
     }
-    let num_failures = ReftestHarness::new(&mut wrench, window, &rx)
-        .run(base_manifest, specific_reftest, &reftest_options);
+    reftest_options.dual_source_blending = !args.is_present("slow_subpixel") &&
+        wrench.renderer.supports_dual_source_blending();
+    reftest_options.subpixel_aa = !args.is_present("no_subpixel_aa");
+    reftest_options.headless = args.is_present("headless");
+    reftest_options.angle = args.is_present("angle");
+    if let Some(mut report) = subargs.values_of("report") {
+        let format = report.next().unwrap();
+        let format = ReftestReportFormat::from_str(format)
+            .unwrap_or_else(|| panic!("Unknown report format {}, expected json or junit", format));
+        reftest_options.report = Some((format, PathBuf::from(report.next().unwrap())));
+    }
+    let jobs = subargs.value_of("jobs").map_or(1, |j| j.parse::<usize>().unwrap());
+    let num_failures = if jobs > 1 {
+        assert!(reftest_options.headless, "Running reftests in parallel requires --headless");
+        reftest::run_parallel(
+            base_manifest,
+            specific_reftest,
+            &reftest_options,
+            jobs,
+            make_headless_instance,
+        )
+    } else {
+        ReftestHarness::new(&mut wrench, window, &rx)
+            .run(base_manifest, specific_reftest, &reftest_options)
+    };
     wrench.shut_down(rx);
     num_failures
 }
@@ -470,10 +498,36 @@ fn main() {
         (None, None)
     };
 
+    // Creates another instance of wrench, with its own headless context,
+    // for running reftests in parallel.
+    let make_headless_instance = || {
+        let mut window = make_window(size, Some(dp_ratio), false, &None, false);
+        let (notifier, rx) = create_notifier();
+        let wrench = Wrench::new(
+            &mut window,
+            None,
+            res_path.clone(),
+            dp_ratio,
+            None,
+            dim,
+            args.is_present("rebuild"),
+            args.is_present("no_subpixel_aa"),
+            args.is_present("verbose"),
+            args.is_present("no_scissor"),
+            args.is_present("no_batch"),
+            args.is_present("precache"),
+            args.is_present("slow_subpixel"),
+            zoom_factor.unwrap_or(1.0),
+            chase_primitive,
+            Some(notifier),
+        );
+        (wrench, window, rx)
+    };
+
     let mut wrench = Wrench::new(
         &mut window,
         events_loop.as_mut().map(|el| el.create_proxy()),
-        res_path,
+        res_path.clone(),
         dp_ratio,
         save_type,
         dim,
@@ -501,7 +555,14 @@ fn main() {
         png::png(&mut wrench, surface, &mut window, reader, rx.unwrap());
     } else if let Some(subargs) = args.subcommand_matches("reftest") {
         // Exit with an error code in order to ensure the CI job fails.
-        process::exit(reftest(wrench, &mut window, subargs, rx.unwrap()) as _);
+        process::exit(reftest(
+            wrench,
+            &mut window,
+            &args,
+            subargs,
+            rx.unwrap(),
+            make_headless_instance,
+        ) as _);
     } else if let Some(_) = args.subcommand_matches("rawtest") {
         rawtest(wrench, &mut window, rx.unwrap());
         return;
@@ -512,13 +573,23 @@ fn main() {
         let harness = PerfHarness::new(&mut wrench, &mut window, rx.unwrap());
         let base_manifest = Path::new("benchmarks/benchmarks.list");
         let filename = subargs.value_of("filename").unwrap();
-        harness.run(base_manifest, filename);
+        let runs = subargs.value_of("runs").map_or(3, |r| r.parse::<usize>().unwrap());
+        harness.run(base_manifest, filename, runs.max(1));
         return;
     } else if let Some(subargs) = args.subcommand_matches("compare_perf") {
         let first_filename = subargs.value_of("first_filename").unwrap();
         let second_filename = subargs.value_of("second_filename").unwrap();
-        perf::compare(first_filename, second_filename);
-        return;
+        let threshold = subargs.value_of("threshold").map_or(2.0, |t| t.parse::<f64>().unwrap());
+        let budget = subargs.value_of("budget").map_or(5.0, |b| b.parse::<f64>().unwrap());
+        let failures = perf::compare(
+            first_filename,
+            second_filename,
+            threshold / 100.0,
+            budget / 100.0,
+        );
+        // Exit with an error code so that regressions, and tests with too
+        // few runs to check, can fail a CI job.
+        process::exit(if failures > 0 { 1 } else { 0 });
     } else {
         panic!("Should never have gotten here! {:?}", args);
     };
@@ -22,6 +22,15 @@ const COLOR_MAGENTA: &str = "\x1b[95m";
 const MIN_SAMPLE_COUNT: usize = 50;
 const SAMPLE_EXCLUDE_COUNT: usize = 10;
 
+// Frames of the same run are not independent samples, so the spread
+// of the times is estimated from the medians of separate runs, of which
+// there must be a few for the estimate to mean anything.
+const MIN_RUN_COUNT: usize = 3;
+const BOOTSTRAP_RESAMPLE_COUNT: usize = 1000;
+// The bounds of a two-sided 95% confidence interval.
+const CONFIDENCE_LOW: f64 = 0.025;
+const CONFIDENCE_HIGH: f64 = 0.975;
+
 pub struct Benchmark {
     pub test: PathBuf,
 }
@@ -78,6 +87,29 @@ struct TestProfile {
     composite_time_ns: u64,
     paint_time_ns: u64,
     draw_calls: usize,
+    // The median time of every run, which the times above are the
+    // medians of. Profiles saved before these existed only have the
+    // single times.
+    #[serde(default)]
+    backend_run_times_ns: Vec<u64>,
+    #[serde(default)]
+    composite_run_times_ns: Vec<u64>,
+    #[serde(default)]
+    paint_run_times_ns: Vec<u64>,
+}
+
+impl TestProfile {
+    fn merge(&mut self, other: TestProfile) {
+        assert_eq!(self.draw_calls, other.draw_calls, "draw calls differ between runs of {}", self.name);
+
+        self.backend_run_times_ns.extend(other.backend_run_times_ns);
+        self.composite_run_times_ns.extend(other.composite_run_times_ns);
+        self.paint_run_times_ns.extend(other.paint_run_times_ns);
+
+        self.backend_time_ns = median(&mut self.backend_run_times_ns);
+        self.composite_time_ns = median(&mut self.composite_run_times_ns);
+        self.paint_time_ns = median(&mut self.paint_run_times_ns);
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -132,14 +164,27 @@ impl<'a> PerfHarness<'a> {
         PerfHarness { wrench, window, rx }
     }
 
-    pub fn run(mut self, base_manifest: &Path, filename: &str) {
+    pub fn run(mut self, base_manifest: &Path, filename: &str, runs: usize) {
         let manifest = BenchmarkManifest::new(base_manifest);
 
-        let mut profile = Profile::new();
+        // Run the whole manifest once per run, rather than each test
+        // several times in a row, so that drift in the machine's state
+        // spreads over all of the tests.
+        let mut tests: Vec<TestProfile> = Vec::new();
+        for run in 0 .. runs {
+            for (i, t) in manifest.benchmarks.iter().enumerate() {
+                let stats = self.render_yaml(t.test.as_path());
+                if run == 0 {
+                    tests.push(stats);
+                } else {
+                    tests[i].merge(stats);
+                }
+            }
+        }
 
-        for t in manifest.benchmarks {
-            let stats = self.render_yaml(t.test.as_path());
-            profile.add(stats);
+        let mut profile = Profile::new();
+        for test in tests {
+            profile.add(test);
         }
 
         profile.save(filename);
@@ -173,9 +218,11 @@ impl<'a> PerfHarness<'a> {
                 .all(|s| s.draw_calls == draw_calls)
         );
 
-        let composite_time_ns = extract_sample(&mut cpu_frame_profiles, |a| a.composite_time_ns);
-        let paint_time_ns = extract_sample(&mut gpu_frame_profiles, |a| a.paint_time_ns);
-        let backend_time_ns = extract_sample(&mut cpu_frame_profiles, |a| a.backend_time_ns);
+        let composite_time_ns =
+            median(&mut extract_samples(&cpu_frame_profiles, |a| a.composite_time_ns));
+        let paint_time_ns = median(&mut extract_samples(&gpu_frame_profiles, |a| a.paint_time_ns));
+        let backend_time_ns =
+            median(&mut extract_samples(&cpu_frame_profiles, |a| a.backend_time_ns));
 
         TestProfile {
             name: filename.to_str().unwrap().to_string(),
@@ -183,49 +230,145 @@ impl<'a> PerfHarness<'a> {
             paint_time_ns,
             backend_time_ns,
             draw_calls,
+            composite_run_times_ns: vec![composite_time_ns],
+            paint_run_times_ns: vec![paint_time_ns],
+            backend_run_times_ns: vec![backend_time_ns],
         }
     }
 }
 
-fn extract_sample<F, T>(profiles: &mut [T], f: F) -> u64
+// Returns the samples without the fastest and slowest few, which
+// are usually outliers from warming up or from other processes.
+fn extract_samples<F, T>(profiles: &[T], f: F) -> Vec<u64>
 where
     F: Fn(&T) -> u64,
 {
     let mut samples: Vec<u64> = profiles.iter().map(f).collect();
     samples.sort();
-    let useful_samples = &samples[SAMPLE_EXCLUDE_COUNT .. samples.len() - SAMPLE_EXCLUDE_COUNT];
-    let total_time: u64 = useful_samples.iter().sum();
-    total_time / useful_samples.len() as u64
+    samples[SAMPLE_EXCLUDE_COUNT .. samples.len() - SAMPLE_EXCLUDE_COUNT].to_vec()
+}
+
+fn median(samples: &mut [u64]) -> u64 {
+    samples.sort();
+    samples[samples.len() / 2]
+}
+
+/// A small xorshift generator, so that resampling gives the same
+/// intervals every time two profiles are compared.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next_index(&mut self, len: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % len as u64) as usize
+    }
+}
+
+/// The median of a time in milliseconds, with its 95% confidence interval
+/// if there are enough runs to estimate it.
+#[derive(Clone, Copy)]
+struct Estimate {
+    median: f64,
+    interval: Option<(f64, f64)>,
+}
+
+impl Estimate {
+    fn new(run_times: &[u64], time_ns: u64) -> Estimate {
+        let to_ms = |ns: u64| ns as f64 / 1000000.0;
+
+        // Profiles with too few runs give no idea of the spread.
+        if run_times.len() < MIN_RUN_COUNT {
+            return Estimate { median: to_ms(time_ns), interval: None };
+        }
+
+        // Bootstrap the interval of the median by resampling the runs.
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        let mut resample = vec![0; run_times.len()];
+        let mut medians: Vec<u64> = (0 .. BOOTSTRAP_RESAMPLE_COUNT)
+            .map(|_| {
+                for time in resample.iter_mut() {
+                    *time = run_times[rng.next_index(run_times.len())];
+                }
+                median(&mut resample)
+            })
+            .collect();
+        medians.sort();
+
+        let rank = |fraction: f64| {
+            let index = (fraction * (BOOTSTRAP_RESAMPLE_COUNT - 1) as f64).round() as usize;
+            to_ms(medians[index])
+        };
+
+        Estimate {
+            median: to_ms(median(&mut run_times.to_vec())),
+            interval: Some((rank(CONFIDENCE_LOW), rank(CONFIDENCE_HIGH))),
+        }
+    }
+
+    fn relative_change(&self, base: &Estimate) -> f64 {
+        if base.median > 0.0 {
+            (self.median - base.median) / base.median
+        } else {
+            0.0
+        }
+    }
+
+    // A change is significant when the confidence intervals are known,
+    // don't overlap, and it is larger than the threshold.
+    fn is_significant_change(&self, base: &Estimate, threshold: f64) -> bool {
+        match (self.interval, base.interval) {
+            (Some((low, high)), Some((base_low, base_high))) => {
+                (low > base_high || high < base_low) &&
+                    self.relative_change(base).abs() > threshold
+            }
+            _ => false,
+        }
+    }
+
+    fn interval_string(&self) -> String {
+        match self.interval {
+            Some((low, high)) => format!("[{:.2}, {:.2}]", low, high),
+            None => "[unknown]".to_string(),
+        }
+    }
 }
 
-fn select_color(base: f32, value: f32) -> &'static str {
-    let tolerance = base * 0.1;
-    if (value - base).abs() < tolerance {
+fn select_color(base: &Estimate, value: &Estimate, threshold: f64) -> &'static str {
+    if !value.is_significant_change(base, threshold) {
         COLOR_DEFAULT
-    } else if value > base {
+    } else if value.median > base.median {
         COLOR_RED
     } else {
         COLOR_GREEN
     }
 }
 
-pub fn compare(first_filename: &str, second_filename: &str) {
+/// Compares two profiles, and returns the number of significant
+/// regressions that are larger than the budget, plus the number of
+/// tests that have too few runs to be checked at all. The threshold
+/// and the budget are fractions of the first profile's times.
+pub fn compare(first_filename: &str, second_filename: &str, threshold: f64, budget: f64) -> usize {
     let profile0 = Profile::load(first_filename);
     let profile1 = Profile::load(second_filename);
 
     let (set0, map0) = profile0.build_set_and_map_of_tests();
     let (set1, map1) = profile1.build_set_and_map_of_tests();
 
+    let mut regressions = Vec::new();
+    let mut unknown = Vec::new();
+
     print!("+------------------------------------------------");
-    println!("+--------------+------------------+------------------+");
+    println!("+--------------+-------------------------+-------------------------+");
     print!("|  Test name                                     ");
-    println!("| Draw Calls   | Composite (ms)   | Paint (ms)       |");
+    println!("| Draw Calls   | Composite (ms)          | Paint (ms)              |");
     print!("+------------------------------------------------");
-    println!("+--------------+------------------+------------------+");
+    println!("+--------------+-------------------------+-------------------------+");
 
     for test_name in set0.symmetric_difference(&set1) {
         println!(
-            "| {}{:47}{}|{:14}|{:18}|{:18}|",
+            "| {}{:47}{}|{:14}|{:25}|{:25}|",
             COLOR_MAGENTA,
             test_name,
             COLOR_DEFAULT,
@@ -239,11 +382,19 @@ pub fn compare(first_filename: &str, second_filename: &str) {
         let test0 = &map0[test_name];
         let test1 = &map1[test_name];
 
-        let composite_time0 = test0.composite_time_ns as f32 / 1000000.0;
-        let composite_time1 = test1.composite_time_ns as f32 / 1000000.0;
+        let composite_time0 =
+            Estimate::new(&test0.composite_run_times_ns, test0.composite_time_ns);
+        let composite_time1 =
+            Estimate::new(&test1.composite_run_times_ns, test1.composite_time_ns);
+
+        let paint_time0 = Estimate::new(&test0.paint_run_times_ns, test0.paint_time_ns);
+        let paint_time1 = Estimate::new(&test1.paint_run_times_ns, test1.paint_time_ns);
 
-        let paint_time0 = test0.paint_time_ns as f32 / 1000000.0;
-        let paint_time1 = test1.paint_time_ns as f32 / 1000000.0;
+        if composite_time0.interval.is_none() || composite_time1.interval.is_none() ||
+            paint_time0.interval.is_none() || paint_time1.interval.is_none()
+        {
+            unknown.push(test_name.clone());
+        }
 
         let draw_calls_color = if test0.draw_calls == test1.draw_calls {
             COLOR_DEFAULT
@@ -253,15 +404,36 @@ pub fn compare(first_filename: &str, second_filename: &str) {
             COLOR_RED
         };
 
-        let composite_time_color = select_color(composite_time0, composite_time1);
-        let paint_time_color = select_color(paint_time0, paint_time1);
+        let composite_time_color = select_color(&composite_time0, &composite_time1, threshold);
+        let paint_time_color = select_color(&paint_time0, &paint_time1, threshold);
+
+        for &(kind, ref time0, ref time1) in &[
+            ("composite", composite_time0, composite_time1),
+            ("paint", paint_time0, paint_time1),
+        ] {
+            if time1.is_significant_change(time0, threshold) &&
+                time1.relative_change(time0) > budget
+            {
+                regressions.push((test_name.clone(), kind, *time0, *time1));
+            }
+        }
 
         let draw_call_string = format!(" {} -> {}", test0.draw_calls, test1.draw_calls);
-        let composite_time_string = format!(" {:.2} -> {:.2}", composite_time0, composite_time1);
-        let paint_time_string = format!(" {:.2} -> {:.2}", paint_time0, paint_time1);
+        let composite_time_string = format!(
+            " {:.2} -> {:.2} ({:+.1}%)",
+            composite_time0.median,
+            composite_time1.median,
+            composite_time1.relative_change(&composite_time0) * 100.0
+        );
+        let paint_time_string = format!(
+            " {:.2} -> {:.2} ({:+.1}%)",
+            paint_time0.median,
+            paint_time1.median,
+            paint_time1.relative_change(&paint_time0) * 100.0
+        );
 
         println!(
-            "| {:47}|{}{:14}{}|{}{:18}{}|{}{:18}{}|",
+            "| {:47}|{}{:14}{}|{}{:25}{}|{}{:25}{}|",
             test_name,
             draw_calls_color,
             draw_call_string,
@@ -276,5 +448,86 @@ pub fn compare(first_filename: &str, second_filename: &str) {
     }
 
     print!("+------------------------------------------------");
-    println!("+--------------+------------------+------------------+");
+    println!("+--------------+-------------------------+-------------------------+");
+
+    for &(ref test_name, kind, ref time0, ref time1) in &regressions {
+        println!(
+            "{}REGRESSION{} {} {}: {:.2} {} -> {:.2} {} ms ({:+.1}%)",
+            COLOR_RED,
+            COLOR_DEFAULT,
+            test_name,
+            kind,
+            time0.median,
+            time0.interval_string(),
+            time1.median,
+            time1.interval_string(),
+            time1.relative_change(time0) * 100.0
+        );
+    }
+    for test_name in &unknown {
+        println!(
+            "{}UNKNOWN{} {}: fewer than {} runs, can't be checked against the budget",
+            COLOR_MAGENTA,
+            COLOR_DEFAULT,
+            test_name,
+            MIN_RUN_COUNT
+        );
+    }
+    println!(
+        "{} regression(s) larger than the budget of {:.1}%, {} unknown test(s)",
+        regressions.len(),
+        budget * 100.0,
+        unknown.len()
+    );
+
+    // A test that can't be checked might hide a regression, so it fails
+    // the comparison too.
+    regressions.len() + unknown.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Estimate, MIN_RUN_COUNT};
+
+    #[test]
+    fn unknown_interval() {
+        let old = Estimate::new(&[], 1000000);
+        assert_eq!(old.median, 1.0);
+        assert!(old.interval.is_none());
+
+        let short = Estimate::new(&vec![1000000; MIN_RUN_COUNT - 1], 1000000);
+        assert!(short.interval.is_none());
+
+        // Changes of unknown spread are never significant.
+        let slow = Estimate::new(&[2000000, 2000000, 2000000], 2000000);
+        assert!(!slow.is_significant_change(&old, 0.0));
+        assert!(!old.is_significant_change(&slow, 0.0));
+    }
+
+    #[test]
+    fn bootstrap_interval() {
+        let runs = [9000000, 10000000, 10000000, 11000000, 12000000];
+        let estimate = Estimate::new(&runs, 0);
+        assert_eq!(estimate.median, 10.0);
+        let (low, high) = estimate.interval.unwrap();
+        assert!(low >= 9.0 && low <= 10.0);
+        assert!(high >= 10.0 && high <= 12.0);
+
+        // The interval covers the same runs regardless of their order.
+        let mut reversed = runs;
+        reversed.reverse();
+        let (reversed_low, reversed_high) = Estimate::new(&reversed, 0).interval.unwrap();
+        assert!(reversed_low >= 9.0 && reversed_high <= 12.0);
+    }
+
+    #[test]
+    fn significant_change() {
+        let base = Estimate::new(&[10000000, 10100000, 9900000, 10000000], 0);
+        let same = Estimate::new(&[10050000, 9950000, 10000000, 10100000], 0);
+        let slower = Estimate::new(&[12000000, 12100000, 11900000, 12000000], 0);
+
+        assert!(!same.is_significant_change(&base, 0.02));
+        assert!(slower.is_significant_change(&base, 0.02));
+        assert!(!slower.is_significant_change(&base, 0.5));
+    }
 }
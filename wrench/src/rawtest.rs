@@ -42,6 +42,9 @@ impl<'a> RawtestHarness<'a> {
 
     pub fn run(mut self) {
         self.test_hit_testing();
+        self.test_hit_testing_depth();
+        self.test_hit_testing_path();
+        self.test_hit_testing_area_clips();
         self.test_retained_blob_images_test();
         self.test_blob_update_test();
         self.test_blob_update_epoch_test();
@@ -51,8 +54,11 @@ impl<'a> RawtestHarness<'a> {
         self.test_offscreen_blob();
         self.test_save_restore();
         self.test_blur_cache();
+        self.test_picture_cache();
+        self.test_picture_cache_outer_clip();
         self.test_capture();
         self.test_zero_height_window();
+        self.test_document_visibility();
     }
 
     fn render_and_get_pixels(&mut self, window_rect: DeviceUintRect) -> Vec<u8> {
@@ -870,6 +876,138 @@ impl<'a> RawtestHarness<'a> {
         assert_ne!(first, second);
     }
 
+    fn test_picture_cache(&mut self) {
+        println!("\tpicture cache...");
+        let window_size = self.window.get_inner_size();
+
+        let test_size = DeviceUintSize::new(400, 400);
+
+        let window_rect = DeviceUintRect::new(
+            DeviceUintPoint::new(0, window_size.height - test_size.height),
+            test_size,
+        );
+        let layout_size = LayoutSize::new(400., 400.);
+
+        let image = self.wrench.api.generate_image_key();
+        let descriptor = ImageDescriptor::new(2, 2, ImageFormat::BGRA8, true, false);
+        let image_data = |bgra: [u8; 4]| {
+            ImageData::new(bgra.iter().cycle().take(16).cloned().collect())
+        };
+
+        let mut epoch = Epoch(0);
+        let mut do_test = |resources: Vec<ResourceUpdate>| {
+            let mut builder = DisplayListBuilder::new(self.wrench.root_pipeline_id, layout_size);
+
+            // The blurred picture is cached in the texture cache.
+            builder.push_stacking_context(
+                &PrimitiveInfo::new(rect(0., 0., 400., 400.)),
+                None,
+                TransformStyle::Flat,
+                MixBlendMode::Normal,
+                vec![FilterOp::Blur(2.0)],
+                GlyphRasterSpace::Screen,
+                None,
+            );
+            builder.push_image(
+                &PrimitiveInfo::new(rect(100., 100., 100., 100.)),
+                size(100., 100.),
+                size(0., 0.),
+                ImageRendering::Auto,
+                AlphaType::PremultipliedAlpha,
+                image,
+            );
+            builder.pop_stacking_context();
+
+            self.submit_dl(&mut epoch, layout_size, builder, &resources);
+            let pixels = self.render_and_get_pixels(window_rect);
+            let (cpu_profiles, _) = self.wrench.get_frame_profiles();
+            (pixels, cpu_profiles.last().unwrap().draw_calls)
+        };
+
+        let mut txn = Transaction::new();
+        txn.add_image(image, descriptor, image_data([0xFF, 0, 0, 0xFF]), None);
+        let (first, first_draw_calls) = do_test(txn.resource_updates);
+
+        // A new display list with the same content reuses the cached picture.
+        let (second, second_draw_calls) = do_test(Vec::new());
+        assert_eq!(first, second);
+        assert!(second_draw_calls < first_draw_calls);
+
+        // Updating the image invalidates it.
+        let mut txn = Transaction::new();
+        txn.update_image(image, descriptor, image_data([0, 0xFF, 0, 0xFF]), None);
+        let (third, third_draw_calls) = do_test(txn.resource_updates);
+        assert_ne!(first, third);
+        assert!(third_draw_calls > second_draw_calls);
+    }
+
+    fn test_picture_cache_outer_clip(&mut self) {
+        println!("\tpicture cache outer clip...");
+        let window_size = self.window.get_inner_size();
+
+        let test_size = DeviceUintSize::new(400, 400);
+
+        let window_rect = DeviceUintRect::new(
+            DeviceUintPoint::new(0, window_size.height - test_size.height),
+            test_size,
+        );
+        let layout_size = LayoutSize::new(400., 400.);
+
+        let mut epoch = Epoch(0);
+        let mut do_test = |radius: f32| {
+            let mut builder = DisplayListBuilder::new(self.wrench.root_pipeline_id, layout_size);
+
+            // A clip that is defined outside of the stacking context, and only
+            // used by an item inside of it. Its rounded corners don't change
+            // the bounds of what is drawn.
+            let clip_rect = rect(100., 100., 100., 100.);
+            let clip_id = builder.define_clip(
+                clip_rect,
+                vec![ComplexClipRegion::new(
+                    clip_rect,
+                    BorderRadius::uniform(radius),
+                    ClipMode::Clip,
+                )],
+                None,
+            );
+
+            builder.push_stacking_context(
+                &PrimitiveInfo::new(rect(0., 0., 400., 400.)),
+                None,
+                TransformStyle::Flat,
+                MixBlendMode::Normal,
+                vec![FilterOp::Blur(2.0)],
+                GlyphRasterSpace::Screen,
+                None,
+            );
+            builder.push_clip_id(clip_id);
+            builder.push_rect(
+                &PrimitiveInfo::new(rect(100., 100., 100., 100.)),
+                ColorF::new(0.0, 1.0, 0.0, 1.0),
+            );
+            builder.pop_clip_id();
+            builder.pop_stacking_context();
+
+            self.submit_dl(&mut epoch, layout_size, builder, &[]);
+            let pixels = self.render_and_get_pixels(window_rect);
+            let (cpu_profiles, _) = self.wrench.get_frame_profiles();
+            (pixels, cpu_profiles.last().unwrap().draw_calls)
+        };
+
+        let (first, first_draw_calls) = do_test(10.);
+
+        // The same clip reuses the cached picture.
+        let (second, second_draw_calls) = do_test(10.);
+        assert_eq!(first, second);
+        assert!(second_draw_calls < first_draw_calls);
+
+        // Changing only the outer clip invalidates it, even though the
+        // items of the stacking context are the same.
+        let (third, third_draw_calls) = do_test(50.);
+        assert_ne!(first, third);
+        assert!(third_draw_calls > second_draw_calls);
+    }
+
     fn test_capture(&mut self) {
         println!("\tcapture...");
         let path = "../captures/test";
@@ -981,6 +1119,46 @@ impl<'a> RawtestHarness<'a> {
         assert!(self.rx.recv().unwrap() == NotifierEvent::WakeUp);
     }
 
+    fn test_document_visibility(&mut self) {
+        println!("\tdocument visibility test...");
+
+        let window_size = self.window.get_inner_size();
+        let test_size = DeviceUintSize::new(100, 100);
+        let window_rect = DeviceUintRect::new(
+            point(0, window_size.height - test_size.height),
+            test_size,
+        );
+        let layout_size = LayoutSize::new(100.0, 100.0);
+        let mut epoch = Epoch(0);
+
+        let mut txn = Transaction::new();
+        txn.set_document_visibility(false);
+        self.wrench.api.send_transaction(self.wrench.document_id, txn);
+
+        // Forget the epochs of the frames of the previous tests.
+        self.wrench.renderer.update();
+        self.wrench.renderer.flush_pipeline_info();
+
+        // The frame of a hidden document is deferred. The render backend
+        // publishes the epochs of a frame as soon as it's built, before it
+        // completes the flush, so no epoch means no frame was built.
+        let mut builder = DisplayListBuilder::new(self.wrench.root_pipeline_id, layout_size);
+        let info = LayoutPrimitiveInfo::new(rect(0.0, 0.0, 100.0, 100.0));
+        builder.push_rect(&info, ColorF::new(0.0, 1.0, 0.0, 1.0));
+        self.submit_dl(&mut epoch, layout_size, builder, &[]);
+        self.wrench.api.flush_scene_builder();
+        self.wrench.renderer.update();
+        assert_eq!(self.wrench.renderer.current_epoch(self.wrench.root_pipeline_id), None);
+
+        // Showing the document builds the deferred frame.
+        let mut txn = Transaction::new();
+        txn.set_document_visibility(true);
+        txn.generate_frame();
+        self.wrench.api.send_transaction(self.wrench.document_id, txn);
+        let pixels = self.render_and_get_pixels(window_rect);
+        assert!(pixels.chunks(4).all(|p| p == [0, 255, 0, 255]));
+    }
+
 
     fn test_hit_testing(&mut self) {
         println!("\thit testing test...");
@@ -1035,6 +1213,24 @@ impl<'a> RawtestHarness<'a> {
         builder.pop_clip_id();
 
 
+        // Add a rectangle inside of a scroll frame.
+        let rect = LayoutRect::new(LayoutPoint::new(0., 300.), LayoutSize::new(100., 100.));
+        let scroll_id = ExternalScrollId(1, self.wrench.root_pipeline_id);
+        let scroll_frame_id = builder.define_scroll_frame(
+            Some(scroll_id),
+            LayoutRect::new(rect.origin, LayoutSize::new(100., 200.)),
+            rect,
+            vec![],
+            None,
+            ScrollSensitivity::ScriptAndInputEvents,
+        );
+        builder.push_clip_id(scroll_frame_id);
+        let mut info = LayoutPrimitiveInfo::new(rect);
+        info.tag = Some((0, 6));
+        builder.push_rect(&info, ColorF::new(1.0, 1.0, 1.0, 1.0));
+        builder.pop_clip_id();
+
+
         let mut epoch = Epoch(0);
         let txn = Transaction::new();
         self.submit_dl(&mut epoch, layout_size, builder, &txn.resource_updates);
@@ -1091,6 +1287,333 @@ impl<'a> RawtestHarness<'a> {
 
         test_rounded_rectangle(WorldPoint::new(100., 100.), WorldSize::new(100., 100.), (0, 4));
         test_rounded_rectangle(WorldPoint::new(200., 100.), WorldSize::new(100., 100.), (0, 5));
+
+        // Each hit should report the scroll frame that encloses it.
+        let result = hit_test(WorldPoint::new(50., 350.));
+        assert_eq!(result.items.len(), 2);
+        assert_eq!(result.items[0].tag, (0, 6));
+        assert_eq!(result.items[0].scroll_node, Some(scroll_id));
+        assert_eq!(result.items[1].tag, (0, 1));
+        assert_eq!(
+            result.items[1].scroll_node,
+            Some(ExternalScrollId(0, self.wrench.root_pipeline_id))
+        );
+
+        let hit_test_area = |area: HitTestArea| -> HitTestResult {
+            self.wrench.api.hit_test_area(
+                self.wrench.document_id,
+                None,
+                area,
+                HitTestFlags::FIND_ALL,
+            )
+        };
+
+        // An area exactly covering the normal rectangle is fully covered by it
+        // and by the background.
+        let result = hit_test_area(HitTestArea::Rect(WorldRect::new(
+            WorldPoint::new(100., 0.),
+            WorldSize::new(100., 100.),
+        )));
+        assert_eq!(result.items.len(), 2);
+        assert_eq!(result.items[0].tag, (0, 2));
+        assert_eq!(result.items[0].coverage, 1.0);
+        assert_eq!(result.items[1].tag, (0, 1));
+        assert_eq!(result.items[1].coverage, 1.0);
+
+        // A circle straddling the left edge of the normal rectangle only
+        // partially covers it.
+        let result = hit_test_area(HitTestArea::Circle(WorldPoint::new(100., 50.), 20.));
+        assert_eq!(result.items.len(), 2);
+        assert_eq!(result.items[0].tag, (0, 1));
+        assert_eq!(result.items[1].tag, (0, 2));
+        assert!(result.items[1].coverage > 0.3 && result.items[1].coverage < 0.7);
+
+        // Without FIND_ALL, only the item with the best coverage is returned.
+        let result = self.wrench.api.hit_test_area(
+            self.wrench.document_id,
+            None,
+            HitTestArea::Circle(WorldPoint::new(100., 50.), 20.),
+            HitTestFlags::empty(),
+        );
+        assert_eq!(result.items.len(), 1);
+        assert_eq!(result.items[0].tag, (0, 1));
+    }
+
+    fn test_hit_testing_path(&mut self) {
+        println!("\thit testing path test...");
+
+        let layout_size = LayoutSize::new(400., 400.);
+        let mut builder = DisplayListBuilder::new(self.wrench.root_pipeline_id, layout_size);
+
+        let mut info = LayoutPrimitiveInfo::new(LayoutRect::new(LayoutPoint::zero(), layout_size));
+        info.tag = Some((0, 1));
+        builder.push_rect(&info, ColorF::new(1.0, 1.0, 1.0, 1.0));
+
+        // A rectangle that is clipped by a circle of radius 100 around 200,200,
+        // drawn with the usual four cubic curves.
+        let k = 100. * 0.5523;
+        let path = [
+            PathOp::MoveTo(LayoutPoint::new(300., 200.)),
+            PathOp::CubicTo(
+                LayoutPoint::new(300., 200. + k),
+                LayoutPoint::new(200. + k, 300.),
+                LayoutPoint::new(200., 300.),
+            ),
+            PathOp::CubicTo(
+                LayoutPoint::new(200. - k, 300.),
+                LayoutPoint::new(100., 200. + k),
+                LayoutPoint::new(100., 200.),
+            ),
+            PathOp::CubicTo(
+                LayoutPoint::new(100., 200. - k),
+                LayoutPoint::new(200. - k, 100.),
+                LayoutPoint::new(200., 100.),
+            ),
+            PathOp::CubicTo(
+                LayoutPoint::new(200. + k, 100.),
+                LayoutPoint::new(300., 200. - k),
+                LayoutPoint::new(300., 200.),
+            ),
+            PathOp::Close,
+        ];
+        let clip_id = builder.define_path_clip(
+            LayoutRect::new(LayoutPoint::zero(), layout_size),
+            &path,
+            FillRule::NonZero,
+        );
+        builder.push_clip_id(clip_id);
+        let mut info = LayoutPrimitiveInfo::new(LayoutRect::new(LayoutPoint::zero(), layout_size));
+        info.tag = Some((0, 2));
+        builder.push_rect(&info, ColorF::new(1.0, 1.0, 1.0, 1.0));
+        builder.pop_clip_id();
+
+        let mut epoch = Epoch(0);
+        self.submit_dl(&mut epoch, layout_size, builder, &[]);
+
+        // We render to ensure that the hit tester is up to date with the current scene.
+        self.rx.recv().unwrap();
+        self.wrench.render();
+
+        let hit_test_tags = |point: WorldPoint| -> Vec<ItemTag> {
+            self.wrench.api
+                .hit_test(self.wrench.document_id, None, point, HitTestFlags::FIND_ALL)
+                .items
+                .iter()
+                .map(|item| item.tag)
+                .collect()
+        };
+
+        // Inside the circle, near its center and its edges.
+        assert_eq!(hit_test_tags(WorldPoint::new(200., 200.)), vec![(0, 2), (0, 1)]);
+        assert_eq!(hit_test_tags(WorldPoint::new(200., 105.)), vec![(0, 2), (0, 1)]);
+        assert_eq!(hit_test_tags(WorldPoint::new(295., 200.)), vec![(0, 2), (0, 1)]);
+        assert_eq!(hit_test_tags(WorldPoint::new(140., 260.)), vec![(0, 2), (0, 1)]);
+
+        // Outside the circle, but inside its bounds, where a rectangle
+        // test would hit it.
+        assert_eq!(hit_test_tags(WorldPoint::new(120., 120.)), vec![(0, 1)]);
+        assert_eq!(hit_test_tags(WorldPoint::new(280., 280.)), vec![(0, 1)]);
+        assert_eq!(hit_test_tags(WorldPoint::new(110., 290.)), vec![(0, 1)]);
+
+        // Outside the bounds of the circle.
+        assert_eq!(hit_test_tags(WorldPoint::new(50., 200.)), vec![(0, 1)]);
+    }
+
+    fn test_hit_testing_area_clips(&mut self) {
+        println!("\thit testing area clips test...");
+
+        let layout_size = LayoutSize::new(400., 400.);
+        let mut builder = DisplayListBuilder::new(self.wrench.root_pipeline_id, layout_size);
+
+        let mut info = LayoutPrimitiveInfo::new(LayoutRect::new(LayoutPoint::zero(), layout_size));
+        info.tag = Some((0, 1));
+        builder.push_rect(&info, ColorF::new(1.0, 1.0, 1.0, 1.0));
+
+        // A rectangle three times as tall as the scroll frame it's in.
+        let content_rect = LayoutRect::new(LayoutPoint::new(100., 100.), LayoutSize::new(100., 300.));
+        let scroll_frame_id = builder.define_scroll_frame(
+            None,
+            content_rect,
+            LayoutRect::new(content_rect.origin, LayoutSize::new(100., 100.)),
+            vec![],
+            None,
+            ScrollSensitivity::ScriptAndInputEvents,
+        );
+        builder.push_clip_id(scroll_frame_id);
+        let mut info = LayoutPrimitiveInfo::new(content_rect);
+        info.tag = Some((0, 2));
+        builder.push_rect(&info, ColorF::new(1.0, 1.0, 1.0, 1.0));
+        builder.pop_clip_id();
+
+        // A square that is clipped into a circle by its rounded corners.
+        let rect = LayoutRect::new(LayoutPoint::new(250., 100.), LayoutSize::new(100., 100.));
+        let clip_id = builder.define_clip(
+            rect,
+            vec![ComplexClipRegion::new(
+                rect,
+                BorderRadius::uniform_size(LayoutSize::new(50., 50.)),
+                ClipMode::Clip,
+            )],
+            None,
+        );
+        builder.push_clip_id(clip_id);
+        let mut info = LayoutPrimitiveInfo::new(rect);
+        info.tag = Some((0, 3));
+        builder.push_rect(&info, ColorF::new(1.0, 1.0, 1.0, 1.0));
+        builder.pop_clip_id();
+
+        let mut epoch = Epoch(0);
+        self.submit_dl(&mut epoch, layout_size, builder, &[]);
+
+        // We render to ensure that the hit tester is up to date with the current scene.
+        self.rx.recv().unwrap();
+        self.wrench.render();
+
+        let hit_test_area = |area: HitTestArea| -> HitTestResult {
+            self.wrench.api.hit_test_area(
+                self.wrench.document_id,
+                None,
+                area,
+                HitTestFlags::FIND_ALL,
+            )
+        };
+
+        // The scroll frame clips the rectangle to the top half of the area.
+        let result = hit_test_area(HitTestArea::Rect(WorldRect::new(
+            WorldPoint::new(100., 150.),
+            WorldSize::new(100., 100.),
+        )));
+        assert_eq!(result.items.len(), 2);
+        assert_eq!(result.items[0].tag, (0, 1));
+        assert_eq!(result.items[0].coverage, 1.0);
+        assert_eq!(result.items[1].tag, (0, 2));
+        assert_eq!(result.items[1].coverage, 0.5);
+
+        // The center of the circle is in the rectangle, but clipped out by
+        // the scroll frame, which only leaves the cap of the circle above it.
+        let result = hit_test_area(HitTestArea::Circle(WorldPoint::new(150., 230.), 40.));
+        assert_eq!(result.items.len(), 2);
+        assert_eq!(result.items[1].tag, (0, 2));
+        assert!(result.items[1].coverage > 0.05 && result.items[1].coverage < 0.1);
+        assert_eq!(result.items[1].point_relative_to_item.y, 100.);
+
+        // The rounded corners leave about pi / 4 of the area.
+        let result = hit_test_area(HitTestArea::Rect(WorldRect::new(
+            WorldPoint::new(250., 100.),
+            WorldSize::new(100., 100.),
+        )));
+        assert_eq!(result.items.len(), 2);
+        assert_eq!(result.items[0].tag, (0, 1));
+        assert_eq!(result.items[1].tag, (0, 3));
+        assert!(result.items[1].coverage > 0.75 && result.items[1].coverage < 0.85);
+
+        // An area in a clipped out corner doesn't hit the circle at all.
+        let result = hit_test_area(HitTestArea::Rect(WorldRect::new(
+            WorldPoint::new(250., 100.),
+            WorldSize::new(10., 10.),
+        )));
+        assert_eq!(result.items.len(), 1);
+        assert_eq!(result.items[0].tag, (0, 1));
+    }
+
+    fn test_hit_testing_depth(&mut self) {
+        println!("\thit testing depth test...");
+
+        let layout_size = LayoutSize::new(400., 400.);
+        let mut builder = DisplayListBuilder::new(self.wrench.root_pipeline_id, layout_size);
+
+        let info = LayoutPrimitiveInfo::new(LayoutRect::new(LayoutPoint::zero(), layout_size));
+        builder.push_stacking_context(
+            &info,
+            None,
+            TransformStyle::Preserve3D,
+            MixBlendMode::Normal,
+            Vec::new(),
+            GlyphRasterSpace::Screen,
+            None,
+        );
+
+        {
+            // Two overlapping planes of the same 3d rendering context, where the one
+            // painted first is closer to the viewer.
+            let mut push_plane = |origin: LayoutPoint, z: f32, tag: ItemTag| {
+                let info = LayoutPrimitiveInfo::new(
+                    LayoutRect::new(LayoutPoint::zero(), layout_size)
+                );
+                let transform = LayoutTransform::create_translation(0., 0., z);
+                let reference_frame_id = builder.push_reference_frame(
+                    &info,
+                    Some(PropertyBinding::Value(transform)),
+                    None,
+                );
+                builder.push_clip_id(reference_frame_id);
+                builder.push_stacking_context(
+                    &info,
+                    None,
+                    TransformStyle::Flat,
+                    MixBlendMode::Normal,
+                    Vec::new(),
+                    GlyphRasterSpace::Screen,
+                    None,
+                );
+
+                let mut info = LayoutPrimitiveInfo::new(
+                    LayoutRect::new(origin, LayoutSize::new(100., 100.))
+                );
+                info.tag = Some(tag);
+                builder.push_rect(&info, ColorF::new(1.0, 1.0, 1.0, 1.0));
+
+                builder.pop_stacking_context();
+                builder.pop_clip_id();
+                builder.pop_reference_frame();
+            };
+            push_plane(LayoutPoint::new(0., 0.), 50., (0, 10));
+            push_plane(LayoutPoint::new(50., 50.), 0., (0, 11));
+        }
+
+        builder.pop_stacking_context();
+
+        let mut epoch = Epoch(0);
+        let txn = Transaction::new();
+        self.submit_dl(&mut epoch, layout_size, builder, &txn.resource_updates);
+
+        // We render to ensure that the hit tester is up to date with the current scene.
+        self.rx.recv().unwrap();
+        self.wrench.render();
+
+        let hit_test_tags = |flags: HitTestFlags| -> Vec<ItemTag> {
+            self.wrench.api
+                .hit_test(self.wrench.document_id, None, WorldPoint::new(75., 75.), flags)
+                .items
+                .iter()
+                .map(|item| item.tag)
+                .collect()
+        };
+
+        // Without SORT_BY_DEPTH, hits are in reverse paint order.
+        assert_eq!(hit_test_tags(HitTestFlags::FIND_ALL), vec![(0, 11), (0, 10)]);
+        assert_eq!(hit_test_tags(HitTestFlags::empty()), vec![(0, 11)]);
+
+        // With SORT_BY_DEPTH, the plane closest to the viewer comes first.
+        assert_eq!(
+            hit_test_tags(HitTestFlags::FIND_ALL | HitTestFlags::SORT_BY_DEPTH),
+            vec![(0, 10), (0, 11)]
+        );
+        assert_eq!(hit_test_tags(HitTestFlags::SORT_BY_DEPTH), vec![(0, 10)]);
+
+        // Points covered by a single plane are unaffected by the ordering.
+        assert_eq!(
+            self.wrench.api
+                .hit_test(
+                    self.wrench.document_id,
+                    None,
+                    WorldPoint::new(25., 25.),
+                    HitTestFlags::FIND_ALL | HitTestFlags::SORT_BY_DEPTH,
+                )
+                .items
+                .len(),
+            1
+        );
     }
 
 }
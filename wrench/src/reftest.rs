@@ -4,17 +4,20 @@
 
 use {WindowWrapper, NotifierEvent};
 use base64;
+use crossbeam;
 use image::load as load_piston_image;
 use image::png::PNGEncoder;
 use image::{ColorType, ImageFormat};
 use parse_function::parse_function;
-use png::save_flipped;
+use png::{save, save_flipped, SaveSettings};
+use serde_json;
 use std::cmp;
 use std::fmt::{Display, Error, Formatter};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::Receiver;
+use std::sync::mpsc::{channel, Receiver};
+use time;
 use webrender::RendererStats;
 use webrender::api::*;
 use wrench::{Wrench, WrenchThing};
@@ -33,11 +36,19 @@ const OPTION_DISABLE_SUBPX: &str = "disable-subpixel";
 const OPTION_DISABLE_AA: &str = "disable-aa";
 const OPTION_DISABLE_DUAL_SOURCE_BLENDING: &str = "disable-dual-source-blending";
 const OPTION_ALLOW_MIPMAPS: &str = "allow-mipmaps";
+const OPTION_DISABLE_COMPRESSED_TEXTURES: &str = "disable-compressed-textures";
 
 pub struct ReftestOptions {
     // These override values that are lower.
     pub allow_max_difference: usize,
     pub allow_num_differences: usize,
+    // The state of the renderer, which manifest conditions can test.
+    pub dual_source_blending: bool,
+    pub subpixel_aa: bool,
+    pub headless: bool,
+    pub angle: bool,
+    // Where to write a machine readable report of the results.
+    pub report: Option<(ReftestReportFormat, PathBuf)>,
 }
 
 impl ReftestOptions {
@@ -45,8 +56,351 @@ impl ReftestOptions {
         ReftestOptions {
             allow_max_difference: 0,
             allow_num_differences: 0,
+            dual_source_blending: true,
+            subpixel_aa: true,
+            headless: false,
+            angle: false,
+            report: None,
         }
     }
+
+    // Get the value of a variable in a manifest condition.
+    fn condition_variable(&self, name: &str) -> bool {
+        match name {
+            "linux" | "mac" | "win" | "other" => name == PLATFORM,
+            "pathfinder" => cfg!(feature = "pathfinder"),
+            "dual-source-blending" => self.dual_source_blending,
+            "subpixel-aa" => self.subpixel_aa,
+            "headless" => self.headless,
+            "angle" => self.angle,
+            _ => panic!("Unknown reftest condition variable: {}", name),
+        }
+    }
+
+    // Evaluate a manifest condition such as `linux&&!(headless||angle)`.
+    // Conditions can't contain whitespace, since the manifest is split
+    // into tokens at whitespace.
+    fn evaluate_condition(&self, condition: &str) -> bool {
+        let mut parser = ConditionParser {
+            input: condition,
+            options: self,
+        };
+        let value = parser.parse_or();
+        assert!(
+            parser.input.is_empty(),
+            "Unexpected '{}' in reftest condition: {}",
+            parser.input,
+            condition
+        );
+        value
+    }
+}
+
+// A recursive descent parser for manifest conditions, which evaluates
+// them as it goes.
+struct ConditionParser<'a> {
+    input: &'a str,
+    options: &'a ReftestOptions,
+}
+
+impl<'a> ConditionParser<'a> {
+    fn eat(&mut self, token: &str) -> bool {
+        if self.input.starts_with(token) {
+            self.input = &self.input[token.len() ..];
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> bool {
+        let mut value = self.parse_and();
+        while self.eat("||") {
+            // Always parse the right hand side, to consume it.
+            value = self.parse_and() || value;
+        }
+        value
+    }
+
+    fn parse_and(&mut self) -> bool {
+        let mut value = self.parse_unary();
+        while self.eat("&&") {
+            value = self.parse_unary() && value;
+        }
+        value
+    }
+
+    fn parse_unary(&mut self) -> bool {
+        if self.eat("!") {
+            return !self.parse_unary();
+        }
+        if self.eat("(") {
+            let value = self.parse_or();
+            assert!(self.eat(")"), "Missing ')' in reftest condition");
+            return value;
+        }
+
+        let end = self.input
+            .find(|c: char| !c.is_alphanumeric() && c != '-' && c != '_')
+            .unwrap_or(self.input.len());
+        assert!(end > 0, "Expected a variable in reftest condition, got '{}'", self.input);
+        let name = &self.input[.. end];
+        self.input = &self.input[end ..];
+        self.options.condition_variable(name)
+    }
+}
+
+// Get the condition of a manifest annotation such as `fails-if(cond)`.
+fn annotation_condition<'a>(token: &'a str, name: &str) -> &'a str {
+    assert!(
+        token.len() > name.len() + 1 && token.ends_with(')'),
+        "Expected a condition in {}",
+        token
+    );
+    &token[name.len() + 1 .. token.len() - 1]
+}
+
+/// The result that a reftest is expected to have.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReftestExpectation {
+    Pass,
+    Fail,
+    /// The test passes or fails at random, so its result is ignored.
+    Random,
+}
+
+impl ReftestExpectation {
+    // The status reported for a test that fails.
+    fn failure_status(&self) -> &'static str {
+        match *self {
+            ReftestExpectation::Pass => "REFTEST TEST-UNEXPECTED-FAIL",
+            ReftestExpectation::Fail => "REFTEST TEST-KNOWN-FAIL",
+            ReftestExpectation::Random => "REFTEST TEST-KNOWN-FAIL(EXPECTED RANDOM)",
+        }
+    }
+}
+
+/// How the difference between two pixels is measured.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReftestMetric {
+    /// The largest difference between any of the channels.
+    MaxChannel,
+    /// The CIE76 color difference (ΔE*ab) of the colors, or the
+    /// difference in alpha if that is larger.
+    DeltaE,
+}
+
+/// A rectangle of the image, in device pixels from the top left, whose
+/// pixels are allowed to differ by a fuzz of their own instead of the
+/// fuzz of the whole test.
+#[derive(Clone, Copy, Debug)]
+pub struct FuzzyRegion {
+    rect: DeviceUintRect,
+    max_difference: usize,
+    num_differences: usize,
+}
+
+impl FuzzyRegion {
+    // Parse a `fuzzy-region(x, y, width, height, max_difference, num_differences)`
+    // annotation.
+    fn parse(function: &str) -> FuzzyRegion {
+        let (_, args, _) = parse_function(function);
+        assert_eq!(args.len(), 6, "Expected 6 arguments in {}", function);
+        let arg = |i: usize| -> u32 {
+            args[i]
+                .parse()
+                .unwrap_or_else(|_| panic!("Invalid argument '{}' in {}", args[i], function))
+        };
+        FuzzyRegion {
+            rect: DeviceUintRect::new(
+                DeviceUintPoint::new(arg(0), arg(1)),
+                DeviceUintSize::new(arg(2), arg(3)),
+            ),
+            max_difference: arg(4) as usize,
+            num_differences: arg(5) as usize,
+        }
+    }
+}
+
+impl Display for FuzzyRegion {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(
+            f,
+            "region {},{},{},{}",
+            self.rect.origin.x,
+            self.rect.origin.y,
+            self.rect.size.width,
+            self.rect.size.height
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReftestReportFormat {
+    Json,
+    JUnit,
+}
+
+impl ReftestReportFormat {
+    pub fn from_str(format: &str) -> Option<Self> {
+        match format {
+            "json" => Some(ReftestReportFormat::Json),
+            "junit" => Some(ReftestReportFormat::JUnit),
+            _ => None,
+        }
+    }
+}
+
+// The status of a finished reftest, as written to reports.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+enum ReftestStatus {
+    #[serde(rename = "PASS")]
+    Pass,
+    #[serde(rename = "FAIL")]
+    Fail,
+    #[serde(rename = "UNEXPECTED-PASS")]
+    UnexpectedPass,
+    #[serde(rename = "KNOWN-FAIL")]
+    KnownFail,
+    #[serde(rename = "RANDOM")]
+    Random,
+}
+
+impl ReftestStatus {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            ReftestStatus::Pass => "PASS",
+            ReftestStatus::Fail => "FAIL",
+            ReftestStatus::UnexpectedPass => "UNEXPECTED-PASS",
+            ReftestStatus::KnownFail => "KNOWN-FAIL",
+            ReftestStatus::Random => "RANDOM",
+        }
+    }
+}
+
+// The outcome of a single reftest, as written to reports.
+#[derive(Serialize)]
+struct ReftestResult {
+    test: String,
+    status: ReftestStatus,
+    duration_ms: f64,
+    // The differences of the pixels outside of any fuzzy region.
+    max_difference: usize,
+    count_different: usize,
+    region_differences: Vec<(usize, usize)>,
+    expected_draw_calls: Option<usize>,
+    draw_calls: usize,
+    expected_alpha_targets: Option<usize>,
+    alpha_targets: usize,
+    expected_color_targets: Option<usize>,
+    color_targets: usize,
+    // The test, reference and diff images of a failing test.
+    images: Vec<PathBuf>,
+    // The output of the test, which is printed rather than reported.
+    #[serde(skip)]
+    log: String,
+}
+
+impl ReftestResult {
+    fn new(t: &Reftest) -> Self {
+        ReftestResult {
+            test: t.to_string(),
+            status: ReftestStatus::Pass,
+            duration_ms: 0.0,
+            max_difference: 0,
+            count_different: 0,
+            region_differences: Vec::new(),
+            expected_draw_calls: t.expected_draw_calls,
+            draw_calls: 0,
+            expected_alpha_targets: t.expected_alpha_targets,
+            alpha_targets: 0,
+            expected_color_targets: t.expected_color_targets,
+            color_targets: 0,
+            images: Vec::new(),
+            log: String::new(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ReftestReport<'a> {
+    passing: usize,
+    failing: usize,
+    unexpected_passing: usize,
+    known_failing: usize,
+    random: usize,
+    duration_ms: f64,
+    tests: &'a [ReftestResult],
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_junit_report<W: Write>(mut out: W, report: &ReftestReport) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<testsuite name="reftest" tests="{}" failures="{}" skipped="{}" time="{:.3}">"#,
+        report.tests.len(),
+        report.failing + report.unexpected_passing,
+        report.known_failing + report.random,
+        report.duration_ms / 1000.0
+    )?;
+
+    for result in report.tests {
+        writeln!(
+            out,
+            r#"  <testcase classname="reftest" name="{}" time="{:.3}">"#,
+            escape_xml(&result.test),
+            result.duration_ms / 1000.0
+        )?;
+
+        let mut details = format!(
+            "max difference: {}, number of differing pixels: {}\n",
+            result.max_difference,
+            result.count_different
+        );
+        for (i, &(max_difference, count_different)) in result.region_differences.iter().enumerate() {
+            details.push_str(&format!(
+                "region {}: max difference: {}, number of differing pixels: {}\n",
+                i,
+                max_difference,
+                count_different
+            ));
+        }
+        details.push_str(&format!(
+            "draw calls: {}, alpha targets: {}, color targets: {}\n",
+            result.draw_calls,
+            result.alpha_targets,
+            result.color_targets
+        ));
+        for image in &result.images {
+            details.push_str(&format!("image: {}\n", image.display()));
+        }
+
+        match result.status {
+            ReftestStatus::Fail | ReftestStatus::UnexpectedPass => {
+                writeln!(
+                    out,
+                    r#"    <failure message="{}">{}</failure>"#,
+                    result.status.as_str(),
+                    escape_xml(&details)
+                )?;
+            }
+            ReftestStatus::KnownFail | ReftestStatus::Random => {
+                writeln!(out, r#"    <skipped message="{}"/>"#, result.status.as_str())?;
+            }
+            ReftestStatus::Pass => {}
+        }
+        writeln!(out, "    <system-out>{}</system-out>", escape_xml(&details))?;
+        writeln!(out, "  </testcase>")?;
+    }
+
+    writeln!(out, "</testsuite>")
 }
 
 pub enum ReftestOp {
@@ -74,12 +428,16 @@ pub struct Reftest {
     font_render_mode: Option<FontRenderMode>,
     max_difference: usize,
     num_differences: usize,
+    metric: ReftestMetric,
+    fuzzy_regions: Vec<FuzzyRegion>,
     expected_draw_calls: Option<usize>,
     expected_alpha_targets: Option<usize>,
     expected_color_targets: Option<usize>,
     disable_dual_source_blending: bool,
+    disable_compressed_textures: bool,
     allow_mipmaps: bool,
     zoom_factor: f32,
+    expectation: ReftestExpectation,
 }
 
 impl Display for Reftest {
@@ -101,43 +459,150 @@ struct ReftestImage {
 enum ReftestImageComparison {
     Equal,
     NotEqual {
+        // The differences of the pixels outside of any fuzzy region.
         max_difference: usize,
         count_different: usize,
+        // The max difference and number of differing pixels in each
+        // fuzzy region, in the order of the regions.
+        region_differences: Vec<(usize, usize)>,
     },
 }
 
+// Convert an sRGB color to CIE L*a*b*, with a D65 white point.
+fn srgb_to_lab(rgb: &[u8]) -> [f32; 3] {
+    let linear: Vec<f32> = rgb.iter().map(|&c| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    }).collect();
+
+    let x = (0.4124 * linear[0] + 0.3576 * linear[1] + 0.1805 * linear[2]) / 0.95047;
+    let y = 0.2126 * linear[0] + 0.7152 * linear[1] + 0.0722 * linear[2];
+    let z = (0.0193 * linear[0] + 0.1192 * linear[1] + 0.9505 * linear[2]) / 1.08883;
+
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn pixel_difference(a: &[u8], b: &[u8], metric: ReftestMetric) -> usize {
+    let alpha_difference = (a[3] as isize - b[3] as isize).abs() as usize;
+
+    match metric {
+        ReftestMetric::MaxChannel => {
+            a.iter()
+                .zip(b.iter())
+                .map(|(x, y)| (*x as isize - *y as isize).abs() as usize)
+                .max()
+                .unwrap()
+        }
+        ReftestMetric::DeltaE => {
+            let lab_a = srgb_to_lab(&a[0 .. 3]);
+            let lab_b = srgb_to_lab(&b[0 .. 3]);
+            let delta_e = lab_a
+                .iter()
+                .zip(lab_b.iter())
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f32>()
+                .sqrt();
+
+            // Differences that are too small to see still count as a
+            // differing pixel, with a difference of at least one.
+            cmp::max(cmp::max(delta_e.ceil() as usize, 1), alpha_difference)
+        }
+    }
+}
+
 impl ReftestImage {
-    fn compare(&self, other: &ReftestImage) -> ReftestImageComparison {
+    // Save the image as a PNG. Unlike `save_flipped`, this never crops the
+    // image to the size of a file left at the same path by an earlier run.
+    fn save(&self, path: &Path) {
+        save(path, self.data.clone(), self.size, SaveSettings {
+            flip_vertical: true,
+            try_crop: false,
+        });
+    }
+
+    // Get the index of the fuzzy region that contains a pixel, given
+    // the index of the pixel in the (bottom up) image data.
+    fn find_region(&self, pixel_index: usize, regions: &[FuzzyRegion]) -> Option<usize> {
+        let x = pixel_index as u32 % self.size.width;
+        let y = self.size.height - 1 - pixel_index as u32 / self.size.width;
+        let point = DeviceUintPoint::new(x, y);
+        regions.iter().position(|region| region.rect.contains(&point))
+    }
+
+    fn compare(
+        &self,
+        other: &ReftestImage,
+        metric: ReftestMetric,
+        regions: &[FuzzyRegion],
+    ) -> ReftestImageComparison {
         assert_eq!(self.size, other.size);
         assert_eq!(self.data.len(), other.data.len());
         assert_eq!(self.data.len() % 4, 0);
 
         let mut count = 0;
         let mut max = 0;
+        let mut region_differences = vec![(0, 0); regions.len()];
 
-        for (a, b) in self.data.chunks(4).zip(other.data.chunks(4)) {
+        for (i, (a, b)) in self.data.chunks(4).zip(other.data.chunks(4)).enumerate() {
             if a != b {
-                let pixel_max = a.iter()
-                    .zip(b.iter())
-                    .map(|(x, y)| (*x as isize - *y as isize).abs() as usize)
-                    .max()
-                    .unwrap();
-
-                count += 1;
-                max = cmp::max(max, pixel_max);
+                let pixel_max = pixel_difference(a, b, metric);
+
+                match self.find_region(i, regions) {
+                    Some(region_index) => {
+                        let region = &mut region_differences[region_index];
+                        region.0 = cmp::max(region.0, pixel_max);
+                        region.1 += 1;
+                    }
+                    None => {
+                        count += 1;
+                        max = cmp::max(max, pixel_max);
+                    }
+                }
             }
         }
 
-        if count != 0 {
+        if count != 0 || region_differences.iter().any(|&(_, count)| count != 0) {
             ReftestImageComparison::NotEqual {
                 max_difference: max,
                 count_different: count,
+                region_differences,
             }
         } else {
             ReftestImageComparison::Equal
         }
     }
 
+    // Create an image showing where this image differs from another. The
+    // pixels that are the same are a dimmed grey version of this image,
+    // while differing pixels are red, or yellow inside a fuzzy region.
+    fn create_diff_image(&self, other: &ReftestImage, regions: &[FuzzyRegion]) -> ReftestImage {
+        let mut data = Vec::with_capacity(self.data.len());
+
+        for (i, (a, b)) in self.data.chunks(4).zip(other.data.chunks(4)).enumerate() {
+            if a == b {
+                let luminance = (a[0] as u32 * 54 + a[1] as u32 * 183 + a[2] as u32 * 19) >> 8;
+                let dimmed = (64 + luminance / 4) as u8;
+                data.extend_from_slice(&[dimmed, dimmed, dimmed, 0xff]);
+            } else if self.find_region(i, regions).is_some() {
+                data.extend_from_slice(&[0xff, 0xff, 0, 0xff]);
+            } else {
+                data.extend_from_slice(&[0xff, 0, 0, 0xff]);
+            }
+        }
+
+        ReftestImage {
+            data,
+            size: self.size,
+        }
+    }
+
     fn create_data_uri(mut self) -> String {
         let width = self.size.width;
         let height = self.size.height;
@@ -191,14 +656,18 @@ impl ReftestManifest {
 
             let mut max_difference = 0;
             let mut max_count = 0;
+            let mut metric = ReftestMetric::MaxChannel;
+            let mut fuzzy_regions = Vec::new();
             let mut op = ReftestOp::Equal;
             let mut font_render_mode = None;
             let mut expected_color_targets = None;
             let mut expected_alpha_targets = None;
             let mut expected_draw_calls = None;
             let mut disable_dual_source_blending = false;
+            let mut disable_compressed_textures = false;
             let mut zoom_factor = 1.0;
             let mut allow_mipmaps = false;
+            let mut expectation = ReftestExpectation::Pass;
 
             for (i, token) in tokens.iter().enumerate() {
                 match *token {
@@ -219,10 +688,43 @@ impl ReftestManifest {
                             break;
                         }
                     }
+                    "skip" => {
+                        break;
+                    }
+                    condition if condition.starts_with("skip-if(") => {
+                        if options.evaluate_condition(annotation_condition(condition, "skip-if")) {
+                            break;
+                        }
+                    }
+                    "fails" => {
+                        expectation = ReftestExpectation::Fail;
+                    }
+                    condition if condition.starts_with("fails-if(") => {
+                        if options.evaluate_condition(annotation_condition(condition, "fails-if")) {
+                            expectation = ReftestExpectation::Fail;
+                        }
+                    }
+                    "random" => {
+                        expectation = ReftestExpectation::Random;
+                    }
+                    condition if condition.starts_with("random-if(") => {
+                        if options.evaluate_condition(annotation_condition(condition, "random-if")) {
+                            expectation = ReftestExpectation::Random;
+                        }
+                    }
                     function if function.starts_with("zoom") => {
                         let (_, args, _) = parse_function(function);
                         zoom_factor = args[0].parse().unwrap();
                     }
+                    function if function.starts_with("fuzzy-region") => {
+                        fuzzy_regions.push(FuzzyRegion::parse(function));
+                    }
+                    function if function.starts_with("fuzzy-perceptual") => {
+                        let (_, args, _) = parse_function(function);
+                        metric = ReftestMetric::DeltaE;
+                        max_difference = args[0].parse().unwrap();
+                        max_count = args[1].parse().unwrap();
+                    }
                     function if function.starts_with("fuzzy") => {
                         let (_, args, _) = parse_function(function);
                         max_difference = args[0].parse().unwrap();
@@ -254,6 +756,9 @@ impl ReftestManifest {
                         if args.iter().any(|arg| arg == &OPTION_ALLOW_MIPMAPS) {
                             allow_mipmaps = true;
                         }
+                        if args.iter().any(|arg| arg == &OPTION_DISABLE_COMPRESSED_TEXTURES) {
+                            disable_compressed_textures = true;
+                        }
                     }
                     "==" => {
                         op = ReftestOp::Equal;
@@ -269,12 +774,16 @@ impl ReftestManifest {
                             font_render_mode,
                             max_difference: cmp::max(max_difference, options.allow_max_difference),
                             num_differences: cmp::max(max_count, options.allow_num_differences),
+                            metric,
+                            fuzzy_regions,
                             expected_draw_calls,
                             expected_alpha_targets,
                             expected_color_targets,
                             disable_dual_source_blending,
+                            disable_compressed_textures,
                             allow_mipmaps,
                             zoom_factor,
+                            expectation,
                         });
 
                         break;
@@ -296,35 +805,92 @@ impl ReftestManifest {
     }
 }
 
-pub struct ReftestHarness<'a> {
-    wrench: &'a mut Wrench,
-    window: &'a mut WindowWrapper,
-    rx: &'a Receiver<NotifierEvent>,
+// Appends a line to the log of a test. The logs are printed in the
+// order of the manifest, whichever order the tests finish in.
+macro_rules! test_log {
+    ($result:expr, $($arg:tt)*) => {{
+        $result.log.push_str(&format!($($arg)*));
+        $result.log.push('\n');
+    }};
 }
-impl<'a> ReftestHarness<'a> {
-    pub fn new(wrench: &'a mut Wrench, window: &'a mut WindowWrapper, rx: &'a Receiver<NotifierEvent>) -> Self {
-        ReftestHarness { wrench, window, rx }
+
+// Collects the results of the tests as they finish.
+struct ReftestResults {
+    results: Vec<Option<ReftestResult>>,
+    next: usize,
+    start_time: u64,
+}
+
+impl ReftestResults {
+    fn new(count: usize) -> Self {
+        ReftestResults {
+            results: (0 .. count).map(|_| None).collect(),
+            next: 0,
+            start_time: time::precise_time_ns(),
+        }
     }
 
-    pub fn run(mut self, base_manifest: &Path, reftests: Option<&Path>, options: &ReftestOptions) -> usize {
-        let manifest = ReftestManifest::new(base_manifest, options);
-        let reftests = manifest.find(reftests.unwrap_or(&PathBuf::new()));
+    fn add(&mut self, index: usize, result: ReftestResult) {
+        self.results[index] = Some(result);
+
+        while self.next < self.results.len() {
+            match self.results[self.next] {
+                Some(ref result) => print!("{}", result.log),
+                None => break,
+            }
+            self.next += 1;
+        }
+    }
+
+    fn finish(self, options: &ReftestOptions) -> usize {
+        assert_eq!(self.next, self.results.len(), "Not every reftest was run");
+        let results: Vec<ReftestResult> = self.results.into_iter().map(Option::unwrap).collect();
 
         let mut total_passing = 0;
+        let mut total_known_failing = 0;
+        let mut total_random = 0;
         let mut failing = Vec::new();
+        let mut unexpected_passing = Vec::new();
 
-        for t in reftests {
-            if self.run_reftest(t) {
-                total_passing += 1;
-            } else {
-                failing.push(t);
+        for result in &results {
+            match result.status {
+                ReftestStatus::Pass => total_passing += 1,
+                ReftestStatus::Fail => failing.push(&result.test),
+                ReftestStatus::UnexpectedPass => unexpected_passing.push(&result.test),
+                ReftestStatus::KnownFail => total_known_failing += 1,
+                ReftestStatus::Random => total_random += 1,
+            }
+        }
+
+        if let Some((format, ref path)) = options.report {
+            let report = ReftestReport {
+                passing: total_passing,
+                failing: failing.len(),
+                unexpected_passing: unexpected_passing.len(),
+                known_failing: total_known_failing,
+                random: total_random,
+                duration_ms: (time::precise_time_ns() - self.start_time) as f64 / 1000000.0,
+                tests: &results,
+            };
+
+            let file = File::create(path)
+                .unwrap_or_else(|e| panic!("couldn't create report {}: {}", path.display(), e));
+            let out = BufWriter::new(file);
+            match format {
+                ReftestReportFormat::Json => serde_json::to_writer_pretty(out, &report)
+                    .expect("Unable to write the JSON report"),
+                ReftestReportFormat::JUnit => write_junit_report(out, &report)
+                    .expect("Unable to write the JUnit report"),
             }
         }
 
         println!(
-            "REFTEST INFO | {} passing, {} failing",
+            "REFTEST INFO | {} passing, {} failing, {} unexpectedly passing, {} known failing, {} random",
             total_passing,
-            failing.len()
+            failing.len(),
+            unexpected_passing.len(),
+            total_known_failing,
+            total_random
         );
 
         if !failing.is_empty() {
@@ -335,11 +901,110 @@ impl<'a> ReftestHarness<'a> {
             }
         }
 
-        failing.len()
+        if !unexpected_passing.is_empty() {
+            println!("\nReftests that were expected to fail, but passed:");
+
+            for reftest in &unexpected_passing {
+                println!("\t{}", reftest);
+            }
+        }
+
+        failing.len() + unexpected_passing.len()
     }
+}
+
+/// Runs the reftests on several instances of wrench at once, each on
+/// its own thread, and returns the number of unexpected results. The
+/// tests are dealt out to the instances in turn, and the results are
+/// reported in the order of the manifest.
+pub fn run_parallel<F>(
+    base_manifest: &Path,
+    reftests: Option<&Path>,
+    options: &ReftestOptions,
+    jobs: usize,
+    make_instance: F,
+) -> usize
+where
+    F: Fn() -> (Wrench, WindowWrapper, Receiver<NotifierEvent>) + Sync,
+{
+    let manifest = ReftestManifest::new(base_manifest, options);
+    let reftests = manifest.find(reftests.unwrap_or(&PathBuf::new()));
+    let mut results = ReftestResults::new(reftests.len());
+    let (tx, rx) = channel();
 
-    fn run_reftest(&mut self, t: &Reftest) -> bool {
-        println!("REFTEST {}", t);
+    crossbeam::scope(|scope| {
+        for job in 0 .. jobs {
+            let tx = tx.clone();
+            let reftests = &reftests;
+            let make_instance = &make_instance;
+            scope.spawn(move || {
+                let (mut wrench, mut window, notifier_rx) = make_instance();
+                {
+                    let mut harness = ReftestHarness::new(&mut wrench, &mut window, &notifier_rx);
+                    for (i, t) in reftests.iter().enumerate().filter(|&(i, _)| i % jobs == job) {
+                        tx.send((i, harness.run_test(t, options))).unwrap();
+                    }
+                }
+                wrench.shut_down(notifier_rx);
+            });
+        }
+
+        // The channel closes once every job is done.
+        drop(tx);
+        for (i, result) in rx {
+            results.add(i, result);
+        }
+    });
+
+    results.finish(options)
+}
+
+pub struct ReftestHarness<'a> {
+    wrench: &'a mut Wrench,
+    window: &'a mut WindowWrapper,
+    rx: &'a Receiver<NotifierEvent>,
+}
+impl<'a> ReftestHarness<'a> {
+    pub fn new(wrench: &'a mut Wrench, window: &'a mut WindowWrapper, rx: &'a Receiver<NotifierEvent>) -> Self {
+        ReftestHarness { wrench, window, rx }
+    }
+
+    pub fn run(mut self, base_manifest: &Path, reftests: Option<&Path>, options: &ReftestOptions) -> usize {
+        let manifest = ReftestManifest::new(base_manifest, options);
+        let reftests = manifest.find(reftests.unwrap_or(&PathBuf::new()));
+        let mut results = ReftestResults::new(reftests.len());
+
+        for (i, t) in reftests.iter().enumerate() {
+            let result = self.run_test(t, options);
+            results.add(i, result);
+        }
+
+        results.finish(options)
+    }
+
+    fn run_test(&mut self, t: &Reftest, options: &ReftestOptions) -> ReftestResult {
+        let mut result = ReftestResult::new(t);
+        let start_time = time::precise_time_ns();
+        let passed = self.run_reftest(t, &mut result, options.report.is_some());
+        result.duration_ms = (time::precise_time_ns() - start_time) as f64 / 1000000.0;
+
+        result.status = match (t.expectation, passed) {
+            (ReftestExpectation::Pass, true) => ReftestStatus::Pass,
+            (ReftestExpectation::Pass, false) => ReftestStatus::Fail,
+            (ReftestExpectation::Fail, true) => {
+                test_log!(result, "REFTEST TEST-UNEXPECTED-PASS | {}", t);
+                ReftestStatus::UnexpectedPass
+            }
+            (ReftestExpectation::Fail, false) => ReftestStatus::KnownFail,
+            (ReftestExpectation::Random, _) => ReftestStatus::Random,
+        };
+
+        result
+    }
+
+    fn run_reftest(&mut self, t: &Reftest, result: &mut ReftestResult, save_images: bool) -> bool {
+        test_log!(result, "REFTEST {}", t);
+        let failure_status = t.expectation.failure_status();
 
         self.wrench
             .api
@@ -357,6 +1022,14 @@ impl<'a> ReftestHarness<'a> {
                 );
         }
 
+        if t.disable_compressed_textures {
+            self.wrench
+                .api
+                .send_debug_cmd(
+                    DebugCommand::EnableCompressedTextures(false)
+                );
+        }
+
         let window_size = self.window.get_inner_size();
         let reference = match t.reference.extension().unwrap().to_str().unwrap() {
             "yaml" => {
@@ -391,35 +1064,59 @@ impl<'a> ReftestHarness<'a> {
                 );
         }
 
-        let comparison = test.compare(&reference);
+        if t.disable_compressed_textures {
+            self.wrench
+                .api
+                .send_debug_cmd(
+                    DebugCommand::EnableCompressedTextures(true)
+                );
+        }
+
+        let comparison = test.compare(&reference, t.metric, &t.fuzzy_regions);
+
+        result.draw_calls = stats.total_draw_calls;
+        result.alpha_targets = stats.alpha_target_count;
+        result.color_targets = stats.color_target_count;
+        if let ReftestImageComparison::NotEqual {
+            max_difference,
+            count_different,
+            ref region_differences,
+        } = comparison {
+            result.max_difference = max_difference;
+            result.count_different = count_different;
+            result.region_differences = region_differences.clone();
+        }
 
         if let Some(expected_draw_calls) = t.expected_draw_calls {
             if expected_draw_calls != stats.total_draw_calls {
-                println!("REFTEST TEST-UNEXPECTED-FAIL | {}/{} | expected_draw_calls",
+                test_log!(result, "{} | {}/{} | expected_draw_calls",
+                    failure_status,
                     stats.total_draw_calls,
                     expected_draw_calls
                 );
-                println!("REFTEST TEST-END | {}", t);
+                test_log!(result, "REFTEST TEST-END | {}", t);
                 return false;
             }
         }
         if let Some(expected_alpha_targets) = t.expected_alpha_targets {
             if expected_alpha_targets != stats.alpha_target_count {
-                println!("REFTEST TEST-UNEXPECTED-FAIL | {}/{} | alpha_target_count",
+                test_log!(result, "{} | {}/{} | alpha_target_count",
+                    failure_status,
                     stats.alpha_target_count,
                     expected_alpha_targets
                 );
-                println!("REFTEST TEST-END | {}", t);
+                test_log!(result, "REFTEST TEST-END | {}", t);
                 return false;
             }
         }
         if let Some(expected_color_targets) = t.expected_color_targets {
             if expected_color_targets != stats.color_target_count {
-                println!("REFTEST TEST-UNEXPECTED-FAIL | {}/{} | color_target_count",
+                test_log!(result, "{} | {}/{} | color_target_count",
+                    failure_status,
                     stats.color_target_count,
                     expected_color_targets
                 );
-                println!("REFTEST TEST-END | {}", t);
+                test_log!(result, "REFTEST TEST-END | {}", t);
                 return false;
             }
         }
@@ -431,31 +1128,78 @@ impl<'a> ReftestHarness<'a> {
                 ReftestImageComparison::NotEqual {
                     max_difference,
                     count_different,
+                    region_differences,
                 },
-            ) => if max_difference > t.max_difference || count_different > t.num_differences {
-                println!(
-                    "{} | {} | {}: {}, {}: {}",
-                    "REFTEST TEST-UNEXPECTED-FAIL",
-                    t,
-                    "image comparison, max difference",
-                    max_difference,
-                    "number of differing pixels",
-                    count_different
-                );
-                println!("REFTEST   IMAGE 1 (TEST): {}", test.create_data_uri());
-                println!(
+            ) => {
+                let mut passed = true;
+
+                if max_difference > t.max_difference || count_different > t.num_differences {
+                    test_log!(
+                        result,
+                        "{} | {} | {}: {}, {}: {}",
+                        failure_status,
+                        t,
+                        "image comparison, max difference",
+                        max_difference,
+                        "number of differing pixels",
+                        count_different
+                    );
+                    passed = false;
+                }
+
+                for (region, &(max_difference, count_different)) in
+                    t.fuzzy_regions.iter().zip(region_differences.iter())
+                {
+                    if max_difference > region.max_difference ||
+                       count_different > region.num_differences {
+                        test_log!(
+                            result,
+                            "{} | {} | {} {}, {}: {}, {}: {}",
+                            failure_status,
+                            t,
+                            "image comparison in",
+                            region,
+                            "max difference",
+                            max_difference,
+                            "number of differing pixels",
+                            count_different
+                        );
+                        passed = false;
+                    }
+                }
+
+                if passed {
+                    return true;
+                }
+
+                let diff_path = t.test.with_extension("yaml.diff.png");
+                let diff = test.create_diff_image(&reference, &t.fuzzy_regions);
+                diff.save(&diff_path);
+
+                if save_images {
+                    let test_path = t.test.with_extension("yaml.test.png");
+                    let reference_path = t.test.with_extension("yaml.ref.png");
+                    test.save(&test_path);
+                    reference.save(&reference_path);
+                    result.images.push(test_path);
+                    result.images.push(reference_path);
+                }
+                result.images.push(diff_path.clone());
+
+                test_log!(result, "REFTEST   IMAGE 1 (TEST): {}", test.create_data_uri());
+                test_log!(
+                    result,
                     "REFTEST   IMAGE 2 (REFERENCE): {}",
                     reference.create_data_uri()
                 );
-                println!("REFTEST TEST-END | {}", t);
+                test_log!(result, "REFTEST   DIFFERENCES: {}", diff_path.display());
+                test_log!(result, "REFTEST TEST-END | {}", t);
 
                 false
-            } else {
-                true
-            },
+            }
             (&ReftestOp::NotEqual, ReftestImageComparison::Equal) => {
-                println!("REFTEST TEST-UNEXPECTED-FAIL | {} | image comparison", t);
-                println!("REFTEST TEST-END | {}", t);
+                test_log!(result, "{} | {} | image comparison", failure_status, t);
+                test_log!(result, "REFTEST TEST-END | {}", t);
 
                 false
             }
@@ -513,3 +1257,233 @@ impl<'a> ReftestHarness<'a> {
         (ReftestImage { data: pixels, size }, stats)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{FuzzyRegion, ReftestMetric, ReftestOptions, ReftestReport, ReftestResult};
+    use super::{ReftestStatus, escape_xml, pixel_difference, srgb_to_lab, write_junit_report};
+    use std::path::PathBuf;
+    use webrender::api::{DeviceUintPoint, DeviceUintRect, DeviceUintSize};
+
+    fn assert_lab_eq(rgb: [u8; 3], expected: [f32; 3]) {
+        let lab = srgb_to_lab(&rgb);
+        for i in 0 .. 3 {
+            assert!(
+                (lab[i] - expected[i]).abs() < 0.05,
+                "L*a*b* of {:?} is {:?}, expected {:?}",
+                rgb,
+                lab,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn convert_srgb_to_lab() {
+        assert_lab_eq([0, 0, 0], [0.0, 0.0, 0.0]);
+        assert_lab_eq([255, 255, 255], [100.0, 0.0, 0.0]);
+        assert_lab_eq([128, 128, 128], [53.585, 0.0, 0.0]);
+        assert_lab_eq([255, 0, 0], [53.23, 80.11, 67.22]);
+        assert_lab_eq([0, 255, 0], [87.73, -86.18, 83.18]);
+        assert_lab_eq([0, 0, 255], [32.30, 79.19, -107.86]);
+    }
+
+    #[test]
+    fn delta_e_difference() {
+        let delta_e = |a: [u8; 4], b: [u8; 4]| pixel_difference(&a, &b, ReftestMetric::DeltaE);
+
+        assert_eq!(delta_e([0, 0, 0, 255], [128, 128, 128, 255]), 54);
+        // The difference of pure red and pure green is about 170.6.
+        assert_eq!(delta_e([255, 0, 0, 255], [0, 255, 0, 255]), 171);
+        // Differences that can't be seen still count as one.
+        assert_eq!(delta_e([100, 100, 100, 255], [100, 100, 101, 255]), 1);
+        // The alpha difference is used when it is larger.
+        assert_eq!(delta_e([100, 100, 100, 255], [100, 100, 100, 55]), 200);
+        // Unlike the largest channel difference, which ignores how the
+        // channels are perceived.
+        assert_eq!(
+            pixel_difference(&[0, 0, 0, 255], &[0, 0, 40, 255], ReftestMetric::MaxChannel),
+            40
+        );
+    }
+
+    #[test]
+    fn parse_fuzzy_region() {
+        let region = FuzzyRegion::parse("fuzzy-region(10,20,30,40,5,100)");
+        assert_eq!(
+            region.rect,
+            DeviceUintRect::new(DeviceUintPoint::new(10, 20), DeviceUintSize::new(30, 40))
+        );
+        assert_eq!(region.max_difference, 5);
+        assert_eq!(region.num_differences, 100);
+        assert_eq!(region.to_string(), "region 10,20,30,40");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected 6 arguments")]
+    fn parse_fuzzy_region_missing_arguments() {
+        FuzzyRegion::parse("fuzzy-region(10,20,30,40,5)");
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid argument '-1'")]
+    fn parse_fuzzy_region_invalid_argument() {
+        FuzzyRegion::parse("fuzzy-region(10,-1,30,40,5,100)");
+    }
+
+    fn headless_options() -> ReftestOptions {
+        ReftestOptions {
+            dual_source_blending: false,
+            headless: true,
+            ..ReftestOptions::default()
+        }
+    }
+
+    #[test]
+    fn evaluate_conditions() {
+        let options = headless_options();
+        let platform = super::PLATFORM;
+
+        assert!(options.evaluate_condition("headless"));
+        assert!(!options.evaluate_condition("angle"));
+        assert!(options.evaluate_condition("subpixel-aa"));
+        assert!(options.evaluate_condition(platform));
+        assert!(!options.evaluate_condition("dual-source-blending"));
+    }
+
+    #[test]
+    fn evaluate_negation() {
+        let options = headless_options();
+
+        assert!(!options.evaluate_condition("!headless"));
+        assert!(options.evaluate_condition("!angle"));
+        assert!(options.evaluate_condition("!!headless"));
+        assert!(options.evaluate_condition("!dual-source-blending"));
+        assert!(!options.evaluate_condition("!(headless||angle)"));
+    }
+
+    #[test]
+    fn evaluate_operators() {
+        let options = headless_options();
+
+        assert!(options.evaluate_condition("headless&&subpixel-aa"));
+        assert!(!options.evaluate_condition("headless&&angle"));
+        assert!(options.evaluate_condition("angle||headless"));
+        assert!(!options.evaluate_condition("angle||dual-source-blending"));
+        // && binds tighter than ||.
+        assert!(options.evaluate_condition("headless||angle&&dual-source-blending"));
+        assert!(!options.evaluate_condition("(headless||angle)&&dual-source-blending"));
+        assert!(options.evaluate_condition("subpixel-aa&&!(angle||dual-source-blending)"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown reftest condition variable: gpu")]
+    fn unknown_condition() {
+        headless_options().evaluate_condition("headless&&gpu");
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing ')'")]
+    fn unbalanced_condition() {
+        headless_options().evaluate_condition("!(headless||angle");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected ')'")]
+    fn trailing_condition() {
+        headless_options().evaluate_condition("headless)");
+    }
+
+    #[test]
+    fn escape_xml_characters() {
+        assert_eq!(escape_xml("a.yaml == b.yaml"), "a.yaml == b.yaml");
+        assert_eq!(
+            escape_xml(r#"<"a" & 'b'>"#),
+            "&lt;&quot;a&quot; &amp; 'b'&gt;"
+        );
+        // Ampersands are escaped first, so entities aren't escaped twice.
+        assert_eq!(escape_xml("&lt;"), "&amp;lt;");
+    }
+
+    fn result(test: &str, status: ReftestStatus) -> ReftestResult {
+        ReftestResult {
+            test: test.to_string(),
+            status,
+            duration_ms: 1500.0,
+            max_difference: 0,
+            count_different: 0,
+            region_differences: Vec::new(),
+            expected_draw_calls: None,
+            draw_calls: 1,
+            expected_alpha_targets: None,
+            alpha_targets: 0,
+            expected_color_targets: None,
+            color_targets: 1,
+            images: Vec::new(),
+            log: String::new(),
+        }
+    }
+
+    #[test]
+    fn junit_report() {
+        let mut failing = result("a.yaml != <b>.yaml", ReftestStatus::Fail);
+        failing.max_difference = 255;
+        failing.count_different = 10;
+        failing.region_differences.push((3, 2));
+        failing.images.push(PathBuf::from("a.png"));
+        let tests = [
+            result("pass.yaml == pass-ref.yaml", ReftestStatus::Pass),
+            failing,
+            result("known.yaml == known-ref.yaml", ReftestStatus::KnownFail),
+            result("random.yaml == random-ref.yaml", ReftestStatus::Random),
+            result("fixed.yaml == fixed-ref.yaml", ReftestStatus::UnexpectedPass),
+        ];
+        let report = ReftestReport {
+            passing: 1,
+            failing: 1,
+            unexpected_passing: 1,
+            known_failing: 1,
+            random: 1,
+            duration_ms: 7500.0,
+            tests: &tests,
+        };
+
+        let mut out = Vec::new();
+        write_junit_report(&mut out, &report).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+
+        assert_eq!(lines[0], r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        assert_eq!(
+            lines[1],
+            r#"<testsuite name="reftest" tests="5" failures="2" skipped="2" time="7.500">"#
+        );
+        assert_eq!(
+            lines[2],
+            r#"  <testcase classname="reftest" name="pass.yaml == pass-ref.yaml" time="1.500">"#
+        );
+        // Passing tests only have their output.
+        assert!(lines[3].starts_with("    <system-out>max difference: 0,"));
+        assert_eq!(lines[5], "</system-out>");
+        assert_eq!(lines[6], "  </testcase>");
+        assert_eq!(
+            lines[7],
+            r#"  <testcase classname="reftest" name="a.yaml != &lt;b&gt;.yaml" time="1.500">"#
+        );
+        assert_eq!(
+            lines[8],
+            r#"    <failure message="FAIL">max difference: 255, number of differing pixels: 10"#
+        );
+        assert_eq!(lines[9], "region 0: max difference: 3, number of differing pixels: 2");
+        assert_eq!(lines[10], "draw calls: 1, alpha targets: 0, color targets: 1");
+        assert_eq!(lines[11], "image: a.png");
+        assert_eq!(lines[12], "</failure>");
+        assert!(out.contains(r#"    <skipped message="KNOWN-FAIL"/>"#));
+        assert!(out.contains(r#"    <skipped message="RANDOM"/>"#));
+        assert!(out.contains(r#"    <failure message="UNEXPECTED-PASS">"#));
+        assert_eq!(out.matches("<failure").count(), 2);
+        assert_eq!(out.matches("<skipped").count(), 2);
+        assert_eq!(out.matches("</testcase>").count(), 5);
+        assert_eq!(lines.last(), Some(&"</testsuite>"));
+    }
+}
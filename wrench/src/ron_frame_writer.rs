@@ -139,6 +139,7 @@ impl RonFrameWriter {
                     }
                 },
                 ResourceUpdate::DeleteFont(_) => {}
+                ResourceUpdate::SetFontVariationAnimations(..) => {}
                 ResourceUpdate::AddFontInstance(_) => {}
                 ResourceUpdate::DeleteFontInstance(_) => {}
                 ResourceUpdate::SetImageVisibleArea(..) => {}
@@ -461,6 +461,7 @@ impl Wrench {
         render_mode: Option<FontRenderMode>,
         bg_color: Option<ColorU>,
         synthetic_italics: SyntheticItalics,
+        color_palette: u16,
     ) -> FontInstanceKey {
         let key = self.api.generate_font_instance_key();
         let mut txn = Transaction::new();
@@ -473,6 +474,7 @@ impl Wrench {
             options.bg_color = bg_color;
         }
         options.synthetic_italics = synthetic_italics;
+        options.color_palette = color_palette;
         txn.add_font_instance(key, font_key, size, Some(options), None, Vec::new());
         self.api.update_resources(txn.resource_updates);
         key
@@ -109,6 +109,105 @@ fn generate_checkerboard_image(
     )
 }
 
+// Encodes an opaque 4x4 block that is either white or black, which every
+// compressed format represents exactly.
+fn encode_solid_compressed_block(format: ImageFormat, white: bool) -> Vec<u8> {
+    let value = if white { 0xff } else { 0 };
+    match format {
+        ImageFormat::BC1 => vec![value, value, value, value, 0, 0, 0, 0],
+        ImageFormat::BC3 => {
+            let mut block = vec![0xff, 0xff, 0, 0, 0, 0, 0, 0];
+            block.extend_from_slice(&encode_solid_compressed_block(ImageFormat::BC1, white));
+            block
+        }
+        ImageFormat::ETC2 => {
+            // The planar mode interpolates between three colors, which have
+            // 6 bits for red and blue, and 7 bits for green.
+            let (r, g, b) = if white { (0x3f, 0x7f, 0x3f) } else { (0, 0, 0) };
+            let mut block = [
+                r << 1 | g >> 6,
+                (g & 0x3f) << 1 | b >> 5,
+                (b >> 3 & 0x3) << 3 | (b >> 1 & 0x3),
+                (b & 0x1) << 7 | (r >> 1) << 2 | 0x2 | (r & 0x1),
+                g << 1 | b >> 5,
+                (b & 0x1f) << 3 | r >> 3,
+                (r & 0x7) << 5 | g >> 2,
+                (g & 0x3) << 6 | b,
+            ];
+            // The planar mode is selected by the blue channel of the differential
+            // mode overflowing, while the red and green ones don't. The bits that
+            // aren't part of the colors are set to make that happen.
+            let fits = |byte: u8| {
+                let value = (byte >> 3) as i32 + (((byte & 0x7) as i32) << 29 >> 29);
+                value >= 0 && value <= 31
+            };
+            let padding = (0 .. 64u8)
+                .find(|padding| {
+                    let r = block[0] | (padding & 0x1) << 7;
+                    let g = block[1] | (padding >> 1 & 0x1) << 7;
+                    let b = block[2] | (padding >> 2 & 0x7) << 5 | (padding >> 5 & 0x1) << 2;
+                    fits(r) && fits(g) && !fits(b)
+                })
+                .unwrap();
+            block[0] |= (padding & 0x1) << 7;
+            block[1] |= (padding >> 1 & 0x1) << 7;
+            block[2] |= (padding >> 2 & 0x7) << 5 | (padding >> 5 & 0x1) << 2;
+            block.to_vec()
+        }
+        ImageFormat::ETC2A8 => {
+            // An opaque alpha block, with every pixel using a modifier of 0.
+            let indices = (0 .. 16).fold(0u64, |indices, _| indices << 3 | 0x4);
+            let mut block = vec![0xff, 0x1d];
+            for i in (0 .. 6).rev() {
+                block.push((indices >> (8 * i)) as u8);
+            }
+            block.extend_from_slice(&encode_solid_compressed_block(ImageFormat::ETC2, white));
+            block
+        }
+        ImageFormat::ASTC4x4 => {
+            // A void-extent block, followed by the 16 bit RGBA color.
+            let mut block = vec![0xfc, 0xfd, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+            block.extend_from_slice(&[value, value, value, value, value, value, 0xff, 0xff]);
+            block
+        }
+        ImageFormat::R8 |
+        ImageFormat::BGRA8 |
+        ImageFormat::RGBAF32 |
+        ImageFormat::RG8 |
+        ImageFormat::RGBAI32 => panic!("{:?} is not a compressed format", format),
+    }
+}
+
+// Generates a compressed checkerboard of white and black squares,
+// starting with a white one.
+fn generate_compressed_checkerboard_image(
+    format: ImageFormat,
+    tile_size: u32,
+    tile_count: u32,
+) -> (ImageDescriptor, ImageData) {
+    assert_eq!(
+        tile_size % COMPRESSED_BLOCK_DIMENSION, 0,
+        "The squares must be made of whole compressed blocks"
+    );
+    let size = tile_size * tile_count;
+    let block_count = size / COMPRESSED_BLOCK_DIMENSION;
+    let mut data = Vec::new();
+
+    for block_y in 0 .. block_count {
+        for block_x in 0 .. block_count {
+            let tile_x = block_x * COMPRESSED_BLOCK_DIMENSION / tile_size;
+            let tile_y = block_y * COMPRESSED_BLOCK_DIMENSION / tile_size;
+            let white = (tile_x + tile_y) % 2 == 0;
+            data.extend_from_slice(&encode_solid_compressed_block(format, white));
+        }
+    }
+
+    (
+        ImageDescriptor::new(size, size, format, true, false),
+        ImageData::new(data),
+    )
+}
+
 fn generate_xy_gradient_image(w: u32, h: u32) -> (ImageDescriptor, ImageData) {
     let mut pixels = Vec::with_capacity((w * h * 4) as usize);
     for y in 0 .. h {
@@ -159,6 +258,22 @@ fn generate_solid_color_image(
 
 
 
+fn text_decoration_from_yaml(item: &Yaml, text_color: ColorF) -> Option<TextDecoration> {
+    if item.is_badvalue() {
+        return None;
+    }
+    Some(TextDecoration {
+        offset: item["offset"].as_f32().expect("text decoration must have an offset"),
+        thickness: item["thickness"].as_f32().unwrap_or(1.0),
+        color: item["color"].as_colorf().unwrap_or(text_color),
+        style: item["style"]
+            .as_str()
+            .map(|style| LineStyle::from_str(style).expect("unknown text decoration style"))
+            .unwrap_or(LineStyle::Solid),
+        skip_ink: item["skip-ink"].as_bool().unwrap_or(true),
+    })
+}
+
 fn is_image_opaque(format: ImageFormat, bytes: &[u8]) -> bool {
     match format {
         ImageFormat::BGRA8 => {
@@ -174,7 +289,12 @@ fn is_image_opaque(format: ImageFormat, bytes: &[u8]) -> bool {
         ImageFormat::RG8 => true,
         ImageFormat::R8 => false,
         ImageFormat::RGBAF32 |
-        ImageFormat::RGBAI32 => unreachable!(),
+        ImageFormat::RGBAI32 |
+        ImageFormat::BC1 |
+        ImageFormat::BC3 |
+        ImageFormat::ETC2 |
+        ImageFormat::ETC2A8 |
+        ImageFormat::ASTC4x4 => unreachable!(),
     }
 }
 
@@ -199,7 +319,10 @@ pub struct YamlFrameReader {
     image_map: HashMap<(PathBuf, Option<i64>), (ImageKey, LayoutSize)>,
 
     fonts: HashMap<FontDescriptor, FontKey>,
-    font_instances: HashMap<(FontKey, Au, FontInstanceFlags, Option<ColorU>, SyntheticItalics), FontInstanceKey>,
+    font_instances: HashMap<
+        (FontKey, Au, FontInstanceFlags, Option<ColorU>, SyntheticItalics, u16),
+        FontInstanceKey
+    >,
     font_render_mode: Option<FontRenderMode>,
     allow_mipmaps: bool,
 
@@ -475,6 +598,21 @@ impl YamlFrameReader {
                         args.get(4).unwrap_or(&"1000").parse::<u32>().unwrap(),
                         args.get(5).unwrap_or(&"1000").parse::<u32>().unwrap(),
                     ),
+                    ("compressed-checkerboard", args, _) => {
+                        let format = match args.get(0) {
+                            Some(&"bc1") => ImageFormat::BC1,
+                            Some(&"bc3") => ImageFormat::BC3,
+                            Some(&"etc2") => ImageFormat::ETC2,
+                            Some(&"etc2a8") => ImageFormat::ETC2A8,
+                            Some(&"astc4x4") => ImageFormat::ASTC4x4,
+                            _ => panic!("invalid compressed-checkerboard format"),
+                        };
+                        generate_compressed_checkerboard_image(
+                            format,
+                            args.get(1).unwrap_or(&"16").parse::<u32>().unwrap(),
+                            args.get(2).unwrap_or(&"2").parse::<u32>().unwrap(),
+                        )
+                    }
                     ("checkerboard", args, _) => {
                         let border = args.get(0).unwrap_or(&"4").parse::<u32>().unwrap();
 
@@ -564,12 +702,13 @@ impl YamlFrameReader {
         bg_color: Option<ColorU>,
         flags: FontInstanceFlags,
         synthetic_italics: SyntheticItalics,
+        color_palette: u16,
         wrench: &mut Wrench,
     ) -> FontInstanceKey {
         let font_render_mode = self.font_render_mode;
 
         *self.font_instances
-            .entry((font_key, size, flags, bg_color, synthetic_italics))
+            .entry((font_key, size, flags, bg_color, synthetic_italics, color_palette))
             .or_insert_with(|| {
                 wrench.add_font_instance(
                     font_key,
@@ -578,6 +717,7 @@ impl YamlFrameReader {
                     font_render_mode,
                     bg_color,
                     synthetic_italics,
+                    color_palette,
                 )
             })
     }
@@ -612,6 +752,40 @@ impl YamlFrameReader {
         })
     }
 
+    fn to_stacking_context_mask(
+        &mut self,
+        item: &Yaml,
+        wrench: &mut Wrench,
+    ) -> Option<StackingContextMask> {
+        if item.as_hash().is_none() {
+            return None;
+        }
+
+        let file = match item["image"].as_str() {
+            Some(filename) => {
+                let mut file = self.aux_dir.clone();
+                file.push(filename);
+                file
+            }
+            None => {
+                warn!("No image provided for the stacking context mask!");
+                return None;
+            }
+        };
+
+        let (image_key, image_dims) =
+            self.add_or_get_image(&file, None, wrench);
+        let image_rect = item["rect"]
+            .as_rect()
+            .unwrap_or(LayoutRect::new(LayoutPoint::zero(), image_dims));
+        let mode = item["mode"].as_mask_mode().unwrap_or(MaskMode::Alpha);
+        Some(StackingContextMask {
+            image: image_key,
+            rect: image_rect,
+            mode,
+        })
+    }
+
     fn to_gradient(&mut self, dl: &mut DisplayListBuilder, item: &Yaml) -> Gradient {
         let start = item["start"].as_point().expect("gradient must have start");
         let end = item["end"].as_point().expect("gradient must have end");
@@ -666,6 +840,33 @@ impl YamlFrameReader {
         dl.create_radial_gradient(center, radius, stops, extend_mode)
     }
 
+    fn to_conic_gradient(&mut self, dl: &mut DisplayListBuilder, item: &Yaml) -> ConicGradient {
+        let center = item["center"].as_point().expect("conic gradient must have center");
+        let angle = item["angle"].as_force_f32().unwrap_or(0.0).to_radians();
+        let stops = item["stops"]
+            .as_vec()
+            .expect("conic gradient must have stops")
+            .chunks(2)
+            .map(|chunk| {
+                GradientStop {
+                    offset: chunk[0]
+                        .as_force_f32()
+                        .expect("gradient stop offset is not f32"),
+                    color: chunk[1]
+                        .as_colorf()
+                        .expect("gradient stop color is not color"),
+                }
+            })
+            .collect::<Vec<_>>();
+        let extend_mode = if item["repeat"].as_bool().unwrap_or(false) {
+            ExtendMode::Repeat
+        } else {
+            ExtendMode::Clamp
+        };
+
+        dl.create_conic_gradient(center, angle, stops, extend_mode)
+    }
+
     fn handle_rect(
         &mut self,
         dl: &mut DisplayListBuilder,
@@ -800,6 +1001,28 @@ impl YamlFrameReader {
         dl.push_radial_gradient(&info, gradient, tile_size, tile_spacing);
     }
 
+    fn handle_conic_gradient(
+        &mut self,
+        dl: &mut DisplayListBuilder,
+        item: &Yaml,
+        info: &mut LayoutPrimitiveInfo,
+    ) {
+        let bounds_key = if item["type"].is_badvalue() {
+            "conic-gradient"
+        } else {
+            "bounds"
+        };
+        let bounds = item[bounds_key]
+            .as_rect()
+            .expect("conic gradient must have bounds");
+        info.rect = bounds;
+        let gradient = self.to_conic_gradient(dl, item);
+        let tile_size = item["tile-size"].as_size().unwrap_or(bounds.size);
+        let tile_spacing = item["tile-spacing"].as_size().unwrap_or(LayoutSize::zero());
+
+        dl.push_conic_gradient(&info, gradient, tile_size, tile_spacing);
+    }
+
     fn handle_border(
         &mut self,
         dl: &mut DisplayListBuilder,
@@ -883,7 +1106,7 @@ impl YamlFrameReader {
                         radius,
                     }))
                 }
-                "image" | "gradient" | "radial-gradient" => {
+                "image" | "gradient" | "radial-gradient" | "conic-gradient" => {
                     let image_width = item["image-width"]
                         .as_i64()
                         .unwrap_or(info.rect.size.width as i64);
@@ -938,6 +1161,10 @@ impl YamlFrameReader {
                             NinePatchBorderSource::RadialGradient(gradient)
 
                         }
+                        "conic-gradient" => {
+                            let gradient = self.to_conic_gradient(dl, item);
+                            NinePatchBorderSource::ConicGradient(gradient)
+                        }
                         _ => unreachable!("Unexpected border type"),
                     };
 
@@ -1110,8 +1337,9 @@ impl YamlFrameReader {
             Some("auto") | None => ImageRendering::Auto,
             Some("crisp-edges") => ImageRendering::CrispEdges,
             Some("pixelated") => ImageRendering::Pixelated,
+            Some("high-quality") => ImageRendering::HighQuality,
             Some(_) => panic!(
-                "ImageRendering can be auto, crisp-edges, or pixelated -- got {:?}",
+                "ImageRendering can be auto, crisp-edges, pixelated, or high-quality -- got {:?}",
                 item
             ),
         };
@@ -1143,6 +1371,7 @@ impl YamlFrameReader {
         } else {
             SyntheticItalics::disabled()
         };
+        let color_palette = item["color-palette"].as_i64().unwrap_or(0) as u16;
 
         let mut flags = FontInstanceFlags::empty();
         if item["synthetic-bold"].as_bool().unwrap_or(false) {
@@ -1173,6 +1402,7 @@ impl YamlFrameReader {
                                                                  bg_color,
                                                                  flags,
                                                                  synthetic_italics,
+                                                                 color_palette,
                                                                  wrench);
 
         assert!(
@@ -1238,7 +1468,35 @@ impl YamlFrameReader {
         };
         info.rect = rect;
 
-        dl.push_text(&info, &glyphs, font_instance_key, color, None);
+        // Emphasis marks are glyphs of the same font, at half its size
+        // unless they have a size of their own.
+        let emphasis_item = &item["emphasis"];
+        let emphasis = if emphasis_item.is_badvalue() {
+            None
+        } else {
+            let mark_size = emphasis_item["size"].as_pt_to_au().unwrap_or(Au(size.0 / 2));
+            Some(TextEmphasis {
+                font_key: self.get_or_create_font_instance(font_key,
+                                                           mark_size,
+                                                           bg_color,
+                                                           flags,
+                                                           synthetic_italics,
+                                                           color_palette,
+                                                           wrench),
+                mark: emphasis_item["glyph"].as_i64().expect("emphasis must have a glyph") as u32,
+                offset: emphasis_item["offset"].as_f32().expect("emphasis must have an offset"),
+                color: emphasis_item["color"].as_colorf().unwrap_or(color),
+            })
+        };
+
+        let decorations = TextDecorations {
+            underline: text_decoration_from_yaml(&item["underline"], color),
+            overline: text_decoration_from_yaml(&item["overline"], color),
+            line_through: text_decoration_from_yaml(&item["line-through"], color),
+            emphasis,
+        };
+
+        dl.push_decorated_text(&info, &glyphs, font_instance_key, color, None, decorations);
     }
 
     fn handle_iframe(
@@ -1271,6 +1529,7 @@ impl YamlFrameReader {
             "border",
             "gradient",
             "radial-gradient",
+            "conic-gradient",
         ];
 
         for shorthand in shorthands.iter() {
@@ -1346,6 +1605,7 @@ impl YamlFrameReader {
                 "border" => self.handle_border(dl, wrench, item, &mut info),
                 "gradient" => self.handle_gradient(dl, item, &mut info),
                 "radial-gradient" => self.handle_radial_gradient(dl, item, &mut info),
+                "conic-gradient" => self.handle_conic_gradient(dl, item, &mut info),
                 "box-shadow" => self.handle_box_shadow(dl, item, &mut info),
                 "iframe" => self.handle_iframe(dl, item, &mut info),
                 "stacking-context" => {
@@ -1492,8 +1752,23 @@ impl YamlFrameReader {
         let numeric_id = yaml["id"].as_i64();
         let complex_clips = self.to_complex_clip_regions(&yaml["complex"]);
         let image_mask = self.to_image_mask(&yaml["image-mask"], wrench);
+        let path = if !yaml["path"].is_badvalue() {
+            Some(yaml["path"].as_path().expect("clip path is invalid"))
+        } else if !yaml["polygon"].is_badvalue() {
+            Some(yaml["polygon"].as_polygon().expect("clip polygon is invalid"))
+        } else {
+            None
+        };
 
-        let real_id = dl.define_clip(clip_rect, complex_clips, image_mask);
+        let real_id = match path {
+            Some(path) => {
+                assert!(complex_clips.is_empty() && image_mask.is_none(),
+                        "path clips can't have complex clips or an image mask");
+                let fill_rule = yaml["fill-rule"].as_fill_rule().unwrap_or(FillRule::NonZero);
+                dl.define_path_clip(clip_rect, &path, fill_rule)
+            }
+            None => dl.define_clip(clip_rect, complex_clips, image_mask),
+        };
         if let Some(numeric_id) = numeric_id {
             self.add_clip_id_mapping(numeric_id as u64, real_id);
         }
@@ -1618,11 +1893,16 @@ impl YamlFrameReader {
         }
 
         let filters = yaml["filters"].as_vec_filter_op().unwrap_or(vec![]);
+        let filter_primitives = yaml["filter-primitives"]
+            .as_vec_filter_primitive()
+            .unwrap_or(vec![]);
+        let mask = self.to_stacking_context_mask(&yaml["mask"], wrench);
 
         if let Some(reference_frame_id) = reference_frame_id {
             dl.push_clip_id(reference_frame_id);
         }
 
+        dl.push_filter_primitives(&filter_primitives);
         dl.push_stacking_context(
             &info,
             clip_node_id,
@@ -1630,6 +1910,7 @@ impl YamlFrameReader {
             mix_blend_mode,
             filters,
             glyph_raster_space,
+            mask,
         );
 
         if !yaml["items"].is_badvalue() {
@@ -149,6 +149,19 @@ fn u32_vec_node(parent: &mut Table, key: &str, value: &[u32]) {
     yaml_node(parent, key, u32_vec_yaml(value, false));
 }
 
+fn path_to_string<I: Iterator<Item = PathOp>>(path: I) -> String {
+    let commands: Vec<String> = path.map(|op| match op {
+        PathOp::MoveTo(p) => format!("M {} {}", p.x, p.y),
+        PathOp::LineTo(p) => format!("L {} {}", p.x, p.y),
+        PathOp::QuadraticTo(c, p) => format!("Q {} {} {} {}", c.x, c.y, p.x, p.y),
+        PathOp::CubicTo(c1, c2, p) => {
+            format!("C {} {} {} {} {} {}", c1.x, c1.y, c2.x, c2.y, p.x, p.y)
+        }
+        PathOp::Close => "Z".to_owned(),
+    }).collect();
+    commands.join(" ")
+}
+
 fn f32_vec_yaml(value: &[f32], check_unique: bool) -> Yaml {
     if !value.is_empty() && check_unique && array_elements_are_same(value) {
         Yaml::Real(value[0].to_string())
@@ -161,6 +174,18 @@ fn f32_vec_node(parent: &mut Table, key: &str, value: &[f32]) {
     yaml_node(parent, key, f32_vec_yaml(value, false));
 }
 
+fn text_decoration_node(parent: &mut Table, key: &str, value: &Option<TextDecoration>) {
+    if let Some(ref decoration) = *value {
+        let mut table = new_table();
+        f32_node(&mut table, "offset", decoration.offset);
+        f32_node(&mut table, "thickness", decoration.thickness);
+        color_node(&mut table, "color", decoration.color);
+        str_node(&mut table, "style", decoration.style.as_str());
+        bool_node(&mut table, "skip-ink", decoration.skip_ink);
+        table_node(parent, key, table);
+    }
+}
+
 fn maybe_radius_yaml(radius: &BorderRadius) -> Option<Yaml> {
     if let Some(radius) = radius.is_uniform_size() {
         if radius == LayoutSize::zero() {
@@ -255,6 +280,83 @@ fn write_stacking_context(
     yaml_node(parent, "filters", Yaml::Array(filters));
 }
 
+fn filter_input_to_yaml(input: FilterPrimitiveInput) -> Yaml {
+    match input {
+        FilterPrimitiveInput::SourceGraphic => Yaml::String("source-graphic".to_owned()),
+        FilterPrimitiveInput::SourceAlpha => Yaml::String("source-alpha".to_owned()),
+        FilterPrimitiveInput::Result(index) => Yaml::Integer(index as i64),
+    }
+}
+
+fn transfer_function_to_string(function: &TransferFunction) -> String {
+    let join = |values: &[f32]| {
+        values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+    };
+    match *function {
+        TransferFunction::Identity => "identity".to_owned(),
+        TransferFunction::Table(ref values) => format!("table({})", join(values)),
+        TransferFunction::Discrete(ref values) => format!("discrete({})", join(values)),
+        TransferFunction::Linear { slope, intercept } => {
+            format!("linear({},{})", slope, intercept)
+        }
+        TransferFunction::Gamma { amplitude, exponent, offset } => {
+            format!("gamma({},{},{})", amplitude, exponent, offset)
+        }
+    }
+}
+
+fn write_filter_primitives(parent: &mut Table, primitive_iter: AuxIter<FilterPrimitive>) {
+    let mut primitives = vec![];
+    for primitive in primitive_iter {
+        let mut table = new_table();
+        match primitive {
+            FilterPrimitive::Flood { color } => {
+                str_node(&mut table, "type", "flood");
+                color_node(&mut table, "color", color);
+            }
+            FilterPrimitive::Offset { input, offset } => {
+                str_node(&mut table, "type", "offset");
+                yaml_node(&mut table, "in", filter_input_to_yaml(input));
+                vector_node(&mut table, "offset", &offset);
+            }
+            FilterPrimitive::ComponentTransfer { input, red, green, blue, alpha } => {
+                str_node(&mut table, "type", "component-transfer");
+                yaml_node(&mut table, "in", filter_input_to_yaml(input));
+                str_node(&mut table, "red", &transfer_function_to_string(&red));
+                str_node(&mut table, "green", &transfer_function_to_string(&green));
+                str_node(&mut table, "blue", &transfer_function_to_string(&blue));
+                str_node(&mut table, "alpha", &transfer_function_to_string(&alpha));
+            }
+            FilterPrimitive::Composite { input1, input2, operator } => {
+                str_node(&mut table, "type", "composite");
+                yaml_node(&mut table, "in", filter_input_to_yaml(input1));
+                yaml_node(&mut table, "in2", filter_input_to_yaml(input2));
+                let operator = match operator {
+                    CompositeOperator::Over => "over".to_owned(),
+                    CompositeOperator::In => "in".to_owned(),
+                    CompositeOperator::Out => "out".to_owned(),
+                    CompositeOperator::Atop => "atop".to_owned(),
+                    CompositeOperator::Xor => "xor".to_owned(),
+                    CompositeOperator::Arithmetic(k) => {
+                        format!("arithmetic({},{},{},{})", k[0], k[1], k[2], k[3])
+                    }
+                };
+                str_node(&mut table, "operator", &operator);
+            }
+            FilterPrimitive::Merge { inputs } => {
+                str_node(&mut table, "type", "merge");
+                let inputs = inputs.into_iter().map(filter_input_to_yaml).collect();
+                yaml_node(&mut table, "inputs", Yaml::Array(inputs));
+            }
+        }
+        primitives.push(Yaml::Hash(table));
+    }
+
+    if !primitives.is_empty() {
+        yaml_node(parent, "filter-primitives", Yaml::Array(primitives));
+    }
+}
+
 #[cfg(target_os = "windows")]
 fn native_font_handle_to_yaml(
     _rsrc: &mut ResourceGenerator,
@@ -328,6 +430,30 @@ fn radial_gradient_to_yaml(
     bool_node(table, "repeat", gradient.extend_mode == ExtendMode::Repeat);
 }
 
+fn conic_gradient_to_yaml(
+    table: &mut Table,
+    gradient: &webrender::api::ConicGradient,
+    stops_range: ItemRange<GradientStop>,
+    display_list: &BuiltDisplayList
+) {
+    point_node(table, "center", &gradient.center);
+    f32_node(table, "angle", gradient.angle.to_degrees());
+
+    let first_offset = gradient.start_offset;
+    let last_offset = gradient.end_offset;
+    let stops_delta = last_offset - first_offset;
+    assert!(first_offset <= last_offset);
+
+    let mut denormalized_stops = vec![];
+    for stop in display_list.get(stops_range) {
+        let denormalized_stop = (stop.offset * stops_delta) + first_offset;
+        denormalized_stops.push(Yaml::Real(denormalized_stop.to_string()));
+        denormalized_stops.push(Yaml::String(color_to_string(stop.color)));
+    }
+    yaml_node(table, "stops", Yaml::Array(denormalized_stops));
+    bool_node(table, "repeat", gradient.extend_mode == ExtendMode::Repeat);
+}
+
 enum CachedFont {
     Native(NativeFontHandle, Option<PathBuf>),
     Raw(Option<Vec<u8>>, u32, Option<PathBuf>),
@@ -526,9 +652,7 @@ impl YamlFrameWriter {
                           }
                     }
 
-                    let stride = img.descriptor.stride.unwrap_or(
-                        img.descriptor.size.width * img.descriptor.format.bytes_per_pixel(),
-                    );
+                    let stride = img.descriptor.compute_stride();
                     let bytes = match img.data {
                         ImageData::Raw(ref v) => (**v).clone(),
                         ImageData::External(_) | ImageData::Blob(_) => {
@@ -580,6 +704,7 @@ impl YamlFrameWriter {
                     }
                 },
                 ResourceUpdate::DeleteFont(_) => {}
+                ResourceUpdate::SetFontVariationAnimations(..) => {}
                 ResourceUpdate::AddFontInstance(ref instance) => {
                     self.font_instances.insert(
                         instance.key,
@@ -700,6 +825,21 @@ impl YamlFrameWriter {
         Some(Yaml::Hash(mask_table))
     }
 
+    fn make_stacking_context_mask_node(&mut self, mask: &Option<StackingContextMask>) -> Option<Yaml> {
+        let mask = match mask {
+            &Some(ref mask) => mask,
+            &None => return None,
+        };
+
+        let mut mask_table = new_table();
+        if let Some(path) = self.path_for_image(mask.image) {
+            path_node(&mut mask_table, "image", &path);
+        }
+        rect_node(&mut mask_table, "rect", &mask.rect);
+        enum_node(&mut mask_table, "mode", mask.mode);
+        Some(Yaml::Hash(mask_table))
+    }
+
     fn write_display_list_items(
         &mut self,
         list: &mut Vec<Yaml>,
@@ -763,6 +903,24 @@ impl YamlFrameWriter {
                     }
                     u32_vec_node(&mut v, "glyphs", &indices);
                     f32_vec_node(&mut v, "offsets", &offsets);
+                    text_decoration_node(&mut v, "underline", &item.decorations.underline);
+                    text_decoration_node(&mut v, "overline", &item.decorations.overline);
+                    text_decoration_node(&mut v, "line-through", &item.decorations.line_through);
+                    if let Some(ref emphasis) = item.decorations.emphasis {
+                        // The marks are read back as glyphs of the font of the run.
+                        let mut table = new_table();
+                        u32_node(&mut table, "glyph", emphasis.mark);
+                        if let Some(instance) = self.font_instances.get(&emphasis.font_key) {
+                            f32_node(
+                                &mut table,
+                                "size",
+                                instance.glyph_size.to_f32_px() * 12.0 / 16.0,
+                            );
+                        }
+                        f32_node(&mut table, "offset", emphasis.offset);
+                        color_node(&mut table, "color", emphasis.color);
+                        table_node(&mut v, "emphasis", table);
+                    }
 
                     let instance = self.font_instances.entry(item.font_key).or_insert_with(|| {
                         println!("Warning: font instance key not found in font instances table!");
@@ -823,6 +981,7 @@ impl YamlFrameWriter {
                         ImageRendering::Auto => (),
                         ImageRendering::CrispEdges => str_node(&mut v, "rendering", "crisp-edges"),
                         ImageRendering::Pixelated => str_node(&mut v, "rendering", "pixelated"),
+                        ImageRendering::HighQuality => str_node(&mut v, "rendering", "high-quality"),
                     };
                     match item.alpha_type {
                         AlphaType::PremultipliedAlpha => str_node(&mut v, "alpha-type", "premultiplied-alpha"),
@@ -915,6 +1074,15 @@ impl YamlFrameWriter {
                                         display_list
                                     );
                                 }
+                                NinePatchBorderSource::ConicGradient(gradient) => {
+                                    str_node(&mut v, "border-type", "conic-gradient");
+                                    conic_gradient_to_yaml(
+                                        &mut v,
+                                        &gradient,
+                                        base.gradient_stops(),
+                                        display_list
+                                    );
+                                }
                             }
 
                             u32_node(&mut v, "image-width", details.width);
@@ -993,6 +1161,17 @@ impl YamlFrameWriter {
                         display_list
                     );
                 }
+                ConicGradient(item) => {
+                    str_node(&mut v, "type", "conic-gradient");
+                    size_node(&mut v, "tile-size", &item.tile_size);
+                    size_node(&mut v, "tile-spacing", &item.tile_spacing);
+                    conic_gradient_to_yaml(
+                        &mut v,
+                        &item.gradient,
+                        base.gradient_stops(),
+                        display_list
+                    );
+                }
                 Iframe(item) => {
                     str_node(&mut v, "type", "iframe");
                     u32_vec_node(&mut v, "id", &[item.pipeline_id.0, item.pipeline_id.1]);
@@ -1009,6 +1188,12 @@ impl YamlFrameWriter {
                         clip_id_mapper,
                     );
 
+                    if let Some(mask_yaml) = self.make_stacking_context_mask_node(&item.stacking_context.mask) {
+                        yaml_node(&mut v, "mask", mask_yaml);
+                    }
+
+                    write_filter_primitives(&mut v, display_list.get(base.filter_primitives()));
+
                     let mut sub_iter = base.sub_iter();
                     self.write_display_list(&mut v, display_list, scene, &mut sub_iter, clip_id_mapper);
                     continue_traversal = Some(sub_iter);
@@ -1042,6 +1227,12 @@ impl YamlFrameWriter {
                     if let Some(mask_yaml) = self.make_clip_mask_image_node(&item.image_mask) {
                         yaml_node(&mut v, "image-mask", mask_yaml);
                     }
+
+                    let path = base.clip_path();
+                    if !path.is_empty() {
+                        str_node(&mut v, "path", &path_to_string(display_list.get(path)));
+                        enum_node(&mut v, "fill-rule", item.fill_rule);
+                    }
                 }
                 ClipChain(item) => {
                     str_node(&mut v, "type", "clip-chain");
@@ -1118,6 +1309,7 @@ impl YamlFrameWriter {
                 PopStackingContext => return,
                 PopReferenceFrame => return,
                 SetGradientStops => panic!("dummy item yielded?"),
+                SetFilterPrimitives => panic!("dummy item yielded?"),
                 PushShadow(shadow) => {
                     str_node(&mut v, "type", "shadow");
                     vector_node(&mut v, "offset", &shadow.offset);
@@ -34,8 +34,17 @@ pub trait YamlHelper {
     fn as_glyph_raster_space(&self) -> Option<GlyphRasterSpace>;
     fn as_clip_mode(&self) -> Option<ClipMode>;
     fn as_mix_blend_mode(&self) -> Option<MixBlendMode>;
+    fn as_mask_mode(&self) -> Option<MaskMode>;
     fn as_filter_op(&self) -> Option<FilterOp>;
     fn as_vec_filter_op(&self) -> Option<Vec<FilterOp>>;
+    fn as_filter_input(&self) -> Option<FilterPrimitiveInput>;
+    fn as_transfer_function(&self) -> Option<TransferFunction>;
+    fn as_composite_operator(&self) -> Option<CompositeOperator>;
+    fn as_filter_primitive(&self) -> Option<FilterPrimitive>;
+    fn as_vec_filter_primitive(&self) -> Option<Vec<FilterPrimitive>>;
+    fn as_fill_rule(&self) -> Option<FillRule>;
+    fn as_path(&self) -> Option<Vec<PathOp>>;
+    fn as_polygon(&self) -> Option<Vec<PathOp>>;
 }
 
 fn string_to_color(color: &str) -> Option<ColorF> {
@@ -138,6 +147,10 @@ define_string_enum!(
 
 define_string_enum!(ClipMode, [Clip = "clip", ClipOut = "clip-out"]);
 
+define_string_enum!(MaskMode, [Alpha = "alpha", Luminance = "luminance"]);
+
+define_string_enum!(FillRule, [NonZero = "nonzero", EvenOdd = "evenodd"]);
+
 // Rotate around `axis` by `degrees` angle
 fn make_rotation(
     origin: &LayoutPoint,
@@ -541,6 +554,10 @@ impl YamlHelper for Yaml {
         self.as_str().and_then(|x| StringEnum::from_str(x))
     }
 
+    fn as_mask_mode(&self) -> Option<MaskMode> {
+        self.as_str().and_then(|x| StringEnum::from_str(x))
+    }
+
     fn as_filter_op(&self) -> Option<FilterOp> {
         if let Some(s) = self.as_str() {
             match parse_function(s) {
@@ -600,4 +617,151 @@ impl YamlHelper for Yaml {
             self.as_filter_op().map(|op| vec![op])
         }
     }
+
+    fn as_filter_input(&self) -> Option<FilterPrimitiveInput> {
+        if let Some(index) = self.as_i64() {
+            return Some(FilterPrimitiveInput::Result(index as usize));
+        }
+        match self.as_str() {
+            Some("source-graphic") => Some(FilterPrimitiveInput::SourceGraphic),
+            Some("source-alpha") => Some(FilterPrimitiveInput::SourceAlpha),
+            _ => None,
+        }
+    }
+
+    fn as_transfer_function(&self) -> Option<TransferFunction> {
+        if let Some(s) = self.as_str() {
+            let args_f32 = |args: &[&str]| -> Vec<f32> {
+                args.iter().map(|a| a.parse().unwrap()).collect()
+            };
+            match parse_function(s) {
+                ("identity", ref args, _) if args.is_empty() => Some(TransferFunction::Identity),
+                ("table", ref args, _) => Some(TransferFunction::Table(args_f32(args))),
+                ("discrete", ref args, _) => Some(TransferFunction::Discrete(args_f32(args))),
+                ("linear", ref args, _) if args.len() == 2 => {
+                    let args = args_f32(args);
+                    Some(TransferFunction::Linear {
+                        slope: args[0],
+                        intercept: args[1],
+                    })
+                }
+                ("gamma", ref args, _) if args.len() == 3 => {
+                    let args = args_f32(args);
+                    Some(TransferFunction::Gamma {
+                        amplitude: args[0],
+                        exponent: args[1],
+                        offset: args[2],
+                    })
+                }
+                (_, _, _) => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    fn as_composite_operator(&self) -> Option<CompositeOperator> {
+        if let Some(s) = self.as_str() {
+            match parse_function(s) {
+                ("over", _, _) => Some(CompositeOperator::Over),
+                ("in", _, _) => Some(CompositeOperator::In),
+                ("out", _, _) => Some(CompositeOperator::Out),
+                ("atop", _, _) => Some(CompositeOperator::Atop),
+                ("xor", _, _) => Some(CompositeOperator::Xor),
+                ("arithmetic", ref args, _) if args.len() == 4 => {
+                    let mut k = [0.0; 4];
+                    for (value, arg) in k.iter_mut().zip(args) {
+                        *value = arg.parse().unwrap();
+                    }
+                    Some(CompositeOperator::Arithmetic(k))
+                }
+                (_, _, _) => None,
+            }
+        } else {
+            None
+        }
+    }
+
+    fn as_filter_primitive(&self) -> Option<FilterPrimitive> {
+        let transfer_function = |key: &str| {
+            self[key].as_transfer_function().unwrap_or(TransferFunction::Identity)
+        };
+        match self["type"].as_str() {
+            Some("flood") => Some(FilterPrimitive::Flood {
+                color: self["color"].as_colorf()?,
+            }),
+            Some("offset") => Some(FilterPrimitive::Offset {
+                input: self["in"].as_filter_input()?,
+                offset: self["offset"].as_vector()?,
+            }),
+            Some("component-transfer") => Some(FilterPrimitive::ComponentTransfer {
+                input: self["in"].as_filter_input()?,
+                red: transfer_function("red"),
+                green: transfer_function("green"),
+                blue: transfer_function("blue"),
+                alpha: transfer_function("alpha"),
+            }),
+            Some("composite") => Some(FilterPrimitive::Composite {
+                input1: self["in"].as_filter_input()?,
+                input2: self["in2"].as_filter_input()?,
+                operator: self["operator"].as_composite_operator()?,
+            }),
+            Some("merge") => Some(FilterPrimitive::Merge {
+                inputs: self["inputs"]
+                    .as_vec()?
+                    .iter()
+                    .map(|input| input.as_filter_input().unwrap())
+                    .collect(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn as_vec_filter_primitive(&self) -> Option<Vec<FilterPrimitive>> {
+        self.as_vec()
+            .map(|v| v.iter().map(|x| x.as_filter_primitive().unwrap()).collect())
+    }
+
+    fn as_fill_rule(&self) -> Option<FillRule> {
+        self.as_str().and_then(|x| StringEnum::from_str(x))
+    }
+
+    /// Parses a path like "M 0 0 L 10 0 Q 10 10 0 10 Z", with the absolute
+    /// SVG path commands M, L, Q, C and Z separated by whitespace.
+    fn as_path(&self) -> Option<Vec<PathOp>> {
+        let s = self.as_str()?;
+        let mut tokens = s.split_whitespace().peekable();
+        let mut ops = Vec::new();
+
+        while let Some(command) = tokens.next() {
+            let mut points = Vec::new();
+            while let Some(&token) = tokens.peek() {
+                if f32::from_str(token).is_err() {
+                    break;
+                }
+                let x = f32::from_str(tokens.next()?).ok()?;
+                let y = f32::from_str(tokens.next()?).ok()?;
+                points.push(LayoutPoint::new(x, y));
+            }
+
+            ops.push(match (command, points.len()) {
+                ("M", 1) => PathOp::MoveTo(points[0]),
+                ("L", 1) => PathOp::LineTo(points[0]),
+                ("Q", 2) => PathOp::QuadraticTo(points[0], points[1]),
+                ("C", 3) => PathOp::CubicTo(points[0], points[1], points[2]),
+                ("Z", 0) => PathOp::Close,
+                _ => return None,
+            });
+        }
+
+        Some(ops)
+    }
+
+    fn as_polygon(&self) -> Option<Vec<PathOp>> {
+        let points = self.as_vec()?
+            .iter()
+            .map(|point| point.as_point())
+            .collect::<Option<Vec<_>>>()?;
+        Some(PathOp::polygon(&points))
+    }
 }